@@ -0,0 +1,367 @@
+use crate::attributes::{self, GritAttributeRule};
+use crate::config::GRIT_DIRECTORY;
+use crate::lockfile::GritLockFile;
+use crate::object_store::{self, GritObjectReader, GritObjectStore};
+use crate::plumbing::{self, GritCatType};
+use crate::utils;
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GritArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl GritArchiveFormat {
+    pub fn parse(name: &str) -> Option<GritArchiveFormat> {
+        match name {
+            "tar" => Some(GritArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Some(GritArchiveFormat::TarGz),
+            "zip" => Some(GritArchiveFormat::Zip),
+            _ => {
+                eprintln!("fatal: Unknown archive format '{name}'");
+                None
+            }
+        }
+    }
+
+    /// Guesses the format from the name of the file being written, as `--output` does.
+    fn from_filename(filename: &str) -> Option<GritArchiveFormat> {
+        if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") {
+            Some(GritArchiveFormat::TarGz)
+        } else if filename.ends_with(".zip") {
+            Some(GritArchiveFormat::Zip)
+        } else if filename.ends_with(".tar") {
+            Some(GritArchiveFormat::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct GritArchiveOptions {
+    /// Taken from the output filename when not given, otherwise tar.
+    pub format: Option<GritArchiveFormat>,
+    /// Put in front of every path as is, so a directory needs its trailing slash.
+    pub prefix: String,
+    /// Where to write the archive instead of stdout.
+    pub output: Option<String>,
+}
+
+/// A file from the tree being archived.
+struct GritArchiveEntry {
+    path: String,
+    mode: String,
+    oid: String,
+}
+
+impl GritArchiveEntry {
+    fn is_symlink(&self) -> bool {
+        self.mode == "120000"
+    }
+
+    fn get_permissions(&self) -> u32 {
+        if self.mode == "100755" {
+            0o755
+        } else {
+            0o644
+        }
+    }
+}
+
+fn get_tree_files(tree_oid: &str) -> Option<Vec<GritArchiveEntry>> {
+    let (_tree_map, _paths, tree_entries, _oids) = plumbing::get_tree_entries(tree_oid)?;
+
+    let mut files = tree_entries
+        .iter()
+        .filter_map(|entry| {
+            let mut fields = entry.splitn(4, ' ');
+
+            Some(GritArchiveEntry {
+                mode: fields.next()?.to_string(),
+                oid: fields.nth(1)?.to_string(),
+                path: fields.next()?.to_string(),
+            })
+        })
+        .collect::<Vec<GritArchiveEntry>>();
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Some(files)
+}
+
+/// Attributes come from the `.gritattributes` in the archived tree, not the
+/// working tree, so an old commit is exported the way it was set up back then.
+fn get_tree_attributes(files: &[GritArchiveEntry]) -> Option<Vec<GritAttributeRule>> {
+    let mut rules = match files.iter().find(|file| file.path == ".gritattributes") {
+        Some(file) => attributes::parse_attributes(&plumbing::generate_cat_content(
+            &file.oid,
+            GritCatType::Pretty,
+        )?),
+        None => vec![],
+    };
+
+    rules.extend(attributes::get_info_attributes()?);
+
+    Some(rules)
+}
+
+fn is_in_paths(path: &str, paths: &[String]) -> bool {
+    paths.is_empty()
+        || paths.iter().any(|pathspec| {
+            let pathspec = pathspec.trim_end_matches('/');
+            path == pathspec || path.starts_with(&format!("{pathspec}/"))
+        })
+}
+
+/// The directories leading to `path` that have not had an entry yet.
+fn get_new_directories(path: &str, written: &mut HashSet<String>) -> Vec<String> {
+    let mut directories = vec![];
+    let mut end = 0;
+
+    while let Some(position) = path[end..].find('/') {
+        end += position + 1;
+        let directory = path[..end].to_string();
+
+        if written.insert(directory.clone()) {
+            directories.push(directory);
+        }
+    }
+
+    directories
+}
+
+/// Opens a file's blob as its size and a reader of its content, so it can be
+/// copied into the archive a chunk at a time.
+fn open_blob<'a>(
+    store: &'a dyn GritObjectStore,
+    oid: &str,
+) -> io::Result<(u64, GritObjectReader<'a>)> {
+    let (_object_type, size, reader) = object_store::open_object(store, oid)
+        .ok_or(io::Error::other(format!("cannot read object {oid}")))?;
+
+    match size.parse::<u64>() {
+        Ok(size) => Ok((size, reader)),
+        Err(_) => Err(io::Error::other(format!("object {oid} is corrupt"))),
+    }
+}
+
+/// The target of a symlink, which is the whole content of its blob.
+fn read_link_target(store: &dyn GritObjectStore, oid: &str) -> io::Result<String> {
+    let (_size, mut reader) = open_blob(store, oid)?;
+    let mut target = String::new();
+
+    reader.read_to_string(&mut target)?;
+
+    Ok(target)
+}
+
+fn write_tar(
+    store: &dyn GritObjectStore,
+    writer: impl Write,
+    files: &[GritArchiveEntry],
+    prefix: &str,
+    mtime: i64,
+) -> io::Result<()> {
+    let mut builder = tar::Builder::new(writer);
+    let mut written = HashSet::new();
+
+    let new_header = |entry_type: tar::EntryType, mode: u32, size: u64| {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(size);
+        header.set_mtime(mtime.max(0) as u64);
+        header
+    };
+
+    for file in files {
+        let path = format!("{prefix}{}", file.path);
+
+        for directory in get_new_directories(&path, &mut written) {
+            let mut header = new_header(tar::EntryType::Directory, 0o755, 0);
+            builder.append_data(&mut header, directory, io::empty())?;
+        }
+
+        if file.is_symlink() {
+            let target = read_link_target(store, &file.oid)?;
+            let mut header = new_header(tar::EntryType::Symlink, 0o777, 0);
+            builder.append_link(&mut header, &path, &target)?;
+        } else {
+            let (size, reader) = open_blob(store, &file.oid)?;
+            let mut header = new_header(tar::EntryType::Regular, file.get_permissions(), size);
+            builder.append_data(&mut header, &path, reader)?;
+        }
+    }
+
+    builder.into_inner()?.flush()
+}
+
+fn write_zip(
+    store: &dyn GritObjectStore,
+    writer: impl Write + Seek,
+    files: &[GritArchiveEntry],
+    prefix: &str,
+    mtime: i64,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new(writer);
+    let mut written = HashSet::new();
+
+    let datetime = NaiveDateTime::from_timestamp_opt(mtime, 0)
+        .and_then(|datetime| {
+            zip::DateTime::from_date_and_time(
+                datetime.year() as u16,
+                datetime.month() as u8,
+                datetime.day() as u8,
+                datetime.hour() as u8,
+                datetime.minute() as u8,
+                datetime.second() as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default();
+
+    let options = FileOptions::default().last_modified_time(datetime);
+
+    for file in files {
+        let path = format!("{prefix}{}", file.path);
+
+        for directory in get_new_directories(&path, &mut written) {
+            zip.add_directory(directory, options.unix_permissions(0o755))?;
+        }
+
+        if file.is_symlink() {
+            zip.add_symlink(path, read_link_target(store, &file.oid)?, options)?;
+        } else {
+            let (_size, mut reader) = open_blob(store, &file.oid)?;
+            zip.start_file(path, options.unix_permissions(file.get_permissions()))?;
+            io::copy(&mut reader, &mut zip)?;
+        }
+    }
+
+    zip.finish()?.flush()
+}
+
+fn build_archive(
+    format: GritArchiveFormat,
+    file: &mut File,
+    files: &[GritArchiveEntry],
+    prefix: &str,
+    mtime: i64,
+) -> io::Result<()> {
+    let store =
+        object_store::get_object_store().ok_or(io::Error::other("cannot open the object store"))?;
+
+    match format {
+        GritArchiveFormat::Zip => write_zip(store.as_ref(), file, files, prefix, mtime),
+        GritArchiveFormat::TarGz => {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            write_tar(store.as_ref(), &mut encoder, files, prefix, mtime)?;
+            encoder.finish()?.flush()
+        }
+        GritArchiveFormat::Tar => write_tar(store.as_ref(), file, files, prefix, mtime),
+    }
+}
+
+/// Builds the whole archive in a lock file before any of it is let out: the
+/// output file only replaces what was there once the archive is complete, and
+/// stdout only gets a copy of a complete archive. A failure leaves nothing behind.
+fn write_archive(
+    format: GritArchiveFormat,
+    output: Option<&str>,
+    files: &[GritArchiveEntry],
+    prefix: &str,
+    mtime: i64,
+) -> Option<()> {
+    let mut lock = match output {
+        Some(output) => GritLockFile::acquire(output)?,
+        None => GritLockFile::acquire(&format!("{GRIT_DIRECTORY}/ARCHIVE"))?,
+    };
+
+    let file = lock.get_file()?;
+
+    let written = build_archive(format, file, files, prefix, mtime).and_then(|_| match output {
+        Some(_) => Ok(()),
+        None => {
+            file.seek(SeekFrom::Start(0))?;
+
+            let mut stdout = io::stdout().lock();
+            io::copy(file, &mut stdout)?;
+            stdout.flush()
+        }
+    });
+
+    if let Err(e) = written {
+        eprintln!("fatal: failed to write archive: {}", e);
+        return None;
+    }
+
+    match output {
+        Some(_) => lock.commit(),
+        // The archive went to stdout, dropping the lock removes the copy
+        None => Some(()),
+    }
+}
+
+/// Writes the files of a commit or tree straight from the object store, leaving
+/// out those with the `export-ignore` attribute. Commits give every file their
+/// commit time, trees the current time.
+pub fn archive(tree_ish: &str, paths: &[String], options: &GritArchiveOptions) -> Option<()> {
+    let oid = plumbing::peel_tag(&plumbing::rev_parse(tree_ish)?)?;
+
+    let (tree_oid, mtime) = match plumbing::generate_cat_content(&oid, GritCatType::Type)?.as_str()
+    {
+        "commit" => (
+            plumbing::get_commit_tree_oid(&oid)?,
+            utils::parse_time(&plumbing::get_commit_date(&oid)?)?,
+        ),
+        "tree" => (oid, utils::get_unix_time()),
+        object_type => {
+            eprintln!("fatal: not a tree object: {tree_ish} is a {object_type}");
+            return None;
+        }
+    };
+
+    let files = get_tree_files(&tree_oid)?;
+    let rules = get_tree_attributes(&files)?;
+
+    for pathspec in paths {
+        if !files
+            .iter()
+            .any(|file| is_in_paths(&file.path, std::slice::from_ref(pathspec)))
+        {
+            eprintln!("fatal: pathspec '{pathspec}' did not match any files");
+            return None;
+        }
+    }
+
+    let files = files
+        .into_iter()
+        .filter(|file| is_in_paths(&file.path, paths))
+        .filter(|file| !attributes::is_set(&file.path, "export-ignore", &rules))
+        .collect::<Vec<GritArchiveEntry>>();
+
+    let format = options
+        .format
+        .or(options
+            .output
+            .as_deref()
+            .and_then(GritArchiveFormat::from_filename))
+        .unwrap_or(GritArchiveFormat::Tar);
+
+    write_archive(
+        format,
+        options.output.as_deref(),
+        &files,
+        &options.prefix,
+        mtime,
+    )
+}
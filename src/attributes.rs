@@ -0,0 +1,119 @@
+use crate::config::GRIT_DIRECTORY;
+use crate::file_handling;
+use crate::utils;
+use std::path;
+
+/// The state an attribute is given for a path.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GritAttributeValue {
+    /// `name`
+    Set,
+    /// `-name`
+    Unset,
+    /// `name=value`
+    Value(String),
+    /// `!name`, which forgets what earlier lines said.
+    Unspecified,
+}
+
+/// A line of `.gritattributes`: a pattern followed by the attributes it gives matching paths.
+pub struct GritAttributeRule {
+    pattern: String,
+    anchored: bool,
+    attributes: Vec<(String, GritAttributeValue)>,
+}
+
+impl GritAttributeRule {
+    fn parse(line: &str) -> Option<GritAttributeRule> {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let mut fields = line.split_whitespace();
+        let pattern = fields.next()?.trim_end_matches('/');
+
+        let attributes = fields
+            .map(|field| {
+                if let Some(name) = field.strip_prefix('-') {
+                    (name.to_string(), GritAttributeValue::Unset)
+                } else if let Some(name) = field.strip_prefix('!') {
+                    (name.to_string(), GritAttributeValue::Unspecified)
+                } else if let Some((name, value)) = field.split_once('=') {
+                    (
+                        name.to_string(),
+                        GritAttributeValue::Value(value.to_string()),
+                    )
+                } else {
+                    (field.to_string(), GritAttributeValue::Set)
+                }
+            })
+            .collect();
+
+        Some(GritAttributeRule {
+            anchored: pattern.contains('/'),
+            pattern: pattern.trim_start_matches('/').to_string(),
+            attributes,
+        })
+    }
+
+    /// Like ignore patterns, a rule for a directory covers everything in it.
+    fn matches(&self, filepath: &str) -> bool {
+        let components = filepath.split('/').collect::<Vec<&str>>();
+
+        (1..=components.len()).any(|count| {
+            let candidate = components[..count].join("/");
+
+            if self.anchored {
+                utils::matches_glob(&self.pattern, &candidate)
+            } else {
+                utils::matches_glob(&self.pattern, components[count - 1])
+            }
+        })
+    }
+}
+
+pub fn parse_attributes(content: &str) -> Vec<GritAttributeRule> {
+    content
+        .lines()
+        .filter_map(GritAttributeRule::parse)
+        .collect()
+}
+
+/// Reads `.grit/info/attributes`, which applies on top of any `.gritattributes` file.
+pub fn get_info_attributes() -> Option<Vec<GritAttributeRule>> {
+    let attributes_path = format!("{GRIT_DIRECTORY}/info/attributes");
+
+    if !path::Path::new(&attributes_path).exists() {
+        return Some(vec![]);
+    }
+
+    Some(parse_attributes(&file_handling::read_file(
+        &attributes_path,
+    )?))
+}
+
+/// The value of one attribute for a path. The last rule that mentions it wins.
+pub fn get_attribute(
+    filepath: &str,
+    name: &str,
+    rules: &[GritAttributeRule],
+) -> GritAttributeValue {
+    rules
+        .iter()
+        .rev()
+        .filter(|rule| rule.matches(filepath))
+        .find_map(|rule| {
+            rule.attributes
+                .iter()
+                .rev()
+                .find(|(attribute, _value)| attribute == name)
+                .map(|(_attribute, value)| value.clone())
+        })
+        .unwrap_or(GritAttributeValue::Unspecified)
+}
+
+pub fn is_set(filepath: &str, name: &str, rules: &[GritAttributeRule]) -> bool {
+    get_attribute(filepath, name, rules) == GritAttributeValue::Set
+}
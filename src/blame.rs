@@ -0,0 +1,320 @@
+use crate::diff;
+use crate::plumbing::{self, GritCatType};
+use crate::utils;
+use std::collections::{HashMap, HashSet};
+
+/// The commit a line of the blamed file was last changed in, and its line number there.
+struct GritBlameLine {
+    commit_oid: String,
+    original_line: usize,
+}
+
+/// Lines of a commit's version of the file that are still waiting for an owner,
+/// as pairs of (line in that version, line in the blamed file).
+struct GritBlameTarget {
+    commit_oid: String,
+    blob_oid: String,
+    lines: Vec<(usize, usize)>,
+}
+
+fn get_blob_oid(commit_oid: &str, path: &str) -> Option<Option<String>> {
+    let (tree_map, _paths, _entries, _oids) = plumbing::get_head_tree_entries(commit_oid)?;
+
+    Some(tree_map.get(path).cloned())
+}
+
+/// For every line of `after`, the line of `before` it was carried over from unchanged.
+fn get_unchanged_lines(before: &str, after: &str) -> Vec<Option<usize>> {
+    let mut origins = vec![None; diff::split_lines(after).len()];
+    let mut before_line = 0;
+    let mut after_line = 0;
+
+    for hunk in diff::diff_lines(before, after) {
+        while after_line < hunk.after.start {
+            origins[after_line] = Some(before_line);
+            before_line += 1;
+            after_line += 1;
+        }
+
+        before_line = hunk.before.end;
+        after_line = hunk.after.end;
+    }
+
+    while after_line < origins.len() {
+        origins[after_line] = Some(before_line);
+        before_line += 1;
+        after_line += 1;
+    }
+
+    origins
+}
+
+/// Parses `-L start,end`, where `end` may also be `+count` or left out.
+fn parse_line_range(range: &str, line_count: usize) -> Option<(usize, usize)> {
+    let (start, end) = range.split_once(',').unwrap_or((range, ""));
+
+    let start = match start.parse::<usize>() {
+        Ok(start) if start >= 1 => start,
+        _ => {
+            println!("Invalid line range: {range}");
+            return None;
+        }
+    };
+
+    let end = if end.is_empty() {
+        line_count
+    } else if let Some(count) = end.strip_prefix('+') {
+        match count.parse::<usize>() {
+            Ok(count) if count >= 1 => start + count - 1,
+            _ => {
+                println!("Invalid line range: {range}");
+                return None;
+            }
+        }
+    } else {
+        match end.parse::<usize>() {
+            Ok(end) if end >= start => end,
+            _ => {
+                println!("Invalid line range: {range}");
+                return None;
+            }
+        }
+    };
+
+    if start > line_count || end > line_count {
+        println!("File has only {line_count} lines");
+        return None;
+    }
+
+    Some((start, end))
+}
+
+fn get_blame(
+    path: &str,
+    head_oid: &str,
+    blob_oid: &str,
+    line_count: usize,
+) -> Option<Vec<GritBlameLine>> {
+    let mut blame = (0..line_count).map(|_| None).collect::<Vec<_>>();
+    let mut contents = HashMap::new();
+    let mut targets = vec![GritBlameTarget {
+        commit_oid: head_oid.to_string(),
+        blob_oid: blob_oid.to_string(),
+        lines: (0..line_count).map(|line| (line, line)).collect(),
+    }];
+
+    while let Some(target) = targets.pop() {
+        let mut remaining = target.lines;
+
+        for parent_oid in plumbing::get_commit_parents(&target.commit_oid)? {
+            if remaining.is_empty() {
+                break;
+            }
+
+            let parent_blob_oid = match get_blob_oid(&parent_oid, path)? {
+                Some(parent_blob_oid) => parent_blob_oid,
+                None => continue,
+            };
+
+            // The file is untouched by this commit, so the parent owns every line
+            if parent_blob_oid == target.blob_oid {
+                targets.push(GritBlameTarget {
+                    commit_oid: parent_oid,
+                    blob_oid: parent_blob_oid,
+                    lines: remaining,
+                });
+                remaining = vec![];
+                break;
+            }
+
+            for blob_oid in [&target.blob_oid, &parent_blob_oid] {
+                if !contents.contains_key(blob_oid) {
+                    let content = plumbing::generate_cat_content(blob_oid, GritCatType::Pretty)?;
+                    contents.insert(blob_oid.to_string(), content);
+                }
+            }
+
+            let origins =
+                get_unchanged_lines(&contents[&parent_blob_oid], &contents[&target.blob_oid]);
+
+            let (passed, kept): (Vec<_>, Vec<_>) = remaining
+                .into_iter()
+                .partition(|(line, _final_line)| origins[*line].is_some());
+
+            if !passed.is_empty() {
+                targets.push(GritBlameTarget {
+                    commit_oid: parent_oid,
+                    blob_oid: parent_blob_oid,
+                    lines: passed
+                        .into_iter()
+                        .map(|(line, final_line)| (origins[line].unwrap_or(line), final_line))
+                        .collect(),
+                });
+            }
+
+            remaining = kept;
+        }
+
+        for (line, final_line) in remaining {
+            blame[final_line] = Some(GritBlameLine {
+                commit_oid: target.commit_oid.clone(),
+                original_line: line,
+            });
+        }
+    }
+
+    blame.into_iter().collect()
+}
+
+fn print_porcelain(
+    path: &str,
+    lines: &[&str],
+    blame: &[GritBlameLine],
+    start: usize,
+) -> Option<()> {
+    let mut described = HashSet::new();
+    let mut report = String::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let blame_line = &blame[i];
+        let final_line = start + i;
+
+        let continues_group = i > 0
+            && blame[i - 1].commit_oid == blame_line.commit_oid
+            && blame[i - 1].original_line + 1 == blame_line.original_line;
+
+        if continues_group {
+            report += format!(
+                "{} {} {}\n",
+                blame_line.commit_oid,
+                blame_line.original_line + 1,
+                final_line
+            )
+            .as_str();
+        } else {
+            let group_size = blame[i..]
+                .iter()
+                .enumerate()
+                .take_while(|(offset, next)| {
+                    next.commit_oid == blame_line.commit_oid
+                        && next.original_line == blame_line.original_line + offset
+                })
+                .count();
+
+            report += format!(
+                "{} {} {} {group_size}\n",
+                blame_line.commit_oid,
+                blame_line.original_line + 1,
+                final_line
+            )
+            .as_str();
+        }
+
+        if described.insert(blame_line.commit_oid.clone()) {
+            let author = plumbing::get_commit_author(&blame_line.commit_oid)?;
+            let time = utils::parse_time(&plumbing::get_commit_date(&blame_line.commit_oid)?)?;
+            let message = plumbing::get_commit_message(&blame_line.commit_oid)?;
+            let subject = message.lines().next().unwrap_or("");
+
+            for role in ["author", "committer"] {
+                report += format!(
+                    "{role} {author}\n{role}-mail <>\n{role}-time {time}\n{role}-tz +0000\n"
+                )
+                .as_str();
+            }
+
+            report += format!("summary {subject}\n").as_str();
+
+            if plumbing::get_commit_parents(&blame_line.commit_oid)?.is_empty() {
+                report += "boundary\n";
+            }
+        }
+
+        report += format!("filename {path}\n\t{}\n", line.trim_end_matches('\n')).as_str();
+    }
+
+    print!("{report}");
+
+    Some(())
+}
+
+pub fn blame(path: &str, line_range: Option<&str>, porcelain: bool) -> Option<()> {
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+
+    if head_oid.is_empty() {
+        println!("Cannot blame a branch without commits");
+        return None;
+    }
+
+    let blob_oid = match get_blob_oid(&head_oid, path)? {
+        Some(blob_oid) => blob_oid,
+        None => {
+            println!("no such path '{path}' in HEAD");
+            return None;
+        }
+    };
+
+    let content = plumbing::generate_cat_content(&blob_oid, GritCatType::Pretty)?;
+    let lines = diff::split_lines(&content);
+
+    let (start, end) = match line_range {
+        Some(line_range) => parse_line_range(line_range, lines.len())?,
+        None => (1, lines.len()),
+    };
+
+    let blame = get_blame(path, &head_oid, &blob_oid, lines.len())?;
+
+    if lines.is_empty() {
+        return Some(());
+    }
+
+    let lines = &lines[start - 1..end];
+    let blame = &blame[start - 1..end];
+
+    if porcelain {
+        return print_porcelain(path, lines, blame, start);
+    }
+
+    let mut authors = HashMap::new();
+    let mut dates = HashMap::new();
+
+    for blame_line in blame {
+        if !authors.contains_key(&blame_line.commit_oid) {
+            authors.insert(
+                blame_line.commit_oid.clone(),
+                plumbing::get_commit_author(&blame_line.commit_oid)?,
+            );
+            dates.insert(
+                blame_line.commit_oid.clone(),
+                plumbing::get_commit_date(&blame_line.commit_oid)?,
+            );
+        }
+    }
+
+    let author_width = authors
+        .values()
+        .map(|author| author.len())
+        .max()
+        .unwrap_or(0);
+    let number_width = end.to_string().len();
+
+    let report = lines
+        .iter()
+        .zip(blame)
+        .enumerate()
+        .map(|(i, (line, blame_line))| {
+            format!(
+                "{} ({:author_width$} {} {:>number_width$}) {}\n",
+                &blame_line.commit_oid[..8],
+                authors[&blame_line.commit_oid],
+                dates[&blame_line.commit_oid],
+                start + i,
+                line.trim_end_matches('\n')
+            )
+        })
+        .collect::<String>();
+
+    print!("{report}");
+
+    Some(())
+}
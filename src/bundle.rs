@@ -0,0 +1,340 @@
+use crate::file_handling;
+use crate::lockfile::GritLockFile;
+use crate::pack;
+use crate::plumbing::{self, GritCatType};
+use crate::refs;
+use crate::transport::{self, GritRefCommand, GritRemoteState, GritTransport};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+const SIGNATURE: &str = "# v2 git bundle\n";
+
+/// A ref list and a pack in one file, for moving history without a connection.
+pub struct GritBundle {
+    /// Commits the pack leaves out, which the receiving repository must already have.
+    pub prerequisites: Vec<(String, String)>,
+    pub refs: Vec<(String, String)>,
+    pub pack: Vec<u8>,
+}
+
+pub fn is_bundle(path: &str) -> bool {
+    let mut signature = [0; SIGNATURE.len()];
+
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok()
+        && signature == SIGNATURE.as_bytes()
+}
+
+pub fn read_bundle(path: &str) -> Option<GritBundle> {
+    let data = file_handling::read_file_as_bytes(path)?;
+
+    let rest = match data.strip_prefix(SIGNATURE.as_bytes()) {
+        Some(rest) => rest,
+        None => {
+            println!("error: '{path}' does not look like a v2 bundle file");
+            return None;
+        }
+    };
+
+    // The header ends at the first blank line, and the pack follows straight after
+    let header_end = if rest.first() == Some(&b'\n') {
+        0
+    } else {
+        match rest.windows(2).position(|window| window == b"\n\n") {
+            Some(position) => position + 1,
+            None => {
+                println!("error: '{path}' has a truncated bundle header");
+                return None;
+            }
+        }
+    };
+
+    let header = String::from_utf8_lossy(&rest[..header_end]);
+    let mut prerequisites = vec![];
+    let mut bundle_refs = vec![];
+
+    for line in header.lines() {
+        match line.strip_prefix('-') {
+            Some(prerequisite) => {
+                let (oid, comment) = prerequisite.split_once(' ').unwrap_or((prerequisite, ""));
+                prerequisites.push((oid.to_string(), comment.to_string()));
+            }
+            None => match line.split_once(' ') {
+                Some((oid, ref_name)) => bundle_refs.push((ref_name.to_string(), oid.to_string())),
+                None => {
+                    println!("error: unrecognized bundle header line '{line}'");
+                    return None;
+                }
+            },
+        }
+    }
+
+    Some(GritBundle {
+        prerequisites,
+        refs: bundle_refs,
+        pack: rest[header_end + 1..].to_vec(),
+    })
+}
+
+fn check_prerequisites(bundle: &GritBundle) -> Option<()> {
+    let missing = bundle
+        .prerequisites
+        .iter()
+        .filter(|(oid, _comment)| !file_handling::object_exists(oid))
+        .collect::<Vec<&(String, String)>>();
+
+    if missing.is_empty() {
+        return Some(());
+    }
+
+    println!("error: Repository lacks these prerequisite commits:");
+    for (oid, comment) in missing {
+        println!("error: {oid} {comment}");
+    }
+
+    None
+}
+
+/// The full ref a revision names, if it names one rather than an ancestor or an object id.
+fn get_bundle_ref_name(rev: &str) -> Option<String> {
+    if rev.contains(['~', '^', ':', '@']) && rev != "@" {
+        return None;
+    }
+
+    let ref_name = plumbing::resolve_ref_name(rev)?;
+
+    // Revisions that happen to match a ref by abbreviated id are not ref names
+    match plumbing::get_ref_oid(&ref_name) {
+        Some(oid) if !oid.is_empty() => Some(ref_name),
+        _ => None,
+    }
+}
+
+/// Writes the history selected by `revs` into a bundle file. Revisions work as
+/// in `rev-list`: `A..B` and `^A B` leave out what `A` already has, and `--all`
+/// takes every ref.
+pub fn create(path: &str, revs: &[String]) -> Option<()> {
+    let mut includes = vec![];
+    let mut excludes = vec![];
+    let mut bundle_refs: Vec<(String, String)> = vec![];
+
+    let mut include = |rev: &str, bundle_refs: &mut Vec<(String, String)>| -> Option<()> {
+        let oid = plumbing::rev_parse(rev)?;
+
+        if let Some(ref_name) = get_bundle_ref_name(rev) {
+            if !bundle_refs.iter().any(|(name, _oid)| name == &ref_name) {
+                bundle_refs.push((ref_name, oid.clone()));
+            }
+        }
+
+        includes.push(oid);
+        Some(())
+    };
+
+    for rev in revs {
+        if rev == "--all" {
+            include("HEAD", &mut bundle_refs)?;
+
+            for ref_name in refs::get_all_refs()? {
+                include(&ref_name, &mut bundle_refs)?;
+            }
+        } else if let Some((exclude, rev)) = rev.split_once("..") {
+            excludes.push(plumbing::rev_parse(exclude)?);
+            include(rev, &mut bundle_refs)?;
+        } else if let Some(exclude) = rev.strip_prefix('^') {
+            excludes.push(plumbing::rev_parse(exclude)?);
+        } else {
+            include(rev, &mut bundle_refs)?;
+        }
+    }
+
+    if bundle_refs.is_empty() {
+        println!("fatal: Refusing to create empty bundle.");
+        return None;
+    }
+
+    let objects = transport::get_objects_to_send(&includes, &excludes)?;
+
+    // The prerequisites are the commits left out whose children go in the bundle
+    let mut prerequisites = vec![];
+
+    for oid in &objects {
+        if plumbing::generate_cat_content(oid, GritCatType::Type)? != "commit" {
+            continue;
+        }
+
+        for parent in plumbing::get_commit_parents(oid)? {
+            if objects.binary_search(&parent).is_err() && !prerequisites.contains(&parent) {
+                prerequisites.push(parent);
+            }
+        }
+    }
+
+    prerequisites.sort();
+
+    let mut header = SIGNATURE.to_string();
+
+    for oid in &prerequisites {
+        let message = plumbing::get_commit_message(oid)?;
+        let subject = message.lines().next().unwrap_or("");
+        header += format!("-{oid} {subject}\n").as_str();
+    }
+
+    for (ref_name, oid) in &bundle_refs {
+        header += format!("{oid} {ref_name}\n").as_str();
+    }
+
+    header += "\n";
+
+    let mut bundle = header.into_bytes();
+    bundle.extend(pack::create_pack(&objects)?);
+
+    let mut lock_file = GritLockFile::acquire(path)?;
+    lock_file.write(&bundle)?;
+    lock_file.commit()
+}
+
+fn print_refs(description: &str, entries: &[(String, String)]) {
+    match entries.len() {
+        1 => println!("The bundle {description} this ref:"),
+        count => println!("The bundle {description} these {count} refs:"),
+    }
+
+    for (name, oid) in entries {
+        println!("{oid} {name}");
+    }
+}
+
+/// Checks that a bundle is intact and that the current repository has what it builds on.
+pub fn verify(path: &str) -> Option<()> {
+    let bundle = read_bundle(path)?;
+
+    pack::verify_pack(&bundle.pack)?;
+    check_prerequisites(&bundle)?;
+
+    print_refs("contains", &bundle.refs);
+
+    if bundle.prerequisites.is_empty() {
+        println!("The bundle records a complete history.");
+    } else {
+        let prerequisites = bundle
+            .prerequisites
+            .iter()
+            .map(|(oid, comment)| (comment.to_string(), oid.to_string()))
+            .collect::<Vec<(String, String)>>();
+
+        print_refs("requires", &prerequisites);
+    }
+
+    println!("{path} is okay");
+
+    Some(())
+}
+
+/// Lists the refs in a bundle, or only those matching `ref_names` if any are given.
+pub fn list_heads(path: &str, ref_names: &[String]) -> Option<()> {
+    let bundle = read_bundle(path)?;
+
+    for (ref_name, oid) in &bundle.refs {
+        let listed = ref_names.is_empty()
+            || ref_names.iter().any(|name| {
+                ref_name == name
+                    || ref_name.ends_with(&format!("/{}", name.trim_start_matches('/')))
+            });
+
+        if listed {
+            println!("{oid} {ref_name}");
+        }
+    }
+
+    Some(())
+}
+
+/// A bundle file read as though it were a repository, so it can be cloned or fetched from.
+pub struct GritBundleTransport {
+    path: String,
+}
+
+impl GritBundleTransport {
+    pub fn new(path: &str) -> GritBundleTransport {
+        GritBundleTransport {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl GritTransport for GritBundleTransport {
+    fn get_remote_state(&mut self) -> Option<GritRemoteState> {
+        let bundle = read_bundle(&self.path)?;
+
+        let branch_refs = bundle
+            .refs
+            .iter()
+            .filter(|(ref_name, _oid)| ref_name.starts_with("refs/heads/"))
+            .collect::<Vec<&(String, String)>>();
+
+        // A bundle only records where HEAD pointed, so take the first branch there
+        let head = match bundle
+            .refs
+            .iter()
+            .find(|(ref_name, _oid)| ref_name == "HEAD")
+        {
+            Some((_head, head_oid)) => branch_refs
+                .iter()
+                .find(|(_ref_name, oid)| oid == head_oid)
+                .map(|(ref_name, oid)| (ref_name.to_string(), oid.to_string()))
+                .unwrap_or(("HEAD".to_string(), head_oid.to_string())),
+            None => branch_refs
+                .iter()
+                .find(|(ref_name, _oid)| ref_name == "refs/heads/main")
+                .or(branch_refs.first())
+                .map(|(ref_name, oid)| (ref_name.to_string(), oid.to_string()))
+                .unwrap_or(("refs/heads/main".to_string(), "".to_string())),
+        };
+
+        Some(GritRemoteState {
+            head_ref: head.0,
+            head_oid: head.1,
+            refs: bundle
+                .refs
+                .into_iter()
+                .filter(|(ref_name, _oid)| {
+                    ref_name.starts_with("refs/heads/") || ref_name.starts_with("refs/tags/")
+                })
+                .collect(),
+        })
+    }
+
+    fn fetch_objects(&mut self, _wants: &[String], _haves: &[String]) -> Option<()> {
+        let bundle = read_bundle(&self.path)?;
+
+        check_prerequisites(&bundle)?;
+
+        pack::unpack(&bundle.pack).map(|_| ())
+    }
+
+    fn push_updates(
+        &mut self,
+        _commands: &[GritRefCommand],
+        _objects: &[String],
+    ) -> Option<Vec<Option<String>>> {
+        println!(
+            "fatal: cannot push to a bundle; use 'grit bundle create {}' instead",
+            self.path
+        );
+        None
+    }
+}
+
+/// The absolute path of a bundle, so a clone of it keeps working from anywhere.
+pub fn get_bundle_url(path: &str) -> Option<String> {
+    match Path::new(path).canonicalize() {
+        Ok(path) => Some(path.display().to_string()),
+        Err(e) => {
+            println!("Failed to resolve {}: {}", path, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::bundle;
+use crate::config;
+use crate::hooks;
+use crate::plumbing;
+use crate::refs::{self, GritRefTransaction};
+use crate::transport::{self, GritRemoteState, GritTransport};
+use std::fs;
+use std::path::Path;
+
+fn get_default_directory(repository_path: &str) -> String {
+    let repository_path = repository_path.trim_end_matches('/');
+
+    let name = Path::new(repository_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or(repository_path.to_string());
+
+    name.strip_suffix(".git").unwrap_or(&name).to_string()
+}
+
+pub fn clone(repository_path: &str, directory: Option<&str>, local: bool) -> Option<()> {
+    // Local paths are stored as absolute paths, so the clone can be used from anywhere
+    let url = if transport::is_remote_url(repository_path) {
+        repository_path.trim_end_matches('/').to_string()
+    } else if bundle::is_bundle(repository_path) {
+        bundle::get_bundle_url(repository_path)?
+    } else {
+        transport::find_repository(repository_path)?
+            .display()
+            .to_string()
+    };
+
+    let directory = directory
+        .map(|directory| directory.to_string())
+        .unwrap_or(get_default_directory(repository_path));
+
+    let target_path = Path::new(&directory);
+    let target_is_empty = match fs::read_dir(target_path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => true,
+    };
+
+    if !target_is_empty {
+        println!(
+            "fatal: destination path '{directory}' already exists and is not an empty directory."
+        );
+        return None;
+    }
+
+    let mut remote = transport::open_transport(&url, local)?;
+
+    println!("Cloning into '{directory}'...");
+
+    let remote_state = remote.get_remote_state()?;
+
+    match fs::create_dir_all(target_path) {
+        Ok(_) => (),
+        Err(e) => {
+            println!("Failed to create directory {}: {}", directory, e);
+            return None;
+        }
+    }
+
+    let target_dir = match fs::canonicalize(target_path) {
+        Ok(target_dir) => target_dir,
+        Err(e) => {
+            println!("Failed to resolve {}: {}", directory, e);
+            return None;
+        }
+    };
+
+    transport::with_repository(&target_dir, || {
+        set_up_clone(&url, remote.as_mut(), &remote_state)
+    })
+}
+
+fn set_up_clone(
+    url: &str,
+    remote: &mut dyn GritTransport,
+    remote_state: &GritRemoteState,
+) -> Option<()> {
+    crate::init()?;
+
+    let mut wants = remote_state
+        .refs
+        .iter()
+        .map(|(_ref_name, oid)| oid.to_string())
+        .collect::<Vec<String>>();
+    if !remote_state.head_oid.is_empty() {
+        wants.push(remote_state.head_oid.clone());
+    }
+    wants.sort();
+    wants.dedup();
+
+    if !wants.is_empty() {
+        remote.fetch_objects(&wants, &[])?;
+    }
+
+    let reason = format!("clone: from {url}");
+
+    config::set_config_value("remote.origin.url", url)?;
+    config::set_config_value("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+    let mut transaction = GritRefTransaction::new();
+
+    for (ref_name, oid) in &remote_state.refs {
+        match ref_name.strip_prefix("refs/heads/") {
+            Some(branch) => {
+                transaction.update(&format!("refs/remotes/origin/{branch}"), oid, None, &reason)
+            }
+            None => transaction.update(ref_name, oid, None, &reason),
+        }
+    }
+
+    transaction.commit()?;
+
+    if remote_state.head_ref == "HEAD" {
+        // The remote HEAD is detached, so the clone starts out detached too
+        refs::remove_ref_value("refs/heads/main")?;
+        plumbing::update_ref("HEAD", &remote_state.head_oid, &reason)?;
+    } else {
+        let branch = remote_state.head_ref.replace("refs/heads/", "");
+
+        if branch != "main" {
+            refs::remove_ref_value("refs/heads/main")?;
+            refs::write_ref_value("HEAD", &format!("ref: {}", remote_state.head_ref))?;
+        }
+
+        if remote_state.head_oid.is_empty() {
+            println!("warning: You appear to have cloned an empty repository.");
+            return Some(());
+        }
+
+        refs::write_ref_value(
+            "refs/remotes/origin/HEAD",
+            &format!("ref: refs/remotes/origin/{branch}"),
+        )?;
+
+        plumbing::update_head(&remote_state.head_oid, &reason)?;
+
+        config::set_config_value(&format!("branch.{branch}.remote"), "origin")?;
+        config::set_config_value(&format!("branch.{branch}.merge"), &remote_state.head_ref)?;
+    }
+
+    plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&remote_state.head_oid)?)?;
+
+    // A new clone has no previous HEAD, and `1` marks a checkout of a branch
+    let _ = hooks::run_hook(
+        "post-checkout",
+        &[plumbing::NULL_OID, &remote_state.head_oid, "1"],
+        None,
+    );
+
+    Some(())
+}
@@ -1,2 +1,234 @@
+use crate::file_handling;
+use crate::lockfile::GritLockFile;
+use std::path::Path;
+
 pub const GRIT_DIRECTORY: &str = ".grit";
 pub const WORKING_DIR: &str = "source";
+
+/// Looks up `key` in `.grit/config`. Keys are written as `section.name` or
+/// `section.subsection.name`, e.g. `gc.pruneExpire` or `remote.origin.url`.
+pub fn get_config_value(key: &str) -> Option<String> {
+    get_repository_config_value(Path::new(GRIT_DIRECTORY), key)
+}
+
+/// Looks up `key` in the config of the repository whose grit directory is `grit_dir`.
+pub fn get_repository_config_value(grit_dir: &Path, key: &str) -> Option<String> {
+    let config_path = grit_dir.join("config");
+
+    if !config_path.exists() {
+        return None;
+    }
+
+    let content = file_handling::read_file(&config_path.display().to_string())?;
+
+    let (section, name) = key.rsplit_once('.')?;
+    let mut current_section = String::new();
+    let mut value = None;
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            current_section = parse_section_header(&line[1..line.len() - 1]);
+            continue;
+        }
+
+        let (entry_name, entry_value) = match line.split_once('=') {
+            Some((entry_name, entry_value)) => (entry_name.trim(), entry_value.trim()),
+            None => (line, "true"),
+        };
+
+        if section_matches(&current_section, section) && entry_name.eq_ignore_ascii_case(name) {
+            value = Some(entry_value.trim_matches('"').to_string());
+        }
+    }
+
+    value
+}
+
+fn format_section_header(section: &str) -> String {
+    match section.split_once('.') {
+        Some((section, subsection)) => format!("[{section} \"{subsection}\"]"),
+        None => format!("[{section}]"),
+    }
+}
+
+/// Sets `key` in `.grit/config`, replacing an existing value or adding the
+/// entry, and its section if needed.
+pub fn set_config_value(key: &str, value: &str) -> Option<()> {
+    let config_path = format!("{GRIT_DIRECTORY}/config");
+    let mut lock = GritLockFile::acquire(&config_path)?;
+
+    let content = if std::path::Path::new(&config_path).exists() {
+        file_handling::read_file(&config_path)?
+    } else {
+        "".to_string()
+    };
+
+    let (section, name) = match key.rsplit_once('.') {
+        Some(parts) => parts,
+        None => {
+            println!("Invalid config key {key}: expected section.name");
+            return None;
+        }
+    };
+
+    let needs_quotes = value.contains(['#', ';']) || value.trim() != value;
+    let entry = if needs_quotes {
+        format!("\t{name} = \"{value}\"")
+    } else {
+        format!("\t{name} = {value}")
+    };
+
+    let mut lines = content
+        .lines()
+        .map(|line| line.to_string())
+        .collect::<Vec<String>>();
+    let mut current_section = String::new();
+    let mut existing_entry = None;
+    let mut section_end = None;
+
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+
+        if line.starts_with('[') && line.ends_with(']') {
+            current_section = parse_section_header(&line[1..line.len() - 1]);
+        }
+
+        if !section_matches(&current_section, section) {
+            continue;
+        }
+
+        section_end = Some(i + 1);
+
+        let entry_name = line
+            .split_once('=')
+            .map_or(line, |(entry_name, _value)| entry_name);
+        if entry_name.trim().eq_ignore_ascii_case(name) {
+            existing_entry = Some(i);
+        }
+    }
+
+    match (existing_entry, section_end) {
+        (Some(i), _) => lines[i] = entry,
+        (None, Some(i)) => lines.insert(i, entry),
+        (None, None) => {
+            lines.push(format_section_header(section));
+            lines.push(entry);
+        }
+    }
+
+    lock.write(format!("{}\n", lines.join("\n")).as_bytes())?;
+    lock.commit()
+}
+
+/// Removes `key` from `.grit/config`, leaving its section in place.
+pub fn unset_config_value(key: &str) -> Option<()> {
+    let (section, name) = key.rsplit_once('.')?;
+
+    rewrite_config(|current_section, line| {
+        let entry_name = line
+            .split_once('=')
+            .map_or(line, |(entry_name, _value)| entry_name);
+
+        !(section_matches(current_section, section)
+            && !line.starts_with('[')
+            && entry_name.trim().eq_ignore_ascii_case(name))
+    })
+}
+
+/// Removes a whole section, e.g. `remote.origin`, with every entry in it.
+pub fn remove_config_section(section: &str) -> Option<()> {
+    rewrite_config(|current_section, _line| !section_matches(current_section, section))
+}
+
+/// Rewrites `.grit/config` under its lock, keeping the lines for which
+/// `keep` returns true. `keep` is given the section each line belongs to.
+fn rewrite_config(keep: impl Fn(&str, &str) -> bool) -> Option<()> {
+    let config_path = format!("{GRIT_DIRECTORY}/config");
+
+    if !std::path::Path::new(&config_path).exists() {
+        return Some(());
+    }
+
+    let mut lock = GritLockFile::acquire(&config_path)?;
+    let content = file_handling::read_file(&config_path)?;
+
+    let mut current_section = String::new();
+    let mut kept = String::new();
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            current_section = parse_section_header(&trimmed[1..trimmed.len() - 1]);
+        }
+
+        if keep(&current_section, trimmed) {
+            kept += format!("{line}\n").as_str();
+        }
+    }
+
+    lock.write(kept.as_bytes())?;
+    lock.commit()
+}
+
+/// Lists the subsections of `section` in the order they appear, e.g. the
+/// names of every `[remote "..."]`.
+pub fn get_subsections(section: &str) -> Option<Vec<String>> {
+    let config_path = format!("{GRIT_DIRECTORY}/config");
+
+    if !std::path::Path::new(&config_path).exists() {
+        return Some(vec![]);
+    }
+
+    let content = file_handling::read_file(&config_path)?;
+    let mut subsections = vec![];
+
+    for line in content.lines() {
+        let line = line.trim();
+
+        if !(line.starts_with('[') && line.ends_with(']')) {
+            continue;
+        }
+
+        let header = parse_section_header(&line[1..line.len() - 1]);
+
+        if let Some((current, subsection)) = header.split_once('.') {
+            if current.eq_ignore_ascii_case(section) && !subsections.iter().any(|s| s == subsection)
+            {
+                subsections.push(subsection.to_string());
+            }
+        }
+    }
+
+    Some(subsections)
+}
+
+fn parse_section_header(header: &str) -> String {
+    match header.split_once(' ') {
+        Some((section, subsection)) => {
+            format!(
+                "{}.{}",
+                section.to_lowercase(),
+                subsection.trim().trim_matches('"')
+            )
+        }
+        None => header.to_lowercase(),
+    }
+}
+
+fn section_matches(current_section: &str, section: &str) -> bool {
+    // Section names are case-insensitive, subsection names are not
+    match (current_section.split_once('.'), section.split_once('.')) {
+        (Some((current, current_sub)), Some((wanted, wanted_sub))) => {
+            current.eq_ignore_ascii_case(wanted) && current_sub == wanted_sub
+        }
+        (None, None) => current_section.eq_ignore_ascii_case(section),
+        _ => false,
+    }
+}
@@ -0,0 +1,351 @@
+use crate::object::GritTreeEntry;
+use crate::plumbing;
+use imara_diff::intern::InternedInput;
+use imara_diff::{diff, sources, Algorithm};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+
+/// A run of lines in `before` that was replaced by a run of lines in `after`.
+/// Either range may be empty for pure insertions and deletions.
+pub struct GritHunk {
+    pub before: Range<usize>,
+    pub after: Range<usize>,
+}
+
+/// A path that differs between two trees, with its `mode type oid path` entry on each side.
+pub struct GritFileChange {
+    pub path: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+/// How far into a file to look for a NUL byte when deciding whether it is binary.
+const BINARY_CHECK_LENGTH: usize = 8000;
+
+/// The content of one side of a change, as lines can only be compared in text.
+pub enum GritBlobContent {
+    Text(String),
+    /// Content that is not text, with its size in bytes.
+    Binary(usize),
+}
+
+impl GritBlobContent {
+    /// Content with a NUL byte near the start, or that is not valid UTF-8, is binary.
+    pub fn from_bytes(content: Vec<u8>) -> GritBlobContent {
+        let size = content.len();
+
+        if content[..size.min(BINARY_CHECK_LENGTH)].contains(&0) {
+            return GritBlobContent::Binary(size);
+        }
+
+        match String::from_utf8(content) {
+            Ok(text) => GritBlobContent::Text(text),
+            Err(_) => GritBlobContent::Binary(size),
+        }
+    }
+
+    fn get_size(&self) -> usize {
+        match self {
+            GritBlobContent::Text(text) => text.len(),
+            GritBlobContent::Binary(size) => *size,
+        }
+    }
+}
+
+/// Splits content into lines, keeping the line terminators.
+pub fn split_lines(content: &str) -> Vec<&str> {
+    content.split_inclusive('\n').collect()
+}
+
+pub fn diff_lines(before: &str, after: &str) -> Vec<GritHunk> {
+    let input = InternedInput::new(
+        sources::lines_with_terminator(before),
+        sources::lines_with_terminator(after),
+    );
+
+    let mut hunks = vec![];
+
+    diff(
+        Algorithm::Histogram,
+        &input,
+        |before: Range<u32>, after: Range<u32>| {
+            hunks.push(GritHunk {
+                before: before.start as usize..before.end as usize,
+                after: after.start as usize..after.end as usize,
+            })
+        },
+    );
+
+    hunks
+}
+
+fn format_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{count}", start + 1),
+    }
+}
+
+fn push_diff_line(report: &mut String, prefix: char, line: &str) {
+    report.push(prefix);
+    report.push_str(line);
+
+    if !line.ends_with('\n') {
+        report.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Renders the `@@` hunks of a unified diff, with `context` unchanged lines around each change.
+pub fn unified_diff(before: &str, after: &str, context: usize) -> String {
+    let before_lines = split_lines(before);
+    let after_lines = split_lines(after);
+    let hunks = diff_lines(before, after);
+
+    let mut report = String::new();
+
+    let mut i = 0;
+    while i < hunks.len() {
+        // Changes whose context would overlap are shown as one hunk
+        let mut j = i + 1;
+        while j < hunks.len() && hunks[j].before.start - hunks[j - 1].before.end <= 2 * context {
+            j += 1;
+        }
+
+        let first = &hunks[i];
+        let last = &hunks[j - 1];
+
+        let before_start = first.before.start.saturating_sub(context);
+        let before_end = (last.before.end + context).min(before_lines.len());
+        let after_start = first.after.start - (first.before.start - before_start);
+        let after_end = last.after.end + (before_end - last.before.end);
+
+        report += format!(
+            "@@ -{} +{} @@\n",
+            format_range(before_start, before_end - before_start),
+            format_range(after_start, after_end - after_start)
+        )
+        .as_str();
+
+        let mut position = before_start;
+
+        for hunk in &hunks[i..j] {
+            for line in &before_lines[position..hunk.before.start] {
+                push_diff_line(&mut report, ' ', line);
+            }
+            for line in &before_lines[hunk.before.clone()] {
+                push_diff_line(&mut report, '-', line);
+            }
+            for line in &after_lines[hunk.after.clone()] {
+                push_diff_line(&mut report, '+', line);
+            }
+
+            position = hunk.before.end;
+        }
+
+        for line in &before_lines[position..before_end] {
+            push_diff_line(&mut report, ' ', line);
+        }
+
+        i = j;
+    }
+
+    report
+}
+
+/// The number of lines added and removed between two versions of a file.
+pub fn count_changes(before: &str, after: &str) -> (usize, usize) {
+    diff_lines(before, after)
+        .iter()
+        .fold((0, 0), |(insertions, deletions), hunk| {
+            (insertions + hunk.after.len(), deletions + hunk.before.len())
+        })
+}
+
+/// Parses the entry on one side of a change, `None` if the file is missing there.
+fn parse_change_entry(entry: Option<&String>) -> Option<Option<GritTreeEntry>> {
+    match entry {
+        Some(entry) => plumbing::parse_entry(entry).map(Some),
+        None => Some(None),
+    }
+}
+
+fn get_entry_content(entry: Option<&String>) -> Option<GritBlobContent> {
+    match parse_change_entry(entry)? {
+        Some(entry) => {
+            let (_object_type, content) = plumbing::read_object(&entry.oid)?;
+            Some(GritBlobContent::from_bytes(content))
+        }
+        None => Some(GritBlobContent::Text("".to_string())),
+    }
+}
+
+/// Compares two sets of tree entries keyed by path, in path order.
+pub fn get_changed_paths(
+    before: &HashMap<String, String>,
+    after: &HashMap<String, String>,
+) -> Vec<GritFileChange> {
+    before
+        .keys()
+        .chain(after.keys())
+        .collect::<BTreeSet<&String>>()
+        .into_iter()
+        .filter(|path| before.get(*path) != after.get(*path))
+        .map(|path| GritFileChange {
+            path: path.to_string(),
+            before: before.get(path).cloned(),
+            after: after.get(path).cloned(),
+        })
+        .collect()
+}
+
+/// Renders changes as a `diff --grit` patch.
+pub fn format_patch(changes: &[GritFileChange]) -> Option<String> {
+    let mut report = String::new();
+
+    for change in changes {
+        let path = &change.path;
+        let before = parse_change_entry(change.before.as_ref())?;
+        let after = parse_change_entry(change.after.as_ref())?;
+
+        let before_mode = before.as_ref().map(|entry| entry.mode.value());
+        let after_mode = after.as_ref().map(|entry| entry.mode.value());
+        let before_oid = before
+            .as_ref()
+            .map_or(plumbing::NULL_OID, |entry| entry.oid.as_str());
+        let after_oid = after
+            .as_ref()
+            .map_or(plumbing::NULL_OID, |entry| entry.oid.as_str());
+
+        report += format!("diff --grit a/{path} b/{path}\n").as_str();
+
+        match (&before_mode, &after_mode) {
+            (None, Some(mode)) => report += format!("new file mode {mode}\n").as_str(),
+            (Some(mode), None) => report += format!("deleted file mode {mode}\n").as_str(),
+            (Some(old_mode), Some(new_mode)) if old_mode != new_mode => {
+                report += format!("old mode {old_mode}\nnew mode {new_mode}\n").as_str()
+            }
+            _ => (),
+        }
+
+        if before_oid == after_oid {
+            continue;
+        }
+
+        report += format!("index {}..{}", &before_oid[..7], &after_oid[..7]).as_str();
+        if before_mode == after_mode {
+            report += format!(" {}", before_mode.unwrap_or_default()).as_str();
+        }
+        report += "\n";
+
+        let before_content = get_entry_content(change.before.as_ref())?;
+        let after_content = get_entry_content(change.after.as_ref())?;
+
+        let before_label = match change.before {
+            Some(_) => format!("a/{path}"),
+            None => "/dev/null".to_string(),
+        };
+        let after_label = match change.after {
+            Some(_) => format!("b/{path}"),
+            None => "/dev/null".to_string(),
+        };
+
+        match (before_content, after_content) {
+            (GritBlobContent::Text(before_content), GritBlobContent::Text(after_content)) => {
+                report += format!("--- {before_label}\n+++ {after_label}\n").as_str();
+                report += unified_diff(&before_content, &after_content, 3).as_str();
+            }
+            _ => {
+                report += format!("Binary files {before_label} and {after_label} differ\n").as_str()
+            }
+        }
+    }
+
+    Some(report)
+}
+
+/// Renders changes as a `--stat` summary with a histogram of added and removed
+/// lines. Binary files have no lines, so their sizes are shown instead.
+pub fn format_stat(changes: &[GritFileChange]) -> Option<String> {
+    let mut counts = vec![];
+    let mut binary_sizes = HashMap::new();
+
+    for change in changes {
+        let before_content = get_entry_content(change.before.as_ref())?;
+        let after_content = get_entry_content(change.after.as_ref())?;
+
+        match (&before_content, &after_content) {
+            (GritBlobContent::Text(before_text), GritBlobContent::Text(after_text)) => {
+                counts.push(count_changes(before_text, after_text))
+            }
+            _ => {
+                binary_sizes.insert(
+                    change.path.as_str(),
+                    (before_content.get_size(), after_content.get_size()),
+                );
+                counts.push((0, 0));
+            }
+        }
+    }
+
+    let path_width = changes
+        .iter()
+        .map(|change| change.path.len())
+        .max()
+        .unwrap_or(0);
+    let largest = counts
+        .iter()
+        .map(|(insertions, deletions)| insertions + deletions)
+        .max()
+        .unwrap_or(0);
+    let count_width = largest.to_string().len();
+    let bar_width = 50;
+
+    let mut report = String::new();
+
+    for (change, (insertions, deletions)) in changes.iter().zip(&counts) {
+        if let Some((before_size, after_size)) = binary_sizes.get(change.path.as_str()) {
+            report += format!(
+                " {:path_width$} | Bin {before_size} -> {after_size} bytes\n",
+                change.path
+            )
+            .as_str();
+            continue;
+        }
+
+        let (plus_count, minus_count) = if largest > bar_width {
+            (
+                (insertions * bar_width).div_ceil(largest),
+                (deletions * bar_width).div_ceil(largest),
+            )
+        } else {
+            (*insertions, *deletions)
+        };
+
+        report += format!(
+            " {:path_width$} | {:>count_width$} {}{}\n",
+            change.path,
+            insertions + deletions,
+            "+".repeat(plus_count),
+            "-".repeat(minus_count)
+        )
+        .as_str();
+    }
+
+    let insertions = counts
+        .iter()
+        .map(|(insertions, _)| insertions)
+        .sum::<usize>();
+    let deletions = counts.iter().map(|(_, deletions)| deletions).sum::<usize>();
+
+    report += format!(
+        " {} file{} changed, {insertions} insertion{}(+), {deletions} deletion{}(-)\n",
+        changes.len(),
+        if changes.len() == 1 { "" } else { "s" },
+        if insertions == 1 { "" } else { "s" },
+        if deletions == 1 { "" } else { "s" }
+    )
+    .as_str();
+
+    Some(report)
+}
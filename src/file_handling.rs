@@ -1,12 +1,14 @@
 use crate::config::{GRIT_DIRECTORY, WORKING_DIR};
-use crate::utils;
+use crate::object_store;
 use sha1::{Digest, Sha1};
 use std::fs::{self, File};
-use std::io::prelude::*;
-use std::io::{BufReader, Read};
+use std::io::{self, BufReader, Read};
+
+/// How much of a file is held in memory at once while it is hashed or compressed.
+pub const CHUNK_SIZE: usize = 64 * 1024;
 
 pub fn read_file_as_bytes(filepath: &str) -> Option<Vec<u8>> {
-    let file = match File::open(&filepath) {
+    let file = match File::open(filepath) {
         Ok(f) => f,
         Err(e) => {
             println!("Failed to open file {}: {}", filepath, e);
@@ -31,7 +33,7 @@ pub fn read_file_as_bytes(filepath: &str) -> Option<Vec<u8>> {
 }
 
 pub fn read_file(filename: &str) -> Option<String> {
-    match std::fs::read_to_string(&filename) {
+    match std::fs::read_to_string(filename) {
         Ok(contents) => Some(contents),
         Err(e) => {
             println!("Failed to read file {}: {}", filename, e);
@@ -59,7 +61,32 @@ pub fn read_source_file(filename: &str) -> Option<String> {
         Ok(contents) => Some(contents),
         Err(e) => {
             println!("Failed to read file {}: {}", filename, e);
-            return None;
+            None
+        }
+    }
+}
+
+pub fn write_file(filepath: &str, content: &str) -> Option<()> {
+    match fs::write(filepath, content) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to write file {}: {}", filepath, e);
+            None
+        }
+    }
+}
+
+/// Removes a file, doing nothing if it does not exist.
+pub fn remove_file(filepath: &str) -> Option<()> {
+    if !std::path::Path::new(filepath).exists() {
+        return Some(());
+    }
+
+    match fs::remove_file(filepath) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to remove file {}: {}", filepath, e);
+            None
         }
     }
 }
@@ -67,84 +94,67 @@ pub fn read_source_file(filename: &str) -> Option<String> {
 pub fn hash_file(input_string: &str) -> String {
     let mut hasher = Sha1::new();
 
-    hasher.update(&input_string);
+    hasher.update(input_string);
 
     let result = hasher.finalize();
 
-    let hex_string = hex::encode(&result);
-    hex_string
+    hex::encode(result)
 }
 
-pub fn get_object_path_from_oid(oid: &str, create_dir: bool) -> String {
-    let sub_directory = &oid[..2].to_string();
-    let filename = &oid[2..];
-    let sub_directory_path = format!("{GRIT_DIRECTORY}/objects/{sub_directory}");
-
-    if create_dir {
-        match fs::create_dir_all(&sub_directory_path) {
-            Ok(_) => (),
-            Err(e) => {
-                println!("{}", e);
-                return "".to_string();
-            }
-        };
-    }
+/// Hashes the object `type size\0content`, reading its `size` bytes of content
+/// from `reader` a chunk at a time and handing the header and every chunk to
+/// `output` as well. Fails if the reader gives more or fewer bytes than
+/// `size`, as when a file changes while it is read.
+pub fn hash_stream(
+    object_type: &str,
+    size: u64,
+    reader: &mut dyn Read,
+    output: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<String> {
+    let mut hasher = Sha1::new();
 
-    format!("{sub_directory_path}/{filename}")
-}
+    let header = format!("{object_type} {size}\0");
+    hasher.update(header.as_bytes());
+    output(header.as_bytes())?;
 
-pub fn save_object(oid: &String, object_content: String) -> Option<()> {
-    let filepath = get_object_path_from_oid(oid, true);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut read = 0;
 
-    let mut object_file = match File::create(&filepath) {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Failed to create file {}: {}", filepath, e);
-            return None;
-        }
-    };
+    loop {
+        let length = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(length) => length,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
 
-    let compressed_object_content = utils::compress_object_content(&object_content)?;
+        hasher.update(&buffer[..length]);
+        output(&buffer[..length])?;
+        read += length as u64;
+    }
 
-    match object_file.write_all(&compressed_object_content) {
-        Ok(_) => Some(()),
-        Err(e) => {
-            println!("Failed to save object: {}", e);
-            return None;
-        }
+    if read != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected {size} bytes but read {read}, it changed while being read"),
+        ));
     }
+
+    Ok(hex::encode(hasher.finalize()))
 }
 
-pub fn object_exists(oid: &str) -> bool {
-    let file_path = get_object_path_from_oid(&oid, false);
+/// Stores an object in the current object store.
+pub fn save_object(oid: &str, object_content: &[u8]) -> Option<()> {
+    object_store::get_object_store()?.write(oid, object_content)
+}
 
-    match File::open(file_path) {
-        Ok(_) => true,
-        Err(_) => false,
-    }
+pub fn object_exists(oid: &str) -> bool {
+    object_store::get_object_store().is_some_and(|store| store.exists(oid))
 }
 
 pub fn get_all_objects() -> Vec<String> {
-    let objects_dir_path = format!("{GRIT_DIRECTORY}/objects");
-
-    let objects = match fs::read_dir(&objects_dir_path) {
-        Ok(result) => result,
-        Err(e) => {
-            println!("Failed to read directory {}: {}", &objects_dir_path, e);
-            return vec![];
-        }
-    };
-
-    objects
-        .map(
-            |object| match object.expect("Failed to read file").path().to_str() {
-                Some(filename) => filename
-                    .split("/")
-                    .collect::<Vec<&str>>()
-                    .join("")
-                    .to_string(),
-                None => "".to_string(),
-            },
-        )
-        .collect()
+    match object_store::get_object_store() {
+        Some(store) => store.iter().collect(),
+        None => vec![],
+    }
 }
@@ -0,0 +1,277 @@
+use crate::config::{self, GRIT_DIRECTORY};
+use crate::file_handling;
+use crate::object_store;
+use crate::plumbing::{self, GritCatType, NULL_OID};
+use crate::reflog;
+use crate::refs;
+use crate::utils;
+use std::collections::HashSet;
+use std::path;
+use walkdir::WalkDir;
+
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+/// Collects the objects every reachability walk starts from: HEAD, all loose and packed refs,
+/// the index and both sides of every reflog entry.
+pub fn get_root_objects() -> Option<Vec<String>> {
+    let mut roots = vec![];
+
+    if let Some(head) = refs::read_ref_value("HEAD")? {
+        if !head.starts_with("ref:") && !head.is_empty() {
+            roots.push(head);
+        }
+    }
+
+    for ref_name in refs::get_all_refs()? {
+        roots.push(plumbing::get_ref_oid(&ref_name)?);
+    }
+
+    // Both sides of every reflog entry
+    for ref_name in reflog::get_all_reflogs() {
+        for entry in reflog::read_reflog(&ref_name)? {
+            roots.push(entry.old_oid);
+            roots.push(entry.new_oid);
+        }
+    }
+
+    roots.extend(refs::read_packed_refs()?.into_values());
+
+    let (_index_tree_map, _paths, _index_entries, index_oids) = plumbing::get_index_entries()?;
+    roots.extend(index_oids);
+
+    roots.retain(|oid| oid.len() == 40 && oid != NULL_OID);
+
+    Some(roots)
+}
+
+pub fn get_reachable_objects() -> Option<HashSet<String>> {
+    get_objects_reachable_from(get_root_objects()?)
+}
+
+/// Walks commits, trees and tags from `roots` to every object they reference.
+pub fn get_objects_reachable_from(roots: Vec<String>) -> Option<HashSet<String>> {
+    let mut reachable = HashSet::new();
+    let mut pending = roots;
+
+    while let Some(oid) = pending.pop() {
+        if reachable.contains(&oid) || !file_handling::object_exists(&oid) {
+            continue;
+        }
+
+        match plumbing::generate_cat_content(&oid, GritCatType::Type)?.as_str() {
+            "commit" => {
+                pending.push(plumbing::get_commit_tree_oid(&oid)?);
+                pending.extend(plumbing::get_commit_parents(&oid)?);
+            }
+            "tree" => {
+                let (_tree_map, _paths, _entries, oids) = plumbing::get_tree_entries(&oid)?;
+                pending.extend(oids);
+            }
+            "tag" => {
+                let tag = plumbing::generate_cat_content(&oid, GritCatType::Pretty)?;
+                pending.extend(
+                    tag.lines()
+                        .filter_map(|line| line.strip_prefix("object "))
+                        .map(|target_oid| target_oid.to_string()),
+                );
+            }
+            _ => (),
+        }
+
+        reachable.insert(oid);
+    }
+
+    Some(reachable)
+}
+
+pub fn prune(dry_run: bool, expire: Option<&str>) -> Option<()> {
+    let expire = match expire {
+        Some(expire) => expire.to_string(),
+        None => {
+            config::get_config_value("gc.pruneExpire").unwrap_or(DEFAULT_PRUNE_EXPIRE.to_string())
+        }
+    };
+    let cutoff = utils::parse_expiry(&expire)?;
+
+    let reachable = get_reachable_objects()?;
+
+    let store = object_store::get_object_store()?;
+    let mut pruned = vec![];
+
+    for oid in store.iter().collect::<Vec<String>>() {
+        if reachable.contains(&oid) {
+            continue;
+        }
+
+        // Recently written objects may still be about to be referenced by another
+        // command, and objects whose age the store cannot tell might be too
+        match store.get_modified_time(&oid) {
+            Some(modified) if modified <= cutoff => (),
+            _ => continue,
+        }
+
+        let object_type = plumbing::generate_cat_content(&oid, GritCatType::Type)
+            .unwrap_or("unknown".to_string());
+
+        println!("{oid} {object_type}");
+
+        if !dry_run {
+            store.remove(&oid)?;
+        }
+
+        pruned.push(oid);
+    }
+
+    let report = format!(
+        "{} {} unreachable object{}",
+        if dry_run { "Would prune" } else { "Pruned" },
+        pruned.len(),
+        if pruned.len() == 1 { "" } else { "s" },
+    );
+
+    println!("{report}");
+
+    Some(())
+}
+
+fn get_pack_index_object_count(index_path: &path::Path) -> Option<u64> {
+    let index = file_handling::read_file_as_bytes(&index_path.display().to_string())?;
+
+    // Version 2 indexes start with a magic number and version before the fan-out table
+    let fanout_start = if index.starts_with(&[0xff, b't', b'O', b'c']) {
+        8
+    } else {
+        0
+    };
+    let last_fanout = fanout_start + 255 * 4;
+
+    let count = index.get(last_fanout..last_fanout + 4)?;
+
+    Some(u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as u64)
+}
+
+fn get_pack_index_oids(index_path: &path::Path) -> Option<Vec<String>> {
+    let index = file_handling::read_file_as_bytes(&index_path.display().to_string())?;
+    let count = get_pack_index_object_count(index_path)? as usize;
+
+    let oids = if index.starts_with(&[0xff, b't', b'O', b'c']) {
+        let start = 8 + 256 * 4;
+        (0..count)
+            .filter_map(|i| index.get(start + i * 20..start + (i + 1) * 20))
+            .map(hex::encode)
+            .collect()
+    } else {
+        let start = 256 * 4;
+        (0..count)
+            .filter_map(|i| index.get(start + i * 24 + 4..start + (i + 1) * 24))
+            .map(hex::encode)
+            .collect()
+    };
+
+    Some(oids)
+}
+
+pub fn count_objects(verbose: bool) -> Option<()> {
+    let objects_dir_path = format!("{GRIT_DIRECTORY}/objects");
+
+    let store = object_store::get_object_store()?;
+    let (count, size) = store.count_objects()?;
+
+    let mut garbage = 0;
+    let mut size_garbage = 0;
+
+    // Whatever store holds the objects, stray files in the fan-out directories are garbage
+    for entry in WalkDir::new(&objects_dir_path)
+        .min_depth(2)
+        .max_depth(2)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let directory = match entry.path().parent().and_then(|parent| parent.file_name()) {
+            Some(directory) => directory.to_string_lossy().to_string(),
+            None => continue,
+        };
+
+        if directory == "pack" || directory == "info" {
+            continue;
+        }
+
+        let filename = entry.file_name().to_string_lossy().to_string();
+
+        let is_object = directory.len() == 2
+            && filename.len() == 38
+            && format!("{directory}{filename}")
+                .chars()
+                .all(|c| c.is_ascii_hexdigit());
+
+        if !is_object {
+            garbage += 1;
+            size_garbage += entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        }
+    }
+
+    let mut in_pack = 0;
+    let mut packs = 0;
+    let mut size_pack = 0;
+    let mut packed_oids = HashSet::new();
+
+    let pack_dir_path = format!("{objects_dir_path}/pack");
+
+    for entry in WalkDir::new(&pack_dir_path)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let file_size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+
+        match entry
+            .path()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("pack") => {
+                let index_path = entry.path().with_extension("idx");
+
+                if !index_path.exists() {
+                    garbage += 1;
+                    size_garbage += file_size;
+                    continue;
+                }
+
+                packs += 1;
+                size_pack += file_size;
+                in_pack += get_pack_index_object_count(&index_path)?;
+                packed_oids.extend(get_pack_index_oids(&index_path)?);
+            }
+            Some("idx") => {
+                size_pack += file_size;
+            }
+            _ => {
+                garbage += 1;
+                size_garbage += file_size;
+            }
+        }
+    }
+
+    let prune_packable = store.iter().filter(|oid| packed_oids.contains(oid)).count();
+
+    if !verbose {
+        println!("{count} objects, {} kilobytes", size / 1024);
+        return Some(());
+    }
+
+    let report = format!("count: {count}")
+        + format!("\nsize: {}", size / 1024).as_str()
+        + format!("\nin-pack: {in_pack}").as_str()
+        + format!("\npacks: {packs}").as_str()
+        + format!("\nsize-pack: {}", size_pack / 1024).as_str()
+        + format!("\nprune-packable: {prune_packable}").as_str()
+        + format!("\ngarbage: {garbage}").as_str()
+        + format!("\nsize-garbage: {}", size_garbage / 1024).as_str();
+
+    println!("{report}");
+
+    Some(())
+}
@@ -0,0 +1,80 @@
+use crate::config::{self, GRIT_DIRECTORY, WORKING_DIR};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Where hooks are looked up: `core.hooksPath`, relative to the top of the
+/// repository unless absolute, or else `.grit/hooks`.
+fn get_hooks_directory() -> PathBuf {
+    match config::get_config_value("core.hooksPath") {
+        Some(hooks_path) => PathBuf::from(hooks_path),
+        None => Path::new(GRIT_DIRECTORY).join("hooks"),
+    }
+}
+
+fn get_absolute_path(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|path| path.display().to_string())
+        .unwrap_or(path.to_string())
+}
+
+/// Runs the `name` hook with `args`, feeding it `stdin`, if the hook exists.
+/// Hooks run from the top of the repository with the same environment git
+/// gives them. `None` when the hook fails, which the caller takes as a veto
+/// and reports in its own words.
+pub fn run_hook(name: &str, args: &[&str], stdin: Option<&str>) -> Option<()> {
+    let hook_path = get_hooks_directory().join(name);
+
+    let metadata = match fs::metadata(&hook_path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Some(()),
+    };
+
+    if metadata.permissions().mode() & 0o111 == 0 {
+        println!(
+            "hint: The '{}' hook was ignored because it's not set as executable.",
+            hook_path.display()
+        );
+        return Some(());
+    }
+
+    let child = Command::new(&hook_path)
+        .args(args)
+        .env("GIT_DIR", get_absolute_path(GRIT_DIRECTORY))
+        .env("GIT_WORK_TREE", get_absolute_path(WORKING_DIR))
+        .env(
+            "GIT_INDEX_FILE",
+            get_absolute_path(&format!("{GRIT_DIRECTORY}/index")),
+        )
+        .env("GIT_PREFIX", "")
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .spawn();
+
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            println!("error: cannot run {}: {}", hook_path.display(), e);
+            return None;
+        }
+    };
+
+    if let (Some(input), Some(mut child_stdin)) = (stdin, child.stdin.take()) {
+        // A hook is free to exit without reading its input
+        let _ = child_stdin.write_all(input.as_bytes());
+    }
+
+    match child.wait() {
+        Ok(status) if status.success() => Some(()),
+        Ok(_) => None,
+        Err(e) => {
+            println!("error: cannot run {}: {}", hook_path.display(), e);
+            None
+        }
+    }
+}
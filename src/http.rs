@@ -0,0 +1,350 @@
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+/// The largest request body a server reads, once decompressed. Responses are
+/// not limited, as fetching a large repository needs a large pack.
+pub const MAX_REQUEST_BODY_SIZE: u64 = 1 << 30;
+
+/// Just enough HTTP/1.1 to carry the smart protocol: plain `http://` only,
+/// one request per connection.
+pub struct GritHttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    /// Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+pub struct GritHttpResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub body: Vec<u8>,
+}
+
+impl GritHttpResponse {
+    pub fn new(status: u16, content_type: &str, body: Vec<u8>) -> GritHttpResponse {
+        GritHttpResponse {
+            status,
+            content_type: content_type.to_string(),
+            body,
+        }
+    }
+
+    pub fn text(status: u16, text: &str) -> GritHttpResponse {
+        GritHttpResponse::new(status, "text/plain", text.as_bytes().to_vec())
+    }
+}
+
+pub fn get_status_text(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Splits `http://host[:port]/path` into the address to connect to, the host and the path.
+fn parse_url(url: &str) -> Option<(String, String, String)> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None => {
+            println!("fatal: unsupported url '{url}', only http:// urls can be used");
+            return None;
+        }
+    };
+
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    };
+
+    Some((address, host.to_string(), path.to_string()))
+}
+
+/// Appends up to `length` bytes to `body`, growing it only as the bytes
+/// arrive, so a length that was claimed but never sent costs nothing.
+fn read_exactly(reader: &mut impl Read, length: u64, body: &mut Vec<u8>) -> Option<()> {
+    match reader.take(length).read_to_end(body) {
+        Ok(read) if read as u64 == length => Some(()),
+        Ok(_) => {
+            println!("Failed to read HTTP body: the connection closed early");
+            None
+        }
+        Err(e) => {
+            println!("Failed to read HTTP body: {}", e);
+            None
+        }
+    }
+}
+
+/// Reads the rest of `reader` into `body`, failing once it passes `max_size` bytes.
+fn read_limited(reader: &mut impl Read, max_size: u64, body: &mut Vec<u8>) -> Option<()> {
+    if let Err(e) = reader.take(max_size.saturating_add(1)).read_to_end(body) {
+        println!("Failed to read HTTP body: {}", e);
+        return None;
+    }
+
+    if body.len() as u64 > max_size {
+        println!("HTTP body is larger than {max_size} bytes");
+        return None;
+    }
+
+    Some(())
+}
+
+fn read_chunked(reader: &mut impl BufRead, max_size: u64) -> Option<Vec<u8>> {
+    let mut body = vec![];
+
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line).ok()?;
+
+        let size_text = size_line.trim().split(';').next().unwrap_or("");
+        let size = match u64::from_str_radix(size_text, 16) {
+            Ok(size) => size,
+            Err(_) => {
+                println!("Invalid chunk size in HTTP body: {size_text:?}");
+                return None;
+            }
+        };
+
+        if size == 0 {
+            // Skip any trailers up to the closing blank line
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).ok()? == 0 || line.trim().is_empty() {
+                    return Some(body);
+                }
+            }
+        }
+
+        if size > max_size - body.len() as u64 {
+            println!("HTTP body is larger than {max_size} bytes");
+            return None;
+        }
+
+        read_exactly(reader, size, &mut body)?;
+
+        let mut line_end = [0; 2];
+        reader.read_exact(&mut line_end).ok()?;
+    }
+}
+
+/// Reads the header lines after the start line, up to the blank line.
+fn read_headers(reader: &mut impl BufRead) -> Option<HashMap<String, String>> {
+    let mut headers = HashMap::new();
+
+    loop {
+        let mut line = String::new();
+
+        match reader.read_line(&mut line) {
+            Ok(0) => return Some(headers),
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to read HTTP headers: {}", e);
+                return None;
+            }
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Some(headers);
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+}
+
+/// Reads a body framed by `Content-Length` or chunked encoding, undoing gzip
+/// if it was used. Bodies over `max_size` bytes, before or after decoding, are
+/// refused.
+fn read_body(
+    reader: &mut impl BufRead,
+    headers: &HashMap<String, String>,
+    read_to_end: bool,
+    max_size: u64,
+) -> Option<Vec<u8>> {
+    let body = if headers
+        .get("transfer-encoding")
+        .map(|value| value.to_lowercase())
+        == Some("chunked".to_string())
+    {
+        read_chunked(reader, max_size)?
+    } else if let Some(length) = headers.get("content-length") {
+        let length = length.parse::<u64>().ok()?;
+
+        if length > max_size {
+            println!("HTTP body is larger than {max_size} bytes");
+            return None;
+        }
+
+        let mut body = vec![];
+        read_exactly(reader, length, &mut body)?;
+        body
+    } else if read_to_end {
+        let mut body = vec![];
+        read_limited(reader, max_size, &mut body)?;
+        body
+    } else {
+        vec![]
+    };
+
+    if headers.get("content-encoding").map(|value| value.as_str()) != Some("gzip") {
+        return Some(body);
+    }
+
+    let mut decoded = vec![];
+    if let Err(e) = GzDecoder::new(body.as_slice())
+        .take(max_size.saturating_add(1))
+        .read_to_end(&mut decoded)
+    {
+        println!("Failed to decompress HTTP body: {}", e);
+        return None;
+    }
+
+    if decoded.len() as u64 > max_size {
+        println!("HTTP body is larger than {max_size} bytes");
+        return None;
+    }
+
+    Some(decoded)
+}
+
+/// Sends one request and waits for the whole response.
+pub fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Option<GritHttpResponse> {
+    let (address, host, path) = parse_url(url)?;
+
+    let mut stream = match TcpStream::connect(&address) {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("fatal: unable to access '{url}': {e}");
+            return None;
+        }
+    };
+
+    let mut head = format!("{method} {path} HTTP/1.1\r\nHost: {host}\r\nUser-Agent: grit/0.1\r\n")
+        + "Connection: close\r\n"
+        + format!("Content-Length: {}\r\n", body.len()).as_str();
+
+    for (name, value) in headers {
+        head += format!("{name}: {value}\r\n").as_str();
+    }
+
+    head += "\r\n";
+
+    let sent = stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body))
+        .and_then(|_| stream.flush());
+
+    if let Err(e) = sent {
+        println!("fatal: unable to access '{url}': {e}");
+        return None;
+    }
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+
+    if let Err(e) = reader.read_line(&mut status_line) {
+        println!("fatal: unable to access '{url}': {e}");
+        return None;
+    }
+
+    let status = match status_line
+        .split(' ')
+        .nth(1)
+        .map(|status| status.parse::<u16>())
+    {
+        Some(Ok(status)) => status,
+        _ => {
+            println!("fatal: invalid HTTP response from '{url}'");
+            return None;
+        }
+    };
+
+    let response_headers = read_headers(&mut reader)?;
+    let body = read_body(&mut reader, &response_headers, true, u64::MAX)?;
+
+    Some(GritHttpResponse {
+        status,
+        content_type: response_headers
+            .get("content-type")
+            .cloned()
+            .unwrap_or_default(),
+        body,
+    })
+}
+
+/// Reads a request from a client connection, answering `Expect: 100-continue` along the way.
+pub fn read_request(stream: &mut TcpStream) -> Option<GritHttpRequest> {
+    let mut reader = BufReader::new(stream.try_clone().ok()?);
+    let mut request_line = String::new();
+
+    if reader.read_line(&mut request_line).ok()? == 0 {
+        return None;
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let headers = read_headers(&mut reader)?;
+
+    if headers.get("expect").map(|value| value.to_lowercase()) == Some("100-continue".to_string()) {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").ok()?;
+    }
+
+    let body = read_body(&mut reader, &headers, false, MAX_REQUEST_BODY_SIZE)?;
+
+    Some(GritHttpRequest {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        headers,
+        body,
+    })
+}
+
+pub fn write_response(stream: &mut TcpStream, response: &GritHttpResponse) -> Option<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        get_status_text(response.status)
+    ) + format!("Content-Type: {}\r\n", response.content_type).as_str()
+        + format!("Content-Length: {}\r\n", response.body.len()).as_str()
+        + "Cache-Control: no-cache\r\nConnection: close\r\n\r\n";
+
+    let written = stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(&response.body))
+        .and_then(|_| stream.flush());
+
+    match written {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to send response: {}", e);
+            None
+        }
+    }
+}
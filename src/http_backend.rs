@@ -0,0 +1,249 @@
+use crate::config::{self, GRIT_DIRECTORY};
+use crate::http::{self, GritHttpResponse, MAX_REQUEST_BODY_SIZE};
+use crate::pkt_line;
+use crate::protocol;
+use crate::transport;
+use flate2::read::GzDecoder;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+
+const ENDPOINTS: [&str; 3] = ["info/refs", "git-upload-pack", "git-receive-pack"];
+
+/// Splits a request path into the repository it names, relative to the
+/// served directory, and the smart HTTP endpoint it asks for.
+fn split_request_path(path: &str) -> Option<(String, &'static str)> {
+    for endpoint in ENDPOINTS {
+        if let Some(repository) = path.strip_suffix(endpoint) {
+            if repository.is_empty() || repository.ends_with('/') {
+                let repository = repository.trim_matches('/').to_string();
+                return Some((repository, endpoint));
+            }
+        }
+    }
+
+    None
+}
+
+fn get_query_value<'a>(query: &'a str, name: &str) -> Option<&'a str> {
+    query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(&format!("{name}=")))
+}
+
+/// Anyone who can reach the server could push, so pushing is refused unless
+/// the served repository turns it on with `http.receivepack = true`.
+fn receive_pack_allowed() -> bool {
+    config::get_config_value("http.receivepack").as_deref() == Some("true")
+}
+
+fn advertise(service: &str) -> Option<GritHttpResponse> {
+    let mut body = pkt_line::encode_str(&format!("# service={service}\n"));
+    body.extend(pkt_line::flush());
+
+    match service {
+        "git-upload-pack" => body.extend(protocol::advertise_upload_pack()),
+        "git-receive-pack" if receive_pack_allowed() => {
+            body.extend(protocol::advertise_receive_pack()?)
+        }
+        "git-receive-pack" => return Some(GritHttpResponse::text(403, "Pushing is disabled\n")),
+        _ => {
+            return Some(GritHttpResponse::text(
+                403,
+                "Only the smart HTTP protocol is supported\n",
+            ))
+        }
+    }
+
+    Some(GritHttpResponse::new(
+        200,
+        &format!("application/x-{service}-advertisement"),
+        body,
+    ))
+}
+
+/// Answers one smart HTTP request against the repository in the current directory.
+fn handle_request(
+    method: &str,
+    endpoint: &str,
+    query: &str,
+    body: &[u8],
+) -> Option<GritHttpResponse> {
+    match (method, endpoint) {
+        ("GET", "info/refs") => advertise(get_query_value(query, "service").unwrap_or("")),
+        ("POST", "git-upload-pack") => Some(GritHttpResponse::new(
+            200,
+            "application/x-git-upload-pack-result",
+            protocol::serve_upload_pack(body)?,
+        )),
+        ("POST", "git-receive-pack") if receive_pack_allowed() => Some(GritHttpResponse::new(
+            200,
+            "application/x-git-receive-pack-result",
+            protocol::serve_receive_pack(body)?,
+        )),
+        ("POST", "git-receive-pack") => Some(GritHttpResponse::text(403, "Pushing is disabled\n")),
+        _ => Some(GritHttpResponse::text(405, "Method not allowed\n")),
+    }
+}
+
+/// Finds the repository a request path names under `root` and answers the request in it.
+fn respond(root: &Path, method: &str, path: &str, query: &str, body: &[u8]) -> GritHttpResponse {
+    let (repository, endpoint) = match split_request_path(path) {
+        Some(request) => request,
+        None => return GritHttpResponse::text(404, "Not found\n"),
+    };
+
+    let repository_dir = root.join(&repository);
+
+    // Refuse anything that would reach outside the served directory
+    let inside_root = fs::canonicalize(&repository_dir)
+        .map(|repository_dir| repository_dir.starts_with(root))
+        .unwrap_or(false);
+
+    if !inside_root || !repository_dir.join(GRIT_DIRECTORY).is_dir() {
+        return GritHttpResponse::text(404, "Repository not found\n");
+    }
+
+    transport::with_repository(&repository_dir, || {
+        handle_request(method, endpoint, query, body)
+    })
+    .unwrap_or(GritHttpResponse::text(500, "Internal error\n"))
+}
+
+fn get_root(directory: Option<&str>) -> Option<std::path::PathBuf> {
+    match fs::canonicalize(directory.unwrap_or(".")) {
+        Ok(root) => Some(root),
+        Err(e) => {
+            println!("Failed to resolve {}: {}", directory.unwrap_or("."), e);
+            None
+        }
+    }
+}
+
+/// Serves the repositories under `directory` over smart HTTP, one request at a
+/// time. A request for `/<path>/info/refs` is answered by the repository at `<path>`.
+pub fn serve(directory: Option<&str>, port: u16) -> Option<()> {
+    let root = get_root(directory)?;
+
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("Failed to listen on port {}: {}", port, e);
+            return None;
+        }
+    };
+
+    let address = match listener.local_addr() {
+        Ok(address) => address,
+        Err(e) => {
+            println!("Failed to read the listening address: {}", e);
+            return None;
+        }
+    };
+
+    println!(
+        "Serving grit repositories under {} at http://{address}/",
+        root.display()
+    );
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let request = match http::read_request(&mut stream) {
+            Some(request) => request,
+            None => continue,
+        };
+
+        let response = respond(
+            &root,
+            &request.method,
+            &request.path,
+            &request.query,
+            &request.body,
+        );
+
+        println!("{} {} {}", request.method, request.path, response.status);
+
+        http::write_response(&mut stream, &response);
+    }
+
+    Some(())
+}
+
+/// Answers a single request as a CGI program, the way a web server runs
+/// `git http-backend`. Repositories are found under `GIT_PROJECT_ROOT`.
+pub fn http_backend() -> Option<()> {
+    // The response goes to stdout, so anything printed on the way goes to stderr
+    let mut output = protocol::take_protocol_output()?;
+    let root = get_root(env::var("GIT_PROJECT_ROOT").ok().as_deref())?;
+    let method = env::var("REQUEST_METHOD").unwrap_or("GET".to_string());
+    let path = env::var("PATH_INFO").unwrap_or_default();
+    let query = env::var("QUERY_STRING").unwrap_or_default();
+
+    let mut body = vec![];
+
+    let limit = MAX_REQUEST_BODY_SIZE + 1;
+
+    let read = match env::var("CONTENT_LENGTH")
+        .ok()
+        .and_then(|length| length.parse::<u64>().ok())
+    {
+        Some(length) => io::stdin().take(length.min(limit)).read_to_end(&mut body),
+        None if method == "POST" => io::stdin().take(limit).read_to_end(&mut body),
+        None => Ok(0),
+    };
+
+    if let Err(e) = read {
+        eprintln!("Failed to read the request body: {}", e);
+        return None;
+    }
+
+    if env::var("HTTP_CONTENT_ENCODING").as_deref() == Ok("gzip") {
+        let mut decoded = vec![];
+
+        if let Err(e) = GzDecoder::new(body.as_slice())
+            .take(limit)
+            .read_to_end(&mut decoded)
+        {
+            eprintln!("Failed to decompress the request body: {}", e);
+            return None;
+        }
+
+        body = decoded;
+    }
+
+    if body.len() as u64 > MAX_REQUEST_BODY_SIZE {
+        eprintln!("The request body is larger than {MAX_REQUEST_BODY_SIZE} bytes");
+        return None;
+    }
+
+    let response = respond(&root, &method, &path, &query, &body);
+
+    let head = format!(
+        "Status: {} {}\r\n",
+        response.status,
+        http::get_status_text(response.status)
+    ) + format!("Content-Type: {}\r\n", response.content_type).as_str()
+        + "Cache-Control: no-cache\r\n\r\n";
+
+    let written = output
+        .write_all(head.as_bytes())
+        .and_then(|_| output.write_all(&response.body))
+        .and_then(|_| output.flush());
+
+    match written {
+        Ok(_) => Some(()),
+        Err(e) => {
+            eprintln!("Failed to write the response: {}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::config::WORKING_DIR;
+use crate::file_handling;
+use crate::utils;
+use std::path;
+
+/// A line of `.gritignore`.
+pub struct GritIgnorePattern {
+    pattern: String,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+impl GritIgnorePattern {
+    fn parse(line: &str) -> Option<GritIgnorePattern> {
+        let line = line.trim_end();
+
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+
+        let directory_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+
+        // A slash anywhere but the end ties the pattern to the top of the working tree
+        let anchored = line.contains('/');
+        let pattern = line.trim_start_matches('/').to_string();
+
+        Some(GritIgnorePattern {
+            pattern,
+            negated,
+            directory_only,
+            anchored,
+        })
+    }
+
+    fn matches_component(&self, candidate: &str) -> bool {
+        if self.anchored {
+            utils::matches_glob(&self.pattern, candidate)
+        } else {
+            let name = candidate.rsplit('/').next().unwrap_or(candidate);
+            utils::matches_glob(&self.pattern, name)
+        }
+    }
+
+    /// Checks the file itself and, since ignoring a directory ignores everything
+    /// in it, each of the directories leading to it.
+    fn matches(&self, filepath: &str) -> bool {
+        let components = filepath.split('/').collect::<Vec<&str>>();
+
+        (1..=components.len()).any(|count| {
+            let is_directory = count < components.len();
+            (is_directory || !self.directory_only)
+                && self.matches_component(&components[..count].join("/"))
+        })
+    }
+}
+
+/// Reads the patterns in `.gritignore` at the top of the working tree.
+pub fn get_ignore_patterns() -> Option<Vec<GritIgnorePattern>> {
+    let ignore_path = format!("{WORKING_DIR}/.gritignore");
+
+    if !path::Path::new(&ignore_path).exists() {
+        return Some(vec![]);
+    }
+
+    let content = file_handling::read_file(&ignore_path)?;
+
+    Some(
+        content
+            .lines()
+            .filter_map(GritIgnorePattern::parse)
+            .collect(),
+    )
+}
+
+/// The last matching pattern wins, so a later `!pattern` can re-include a path.
+pub fn is_ignored(filepath: &str, patterns: &[GritIgnorePattern]) -> bool {
+    patterns
+        .iter()
+        .rev()
+        .find(|pattern| pattern.matches(filepath))
+        .is_some_and(|pattern| !pattern.negated)
+}
@@ -1,11 +1,43 @@
+pub mod archive;
+pub mod attributes;
+pub mod blame;
+pub mod bundle;
+pub mod clone;
 mod config;
+pub mod diff;
 pub mod file_handling;
+pub mod gc;
+pub mod hooks;
+pub mod http;
+pub mod http_backend;
+pub mod ignore;
+pub mod lockfile;
+pub mod mailbox;
+pub mod merge;
+pub mod object;
+pub mod object_store;
+pub mod pack;
+pub mod patch;
+pub mod pkt_line;
 pub mod plumbing;
+pub mod protocol;
+pub mod rebase;
+pub mod reflog;
+pub mod refs;
+pub mod remote;
+pub mod sequencer;
+pub mod show;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+pub mod stash;
+pub mod storage;
+pub mod transport;
 mod utils;
 
 pub use crate::config::{GRIT_DIRECTORY, WORKING_DIR};
 use colored::*;
-use plumbing::{GritMode, GritObjectType};
+use plumbing::GritMode;
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::prelude::*;
 
@@ -14,7 +46,7 @@ pub fn init() -> Option<()> {
     let grit_files = vec!["HEAD", "index", "refs/heads/main"];
 
     // Create the grit directory
-    match fs::create_dir_all(GRIT_DIRECTORY.clone()) {
+    match fs::create_dir_all(GRIT_DIRECTORY) {
         Ok(_) => (),
         Err(e) => {
             println!("{}", e);
@@ -60,7 +92,7 @@ pub fn init() -> Option<()> {
 }
 
 pub fn add(mut filenames: Vec<String>) -> Option<()> {
-    let (_index_tree_map, index_tree_paths, _index_tree_entries, index_tree_entry_oids) =
+    let (index_tree_map, index_tree_paths, _index_tree_entries, _index_tree_entry_oids) =
         plumbing::get_index_entries()?;
 
     if filenames[0] == "." {
@@ -77,37 +109,33 @@ pub fn add(mut filenames: Vec<String>) -> Option<()> {
 
     let mut added_files = String::new();
 
-    for filename in &filenames {
-        let lines = match file_handling::read_source_file(filename) {
-            Some(content) => content,
-            None => {
+    // Hashing and writing are spread over threads, staging stays in the order of `filenames`
+    let working_tree_oids = plumbing::write_working_tree_files(&filenames)?;
+    let mut staged_blobs = vec![];
+
+    for (filename, working_tree_oid) in filenames.iter().zip(working_tree_oids) {
+        let oid = match working_tree_oid {
+            Ok(oid) => oid,
+            Err(e) => {
+                println!("Failed to read file {}: {}", filename, e);
                 println!("here: {filename}");
-                plumbing::remove_from_index(&filename);
+                plumbing::remove_from_index(filename);
                 continue;
             }
         };
 
-        let oid: String = plumbing::hash_object(&lines, GritObjectType::Blob, false)?;
-
-        if match index_tree_entry_oids
-            .iter()
-            .find(|index_tree_entry_oid| **index_tree_entry_oid == oid)
-        {
-            Some(_index_tree_object_oid) => true,
-            None => false,
-        } {
+        if index_tree_map.get(filename) == Some(&oid) {
             continue;
         }
 
-        let oid: String = plumbing::hash_object(&lines, GritObjectType::Blob, true)?;
-
-        plumbing::update_index(GritMode::Normal, GritObjectType::Blob, &oid, filename)?;
-
-        let oid_and_file = format!("\n{} {}", oid[..7].to_string(), filename);
+        let oid_and_file = format!("\n{} {}", &oid[..7], filename);
         added_files.push_str(oid_and_file.as_str());
         oids.push(oid.to_string());
+        staged_blobs.push((oid, filename.to_string()));
     }
 
+    plumbing::update_index_blobs(GritMode::Normal, &staged_blobs)?;
+
     let report = format!(
         "Added {} file{}:{}",
         oids.len(),
@@ -150,43 +178,118 @@ pub fn rm(mut filenames: Vec<String>) -> Option<()> {
     Some(())
 }
 
-pub fn commit(message: &str) -> Option<()> {
+/// Commits the index. The pre-commit and commit-msg hooks can stop the commit,
+/// and commit-msg can rewrite the message, unless `no_verify` is set.
+pub fn commit(message: &str, no_verify: bool) -> Option<()> {
     println!("Committing changes...");
 
+    let merge_head = merge::get_merge_head()?;
+
+    if !merge_head.is_empty() {
+        let unresolved = merge::get_unresolved_paths()?;
+
+        if !unresolved.is_empty() {
+            println!(
+                "Committing is not possible because you have unmerged files:\n  {}",
+                unresolved.join("\n  ")
+            );
+            return None;
+        }
+    }
+
+    if !no_verify && hooks::run_hook("pre-commit", &[], None).is_none() {
+        println!("error: pre-commit hook declined the commit");
+        return None;
+    }
+
+    let message_path = format!("{GRIT_DIRECTORY}/COMMIT_EDITMSG");
+    file_handling::write_file(&message_path, &format!("{message}\n"))?;
+
+    if !no_verify && hooks::run_hook("commit-msg", &[&message_path], None).is_none() {
+        println!("error: commit-msg hook declined the commit");
+        return None;
+    }
+
+    let message = file_handling::read_file(&message_path)?
+        .trim_end()
+        .to_string();
+    let message = message.as_str();
+
     let tree_oid = plumbing::write_tree()?;
 
     let head_oid = plumbing::get_head_oid()?;
     let branch = plumbing::get_current_branch()?;
 
-    let mut parent_oid = None;
+    let mut parent_oids = vec![];
 
     if !head_oid.is_empty() {
-        parent_oid = Some(head_oid.as_str());
+        parent_oids.push(head_oid.trim().to_string());
     }
 
-    let commit_oid = plumbing::commit_tree(&tree_oid, message, parent_oid)?;
+    if !merge_head.is_empty() {
+        parent_oids.push(merge_head.clone());
+    }
 
-    plumbing::update_head(&commit_oid);
+    let commit_oid = plumbing::commit_tree_with_parents(&tree_oid, message, &parent_oids)?;
+
+    let subject = message.lines().next().unwrap_or("");
+
+    let reason = format!(
+        "commit{}: {subject}",
+        if parent_oids.is_empty() {
+            " (initial)"
+        } else if !merge_head.is_empty() {
+            " (merge)"
+        } else {
+            ""
+        }
+    );
 
-    let report = format!("[{branch} {}] {}", &commit_oid.to_string()[..7], message,);
+    plumbing::update_head(&commit_oid, &reason)?;
+
+    if !merge_head.is_empty() {
+        merge::remove_merge_state()?;
+    }
+
+    let report = format!("[{branch} {}] {}", &commit_oid.to_string()[..7], subject,);
 
     println!("{report}");
 
+    // Too late to stop the commit, so how the hook went does not matter
+    let _ = hooks::run_hook("post-commit", &[], None);
+
     Some(())
 }
 
 pub fn status() -> Option<()> {
     let branch = plumbing::get_current_branch()?;
 
-    let mut report_header = format!("On branch {branch}");
-    let mut staging_report = format!("");
-    let mut working_tree_report = format!("");
+    let mut report_header = if plumbing::is_head_detached()? {
+        let head_oid = plumbing::get_head_oid()?.trim().to_string();
+        format!("HEAD detached at {}", &head_oid[..7])
+    } else {
+        format!("On branch {branch}")
+    };
+    let mut staging_report = String::new();
+    let mut working_tree_report = String::new();
 
     let (index_tree_map, index_tree_paths, _index_tree_entries, _index_tree_oids) =
         plumbing::get_index_entries()?;
 
     let working_tree_paths = plumbing::get_working_tree_entries_recursively()?;
 
+    let tracked_paths = working_tree_paths
+        .iter()
+        .filter(|working_tree_path| index_tree_map.contains_key(*working_tree_path))
+        .cloned()
+        .collect::<Vec<String>>();
+    let working_tree_oids = tracked_paths
+        .iter()
+        .cloned()
+        .zip(plumbing::hash_working_tree_files(&tracked_paths)?)
+        .map(|(path, oid)| (path, oid.unwrap_or_default()))
+        .collect::<HashMap<String, String>>();
+
     let mut to_be_staged: Vec<String> = vec![];
     let mut untracked: Vec<String> = vec![];
     let mut to_be_committed: Vec<String> = vec![];
@@ -196,17 +299,7 @@ pub fn status() -> Option<()> {
     working_tree_paths.iter().for_each(|working_tree_path| {
         match index_tree_map.get(working_tree_path) {
             Some(index_tree_object_oid) => {
-                let contents = match file_handling::read_source_file(working_tree_path) {
-                    Some(lines) => lines,
-                    None => "".to_string(),
-                };
-                let working_tree_object_oid =
-                    match plumbing::hash_object(&contents, GritObjectType::Blob, false) {
-                        Some(oid) => oid,
-                        None => "".to_string(),
-                    };
-
-                if working_tree_object_oid != *index_tree_object_oid {
+                if working_tree_oids.get(working_tree_path) != Some(index_tree_object_oid) {
                     to_be_staged.push(format!("modified:    {working_tree_path}"));
                 }
             }
@@ -216,24 +309,21 @@ pub fn status() -> Option<()> {
         }
     });
 
-    // Get files to be removed
+    // Get files to be removed, the working tree paths come sorted
     index_tree_paths.iter().for_each(|index_tree_path| {
-        match working_tree_paths
-            .iter()
-            .find(|working_tree_path| working_tree_path == &index_tree_path)
-        {
-            Some(_index_tree_object_oid) => (),
-            None => {
-                to_be_removed.push(format!("deleted:    {index_tree_path}"));
-            }
+        if working_tree_paths.binary_search(index_tree_path).is_err() {
+            to_be_removed.push(format!("deleted:    {index_tree_path}"));
         }
     });
 
     let head_commit_oid = plumbing::get_head_oid()?;
-    let (head_tree_map, _paths, _head_tree_entries, _oids) =
+    let (head_tree_map, head_tree_paths, _head_tree_entries, _oids) =
         plumbing::get_head_tree_entries(&head_commit_oid)?;
 
-    for (index_tree_entry, index_tree_entry_oid) in index_tree_map.iter() {
+    // Walk the paths rather than the maps so the report comes out in the same order every time
+    for index_tree_entry in &index_tree_paths {
+        let index_tree_entry_oid = &index_tree_map[index_tree_entry];
+
         match head_tree_map.get(index_tree_entry) {
             Some(head_object_oid) => {
                 if *head_object_oid != *index_tree_entry_oid {
@@ -246,7 +336,7 @@ pub fn status() -> Option<()> {
         }
     }
 
-    for (head_tree_entry, _head_tree_entry_oid) in head_tree_map.iter() {
+    for head_tree_entry in &head_tree_paths {
         match index_tree_map.get(head_tree_entry) {
             Some(_index_object_oid) => (),
             None => {
@@ -267,7 +357,7 @@ pub fn status() -> Option<()> {
     }
 
     if !to_be_staged.is_empty() {
-        working_tree_report = format!("\n\nChanges not staged for commit:")
+        working_tree_report = "\n\nChanges not staged for commit:".to_string()
             + "\n  (use `grit add <file>...` to include in what will be committed)"
             + "\n  (use `grit restore <file>...` to discard changes in working directory)"
             + format!("\n\t{}", &to_be_staged.join("\n\t").red()).as_str();
@@ -280,7 +370,7 @@ pub fn status() -> Option<()> {
             // + "\n\nnothing added to commit but untracked files present (use `grit add` to track)";
         }
     } else if !to_be_removed.is_empty() {
-        working_tree_report = format!("\n\nChanges not staged for commit:")
+        working_tree_report = "\n\nChanges not staged for commit:".to_string()
             + "\n  (use `grit add <file>...` to include in what will be committed)"
             + "\n  (use `grit restore <file>...` to discard changes in working directory)"
             + format!("\n\t{}", &to_be_removed.join("\n\t").red()).as_str();
@@ -294,5 +384,5 @@ pub fn status() -> Option<()> {
     }
 
     println!("{report_header}{staging_report}{working_tree_report}");
-    return None;
+    None
 }
@@ -0,0 +1,135 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path;
+
+/// An exclusive claim on a file, held through `<path>.lock`. New content is
+/// written to the lock file and only replaces the original on `commit`, so
+/// readers never see a half-written file. Dropping an uncommitted lock rolls it back.
+pub struct GritLockFile {
+    path: String,
+    lock_path: String,
+    file: Option<File>,
+}
+
+impl GritLockFile {
+    pub fn acquire(filepath: &str) -> Option<GritLockFile> {
+        let lock_path = format!("{filepath}.lock");
+
+        if let Some(parent) = path::Path::new(filepath).parent() {
+            match fs::create_dir_all(parent) {
+                Ok(_) => (),
+                Err(e) => {
+                    println!("Failed to create directory {:?}: {}", parent, e);
+                    return None;
+                }
+            }
+        }
+
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => Some(GritLockFile {
+                path: filepath.to_string(),
+                lock_path,
+                file: Some(file),
+            }),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let report = format!("Unable to create '{lock_path}': File exists.")
+                    + "\nAnother grit process seems to be running in this repository."
+                    + "\nIf no other process is running, remove the file manually to continue.";
+
+                println!("{report}");
+                None
+            }
+            Err(e) => {
+                println!("Unable to create '{}': {}", lock_path, e);
+                None
+            }
+        }
+    }
+
+    pub fn get_path(&self) -> &str {
+        &self.path
+    }
+
+    /// The lock file itself, for content that is written a piece at a time.
+    pub fn get_file(&mut self) -> Option<&mut File> {
+        self.file.as_mut()
+    }
+
+    pub fn write(&mut self, content: &[u8]) -> Option<()> {
+        let file = self.file.as_mut()?;
+
+        match file.write_all(content) {
+            Ok(_) => Some(()),
+            Err(e) => {
+                println!("Failed to write {}: {}", self.lock_path, e);
+                None
+            }
+        }
+    }
+
+    /// Flushes the new content to disk and moves it over the original file.
+    pub fn commit(mut self) -> Option<()> {
+        let file = self.file.take()?;
+
+        match file.sync_all() {
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to sync {}: {}", self.lock_path, e);
+                return None;
+            }
+        }
+
+        drop(file);
+
+        match fs::rename(&self.lock_path, &self.path) {
+            Ok(_) => Some(()),
+            Err(e) => {
+                println!(
+                    "Failed to rename {} to {}: {}",
+                    self.lock_path, self.path, e
+                );
+                None
+            }
+        }
+    }
+
+    /// Removes the original file instead of replacing it, then releases the lock.
+    pub fn commit_removal(mut self) -> Option<()> {
+        self.file.take();
+
+        if path::Path::new(&self.path).exists() {
+            match fs::remove_file(&self.path) {
+                Ok(_) => (),
+                Err(e) => {
+                    println!("Failed to remove {}: {}", self.path, e);
+                    return None;
+                }
+            }
+        }
+
+        self.rollback();
+
+        Some(())
+    }
+
+    pub fn rollback(&mut self) {
+        self.file.take();
+
+        if path::Path::new(&self.lock_path).exists() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+impl Drop for GritLockFile {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            self.rollback();
+        }
+    }
+}
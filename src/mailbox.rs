@@ -0,0 +1,685 @@
+use crate::config::GRIT_DIRECTORY;
+use crate::diff;
+use crate::file_handling;
+use crate::merge::{self, GritMergeResult};
+use crate::patch::{self, GritFilePatch};
+use crate::plumbing;
+use crate::rebase;
+use crate::sequencer;
+use crate::utils;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path;
+
+/// The date line git puts on every patch, which mail programs recognise as an mbox separator.
+const MBOX_DATE: &str = "Mon Sep 17 00:00:00 2001";
+
+pub struct GritFormatPatchOptions {
+    /// Writes all patches to stdout instead of one file each.
+    pub stdout: bool,
+    pub output_directory: Option<String>,
+}
+
+/// The commits a `format-patch` range names, oldest first: `A..B`, `A` for
+/// `A..HEAD`, or `-<n>` for the last `n` commits of HEAD.
+fn get_commits_in_range(range: &str) -> Option<Vec<String>> {
+    if let Some(count) = range.strip_prefix('-') {
+        let count = match count.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                println!("fatal: unrecognized argument: {range}");
+                return None;
+            }
+        };
+
+        let mut commits = vec![];
+        let mut current = Some(plumbing::rev_parse("HEAD")?);
+
+        while let Some(oid) = current {
+            if commits.len() == count {
+                break;
+            }
+
+            let parents = plumbing::get_commit_parents(&oid)?;
+
+            if parents.len() < 2 {
+                commits.push(oid);
+            }
+
+            current = parents.into_iter().next();
+        }
+
+        commits.reverse();
+        return Some(commits);
+    }
+
+    let (since, until) = range.split_once("..").unwrap_or((range, "HEAD"));
+    let since = if since.is_empty() { "HEAD" } else { since };
+    let until = if until.is_empty() { "HEAD" } else { until };
+
+    rebase::get_commits_to_replay(&plumbing::rev_parse(until)?, &plumbing::rev_parse(since)?)
+}
+
+/// Turns a subject into the part of a patch filename after its number.
+fn get_patch_slug(subject: &str) -> String {
+    let mut slug = String::new();
+
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    slug.chars()
+        .take(52)
+        .collect::<String>()
+        .trim_end_matches(['-', '.'])
+        .to_string()
+}
+
+/// The date of a commit as it appears in a mail header.
+fn get_mail_date(commit_oid: &str) -> Option<String> {
+    let timestamp = utils::parse_time(&plumbing::get_commit_date(commit_oid)?)?;
+
+    Some(
+        DateTime::<Utc>::from_utc(
+            NaiveDateTime::from_timestamp_opt(timestamp, 0).unwrap_or_default(),
+            Utc,
+        )
+        .to_rfc2822(),
+    )
+}
+
+/// The author of a commit with an email address, which mail needs. Grit only
+/// records names, so the configured email is used when the name is ours.
+fn get_mail_author(commit_oid: &str) -> Option<String> {
+    let author = plumbing::get_commit_author(commit_oid)?;
+
+    if author.contains('<') {
+        return Some(author);
+    }
+
+    if author == plumbing::get_identity_name() {
+        return Some(plumbing::get_identity());
+    }
+
+    Some(author)
+}
+
+fn format_mail(commit_oid: &str, number: usize, total: usize) -> Option<String> {
+    let message = plumbing::get_commit_message(commit_oid)?;
+    let (subject, body) = message
+        .trim()
+        .split_once('\n')
+        .unwrap_or((message.trim(), ""));
+
+    let subject_prefix = if total > 1 {
+        format!("[PATCH {number}/{total}]")
+    } else {
+        "[PATCH]".to_string()
+    };
+
+    let mut mail = format!("From {commit_oid} {MBOX_DATE}\n")
+        + format!("From: {}\n", get_mail_author(commit_oid)?).as_str()
+        + format!("Date: {}\n", get_mail_date(commit_oid)?).as_str()
+        + format!("Subject: {subject_prefix} {}\n\n", subject.trim()).as_str();
+
+    if !body.trim().is_empty() {
+        mail += format!("{}\n\n", body.trim()).as_str();
+    }
+
+    let parent_oids = plumbing::get_commit_parents(commit_oid)?;
+    let (_parent_map, _parent_paths, parent_entries, _parent_oids) =
+        plumbing::get_head_tree_entries(parent_oids.first().map_or("", |oid| oid.as_str()))?;
+    let (_commit_map, _commit_paths, commit_entries, _commit_oids) =
+        plumbing::get_head_tree_entries(commit_oid)?;
+
+    let changes = diff::get_changed_paths(
+        &merge::get_entries_by_path(&parent_entries),
+        &merge::get_entries_by_path(&commit_entries),
+    );
+
+    mail += "---\n";
+
+    if !changes.is_empty() {
+        mail += diff::format_stat(&changes)?.as_str();
+        mail += "\n";
+        mail += diff::format_patch(&changes)?.as_str();
+    }
+
+    mail += format!("-- \ngrit {}\n\n", env!("CARGO_PKG_VERSION")).as_str();
+
+    Some(mail)
+}
+
+/// Writes each commit in a range as a mail in mbox format, numbered `[PATCH n/m]`,
+/// for sending to a mailing list and applying with `grit am`.
+pub fn format_patch(range: &str, options: &GritFormatPatchOptions) -> Option<()> {
+    let commits = get_commits_in_range(range)?;
+
+    let output_directory = options.output_directory.as_deref().unwrap_or(".");
+
+    if !options.stdout {
+        if let Err(e) = fs::create_dir_all(output_directory) {
+            println!("Failed to create directory {}: {}", output_directory, e);
+            return None;
+        }
+    }
+
+    for (i, commit_oid) in commits.iter().enumerate() {
+        let mail = format_mail(commit_oid, i + 1, commits.len())?;
+
+        if options.stdout {
+            print!("{mail}");
+            continue;
+        }
+
+        let message = plumbing::get_commit_message(commit_oid)?;
+        let subject = message.trim().lines().next().unwrap_or("");
+        let filename = format!("{:04}-{}.patch", i + 1, get_patch_slug(subject));
+
+        let filepath = match options.output_directory {
+            Some(_) => path::Path::new(output_directory).join(filename),
+            None => path::PathBuf::from(filename),
+        };
+
+        if let Err(e) = fs::write(&filepath, mail) {
+            println!("Failed to write {}: {}", filepath.display(), e);
+            return None;
+        }
+
+        println!("{}", filepath.display());
+    }
+
+    Some(())
+}
+
+/// A patch mail split into the commit it describes and the diff that makes it.
+struct GritMail {
+    author: String,
+    message: String,
+    patch: String,
+}
+
+/// Drops the `[PATCH n/m]` and `Re:` prefixes mail adds to a subject.
+fn clean_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+
+    loop {
+        if let Some(rest) = subject.strip_prefix("Re:").or(subject.strip_prefix("RE:")) {
+            subject = rest.trim_start();
+        } else if subject.starts_with('[') {
+            match subject.find(']') {
+                Some(end) => subject = subject[end + 1..].trim_start(),
+                None => return subject.to_string(),
+            }
+        } else {
+            return subject.to_string();
+        }
+    }
+}
+
+/// The name in a `Name <email>` address, unquoted, or the address when there is no name.
+fn get_author_name(address: &str) -> String {
+    let address = address.trim();
+
+    match address.split_once('<') {
+        Some((name, _email)) if !name.trim().is_empty() => {
+            name.trim().trim_matches('"').to_string()
+        }
+        Some((_name, email)) => email.trim_end_matches('>').to_string(),
+        None => address.to_string(),
+    }
+}
+
+fn parse_mail(mail: &str) -> GritMail {
+    let (header_text, rest) = mail.split_once("\n\n").unwrap_or((mail, ""));
+    let mut headers: Vec<(String, String)> = vec![];
+
+    for line in header_text.lines() {
+        if line.starts_with("From ") && headers.is_empty() {
+            continue;
+        }
+
+        // Long headers carry on over lines that start with whitespace
+        if line.starts_with([' ', '\t']) {
+            if let Some((_name, value)) = headers.last_mut() {
+                *value += format!(" {}", line.trim()).as_str();
+            }
+            continue;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let get_header = |name: &str| {
+        headers
+            .iter()
+            .find(|(header, _value)| header == name)
+            .map(|(_header, value)| value.to_string())
+            .unwrap_or_default()
+    };
+
+    // The message ends at the `---` before the diffstat, or at the diff itself
+    let mut body = vec![];
+    let mut patch_start = rest.len();
+    let mut offset = 0;
+
+    for line in rest.split_inclusive('\n') {
+        let text = line.trim_end_matches('\n');
+
+        if text == "---" || text.starts_with("diff -") || text.starts_with("Index: ") {
+            patch_start = offset;
+            break;
+        }
+
+        body.push(text);
+        offset += line.len();
+    }
+
+    let body = body.join("\n");
+    let subject = clean_subject(&get_header("subject"));
+
+    let message = if body.trim().is_empty() {
+        subject
+    } else {
+        format!("{subject}\n\n{}", body.trim())
+    };
+
+    GritMail {
+        author: get_author_name(&get_header("from")),
+        message,
+        patch: rest[patch_start..].to_string(),
+    }
+}
+
+/// Whether a line starts a new message in an mbox: the exact
+/// `From <oid> <date>` line `format_mail` writes. A body paragraph that merely
+/// starts with "From " is never taken for one.
+fn is_mbox_separator(line: &str) -> bool {
+    line.trim_end()
+        .strip_prefix("From ")
+        .and_then(|rest| rest.strip_suffix(MBOX_DATE))
+        .and_then(|oid| oid.strip_suffix(' '))
+        .is_some_and(|oid| oid.len() == 40 && oid.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn split_mbox(mbox: &str) -> Vec<String> {
+    let mut mails: Vec<String> = vec![];
+    let mut current = String::new();
+
+    for line in mbox.split_inclusive('\n') {
+        if is_mbox_separator(line) && !current.is_empty() {
+            mails.push(current);
+            current = String::new();
+        }
+
+        current += line;
+    }
+
+    if !current.trim().is_empty() {
+        mails.push(current);
+    }
+
+    mails
+}
+
+fn get_am_path(filename: &str) -> String {
+    format!("{GRIT_DIRECTORY}/rebase-apply/{filename}")
+}
+
+fn read_am_state(filename: &str) -> Option<String> {
+    Some(
+        file_handling::read_file(&get_am_path(filename))?
+            .trim_end()
+            .to_string(),
+    )
+}
+
+pub fn am_in_progress() -> bool {
+    path::Path::new(&get_am_path("next")).exists()
+}
+
+fn remove_am_state() -> Option<()> {
+    merge::clear_unmerged_paths()?;
+
+    let am_dir_path = format!("{GRIT_DIRECTORY}/rebase-apply");
+
+    match fs::remove_dir_all(&am_dir_path) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to remove {}: {}", am_dir_path, e);
+            None
+        }
+    }
+}
+
+/// Commits the index as the patch currently being applied.
+fn commit_current_patch(author: &str, message: &str) -> Option<()> {
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+
+    let (_index_tree_map, _index_paths, index_entries, _index_oids) =
+        plumbing::get_index_entries()?;
+    let tree_oid = plumbing::write_tree_from_entries(&index_entries)?;
+
+    let parent_oids = if head_oid.is_empty() {
+        vec![]
+    } else {
+        vec![head_oid]
+    };
+
+    let commit_oid = plumbing::commit_tree_with_author(&tree_oid, message, &parent_oids, author)?;
+    let subject = message.lines().next().unwrap_or("");
+
+    plumbing::update_head(&commit_oid, &format!("am: {subject}"))
+}
+
+/// Falls back on the blobs a patch was made against, recorded in its `index`
+/// lines, and merges the patched versions of them into HEAD.
+fn apply_three_way(
+    head_entries: &HashMap<String, String>,
+    file_patches: &[GritFilePatch],
+    subject: &str,
+) -> Option<Option<GritMergeResult>> {
+    println!("Using index info to reconstruct a base tree...");
+
+    let mut base_entries = head_entries.clone();
+
+    for file_patch in file_patches {
+        if let Some(new_path) = &file_patch.new_path {
+            if file_patch.old_path.is_none() {
+                base_entries.remove(new_path);
+            }
+        }
+
+        let old_path = match &file_patch.old_path {
+            Some(old_path) => old_path,
+            None => continue,
+        };
+
+        let old_oid = file_patch
+            .old_oid
+            .as_deref()
+            .and_then(plumbing::find_object_oid);
+
+        let old_oid = match old_oid {
+            Some(old_oid) => old_oid,
+            None => {
+                println!("error: repository lacks the necessary blob to perform 3-way merge.");
+                return Some(None);
+            }
+        };
+
+        let mode = file_patch.old_mode.as_deref().unwrap_or("100644");
+        base_entries.insert(
+            old_path.to_string(),
+            format!("{mode} blob {old_oid} {old_path}"),
+        );
+    }
+
+    let theirs_entries = match patch::apply_to_entries(&base_entries, file_patches)? {
+        Some(theirs_entries) => theirs_entries,
+        None => {
+            println!("error: the patch does not apply to the blobs it was made against");
+            return Some(None);
+        }
+    };
+
+    println!("Falling back to patching base and 3-way merge...");
+
+    let merge_result = merge::merge_trees(
+        &base_entries,
+        head_entries,
+        &theirs_entries,
+        "HEAD",
+        subject,
+    )?;
+
+    Some(Some(merge_result))
+}
+
+fn stop_am(number: usize, subject: &str) -> Option<()> {
+    let report = format!("Patch failed at {number:04} {subject}")
+        + "\nhint: when you have resolved this problem, run `grit am --continue`."
+        + "\nhint: if you prefer to skip this patch, run `grit am --skip` instead."
+        + "\nhint: to restore the original branch and stop patching, run `grit am --abort`.";
+
+    println!("{report}");
+
+    None
+}
+
+/// Applies the remaining patches in order, stopping at the first that fails,
+/// which counts as `am` failing until it is resumed.
+fn run_am() -> Option<()> {
+    let last = read_am_state("last")?.parse::<usize>().unwrap_or(0);
+    let three_way = path::Path::new(&get_am_path("threeway")).exists();
+
+    loop {
+        let next = read_am_state("next")?.parse::<usize>().unwrap_or(last + 1);
+
+        if next > last {
+            return remove_am_state();
+        }
+
+        let mail = parse_mail(&file_handling::read_file(&get_am_path(&format!(
+            "{next:04}"
+        )))?);
+        let subject = mail.message.lines().next().unwrap_or("").to_string();
+
+        file_handling::write_file(&get_am_path("author"), &mail.author)?;
+        file_handling::write_file(&get_am_path("final-commit"), &mail.message)?;
+
+        println!("Applying: {subject}");
+
+        let file_patches = patch::parse_patch(&mail.patch)?;
+
+        if file_patches.is_empty() {
+            println!("Patch is empty.");
+            return stop_am(next, &subject);
+        }
+
+        let head_oid = plumbing::get_head_oid()?.trim().to_string();
+        let (_head_map, _head_paths, head_entries, _head_oids) =
+            plumbing::get_head_tree_entries(&head_oid)?;
+        let head_entries = merge::get_entries_by_path(&head_entries);
+
+        let merge_result = match patch::apply_to_entries(&head_entries, &file_patches)? {
+            Some(entries) => Some(GritMergeResult {
+                entries: entries.into_values().collect(),
+                conflicts: HashMap::new(),
+            }),
+            None if three_way => apply_three_way(&head_entries, &file_patches, &subject)?,
+            None => None,
+        };
+
+        let merge_result = match merge_result {
+            Some(merge_result) => merge_result,
+            None => return stop_am(next, &subject),
+        };
+
+        let mut merge_result = merge_result;
+        merge_result
+            .entries
+            .sort_by_key(|entry| plumbing::get_entry_path(entry));
+
+        merge::write_merge_result(&head_entries, &merge_result)?;
+
+        if !merge_result.conflicts.is_empty() {
+            let mut conflicted_paths = merge_result
+                .conflicts
+                .keys()
+                .cloned()
+                .collect::<Vec<String>>();
+            conflicted_paths.sort();
+
+            for path in &conflicted_paths {
+                println!("CONFLICT (content): Merge conflict in {path}");
+            }
+
+            merge::save_unmerged_paths(&conflicted_paths)?;
+
+            return stop_am(next, &subject);
+        }
+
+        commit_current_patch(&mail.author, &mail.message)?;
+
+        file_handling::write_file(&get_am_path("next"), &(next + 1).to_string())?;
+    }
+}
+
+/// Applies the patches in one or more mbox files as commits on the current
+/// branch, keeping the author and message of each. Reads stdin without files.
+pub fn am(mbox_paths: &[String], three_way: bool) -> Option<()> {
+    if am_in_progress() {
+        println!("previous rebase-apply directory {GRIT_DIRECTORY}/rebase-apply still exists\n  (use `grit am --continue`, `--skip` or `--abort`)");
+        return None;
+    }
+
+    if merge::merge_in_progress()
+        || rebase::rebase_in_progress()
+        || sequencer::sequencer_in_progress()
+    {
+        println!("A merge, rebase, cherry-pick or revert is in progress, finish it before applying patches");
+        return None;
+    }
+
+    if !plumbing::get_local_changes()?.is_empty() {
+        println!("Cannot apply patches: You have local changes.\n  (commit your changes or stash them to proceed)");
+        return None;
+    }
+
+    let mut mails = vec![];
+
+    if mbox_paths.is_empty() {
+        let mut mbox = String::new();
+
+        if let Err(e) = io::stdin().read_to_string(&mut mbox) {
+            println!("Failed to read patches from stdin: {}", e);
+            return None;
+        }
+
+        mails.extend(split_mbox(&mbox));
+    }
+
+    for mbox_path in mbox_paths {
+        mails.extend(split_mbox(&file_handling::read_file(mbox_path)?));
+    }
+
+    if mails.is_empty() {
+        println!("Patch format detection failed.");
+        return None;
+    }
+
+    if let Err(e) = fs::create_dir_all(format!("{GRIT_DIRECTORY}/rebase-apply")) {
+        println!("Failed to create am state directory: {}", e);
+        return None;
+    }
+
+    for (i, mail) in mails.iter().enumerate() {
+        file_handling::write_file(&get_am_path(&format!("{:04}", i + 1)), mail)?;
+    }
+
+    file_handling::write_file(&get_am_path("orig-head"), plumbing::get_head_oid()?.trim())?;
+    file_handling::write_file(&get_am_path("last"), &mails.len().to_string())?;
+    file_handling::write_file(&get_am_path("next"), "1")?;
+
+    if three_way {
+        file_handling::write_file(&get_am_path("threeway"), "")?;
+    }
+
+    run_am()
+}
+
+/// Commits the patch `am` stopped at, once its changes have been made and added by hand.
+pub fn am_continue() -> Option<()> {
+    if !am_in_progress() {
+        println!("Resolve operation not in progress, we are not resuming.");
+        return None;
+    }
+
+    let unresolved = merge::get_unresolved_paths()?;
+    if !unresolved.is_empty() {
+        println!(
+            "You must edit all merge conflicts and then mark them as resolved using `grit add`:\n\t{}",
+            unresolved.join("\n\t")
+        );
+        return None;
+    }
+
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    let (_index_tree_map, _index_paths, index_entries, _index_oids) =
+        plumbing::get_index_entries()?;
+    let tree_oid = plumbing::write_tree_from_entries(&index_entries)?;
+
+    let head_tree_oid = if head_oid.is_empty() {
+        "".to_string()
+    } else {
+        plumbing::get_commit_tree_oid(&head_oid)?
+    };
+
+    if tree_oid == head_tree_oid {
+        println!("No changes - did you forget to use `grit add`?\nIf there is nothing left to stage, chances are that something else\nalready introduced the same changes; you might want to skip this patch.");
+        return None;
+    }
+
+    let next = read_am_state("next")?.parse::<usize>().unwrap_or(0);
+    let author = read_am_state("author")?;
+    let message = file_handling::read_file(&get_am_path("final-commit"))?;
+
+    println!("Applying: {}", message.lines().next().unwrap_or(""));
+
+    commit_current_patch(&author, &message)?;
+    merge::clear_unmerged_paths()?;
+
+    file_handling::write_file(&get_am_path("next"), &(next + 1).to_string())?;
+
+    run_am()
+}
+
+/// Drops the patch `am` stopped at, along with any changes it left behind, and carries on.
+pub fn am_skip() -> Option<()> {
+    if !am_in_progress() {
+        println!("Resolve operation not in progress, we are not resuming.");
+        return None;
+    }
+
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+
+    if !head_oid.is_empty() {
+        plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&head_oid)?)?;
+    }
+
+    merge::clear_unmerged_paths()?;
+
+    let next = read_am_state("next")?.parse::<usize>().unwrap_or(0);
+    file_handling::write_file(&get_am_path("next"), &(next + 1).to_string())?;
+
+    run_am()
+}
+
+/// Stops applying patches and puts the branch back where it was before `am` started.
+pub fn am_abort() -> Option<()> {
+    if !am_in_progress() {
+        println!("Resolve operation not in progress, we are not resuming.");
+        return None;
+    }
+
+    let original_head_oid = read_am_state("orig-head")?;
+
+    if !original_head_oid.is_empty() {
+        plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&original_head_oid)?)?;
+
+        if plumbing::get_head_oid()?.trim() != original_head_oid {
+            plumbing::update_head(&original_head_oid, "am --abort")?;
+        }
+    }
+
+    remove_am_state()
+}
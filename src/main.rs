@@ -1,8 +1,15 @@
-use grit::plumbing::{GritCatType, GritObjectType};
+use grit::archive::{GritArchiveFormat, GritArchiveOptions};
+use grit::mailbox::GritFormatPatchOptions;
+use grit::patch::GritApplyOptions;
+use grit::plumbing::{GritCatType, GritLsFilesOptions, GritObjectType};
+use grit::remote::GritPushOptions;
+use grit::sequencer::GritSequencerAction;
+use grit::show::GritShowFormat;
 use grit::{self, plumbing, WORKING_DIR};
 use grit::{add, commit, init, rm, status};
 use std::env;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, Read};
+use std::process;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -38,12 +45,20 @@ fn main() {
 
             let mut write = false;
             let mut content = "".to_string();
+            let mut filepath = None;
 
             if !parameters.contains(&"--stdin".to_string()) && parameters.len() < 2 {
                 println!("Please provide either a filename or use --stdin to read the content from stdin: grit hash-object [--stdin] <filename>");
                 return;
             } else if !parameters.contains(&"--stdin".to_string()) {
-                let filename = match parameters.iter().find(|&x| x != "-w") {
+                let filename = match parameters
+                    .iter()
+                    .enumerate()
+                    .find(|(i, x)| {
+                        *x != "-w" && *x != "-t" && (*i == 0 || parameters[i - 1] != "-t")
+                    })
+                    .map(|(_i, x)| x)
+                {
                     Some(x) => x.clone(),
                     None => {
                         println!("Please provide either a filename or use --stdin to read the content from stdin: grit hash-object [--stdin] <filename>");
@@ -51,18 +66,11 @@ fn main() {
                     }
                 };
 
-                let filepath = format!("{WORKING_DIR}/{filename}");
-
-                content = match grit::file_handling::read_file(&filepath) {
-                    Some(content) => content,
-                    None => {
-                        println!("Failed to read file");
-                        return;
-                    }
-                };
+                // Files are streamed in when hashed rather than read here
+                filepath = Some(format!("{WORKING_DIR}/{filename}"));
             }
 
-            for parameter in parameters {
+            for parameter in &parameters {
                 match parameter.as_str() {
                     "--stdin" => {
                         let mut buffer = String::new();
@@ -77,7 +85,26 @@ fn main() {
                 }
             }
 
-            let oid = match plumbing::hash_object(&content, GritObjectType::Blob, write) {
+            let object_type = match parameters.iter().position(|x| x == "-t") {
+                Some(position) => match parameters
+                    .get(position + 1)
+                    .and_then(|x| GritObjectType::parse(x))
+                {
+                    Some(object_type) => object_type,
+                    None => {
+                        println!("Please provide a valid object type: grit hash-object -t <blob|tree|commit|tag>");
+                        return;
+                    }
+                },
+                None => GritObjectType::Blob,
+            };
+
+            let oid = match &filepath {
+                Some(filepath) => plumbing::hash_file_object(filepath, object_type, write),
+                None => plumbing::hash_object(&content, object_type, write),
+            };
+
+            let oid = match oid {
                 Some(oid) => oid,
                 None => {
                     println!("Failed to hash object");
@@ -125,26 +152,960 @@ fn main() {
                 return;
             }
 
-            let oid = &args[3];
+            let oid = match plumbing::rev_parse(&args[3]) {
+                Some(oid) => oid,
+                None => return,
+            };
             let type_or_pretty = args[2].as_str();
             let cat_type: GritCatType = match type_or_pretty {
                 "-t" => GritCatType::Type,
                 "-p" => GritCatType::Pretty,
                 "-s" => GritCatType::Size,
-                _ => GritCatType::Type,
+                object_type => match GritObjectType::parse(object_type) {
+                    Some(object_type) => GritCatType::Raw(object_type),
+                    None => GritCatType::Type,
+                },
             };
 
-            plumbing::cat_file(oid, cat_type);
+            if plumbing::cat_file(&oid, cat_type).is_none() {
+                process::exit(1);
+            }
         }
         "commit" => {
+            let no_verify = args[2..]
+                .iter()
+                .any(|parameter| parameter == "--no-verify" || parameter == "-n");
+            let message = args[2..]
+                .iter()
+                .find(|parameter| *parameter != "--no-verify" && *parameter != "-n");
+
+            let message = match message {
+                Some(message) => message,
+                None => {
+                    println!("Please provide the message: grit commit [--no-verify] <message>");
+                    return;
+                }
+            };
+
+            if commit(message, no_verify).is_none() {
+                process::exit(1);
+            }
+        }
+        "prune" => {
+            let parameters = Vec::from(&args[2..]);
+
+            let mut dry_run = false;
+            let mut expire = None;
+
+            let mut parameters_iter = parameters.iter();
+            while let Some(parameter) = parameters_iter.next() {
+                match parameter.as_str() {
+                    "-n" | "--dry-run" => dry_run = true,
+                    "--expire" => match parameters_iter.next() {
+                        Some(value) => expire = Some(value.clone()),
+                        None => {
+                            println!("Please provide an expiry: grit prune [--dry-run] [--expire <time>]");
+                            return;
+                        }
+                    },
+                    _ => match parameter.strip_prefix("--expire=") {
+                        Some(value) => expire = Some(value.to_string()),
+                        None => {
+                            println!("Unknown option {parameter}: grit prune [--dry-run] [--expire <time>]");
+                            return;
+                        }
+                    },
+                }
+            }
+
+            if grit::gc::prune(dry_run, expire.as_deref()).is_none() {
+                process::exit(1);
+            }
+        }
+        "count-objects" => {
+            let verbose = args[2..]
+                .iter()
+                .any(|arg| arg == "-v" || arg == "--verbose");
+
+            if grit::gc::count_objects(verbose).is_none() {
+                process::exit(1);
+            }
+        }
+        "rev-parse" => {
             if args.len() < 3 {
-                println!("Please provide the message: grit commit <message>");
+                println!("Please provide a revision: grit rev-parse <rev>");
                 return;
             }
 
-            let message = &args[2];
+            if let Some(oid) = plumbing::rev_parse(&args[2]) {
+                println!("{oid}");
+            }
+        }
+        "reflog" => {
+            let subcommand = match args.get(2) {
+                Some(subcommand) => subcommand.as_str(),
+                None => "show",
+            };
+
+            match subcommand {
+                "expire" => {
+                    let mut expire = None;
+                    let mut all = false;
+                    let mut ref_names = vec![];
+
+                    for parameter in &args[3..] {
+                        match parameter.as_str() {
+                            "--all" => all = true,
+                            _ => match parameter.strip_prefix("--expire=") {
+                                Some(value) => expire = Some(value.to_string()),
+                                None => match plumbing::resolve_ref_name(parameter) {
+                                    Some(ref_name) => ref_names.push(ref_name),
+                                    None => {
+                                        println!("Unknown ref {parameter}");
+                                        process::exit(1);
+                                    }
+                                },
+                            },
+                        }
+                    }
+
+                    if all {
+                        ref_names = grit::reflog::get_all_reflogs();
+                    }
 
-            commit(message);
+                    if ref_names.is_empty() {
+                        println!("Please provide the refs to expire: grit reflog expire [--expire=<time>] [--all | <refs>]");
+                        return;
+                    }
+
+                    if grit::reflog::expire_reflogs(&ref_names, expire.as_deref()).is_none() {
+                        process::exit(1);
+                    }
+                }
+                "delete" => {
+                    if args.len() < 4 {
+                        println!("Please provide the entries to delete: grit reflog delete <ref>@{{<n>}}");
+                        return;
+                    }
+
+                    if grit::reflog::delete_reflog_entries(&args[3..]).is_none() {
+                        process::exit(1);
+                    }
+                }
+                "show" => {
+                    let name = match args.get(3) {
+                        Some(name) => name.as_str(),
+                        None => "HEAD",
+                    };
+
+                    if grit::reflog::show_reflog(name).is_none() {
+                        process::exit(1);
+                    }
+                }
+                name => {
+                    if grit::reflog::show_reflog(name).is_none() {
+                        process::exit(1);
+                    }
+                }
+            }
+        }
+        "stash" => {
+            let subcommand = match args.get(2) {
+                Some(subcommand) => subcommand.as_str(),
+                None => "push",
+            };
+
+            let parameters = if args.len() > 3 {
+                Vec::from(&args[3..])
+            } else {
+                vec![]
+            };
+
+            let stash = parameters
+                .iter()
+                .find(|parameter| !parameter.starts_with('-'))
+                .map(|stash| stash.as_str());
+            let restore_index = parameters.contains(&"--index".to_string());
+            let include_untracked = parameters
+                .iter()
+                .any(|parameter| parameter == "-u" || parameter == "--include-untracked");
+
+            match subcommand {
+                "push" => {
+                    let mut message = None;
+                    let mut pathspecs = vec![];
+
+                    let mut parameters_iter = parameters.iter();
+                    while let Some(parameter) = parameters_iter.next() {
+                        match parameter.as_str() {
+                            "-m" | "--message" => match parameters_iter.next() {
+                                Some(value) => message = Some(value.clone()),
+                                None => {
+                                    println!("Please provide a message: grit stash push [-m <message>] [-u] [--] [<pathspec>...]");
+                                    return;
+                                }
+                            },
+                            "-u" | "--include-untracked" | "--" => (),
+                            _ => pathspecs.push(parameter.clone()),
+                        }
+                    }
+
+                    if grit::stash::stash_push(message.as_deref(), &pathspecs, include_untracked)
+                        .is_none()
+                    {
+                        process::exit(1);
+                    }
+                }
+                "apply" => {
+                    if grit::stash::stash_apply(stash, restore_index).is_none() {
+                        process::exit(1);
+                    }
+                }
+                "pop" => {
+                    if grit::stash::stash_pop(stash, restore_index).is_none() {
+                        process::exit(1);
+                    }
+                }
+                "drop" => {
+                    if grit::stash::stash_drop(stash).is_none() {
+                        process::exit(1);
+                    }
+                }
+                "list" => {
+                    if grit::stash::stash_list().is_none() {
+                        process::exit(1);
+                    }
+                }
+                "show" => {
+                    if grit::stash::stash_show(stash, include_untracked).is_none() {
+                        process::exit(1);
+                    }
+                }
+                _ => {
+                    println!("Unknown stash subcommand: grit stash [push|pop|apply|list|drop|show]")
+                }
+            }
+        }
+        "cherry-pick" | "revert" => {
+            let action = if command == "revert" {
+                GritSequencerAction::Revert
+            } else {
+                GritSequencerAction::Pick
+            };
+
+            if args.len() < 3 {
+                println!(
+                    "Please provide the commits: grit {command} [--continue | --abort] <commits>"
+                );
+                return;
+            }
+
+            match args[2].as_str() {
+                "--continue" => {
+                    if grit::sequencer::sequencer_continue(action).is_none() {
+                        process::exit(1);
+                    }
+                }
+                "--abort" => {
+                    if grit::sequencer::sequencer_abort(action).is_none() {
+                        process::exit(1);
+                    }
+                }
+                _ => {
+                    if grit::sequencer::sequencer_start(action, &args[2..]).is_none() {
+                        process::exit(1);
+                    }
+                }
+            }
+        }
+        "am" => {
+            let mut three_way = false;
+            let mut mbox_paths = vec![];
+
+            for parameter in &args[2..] {
+                match parameter.as_str() {
+                    "--continue" | "--resolved" => {
+                        if grit::mailbox::am_continue().is_none() {
+                            process::exit(1);
+                        }
+                        return;
+                    }
+                    "--skip" => {
+                        if grit::mailbox::am_skip().is_none() {
+                            process::exit(1);
+                        }
+                        return;
+                    }
+                    "--abort" => {
+                        if grit::mailbox::am_abort().is_none() {
+                            process::exit(1);
+                        }
+                        return;
+                    }
+                    "-3" | "--3way" => three_way = true,
+                    _ => mbox_paths.push(parameter.to_string()),
+                }
+            }
+
+            if grit::mailbox::am(&mbox_paths, three_way).is_none() {
+                process::exit(1);
+            }
+        }
+        "apply" => {
+            let mut options = GritApplyOptions {
+                cached: false,
+                check: false,
+                reject: false,
+            };
+            let mut patch_path = None;
+
+            for parameter in &args[2..] {
+                match parameter.as_str() {
+                    "--cached" => options.cached = true,
+                    "--check" => options.check = true,
+                    "--reject" => options.reject = true,
+                    _ if patch_path.is_none() => patch_path = Some(parameter.as_str()),
+                    _ => {
+                        println!("Please provide a single patch: grit apply [--cached] [--check] [--reject] [<patch>]");
+                        process::exit(1);
+                    }
+                }
+            }
+
+            if grit::patch::apply(patch_path, &options).is_none() {
+                process::exit(1);
+            }
+        }
+        "archive" => {
+            let mut options = GritArchiveOptions {
+                format: None,
+                prefix: "".to_string(),
+                output: None,
+            };
+            let mut tree_ish = None;
+            let mut paths = vec![];
+            let mut parameters = args[2..].iter();
+
+            while let Some(parameter) = parameters.next() {
+                if let Some(format) = parameter.strip_prefix("--format=") {
+                    match GritArchiveFormat::parse(format) {
+                        Some(format) => options.format = Some(format),
+                        None => process::exit(1),
+                    }
+                } else if let Some(prefix) = parameter.strip_prefix("--prefix=") {
+                    options.prefix = prefix.to_string();
+                } else if let Some(output) = parameter.strip_prefix("--output=") {
+                    options.output = Some(output.to_string());
+                } else if parameter == "-o" || parameter == "--output" {
+                    options.output = parameters.next().cloned();
+                } else if tree_ish.is_none() {
+                    tree_ish = Some(parameter.to_string());
+                } else {
+                    paths.push(parameter.to_string());
+                }
+            }
+
+            let tree_ish = match tree_ish {
+                Some(tree_ish) => tree_ish,
+                None => {
+                    eprintln!("Please provide a tree-ish: grit archive [--format=tar|tar.gz|zip] [--prefix=<dir>/] [-o <file>] <tree-ish> [<path>...]");
+                    process::exit(1);
+                }
+            };
+
+            if grit::archive::archive(&tree_ish, &paths, &options).is_none() {
+                process::exit(1);
+            }
+        }
+        "bundle" => {
+            if args.len() < 4 {
+                println!("Please provide a subcommand and a file: grit bundle create <file> <rev>... | verify <file> | list-heads <file> [<ref>...]");
+                return;
+            }
+
+            let bundled = match args[2].as_str() {
+                "create" if args.len() < 5 => {
+                    println!("Please provide the revisions to bundle: grit bundle create <file> <rev>...");
+                    return;
+                }
+                "create" => grit::bundle::create(&args[3], &args[4..]),
+                "verify" => grit::bundle::verify(&args[3]),
+                "list-heads" => grit::bundle::list_heads(&args[3], &args[4..]),
+                subcommand => {
+                    println!("Unknown bundle subcommand {subcommand}");
+                    None
+                }
+            };
+
+            if bundled.is_none() {
+                process::exit(1);
+            }
+        }
+        "format-patch" => {
+            let mut options = GritFormatPatchOptions {
+                stdout: false,
+                output_directory: None,
+            };
+            let mut range = None;
+            let mut parameters = args[2..].iter();
+
+            while let Some(parameter) = parameters.next() {
+                if parameter == "--stdout" {
+                    options.stdout = true;
+                } else if parameter == "-o" || parameter == "--output-directory" {
+                    options.output_directory = parameters.next().cloned();
+                } else if let Some(directory) = parameter.strip_prefix("--output-directory=") {
+                    options.output_directory = Some(directory.to_string());
+                } else if range.is_none() {
+                    range = Some(parameter.to_string());
+                } else {
+                    println!("fatal: unrecognized argument: {parameter}");
+                    process::exit(1);
+                }
+            }
+
+            let range = match range {
+                Some(range) => range,
+                None => {
+                    println!("Please provide the commits to format: grit format-patch [--stdout] [-o <dir>] <since>[..<until>] | -<n>");
+                    process::exit(1);
+                }
+            };
+
+            if grit::mailbox::format_patch(&range, &options).is_none() {
+                process::exit(1);
+            }
+        }
+        "clone" => {
+            let local = args[2..].iter().any(|arg| arg == "--local" || arg == "-l");
+            let operands = args[2..]
+                .iter()
+                .filter(|arg| !arg.starts_with('-'))
+                .collect::<Vec<&String>>();
+
+            if operands.is_empty() {
+                println!(
+                    "Please provide the repository: grit clone [--local] <path> [<directory>]"
+                );
+                return;
+            }
+
+            if grit::clone::clone(operands[0], operands.get(1).map(|dir| dir.as_str()), local)
+                .is_none()
+            {
+                process::exit(1);
+            }
+        }
+        "remote" => match args.get(2).map(|arg| arg.as_str()) {
+            None | Some("-v") | Some("--verbose") | Some("list") => {
+                let verbose = args[2..]
+                    .iter()
+                    .any(|arg| arg == "-v" || arg == "--verbose");
+
+                if grit::remote::remote_list(verbose).is_none() {
+                    process::exit(1);
+                }
+            }
+            Some("add") => {
+                if args.len() < 5 {
+                    println!("Please provide the name and url: grit remote add <name> <url>");
+                    return;
+                }
+
+                if grit::remote::remote_add(&args[3], &args[4]).is_none() {
+                    process::exit(1);
+                }
+            }
+            Some("remove") | Some("rm") => {
+                if args.len() < 4 {
+                    println!("Please provide the name: grit remote remove <name>");
+                    return;
+                }
+
+                if grit::remote::remote_remove(&args[3]).is_none() {
+                    process::exit(1);
+                }
+            }
+            Some(_) => {
+                println!("Unknown remote subcommand: grit remote [-v | list | add | remove]")
+            }
+        },
+        "fetch" => {
+            if grit::remote::fetch(args.get(2).map(|arg| arg.as_str())).is_none() {
+                process::exit(1);
+            }
+        }
+        "pull" => {
+            let mut rebase = None;
+            let mut operands = vec![];
+
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--rebase" | "-r" => rebase = Some(true),
+                    "--no-rebase" => rebase = Some(false),
+                    operand => operands.push(operand),
+                }
+            }
+
+            if grit::remote::pull(operands.first().copied(), operands.get(1).copied(), rebase)
+                .is_none()
+            {
+                process::exit(1);
+            }
+        }
+        "push" => {
+            let mut options = GritPushOptions {
+                force: false,
+                force_with_lease: None,
+                set_upstream: false,
+                no_verify: false,
+            };
+            let mut operands = vec![];
+
+            for arg in &args[2..] {
+                match arg.as_str() {
+                    "--force" | "-f" => options.force = true,
+                    "--set-upstream" | "-u" => options.set_upstream = true,
+                    "--no-verify" => options.no_verify = true,
+                    "--force-with-lease" => options.force_with_lease = Some("".to_string()),
+                    arg if arg.starts_with("--force-with-lease=") => {
+                        options.force_with_lease =
+                            Some(arg["--force-with-lease=".len()..].to_string())
+                    }
+                    operand => operands.push(operand.to_string()),
+                }
+            }
+
+            let remote = operands.first().map(|remote| remote.as_str());
+            let refspecs = operands.get(1..).unwrap_or_default();
+
+            if grit::remote::push(remote, refspecs, &options).is_none() {
+                process::exit(1);
+            }
+        }
+        "unpack-objects" => {
+            let mut pack = vec![];
+
+            if let Err(e) = io::stdin().read_to_end(&mut pack) {
+                println!("Failed to read the pack from stdin: {}", e);
+                process::exit(1);
+            }
+
+            match grit::pack::unpack(&pack) {
+                Some(count) => println!("Unpacked {count} objects"),
+                None => process::exit(1),
+            }
+        }
+        "serve" => {
+            let mut port = 8080;
+            let mut directory = None;
+            let mut i = 2;
+
+            while i < args.len() {
+                if args[i] == "--port" && i + 1 < args.len() {
+                    port = match args[i + 1].parse::<u16>() {
+                        Ok(port) => port,
+                        Err(_) => {
+                            println!("Invalid port: {}", args[i + 1]);
+                            return;
+                        }
+                    };
+                    i += 1;
+                } else {
+                    directory = Some(args[i].as_str());
+                }
+                i += 1;
+            }
+
+            if grit::http_backend::serve(directory, port).is_none() {
+                process::exit(1);
+            }
+        }
+        "http-backend" => {
+            if grit::http_backend::http_backend().is_none() {
+                process::exit(1);
+            }
+        }
+        "upload-pack" | "receive-pack" => {
+            if args.len() < 3 {
+                eprintln!("Please provide the repository: grit {command} <directory>");
+                process::exit(1);
+            }
+
+            let served = if command == "upload-pack" {
+                grit::protocol::upload_pack(&args[2])
+            } else {
+                grit::protocol::receive_pack(&args[2])
+            };
+
+            if served.is_none() {
+                process::exit(1);
+            }
+        }
+        "merge" => {
+            if args.len() < 3 {
+                println!("Please provide the commit: grit merge [-m <message>] <commit> | --abort");
+                return;
+            }
+
+            if args[2] == "--abort" {
+                if grit::merge::merge_abort().is_none() {
+                    process::exit(1);
+                }
+                return;
+            }
+
+            let mut message = None;
+            let mut rev = None;
+            let mut i = 2;
+
+            while i < args.len() {
+                if args[i] == "-m" && i + 1 < args.len() {
+                    message = Some(args[i + 1].as_str());
+                    i += 1;
+                } else {
+                    rev = Some(args[i].as_str());
+                }
+                i += 1;
+            }
+
+            match rev {
+                Some(rev) => {
+                    if grit::merge::merge(rev, message).is_none() {
+                        process::exit(1);
+                    }
+                }
+                None => println!("Please provide the commit: grit merge [-m <message>] <commit>"),
+            }
+        }
+        "update-ref" => {
+            let mut reason = String::new();
+            let mut delete = false;
+            let mut no_deref = false;
+            let mut stdin = false;
+            let mut operands = vec![];
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "-m" => {
+                        i += 1;
+                        match args.get(i) {
+                            Some(message) => reason = message.to_string(),
+                            None => {
+                                println!("Please provide the reason: grit update-ref -m <reason> [--no-deref] <ref> <new-oid> [<old-oid>]");
+                                return;
+                            }
+                        }
+                    }
+                    "-d" => delete = true,
+                    "--no-deref" => no_deref = true,
+                    "--stdin" => stdin = true,
+                    operand => operands.push(operand.to_string()),
+                }
+                i += 1;
+            }
+
+            if stdin {
+                let mut commands = String::new();
+
+                if let Err(e) = io::stdin().read_to_string(&mut commands) {
+                    println!("Failed to read commands from stdin: {}", e);
+                    process::exit(1);
+                }
+
+                if grit::refs::update_refs_from_commands(&commands, &reason, no_deref).is_none() {
+                    process::exit(1);
+                }
+
+                return;
+            }
+
+            let result = match (delete, operands.as_slice()) {
+                (true, [ref_name]) => grit::refs::delete_ref_command(ref_name, None, no_deref),
+                (true, [ref_name, old_value]) => {
+                    grit::refs::delete_ref_command(ref_name, Some(old_value), no_deref)
+                }
+                (false, [ref_name, new_value]) => {
+                    grit::refs::update_ref_command(ref_name, new_value, None, &reason, no_deref)
+                }
+                (false, [ref_name, new_value, old_value]) => grit::refs::update_ref_command(
+                    ref_name,
+                    new_value,
+                    Some(old_value),
+                    &reason,
+                    no_deref,
+                ),
+                _ => {
+                    println!("Please provide the ref: grit update-ref [-m <reason>] [--no-deref] (-d <ref> [<old-oid>] | <ref> <new-oid> [<old-oid>])");
+                    return;
+                }
+            };
+
+            if result.is_none() {
+                process::exit(1);
+            }
+        }
+        "migrate-storage" => {
+            let target = match args.get(2) {
+                Some(target) => target,
+                None => {
+                    println!("Please provide the storage to migrate to: grit migrate-storage <files|sqlite>");
+                    return;
+                }
+            };
+
+            if grit::storage::migrate_storage(target).is_none() {
+                process::exit(1);
+            }
+        }
+        "pack-refs" => {
+            let all = args[2..].iter().any(|arg| arg == "--all");
+            let prune = !args[2..].iter().any(|arg| arg == "--no-prune");
+
+            if grit::refs::pack_refs(all, prune).is_none() {
+                process::exit(1);
+            }
+        }
+        "symbolic-ref" => {
+            let mut reason = String::new();
+            let mut short = false;
+            let mut operands = vec![];
+
+            let mut i = 2;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "-m" => {
+                        i += 1;
+                        match args.get(i) {
+                            Some(message) => reason = message.to_string(),
+                            None => {
+                                println!("Please provide the reason: grit symbolic-ref -m <reason> HEAD <ref>");
+                                return;
+                            }
+                        }
+                    }
+                    "--short" => short = true,
+                    operand => operands.push(operand.to_string()),
+                }
+                i += 1;
+            }
+
+            if operands.is_empty() {
+                println!("Please provide the name: grit symbolic-ref [--short] HEAD [<ref>]");
+                return;
+            }
+
+            let result = grit::refs::symbolic_ref(
+                &operands[0],
+                operands.get(1).map(|target| target.as_str()),
+                short,
+                &reason,
+            );
+
+            if result.is_none() {
+                process::exit(1);
+            }
+        }
+        "for-each-ref" => {
+            let mut format = None;
+            let mut sort_keys = vec![];
+            let mut patterns = vec![];
+
+            for parameter in &args[2..] {
+                if let Some(value) = parameter.strip_prefix("--format=") {
+                    format = Some(value);
+                } else if let Some(value) = parameter.strip_prefix("--sort=") {
+                    sort_keys.push(value.to_string());
+                } else {
+                    patterns.push(parameter.to_string());
+                }
+            }
+
+            if grit::refs::for_each_ref(format, &sort_keys, &patterns).is_none() {
+                process::exit(1);
+            }
+        }
+        "check-ref-format" => {
+            let mut allow_onelevel = false;
+            let mut branch = false;
+            let mut ref_name = None;
+
+            for parameter in &args[2..] {
+                match parameter.as_str() {
+                    "--allow-onelevel" => allow_onelevel = true,
+                    "--no-allow-onelevel" => allow_onelevel = false,
+                    "--branch" => branch = true,
+                    name => ref_name = Some(name),
+                }
+            }
+
+            let ref_name = match ref_name {
+                Some(ref_name) => ref_name,
+                None => {
+                    println!("Please provide the ref name: grit check-ref-format [--allow-onelevel] [--branch] <refname>");
+                    return;
+                }
+            };
+
+            if grit::refs::check_ref_format_command(ref_name, allow_onelevel, branch).is_none() {
+                process::exit(1);
+            }
+        }
+        "ls-files" => {
+            let mut options = GritLsFilesOptions {
+                stage: false,
+                modified: false,
+                deleted: false,
+                others: false,
+                ignored: false,
+                null_terminated: false,
+            };
+
+            for parameter in &args[2..] {
+                match parameter.as_str() {
+                    "-s" | "--stage" => options.stage = true,
+                    "-m" | "--modified" => options.modified = true,
+                    "-d" | "--deleted" => options.deleted = true,
+                    "-o" | "--others" => options.others = true,
+                    "-i" | "--ignored" => options.ignored = true,
+                    "-z" => options.null_terminated = true,
+                    _ => {
+                        println!("Unknown option {parameter}: grit ls-files [--stage] [--modified] [--deleted] [--others] [--ignored] [-z]");
+                        return;
+                    }
+                }
+            }
+
+            if plumbing::ls_files(&options).is_none() {
+                process::exit(1);
+            }
+        }
+        "ls-tree" => {
+            let mut recursive = false;
+            let mut name_only = false;
+            let mut long = false;
+            let mut operands = vec![];
+
+            for parameter in &args[2..] {
+                match parameter.as_str() {
+                    "-r" => recursive = true,
+                    "--name-only" => name_only = true,
+                    "-l" | "--long" => long = true,
+                    operand => operands.push(operand.to_string()),
+                }
+            }
+
+            if operands.is_empty() {
+                println!("Please provide the tree: grit ls-tree <tree-ish> [-r] [--name-only] [-l] [<path>...]");
+                return;
+            }
+
+            if plumbing::ls_tree(&operands[0], &operands[1..], recursive, name_only, long).is_none()
+            {
+                process::exit(1);
+            }
+        }
+        "show" => {
+            let mut format = GritShowFormat::Patch;
+            let mut revs = vec![];
+
+            for parameter in &args[2..] {
+                match parameter.as_str() {
+                    "--stat" => format = GritShowFormat::Stat,
+                    "--name-only" => format = GritShowFormat::NameOnly,
+                    rev => revs.push(rev.to_string()),
+                }
+            }
+
+            if grit::show::show(&revs, format).is_none() {
+                process::exit(1);
+            }
+        }
+        "blame" => {
+            if args.len() < 3 {
+                println!(
+                    "Please provide the file: grit blame [-L <start>,<end>] [--porcelain] <file>"
+                );
+                return;
+            }
+
+            let parameters = Vec::from(&args[2..]);
+
+            let mut line_range = None;
+            let mut porcelain = false;
+            let mut path = None;
+
+            let mut i = 0;
+            while i < parameters.len() {
+                match parameters[i].as_str() {
+                    "-L" => {
+                        i += 1;
+                        match parameters.get(i) {
+                            Some(range) => line_range = Some(range.as_str()),
+                            None => {
+                                println!("Please provide the line range: grit blame -L <start>,<end> <file>");
+                                return;
+                            }
+                        }
+                    }
+                    "--porcelain" => porcelain = true,
+                    parameter => match parameter.strip_prefix("-L") {
+                        Some(range) => line_range = Some(range),
+                        None => path = Some(parameter),
+                    },
+                }
+                i += 1;
+            }
+
+            match path {
+                Some(path) => {
+                    if grit::blame::blame(path, line_range, porcelain).is_none() {
+                        process::exit(1);
+                    }
+                }
+                None => println!(
+                    "Please provide the file: grit blame [-L <start>,<end>] [--porcelain] <file>"
+                ),
+            }
+        }
+        "rebase" => {
+            if args.len() < 3 {
+                println!("Please provide the upstream: grit rebase [-i] <upstream> | --continue | --skip | --abort");
+                return;
+            }
+
+            match args[2].as_str() {
+                "--continue" => {
+                    if grit::rebase::rebase_continue().is_none() {
+                        process::exit(1);
+                    }
+                }
+                "--skip" => {
+                    if grit::rebase::rebase_skip().is_none() {
+                        process::exit(1);
+                    }
+                }
+                "--abort" => {
+                    if grit::rebase::rebase_abort().is_none() {
+                        process::exit(1);
+                    }
+                }
+                "-i" | "--interactive" => {
+                    if args.len() < 4 {
+                        println!("Please provide the upstream: grit rebase -i <upstream>");
+                        return;
+                    }
+                    if grit::rebase::rebase(&args[3], true).is_none() {
+                        process::exit(1);
+                    }
+                }
+                upstream => {
+                    if grit::rebase::rebase(upstream, false).is_none() {
+                        process::exit(1);
+                    }
+                }
+            }
         }
         _ => println!("Unknown command"),
     }
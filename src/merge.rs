@@ -0,0 +1,570 @@
+use crate::config::{GRIT_DIRECTORY, WORKING_DIR};
+use crate::diff::{self, GritHunk};
+use crate::file_handling;
+use crate::hooks;
+use crate::plumbing::{self, GritObjectType};
+use crate::refs;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path;
+
+pub struct GritMergeResult {
+    /// Index entries after the merge. Conflicted paths keep our version, or
+    /// are left out when one side deleted them, so that only staging a
+    /// resolution can make them match the working tree again.
+    pub entries: Vec<String>,
+    /// Conflicted paths and the content, with conflict markers, to leave in the working tree.
+    pub conflicts: HashMap<String, String>,
+}
+
+pub fn get_entries_by_path(entries: &[String]) -> HashMap<String, String> {
+    entries
+        .iter()
+        .map(|entry| (plumbing::get_entry_path(entry), entry.to_string()))
+        .collect()
+}
+
+fn get_blob_content(entry: Option<&String>) -> Option<String> {
+    match entry {
+        Some(entry) => plumbing::generate_cat_content(
+            &plumbing::parse_entry(entry)?.oid,
+            plumbing::GritCatType::Pretty,
+        ),
+        None => Some("".to_string()),
+    }
+}
+
+fn ensure_trailing_newline(text: &mut String) {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+}
+
+/// Three-way merges the lines of a file. Returns the merged content and
+/// whether any conflict markers had to be written.
+pub fn merge_file(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    ours_label: &str,
+    theirs_label: &str,
+) -> (String, bool) {
+    let base_lines = diff::split_lines(base);
+    let sides = [diff::split_lines(ours), diff::split_lines(theirs)];
+
+    let mut hunks = diff::diff_lines(base, ours)
+        .into_iter()
+        .map(|hunk| (0, hunk))
+        .chain(
+            diff::diff_lines(base, theirs)
+                .into_iter()
+                .map(|hunk| (1, hunk)),
+        )
+        .collect::<Vec<(usize, GritHunk)>>();
+    hunks.sort_by_key(|(side, hunk)| (hunk.before.start, hunk.before.end, *side));
+
+    let mut merged = String::new();
+    let mut conflicted = false;
+    let mut base_position = 0;
+    // How far each side's line numbers have drifted from the base so far
+    let mut offsets = [0isize, 0isize];
+
+    let mut i = 0;
+    while i < hunks.len() {
+        let group_start = hunks[i].1.before.start;
+        let mut group_end = hunks[i].1.before.end;
+
+        // Changes that overlap or touch are resolved together
+        let mut j = i + 1;
+        while j < hunks.len() && hunks[j].1.before.start <= group_end {
+            group_end = group_end.max(hunks[j].1.before.end);
+            j += 1;
+        }
+
+        merged.push_str(&base_lines[base_position..group_start].concat());
+
+        let mut side_texts: [Option<String>; 2] = [None, None];
+
+        for side in 0..2 {
+            let group_hunks = hunks[i..j]
+                .iter()
+                .filter(|(hunk_side, _hunk)| *hunk_side == side)
+                .map(|(_hunk_side, hunk)| hunk)
+                .collect::<Vec<&GritHunk>>();
+
+            if group_hunks.is_empty() {
+                continue;
+            }
+
+            let growth = group_hunks
+                .iter()
+                .map(|hunk| hunk.after.len() as isize - hunk.before.len() as isize)
+                .sum::<isize>();
+
+            let start = (group_start as isize + offsets[side]) as usize;
+            let end = (group_end as isize + offsets[side] + growth) as usize;
+
+            side_texts[side] = Some(sides[side][start..end].concat());
+            offsets[side] += growth;
+        }
+
+        match side_texts {
+            [Some(ours_text), Some(theirs_text)] if ours_text != theirs_text => {
+                let mut ours_text = ours_text;
+                let mut theirs_text = theirs_text;
+                ensure_trailing_newline(&mut merged);
+                ensure_trailing_newline(&mut ours_text);
+                ensure_trailing_newline(&mut theirs_text);
+
+                merged.push_str(&format!("<<<<<<< {ours_label}\n"));
+                merged.push_str(&ours_text);
+                merged.push_str("=======\n");
+                merged.push_str(&theirs_text);
+                merged.push_str(&format!(">>>>>>> {theirs_label}\n"));
+                conflicted = true;
+            }
+            [Some(text), _] | [None, Some(text)] => merged.push_str(&text),
+            [None, None] => (),
+        }
+
+        base_position = group_end;
+        i = j;
+    }
+
+    merged.push_str(&base_lines[base_position..].concat());
+
+    (merged, conflicted)
+}
+
+/// Three-way merges two trees, given as `mode type oid path` entries keyed by path.
+pub fn merge_trees(
+    base: &HashMap<String, String>,
+    ours: &HashMap<String, String>,
+    theirs: &HashMap<String, String>,
+    ours_label: &str,
+    theirs_label: &str,
+) -> Option<GritMergeResult> {
+    let mut entries = vec![];
+    let mut conflicts = HashMap::new();
+
+    let paths = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect::<BTreeSet<&String>>();
+
+    for path in paths {
+        let base_entry = base.get(path);
+        let ours_entry = ours.get(path);
+        let theirs_entry = theirs.get(path);
+
+        if ours_entry == theirs_entry || base_entry == theirs_entry {
+            entries.extend(ours_entry.cloned());
+            continue;
+        }
+
+        if base_entry == ours_entry {
+            entries.extend(theirs_entry.cloned());
+            continue;
+        }
+
+        // Both sides changed the path in different ways
+        let base_content = get_blob_content(base_entry)?;
+        let ours_content = get_blob_content(ours_entry)?;
+        let theirs_content = get_blob_content(theirs_entry)?;
+
+        if ours_entry.is_none() || theirs_entry.is_none() {
+            // Modified on one side and deleted on the other. The modified
+            // version goes in the working tree but not the index, until the
+            // user stages it or deletes the file.
+            let kept_content = if ours_entry.is_some() {
+                ours_content
+            } else {
+                theirs_content
+            };
+
+            conflicts.insert(path.to_string(), kept_content);
+            continue;
+        }
+
+        let (merged_content, conflicted) = merge_file(
+            &base_content,
+            &ours_content,
+            &theirs_content,
+            ours_label,
+            theirs_label,
+        );
+
+        if conflicted {
+            entries.extend(ours_entry.cloned());
+            conflicts.insert(path.to_string(), merged_content);
+            continue;
+        }
+
+        let merged_oid = plumbing::hash_object(&merged_content, GritObjectType::Blob, true)?;
+        let mode = plumbing::parse_entry(ours_entry?)?.mode;
+
+        entries.push(format!("{} blob {merged_oid} {path}", mode.value()));
+    }
+
+    entries.sort_by_key(|entry| plumbing::get_entry_path(entry));
+
+    Some(GritMergeResult { entries, conflicts })
+}
+
+/// Writes a merge result into the index and the working tree, which are
+/// expected to match `ours` beforehand.
+pub fn write_merge_result(
+    ours: &HashMap<String, String>,
+    merge_result: &GritMergeResult,
+) -> Option<()> {
+    let merged = get_entries_by_path(&merge_result.entries);
+
+    for (path, merged_entry) in &merged {
+        if ours.get(path) != Some(merged_entry) {
+            plumbing::checkout_file(path, &plumbing::parse_entry(merged_entry)?.oid)?;
+        }
+    }
+
+    for path in ours.keys() {
+        if !merged.contains_key(path) {
+            plumbing::remove_working_file(path)?;
+        }
+    }
+
+    for (path, content) in &merge_result.conflicts {
+        let file_path = format!("{WORKING_DIR}/{path}");
+
+        // The path may be new to the working tree, directories and all
+        let written = match path::Path::new(&file_path).parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|_| fs::write(&file_path, content));
+
+        match written {
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to write file {}: {}", path, e);
+                return None;
+            }
+        }
+    }
+
+    plumbing::write_index(&merge_result.entries)
+}
+
+fn get_unmerged_file_path() -> String {
+    format!("{GRIT_DIRECTORY}/UNMERGED")
+}
+
+/// Remembers which paths were left conflicted until the merge is concluded.
+pub fn save_unmerged_paths(paths: &[String]) -> Option<()> {
+    match fs::write(get_unmerged_file_path(), paths.join("\n")) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to record conflicted paths: {}", e);
+            None
+        }
+    }
+}
+
+pub fn clear_unmerged_paths() -> Option<()> {
+    if !path::Path::new(&get_unmerged_file_path()).exists() {
+        return Some(());
+    }
+
+    match fs::remove_file(get_unmerged_file_path()) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to clear conflicted paths: {}", e);
+            None
+        }
+    }
+}
+
+/// Conflicted paths that still contain markers or have not been staged with `grit add`.
+pub fn get_unresolved_paths() -> Option<Vec<String>> {
+    if !path::Path::new(&get_unmerged_file_path()).exists() {
+        return Some(vec![]);
+    }
+
+    let unmerged = file_handling::read_file(&get_unmerged_file_path())?;
+    let (index_tree_map, _index_paths, _index_entries, _index_oids) =
+        plumbing::get_index_entries()?;
+
+    let unresolved = unmerged
+        .lines()
+        .filter(|path| !path.is_empty())
+        .filter(|path| {
+            let has_markers = match fs::read_to_string(format!("{WORKING_DIR}/{path}")) {
+                Ok(content) => content
+                    .lines()
+                    .any(|line| line.starts_with("<<<<<<< ") || line.starts_with(">>>>>>> ")),
+                Err(_) => false,
+            };
+
+            has_markers
+                || plumbing::get_working_tree_oid(path).as_ref() != index_tree_map.get(*path)
+        })
+        .map(|path| path.to_string())
+        .collect();
+
+    Some(unresolved)
+}
+
+/// Every commit reachable from `commit_oid`, including itself.
+fn get_ancestors(commit_oid: &str) -> Option<HashSet<String>> {
+    let mut ancestors = HashSet::new();
+    let mut pending = vec![commit_oid.to_string()];
+
+    while let Some(oid) = pending.pop() {
+        if ancestors.insert(oid.clone()) {
+            pending.extend(plumbing::get_commit_parents(&oid)?);
+        }
+    }
+
+    Some(ancestors)
+}
+
+pub fn is_ancestor(ancestor_oid: &str, commit_oid: &str) -> Option<bool> {
+    Some(get_ancestors(commit_oid)?.contains(ancestor_oid))
+}
+
+/// The best common ancestor of two commits: one reachable from both that is
+/// not itself an ancestor of another common ancestor. `None` for unrelated histories.
+pub fn get_merge_base(first_oid: &str, second_oid: &str) -> Option<Option<String>> {
+    let first_ancestors = get_ancestors(first_oid)?;
+
+    let mut candidates = vec![];
+    let mut visited = HashSet::new();
+    let mut pending = vec![second_oid.to_string()];
+
+    while let Some(oid) = pending.pop() {
+        if !visited.insert(oid.clone()) {
+            continue;
+        }
+
+        // Everything behind a common ancestor is common too, so stop walking here
+        if first_ancestors.contains(&oid) {
+            candidates.push(oid);
+            continue;
+        }
+
+        pending.extend(plumbing::get_commit_parents(&oid)?);
+    }
+
+    for candidate in &candidates {
+        let mut is_best = true;
+
+        for other in &candidates {
+            if other != candidate && is_ancestor(candidate, other)? {
+                is_best = false;
+                break;
+            }
+        }
+
+        if is_best {
+            return Some(Some(candidate.to_string()));
+        }
+    }
+
+    Some(None)
+}
+
+fn get_merge_state_path(filename: &str) -> String {
+    format!("{GRIT_DIRECTORY}/{filename}")
+}
+
+pub fn merge_in_progress() -> bool {
+    path::Path::new(&get_merge_state_path("MERGE_HEAD")).exists()
+}
+
+/// The commit being merged in, while a conflicted merge waits to be committed.
+pub fn get_merge_head() -> Option<String> {
+    if !merge_in_progress() {
+        return Some("".to_string());
+    }
+
+    Some(
+        file_handling::read_file(&get_merge_state_path("MERGE_HEAD"))?
+            .trim()
+            .to_string(),
+    )
+}
+
+pub fn remove_merge_state() -> Option<()> {
+    for filename in ["MERGE_HEAD", "MERGE_MSG"] {
+        let state_path = get_merge_state_path(filename);
+
+        if path::Path::new(&state_path).exists() {
+            file_handling::remove_file(&state_path)?;
+        }
+    }
+
+    clear_unmerged_paths()
+}
+
+fn get_default_message(rev: &str) -> Option<String> {
+    let branch = plumbing::get_current_branch()?;
+
+    let message = match plumbing::resolve_ref_name(rev) {
+        Some(ref_name) if ref_name.starts_with("refs/heads/") => {
+            format!("Merge branch '{}'", refs::get_short_name(&ref_name))
+        }
+        Some(ref_name) if ref_name.starts_with("refs/remotes/") => {
+            format!(
+                "Merge remote-tracking branch '{}'",
+                refs::get_short_name(&ref_name)
+            )
+        }
+        _ => format!("Merge commit '{rev}'"),
+    };
+
+    if branch == "main" {
+        Some(message)
+    } else {
+        Some(format!("{message} into {branch}"))
+    }
+}
+
+fn fast_forward(head_oid: &str, commit_oid: &str, rev: &str) -> Option<()> {
+    if !head_oid.is_empty() {
+        println!("Updating {}..{}", &head_oid[..7], &commit_oid[..7]);
+    }
+    println!("Fast-forward");
+
+    plumbing::checkout_tree(&plumbing::get_commit_tree_oid(commit_oid)?)?;
+    plumbing::update_head(commit_oid, &format!("merge {rev}: Fast-forward"))?;
+
+    run_post_merge_hook();
+
+    Some(())
+}
+
+/// Lets the post-merge hook know a merge has completed. `0` says it was not a
+/// squash merge. The merge is done already, so the hook cannot stop it.
+fn run_post_merge_hook() {
+    let _ = hooks::run_hook("post-merge", &["0"], None);
+}
+
+/// Merges `rev` into the current branch, fast-forwarding when the branch has
+/// no commits of its own. Conflicts are left in the working tree to be
+/// resolved and concluded with `grit commit`.
+pub fn merge(rev: &str, message: Option<&str>) -> Option<()> {
+    if merge_in_progress() {
+        println!("You have not concluded your merge (MERGE_HEAD exists).\n  (fix conflicts and run `grit commit`, or use `grit merge --abort`)");
+        return None;
+    }
+
+    if crate::rebase::rebase_in_progress()
+        || crate::sequencer::sequencer_in_progress()
+        || crate::mailbox::am_in_progress()
+    {
+        println!("A rebase, am, cherry-pick or revert is in progress, finish it before merging");
+        return None;
+    }
+
+    if !plumbing::get_local_changes()?.is_empty() {
+        println!("Cannot merge: You have unstaged changes.\n  (commit your changes or stash them to proceed)");
+        return None;
+    }
+
+    let commit_oid = plumbing::peel_tag(&plumbing::rev_parse(rev)?)?;
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+
+    if head_oid.is_empty() {
+        return fast_forward(&head_oid, &commit_oid, rev);
+    }
+
+    let base_oid = match get_merge_base(&head_oid, &commit_oid)? {
+        Some(base_oid) => base_oid,
+        None => {
+            println!("fatal: refusing to merge unrelated histories");
+            return None;
+        }
+    };
+
+    if base_oid == commit_oid {
+        println!("Already up to date.");
+        return Some(());
+    }
+
+    if base_oid == head_oid {
+        return fast_forward(&head_oid, &commit_oid, rev);
+    }
+
+    let message = match message {
+        Some(message) => message.to_string(),
+        None => get_default_message(rev)?,
+    };
+
+    let (_base_map, _base_paths, base_entries, _base_oids) =
+        plumbing::get_head_tree_entries(&base_oid)?;
+    let (_head_map, _head_paths, head_entries, _head_oids) =
+        plumbing::get_head_tree_entries(&head_oid)?;
+    let (_commit_map, _commit_paths, commit_entries, _commit_oids) =
+        plumbing::get_head_tree_entries(&commit_oid)?;
+
+    let head_entries = get_entries_by_path(&head_entries);
+
+    let merge_result = merge_trees(
+        &get_entries_by_path(&base_entries),
+        &head_entries,
+        &get_entries_by_path(&commit_entries),
+        "HEAD",
+        rev,
+    )?;
+
+    write_merge_result(&head_entries, &merge_result)?;
+
+    if !merge_result.conflicts.is_empty() {
+        let mut conflicted_paths = merge_result
+            .conflicts
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>();
+        conflicted_paths.sort();
+
+        for path in &conflicted_paths {
+            println!("CONFLICT (content): Merge conflict in {path}");
+        }
+
+        save_unmerged_paths(&conflicted_paths)?;
+        file_handling::write_file(&get_merge_state_path("MERGE_HEAD"), &commit_oid)?;
+        file_handling::write_file(&get_merge_state_path("MERGE_MSG"), &message)?;
+
+        println!("Automatic merge failed; fix conflicts and then commit the result.");
+
+        return None;
+    }
+
+    let tree_oid = plumbing::write_tree_from_entries(&merge_result.entries)?;
+    let merge_oid =
+        plumbing::commit_tree_with_parents(&tree_oid, &message, &[head_oid, commit_oid])?;
+
+    plumbing::update_head(
+        &merge_oid,
+        &format!("merge {rev}: Merge made by three-way merge"),
+    )?;
+
+    println!("Merge made by three-way merge.");
+
+    run_post_merge_hook();
+
+    Some(())
+}
+
+/// Throws away a conflicted merge, restoring the index and working tree to HEAD.
+pub fn merge_abort() -> Option<()> {
+    if !merge_in_progress() {
+        println!("There is no merge to abort (MERGE_HEAD missing).");
+        return None;
+    }
+
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+
+    plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&head_oid)?)?;
+
+    remove_merge_state()
+}
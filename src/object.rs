@@ -0,0 +1,501 @@
+use crate::object_store;
+use crate::plumbing::{self, GritMode, GritObjectType};
+use std::fmt;
+
+/// The content of a file, which may be anything.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GritBlob {
+    pub content: Vec<u8>,
+}
+
+impl GritBlob {
+    pub fn parse(content: &[u8]) -> Option<GritBlob> {
+        Some(GritBlob {
+            content: content.to_vec(),
+        })
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.content.clone()
+    }
+}
+
+/// One line of a tree. Grit trees are flat, so the path runs from the top of
+/// the working tree and may contain spaces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GritTreeEntry {
+    pub mode: GritMode,
+    pub object_type: GritObjectType,
+    pub oid: String,
+    pub path: String,
+}
+
+impl GritTreeEntry {
+    /// Reads a `mode type oid path` line, as found in trees and the index.
+    pub fn parse(line: &str) -> Option<GritTreeEntry> {
+        let mut fields = line.splitn(4, ' ');
+
+        let entry = GritTreeEntry {
+            mode: GritMode::parse(fields.next()?)?,
+            object_type: GritObjectType::parse(fields.next()?)?,
+            oid: fields.next()?.to_string(),
+            path: fields.next()?.to_string(),
+        };
+
+        if entry.oid.is_empty() {
+            return None;
+        }
+
+        Some(entry)
+    }
+}
+
+impl fmt::Display for GritTreeEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.mode.value(),
+            self.object_type.name(),
+            self.oid,
+            self.path
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GritTree {
+    pub entries: Vec<GritTreeEntry>,
+}
+
+impl GritTree {
+    pub fn parse(content: &str) -> Option<GritTree> {
+        if content.is_empty() {
+            return Some(GritTree { entries: vec![] });
+        }
+
+        let mut entries = vec![];
+
+        for line in content.split('\n') {
+            match GritTreeEntry::parse(line) {
+                Some(entry) => entries.push(entry),
+                None => {
+                    println!("Malformed tree entry '{line}'");
+                    return None;
+                }
+            }
+        }
+
+        Some(GritTree { entries })
+    }
+
+    pub fn serialize(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| entry.to_string())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+/// Splits the header block of a commit or tag into names and values. Lines
+/// starting with a space carry on the value above, as in a signature.
+fn parse_headers(headers: &str) -> Option<Vec<(String, String)>> {
+    let mut parsed: Vec<(String, String)> = vec![];
+
+    for line in headers.split('\n') {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            let (_name, value) = parsed.last_mut()?;
+            *value += format!("\n{continuation}").as_str();
+            continue;
+        }
+
+        let (name, value) = line.split_once(' ')?;
+        parsed.push((name.to_string(), value.to_string()));
+    }
+
+    Some(parsed)
+}
+
+fn format_header(name: &str, value: &str) -> String {
+    format!("{name} {}", value.replace('\n', "\n "))
+}
+
+/// Objects are parsed strictly: one that would not serialize to the same bytes
+/// is refused rather than silently changed.
+fn check_round_trip(kind: &str, content: &str, serialized: &str) -> Option<()> {
+    if content == serialized {
+        Some(())
+    } else {
+        println!("Malformed {kind} object: headers are missing, repeated or out of order");
+        None
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GritCommit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    pub committer: String,
+    /// When the commit was made, as `dd/mm/YYYY HH:MM:SS` in UTC.
+    pub date: String,
+    /// Headers grit has no field for, kept in order after the ones it does.
+    pub extra_headers: Vec<(String, String)>,
+    pub message: String,
+}
+
+impl GritCommit {
+    pub fn parse(content: &str) -> Option<GritCommit> {
+        let parsed = content
+            .split_once("\n\n")
+            .and_then(|(headers, message)| Some((parse_headers(headers)?, message)));
+
+        let (headers, message) = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                println!("Malformed commit object: cannot read its headers");
+                return None;
+            }
+        };
+
+        let mut commit = GritCommit {
+            tree: "".to_string(),
+            parents: vec![],
+            author: "".to_string(),
+            committer: "".to_string(),
+            date: "".to_string(),
+            extra_headers: vec![],
+            message: message.to_string(),
+        };
+
+        for (name, value) in headers {
+            match name.as_str() {
+                "tree" => commit.tree = value,
+                "parent" => commit.parents.push(value),
+                "author" => commit.author = value,
+                "committer" => commit.committer = value,
+                "date" => commit.date = value,
+                _ => commit.extra_headers.push((name, value)),
+            }
+        }
+
+        check_round_trip("commit", content, &commit.serialize())?;
+
+        Some(commit)
+    }
+
+    /// Parents come first, the way grit has always written commits.
+    pub fn serialize(&self) -> String {
+        let mut headers = self
+            .parents
+            .iter()
+            .map(|parent| format_header("parent", parent))
+            .collect::<Vec<String>>();
+
+        headers.push(format_header("tree", &self.tree));
+        headers.push(format_header("author", &self.author));
+        headers.push(format_header("committer", &self.committer));
+        headers.push(format_header("date", &self.date));
+
+        for (name, value) in &self.extra_headers {
+            headers.push(format_header(name, value));
+        }
+
+        format!("{}\n\n{}", headers.join("\n"), self.message)
+    }
+}
+
+/// An annotated tag: a named, signed-off pointer at another object.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GritTag {
+    pub object: String,
+    pub object_type: String,
+    pub tag: String,
+    pub tagger: Option<String>,
+    pub extra_headers: Vec<(String, String)>,
+    pub message: String,
+}
+
+impl GritTag {
+    pub fn parse(content: &str) -> Option<GritTag> {
+        let parsed = content
+            .split_once("\n\n")
+            .and_then(|(headers, message)| Some((parse_headers(headers)?, message)));
+
+        let (headers, message) = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                println!("Malformed tag object: cannot read its headers");
+                return None;
+            }
+        };
+
+        let mut tag = GritTag {
+            object: "".to_string(),
+            object_type: "".to_string(),
+            tag: "".to_string(),
+            tagger: None,
+            extra_headers: vec![],
+            message: message.to_string(),
+        };
+
+        for (name, value) in headers {
+            match name.as_str() {
+                "object" => tag.object = value,
+                "type" => tag.object_type = value,
+                "tag" => tag.tag = value,
+                "tagger" => tag.tagger = Some(value),
+                _ => tag.extra_headers.push((name, value)),
+            }
+        }
+
+        check_round_trip("tag", content, &tag.serialize())?;
+
+        Some(tag)
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut headers = vec![
+            format_header("object", &self.object),
+            format_header("type", &self.object_type),
+            format_header("tag", &self.tag),
+        ];
+
+        if let Some(tagger) = &self.tagger {
+            headers.push(format_header("tagger", tagger));
+        }
+
+        for (name, value) in &self.extra_headers {
+            headers.push(format_header(name, value));
+        }
+
+        format!("{}\n\n{}", headers.join("\n"), self.message)
+    }
+}
+
+/// Any object in the object store, read into the structure of its type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GritObject {
+    Blob(GritBlob),
+    Tree(GritTree),
+    Commit(GritCommit),
+    Tag(GritTag),
+}
+
+impl GritObject {
+    /// Blobs are taken as they are, the other types are text.
+    pub fn parse(object_type: &GritObjectType, content: &[u8]) -> Option<GritObject> {
+        if let GritObjectType::Blob = object_type {
+            return GritBlob::parse(content).map(GritObject::Blob);
+        }
+
+        let content = match std::str::from_utf8(content) {
+            Ok(content) => content,
+            Err(_) => {
+                println!("Malformed {} object: not valid UTF-8", object_type.name());
+                return None;
+            }
+        };
+
+        match object_type {
+            GritObjectType::Blob => None,
+            GritObjectType::Tree => GritTree::parse(content).map(GritObject::Tree),
+            GritObjectType::Commit => GritCommit::parse(content).map(GritObject::Commit),
+            GritObjectType::Tag => GritTag::parse(content).map(GritObject::Tag),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        match self {
+            GritObject::Blob(blob) => blob.serialize(),
+            GritObject::Tree(tree) => tree.serialize().into_bytes(),
+            GritObject::Commit(commit) => commit.serialize().into_bytes(),
+            GritObject::Tag(tag) => tag.serialize().into_bytes(),
+        }
+    }
+
+    pub fn get_type(&self) -> GritObjectType {
+        match self {
+            GritObject::Blob(_) => GritObjectType::Blob,
+            GritObject::Tree(_) => GritObjectType::Tree,
+            GritObject::Commit(_) => GritObjectType::Commit,
+            GritObject::Tag(_) => GritObjectType::Tag,
+        }
+    }
+
+    /// Reads an object from the object store by its full id.
+    pub fn read(oid: &str) -> Option<GritObject> {
+        let (object_type, content) = plumbing::read_object(oid)?;
+
+        GritObject::parse(&object_type, &content)
+    }
+
+    /// Stores the object, giving back its id.
+    pub fn write(&self) -> Option<String> {
+        let content = self.serialize();
+
+        object_store::get_object_store()?.write_stream(
+            self.get_type(),
+            content.len() as u64,
+            &mut content.as_slice(),
+        )
+    }
+}
+
+fn read_typed<T>(
+    oid: &str,
+    expected: GritObjectType,
+    unwrap: impl FnOnce(GritObject) -> Option<T>,
+) -> Option<T> {
+    let (object_type, content) = plumbing::read_object(oid)?;
+
+    if object_type != expected {
+        println!(
+            "Object {oid} is a {}, not a {}",
+            object_type.name(),
+            expected.name()
+        );
+        return None;
+    }
+
+    unwrap(GritObject::parse(&object_type, &content)?)
+}
+
+pub fn read_tree(oid: &str) -> Option<GritTree> {
+    read_typed(oid, GritObjectType::Tree, |object| match object {
+        GritObject::Tree(tree) => Some(tree),
+        _ => None,
+    })
+}
+
+pub fn read_commit(oid: &str) -> Option<GritCommit> {
+    read_typed(oid, GritObjectType::Commit, |object| match object {
+        GritObject::Commit(commit) => Some(commit),
+        _ => None,
+    })
+}
+
+pub fn read_tag(oid: &str) -> Option<GritTag> {
+    read_typed(oid, GritObjectType::Tag, |object| match object {
+        GritObject::Tag(tag) => Some(tag),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OID: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+    fn assert_round_trip(object_type: GritObjectType, content: &[u8]) -> GritObject {
+        let object = GritObject::parse(&object_type, content).unwrap();
+
+        assert_eq!(object.serialize(), content);
+        assert_eq!(object.get_type(), object_type);
+        assert_eq!(
+            GritObject::parse(&object_type, &object.serialize()),
+            Some(object.clone())
+        );
+
+        object
+    }
+
+    #[test]
+    fn blob_keeps_any_bytes() {
+        let content = [b'a', 0x00, 0xff, b'\n', 0x80, 0xfe];
+
+        match assert_round_trip(GritObjectType::Blob, &content) {
+            GritObject::Blob(blob) => assert_eq!(blob.content, content),
+            object => panic!("expected a blob, got {object:?}"),
+        }
+    }
+
+    #[test]
+    fn tree_keeps_paths_with_spaces() {
+        let content = format!(
+            "100644 blob {OID} docs/read me.txt\n100755 blob {OID} bin/run  twice\n120000 blob {OID} link"
+        );
+
+        let tree = match assert_round_trip(GritObjectType::Tree, content.as_bytes()) {
+            GritObject::Tree(tree) => tree,
+            object => panic!("expected a tree, got {object:?}"),
+        };
+
+        assert_eq!(tree.entries[0].path, "docs/read me.txt");
+        assert_eq!(tree.entries[1].path, "bin/run  twice");
+        assert_eq!(tree.entries[1].mode, GritMode::Executable);
+        assert_eq!(tree.entries[2].mode, GritMode::Symlink);
+        assert_eq!(tree.entries[2].object_type, GritObjectType::Blob);
+    }
+
+    #[test]
+    fn tree_rejects_unknown_modes_and_types() {
+        assert_eq!(GritTreeEntry::parse(&format!("100600 blob {OID} a")), None);
+        assert_eq!(GritTreeEntry::parse(&format!("100644 note {OID} a")), None);
+        assert_eq!(GritTree::parse("100644 blob"), None);
+    }
+
+    #[test]
+    fn empty_tree_round_trips() {
+        match assert_round_trip(GritObjectType::Tree, b"") {
+            GritObject::Tree(tree) => assert!(tree.entries.is_empty()),
+            object => panic!("expected a tree, got {object:?}"),
+        }
+    }
+
+    #[test]
+    fn commit_keeps_extra_headers_and_messages_with_nul() {
+        let content = format!(
+            "parent {OID}\ntree {OID}\nauthor A U Thor <a@example.com>\ncommitter C O Mitter <c@example.com>\ndate 01/02/2024 10:11:12\ngpgsig -----BEGIN-----\n line\n -----END-----\n\nSubject\n\nBody with \0 in it\n"
+        );
+
+        let commit = match assert_round_trip(GritObjectType::Commit, content.as_bytes()) {
+            GritObject::Commit(commit) => commit,
+            object => panic!("expected a commit, got {object:?}"),
+        };
+
+        assert_eq!(commit.parents, vec![OID.to_string()]);
+        assert_eq!(
+            commit.extra_headers,
+            vec![(
+                "gpgsig".to_string(),
+                "-----BEGIN-----\nline\n-----END-----".to_string()
+            )]
+        );
+        assert_eq!(commit.message, "Subject\n\nBody with \0 in it\n");
+    }
+
+    #[test]
+    fn commit_rejects_reordered_headers() {
+        let content = format!("tree {OID}\nparent {OID}\nauthor a\ncommitter c\ndate d\n\nmessage");
+
+        assert_eq!(
+            GritObject::parse(&GritObjectType::Commit, content.as_bytes()),
+            None
+        );
+    }
+
+    #[test]
+    fn tag_keeps_messages_with_nul() {
+        let content = format!(
+            "object {OID}\ntype commit\ntag v1.0\ntagger T Agger <t@example.com>\n\nRelease\0notes\n"
+        );
+
+        let tag = match assert_round_trip(GritObjectType::Tag, content.as_bytes()) {
+            GritObject::Tag(tag) => tag,
+            object => panic!("expected a tag, got {object:?}"),
+        };
+
+        assert_eq!(tag.tag, "v1.0");
+        assert_eq!(tag.tagger, Some("T Agger <t@example.com>".to_string()));
+        assert_eq!(tag.message, "Release\0notes\n");
+    }
+
+    #[test]
+    fn text_objects_must_be_utf8() {
+        assert_eq!(GritObject::parse(&GritObjectType::Commit, &[0xff]), None);
+        assert_eq!(GritObject::parse(&GritObjectType::Tree, &[0xff]), None);
+    }
+}
@@ -0,0 +1,684 @@
+use crate::config::GRIT_DIRECTORY;
+use crate::file_handling;
+use crate::plumbing::GritObjectType;
+use crate::storage;
+use crate::utils;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+static TEMP_OBJECT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Where objects are kept. Objects go in and come out as stored, that is
+/// `type size\0content`, under the id of those bytes.
+pub trait GritObjectStore {
+    fn read(&self, oid: &str) -> Option<Vec<u8>>;
+
+    /// Stores an object. Objects never change, so writing one that is already
+    /// there does nothing.
+    fn write(&self, oid: &str, object: &[u8]) -> Option<()>;
+
+    fn exists(&self, oid: &str) -> bool;
+
+    /// The ids of every object in the store, in order.
+    fn iter(&self) -> Box<dyn Iterator<Item = String> + '_>;
+
+    /// Removes an object, as gc does once nothing can reach it.
+    fn remove(&self, oid: &str) -> Option<()>;
+
+    /// How many objects the store holds and how many bytes they take up in it.
+    fn count_objects(&self) -> Option<(u64, u64)> {
+        Some((self.iter().count() as u64, 0))
+    }
+
+    /// When an object was written, as a unix timestamp, so prune can spare
+    /// the ones that are too new. `None` when the store cannot tell.
+    fn get_modified_time(&self, _oid: &str) -> Option<i64> {
+        None
+    }
+
+    /// The ids starting with `prefix`, for resolving abbreviated ids.
+    fn find_by_prefix(&self, prefix: &str) -> Vec<String> {
+        self.iter().filter(|oid| oid.starts_with(prefix)).collect()
+    }
+
+    /// The file an object lives in, for stores that keep one file per object.
+    fn get_object_path(&self, _oid: &str) -> Option<PathBuf> {
+        None
+    }
+
+    /// The directory objects are kept under, for stores that keep one file per
+    /// object, so worker threads can write to it through a store of their own.
+    fn get_objects_dir(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Opens an object to be read a chunk at a time, header included.
+    fn open(&self, oid: &str) -> Option<Box<dyn Read + '_>> {
+        Some(Box::new(Cursor::new(self.read(oid)?)))
+    }
+
+    /// Stores an object whose `size` bytes of content come from `reader`,
+    /// giving back its id. Stores that keep objects on disk override this to
+    /// hash and compress a chunk at a time; the rest collect the object first.
+    fn write_stream(
+        &self,
+        object_type: GritObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Option<String> {
+        let mut object = vec![];
+
+        let hashed = file_handling::hash_stream(object_type.name(), size, reader, &mut |chunk| {
+            object.extend_from_slice(chunk);
+            Ok(())
+        });
+
+        let oid = match hashed {
+            Ok(oid) => oid,
+            Err(e) => {
+                println!("Failed to read object content: {}", e);
+                return None;
+            }
+        };
+
+        self.write(&oid, &object)?;
+
+        Some(oid)
+    }
+}
+
+/// Hashes an object while compressing it into `output`, as objects are kept
+/// on disk, reading its content from `reader` a chunk at a time.
+pub fn compress_object_stream<W: Write>(
+    object_type: GritObjectType,
+    size: u64,
+    reader: &mut dyn Read,
+    output: W,
+) -> io::Result<(String, W)> {
+    let mut encoder = DeflateEncoder::new(output, Compression::best());
+
+    let oid = file_handling::hash_stream(object_type.name(), size, reader, &mut |chunk| {
+        encoder.write_all(chunk)
+    })?;
+
+    Ok((oid, encoder.finish()?))
+}
+
+/// The content of an opened object, read a chunk at a time.
+pub type GritObjectReader<'a> = BufReader<Box<dyn Read + 'a>>;
+
+/// Opens a stored object and reads its header, giving its type and size as
+/// written there and a reader positioned at the start of its content.
+pub fn open_object<'a>(
+    store: &'a dyn GritObjectStore,
+    oid: &str,
+) -> Option<(String, String, GritObjectReader<'a>)> {
+    let mut reader = BufReader::new(store.open(oid)?);
+    let mut header = vec![];
+
+    let header = match reader.read_until(b'\0', &mut header) {
+        Ok(_) if header.ends_with(b"\0") => {
+            String::from_utf8(header[..header.len() - 1].to_vec()).ok()
+        }
+        _ => None,
+    };
+
+    match header.as_ref().and_then(|header| header.split_once(' ')) {
+        Some((object_type, size)) => Some((object_type.to_string(), size.to_string(), reader)),
+        None => {
+            println!("Object {oid} is corrupt");
+            None
+        }
+    }
+}
+
+/// Copies an object from one store to another a chunk at a time, so objects
+/// of any size and content move between backends.
+pub fn copy_object(
+    source: &dyn GritObjectStore,
+    target: &dyn GritObjectStore,
+    oid: &str,
+) -> Option<()> {
+    let (object_type, size, mut reader) = open_object(source, oid)?;
+
+    let (object_type, size) = match (GritObjectType::parse(&object_type), size.parse::<u64>()) {
+        (Some(object_type), Ok(size)) => (object_type, size),
+        _ => {
+            println!("Object {oid} is corrupt");
+            return None;
+        }
+    };
+
+    if target.write_stream(object_type, size, &mut reader)? != oid {
+        println!("Failed to copy object {oid}: its content does not match its id");
+        return None;
+    }
+
+    Some(())
+}
+
+/// The classic layout: one zlib compressed file per object under
+/// `objects/xx/yyyy...`, fanned out by the first two characters of the id.
+pub struct GritLooseObjectStore {
+    objects_dir: PathBuf,
+}
+
+impl GritLooseObjectStore {
+    pub fn new(objects_dir: impl Into<PathBuf>) -> GritLooseObjectStore {
+        GritLooseObjectStore {
+            objects_dir: objects_dir.into(),
+        }
+    }
+
+    fn get_path(&self, oid: &str) -> PathBuf {
+        self.objects_dir.join(&oid[..2]).join(&oid[2..])
+    }
+
+    /// Compresses into a temporary file at the top of the store, since the
+    /// fan-out directory is only known once the whole object has been hashed.
+    /// Failures are left to the caller to report, so it can run on any thread.
+    pub fn try_write_stream(
+        &self,
+        object_type: GritObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> io::Result<String> {
+        let temp_filepath = get_temp_path(&self.objects_dir);
+
+        let written = File::create(&temp_filepath)
+            .and_then(|object_file| compress_object_stream(object_type, size, reader, object_file))
+            .and_then(|(oid, object_file)| {
+                if self.exists(&oid) {
+                    fs::remove_file(&temp_filepath)?;
+                    return Ok(oid);
+                }
+
+                fs::create_dir_all(self.objects_dir.join(&oid[..2]))?;
+                finish_object_file(object_file, &temp_filepath, &self.get_path(&oid))?;
+                Ok(oid)
+            });
+
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_filepath);
+        }
+
+        written
+    }
+}
+
+/// Makes a fully written object file durable and read-only, then moves it to
+/// its place, so a crash never leaves a truncated object behind.
+fn get_temp_path(directory: &Path) -> PathBuf {
+    directory.join(format!(
+        "tmp_obj_{}_{}",
+        process::id(),
+        TEMP_OBJECT_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Copies an object file from another store through a temporary file beside
+/// `target_path`, so an interrupted copy never leaves a truncated object.
+pub fn copy_object_file(source_path: &Path, target_path: &Path) -> io::Result<()> {
+    let directory = target_path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(directory)?;
+
+    let temp_filepath = get_temp_path(directory);
+
+    let copied = File::open(source_path).and_then(|mut source_file| {
+        let mut object_file = File::create(&temp_filepath)?;
+        io::copy(&mut source_file, &mut object_file)?;
+        finish_object_file(object_file, &temp_filepath, target_path)
+    });
+
+    if copied.is_err() {
+        let _ = fs::remove_file(&temp_filepath);
+    }
+
+    copied
+}
+
+fn finish_object_file(object_file: File, temp_filepath: &Path, filepath: &Path) -> io::Result<()> {
+    object_file.sync_all()?;
+
+    let mut permissions = object_file.metadata()?.permissions();
+    permissions.set_readonly(true);
+    object_file.set_permissions(permissions)?;
+
+    fs::rename(temp_filepath, filepath)
+}
+
+impl GritObjectStore for GritLooseObjectStore {
+    fn read(&self, oid: &str) -> Option<Vec<u8>> {
+        let compressed_contents =
+            file_handling::read_file_as_bytes(&self.get_path(oid).display().to_string())?;
+
+        utils::decompress_object_content(&compressed_contents)
+    }
+
+    /// Writes through a temporary file in the fan-out directory.
+    fn write(&self, oid: &str, object: &[u8]) -> Option<()> {
+        if self.exists(oid) {
+            return Some(());
+        }
+
+        let sub_directory_path = self.objects_dir.join(&oid[..2]);
+
+        if let Err(e) = fs::create_dir_all(&sub_directory_path) {
+            println!(
+                "Failed to create directory {}: {}",
+                sub_directory_path.display(),
+                e
+            );
+            return None;
+        }
+
+        let temp_filepath = get_temp_path(&sub_directory_path);
+
+        let compressed_object_content = utils::compress_object_content(object)?;
+
+        let written = File::create(&temp_filepath).and_then(|mut object_file| {
+            object_file.write_all(&compressed_object_content)?;
+            finish_object_file(object_file, &temp_filepath, &self.get_path(oid))
+        });
+
+        match written {
+            Ok(_) => Some(()),
+            Err(e) => {
+                let _ = fs::remove_file(&temp_filepath);
+                println!("Failed to save object {}: {}", oid, e);
+                None
+            }
+        }
+    }
+
+    fn write_stream(
+        &self,
+        object_type: GritObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Option<String> {
+        match self.try_write_stream(object_type, size, reader) {
+            Ok(oid) => Some(oid),
+            Err(e) => {
+                println!("Failed to save object: {}", e);
+                None
+            }
+        }
+    }
+
+    fn open(&self, oid: &str) -> Option<Box<dyn Read + '_>> {
+        let path = self.get_path(oid);
+
+        match File::open(&path) {
+            Ok(file) => Some(Box::new(DeflateDecoder::new(BufReader::new(file)))),
+            Err(e) => {
+                println!("Failed to open file {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn exists(&self, oid: &str) -> bool {
+        oid.len() > 2 && File::open(self.get_path(oid)).is_ok()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        let sub_directories = match fs::read_dir(&self.objects_dir) {
+            Ok(result) => result,
+            Err(e) => {
+                println!(
+                    "Failed to read directory {}: {}",
+                    self.objects_dir.display(),
+                    e
+                );
+                return Box::new(std::iter::empty());
+            }
+        };
+
+        let mut oids = vec![];
+
+        // Loose objects live in two character fan-out directories, `info` and `pack` are skipped
+        for sub_directory in sub_directories.filter_map(Result::ok) {
+            let prefix = sub_directory.file_name().to_string_lossy().to_string();
+
+            if prefix.len() != 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+                continue;
+            }
+
+            oids.append(&mut read_fan_out_directory(&sub_directory.path(), &prefix));
+        }
+
+        oids.sort();
+        Box::new(oids.into_iter())
+    }
+
+    fn remove(&self, oid: &str) -> Option<()> {
+        match fs::remove_file(self.get_path(oid)) {
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to remove object {}: {}", oid, e);
+                return None;
+            }
+        }
+
+        // Only succeeds once the fan-out directory is empty
+        let _ = fs::remove_dir(self.objects_dir.join(&oid[..2]));
+
+        Some(())
+    }
+
+    /// Only the one fan-out directory the prefix falls in needs reading.
+    fn find_by_prefix(&self, prefix: &str) -> Vec<String> {
+        if prefix.len() < 2 {
+            return self.iter().filter(|oid| oid.starts_with(prefix)).collect();
+        }
+
+        let mut oids = read_fan_out_directory(&self.objects_dir.join(&prefix[..2]), &prefix[..2])
+            .into_iter()
+            .filter(|oid| oid.starts_with(prefix))
+            .collect::<Vec<String>>();
+
+        oids.sort();
+        oids
+    }
+
+    fn get_object_path(&self, oid: &str) -> Option<PathBuf> {
+        Some(self.get_path(oid))
+    }
+
+    fn get_objects_dir(&self) -> Option<PathBuf> {
+        Some(self.objects_dir.clone())
+    }
+
+    fn count_objects(&self) -> Option<(u64, u64)> {
+        let mut count = 0;
+        let mut size = 0;
+
+        for oid in self.iter() {
+            count += 1;
+            size += fs::metadata(self.get_path(&oid))
+                .map(|metadata| metadata.len())
+                .unwrap_or(0);
+        }
+
+        Some((count, size))
+    }
+
+    fn get_modified_time(&self, oid: &str) -> Option<i64> {
+        let modified = fs::metadata(self.get_path(oid))
+            .and_then(|metadata| metadata.modified())
+            .ok()?;
+
+        match modified.duration_since(UNIX_EPOCH) {
+            Ok(duration) => Some(duration.as_secs() as i64),
+            Err(_) => Some(0),
+        }
+    }
+}
+
+/// The ids of the objects in one fan-out directory, skipping the temporary
+/// files of writes in progress.
+fn read_fan_out_directory(path: &Path, prefix: &str) -> Vec<String> {
+    let objects = match fs::read_dir(path) {
+        Ok(result) => result,
+        Err(_) => return vec![],
+    };
+
+    objects
+        .filter_map(Result::ok)
+        .map(|object| object.file_name().to_string_lossy().to_string())
+        .filter(|filename| filename.len() == 38 && filename.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|filename| format!("{prefix}{filename}"))
+        .collect()
+}
+
+/// Keeps objects in memory only. Clones share the same objects, so a caller
+/// can hand one to `with_object_store` and look inside it afterwards.
+#[derive(Clone, Default)]
+pub struct GritMemoryObjectStore {
+    objects: Rc<RefCell<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl GritMemoryObjectStore {
+    pub fn new() -> GritMemoryObjectStore {
+        GritMemoryObjectStore::default()
+    }
+}
+
+impl GritObjectStore for GritMemoryObjectStore {
+    fn read(&self, oid: &str) -> Option<Vec<u8>> {
+        let object = self.objects.borrow().get(oid).cloned();
+
+        if object.is_none() {
+            println!("Object {oid} not found");
+        }
+
+        object
+    }
+
+    fn write(&self, oid: &str, object: &[u8]) -> Option<()> {
+        self.objects
+            .borrow_mut()
+            .entry(oid.to_string())
+            .or_insert_with(|| object.to_vec());
+
+        Some(())
+    }
+
+    fn exists(&self, oid: &str) -> bool {
+        self.objects.borrow().contains_key(oid)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        Box::new(
+            self.objects
+                .borrow()
+                .keys()
+                .cloned()
+                .collect::<Vec<String>>()
+                .into_iter(),
+        )
+    }
+
+    fn remove(&self, oid: &str) -> Option<()> {
+        self.objects.borrow_mut().remove(oid);
+
+        Some(())
+    }
+
+    fn count_objects(&self) -> Option<(u64, u64)> {
+        let objects = self.objects.borrow();
+
+        Some((
+            objects.len() as u64,
+            objects.values().map(|object| object.len() as u64).sum(),
+        ))
+    }
+}
+
+thread_local! {
+    static OBJECT_STORE: RefCell<Option<Rc<dyn GritObjectStore>>> = const { RefCell::new(None) };
+}
+
+/// Opens the object store of the repository whose grit directory is `grit_dir`.
+pub fn open_object_store(grit_dir: &Path) -> Option<Rc<dyn GritObjectStore>> {
+    Some(storage::open_storage(grit_dir)?.get_object_store(grit_dir))
+}
+
+/// The store every object read and write on this thread goes through: the one
+/// installed by `with_object_store`, or else the current repository's own.
+pub fn get_object_store() -> Option<Rc<dyn GritObjectStore>> {
+    match OBJECT_STORE.with(|store| store.borrow().clone()) {
+        Some(store) => Some(store),
+        None => Some(storage::get_storage()?.get_object_store(Path::new(GRIT_DIRECTORY))),
+    }
+}
+
+/// Puts back the store that was current before `with_object_store` once
+/// dropped, so a panic in `run` does not leave the override installed.
+struct GritObjectStoreGuard {
+    previous: Option<Rc<dyn GritObjectStore>>,
+}
+
+impl Drop for GritObjectStoreGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+
+        OBJECT_STORE.with(|current| current.replace(previous));
+    }
+}
+
+/// Runs `run` with every object read and write on this thread going to
+/// `store`, putting back the previous store afterwards. The rest of grit is
+/// written against whichever store is current, so this is how it works on
+/// objects that are not in the repository's own store.
+pub fn with_object_store<S, T>(store: S, run: impl FnOnce() -> T) -> T
+where
+    S: GritObjectStore + 'static,
+{
+    let _guard = GritObjectStoreGuard {
+        previous: OBJECT_STORE.with(|current| current.replace(Some(Rc::new(store)))),
+    };
+
+    run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{object, plumbing, transport, WORKING_DIR};
+    use std::env;
+    use std::panic;
+    use std::sync::Mutex;
+
+    // The working directory is shared by every test thread
+    static REPOSITORY_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Runs `run` inside a new repository whose working tree holds `files`.
+    fn in_repository<T>(name: &str, files: &[(&str, &[u8])], run: impl FnOnce() -> T) -> T {
+        let _lock = REPOSITORY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let repository_dir = env::temp_dir().join(format!("grit-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&repository_dir);
+        fs::create_dir_all(repository_dir.join(WORKING_DIR)).unwrap();
+
+        for (path, content) in files {
+            fs::write(repository_dir.join(WORKING_DIR).join(path), content).unwrap();
+        }
+
+        let result = transport::with_repository(&repository_dir, || {
+            crate::init()?;
+            Some(run())
+        });
+
+        let _ = fs::remove_dir_all(&repository_dir);
+        result.unwrap()
+    }
+
+    #[test]
+    fn add_and_commit_write_only_to_the_memory_store() {
+        let binary = [0x00, 0xff, 0xfe, b'\n', 0x80];
+        let files: [(&str, &[u8]); 2] = [("notes.txt", b"hello\n"), ("image.bin", &binary)];
+
+        in_repository("memory-commit", &files, || {
+            let store = GritMemoryObjectStore::new();
+
+            with_object_store(store.clone(), || {
+                crate::add(vec!["notes.txt".to_string(), "image.bin".to_string()]).unwrap();
+                crate::commit("First commit", true).unwrap();
+            });
+
+            // Blobs, tree and commit
+            assert_eq!(store.iter().count(), 4);
+            assert_eq!(
+                open_object_store(Path::new(GRIT_DIRECTORY))
+                    .unwrap()
+                    .iter()
+                    .count(),
+                0
+            );
+
+            with_object_store(store, || {
+                let commit = object::read_commit(&plumbing::get_head_oid().unwrap()).unwrap();
+                assert_eq!(commit.message, "First commit");
+
+                let tree = object::read_tree(&commit.tree).unwrap();
+                let image = tree
+                    .entries
+                    .iter()
+                    .find(|entry| entry.path == "image.bin")
+                    .unwrap();
+
+                let (object_type, content) = plumbing::read_object(&image.oid).unwrap();
+                assert_eq!(object_type, GritObjectType::Blob);
+                assert_eq!(content, binary);
+            });
+        });
+    }
+
+    #[test]
+    fn read_tree_stages_a_tree_from_the_memory_store() {
+        in_repository("memory-read-tree", &[], || {
+            let store = GritMemoryObjectStore::new();
+
+            with_object_store(store, || {
+                let blob_oid =
+                    plumbing::hash_object("content\n", GritObjectType::Blob, true).unwrap();
+                let tree_oid = plumbing::write_tree_from_entries(&[format!(
+                    "100644 blob {blob_oid} docs/read me.txt"
+                )])
+                .unwrap();
+
+                plumbing::read_tree("vendor", &tree_oid);
+
+                let (_map, paths, _entries, oids) = plumbing::get_index_entries().unwrap();
+                assert_eq!(paths, vec!["vendor".to_string()]);
+                assert_eq!(oids, vec![tree_oid]);
+            });
+        });
+    }
+
+    #[test]
+    fn memory_store_keeps_binary_objects() {
+        let store = GritMemoryObjectStore::new();
+        let content = [0x00, 0x9f, 0x92, 0x96, 0xff];
+
+        let oid = store
+            .write_stream(GritObjectType::Blob, 5, &mut &content[..])
+            .unwrap();
+
+        let (object_type, size, mut reader) = open_object(&store, &oid).unwrap();
+        let mut read = vec![];
+        reader.read_to_end(&mut read).unwrap();
+
+        assert_eq!((object_type.as_str(), size.as_str()), ("blob", "5"));
+        assert_eq!(read, content);
+    }
+
+    #[test]
+    fn with_object_store_restores_the_previous_store_after_a_panic() {
+        let outer = GritMemoryObjectStore::new();
+        outer.write("outer", b"blob 0\0").unwrap();
+
+        with_object_store(outer, || {
+            let result = panic::catch_unwind(|| {
+                with_object_store(GritMemoryObjectStore::new(), || panic!("interrupted"))
+            });
+
+            assert!(result.is_err());
+            assert!(get_object_store().unwrap().exists("outer"));
+        });
+
+        assert!(OBJECT_STORE.with(|current| current.borrow().is_none()));
+    }
+}
@@ -0,0 +1,534 @@
+use crate::object_store::{self, GritObjectReader, GritObjectStore};
+use crate::plumbing::GritObjectType;
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read};
+use std::mem;
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// Where a packed object gets its content from before any delta is applied.
+enum GritPackBase {
+    None,
+    /// A delta against the object starting at this offset in the same pack.
+    Offset(usize),
+    /// A delta against the object with this id, in the pack or already stored locally.
+    Oid(String),
+}
+
+struct GritPackEntry {
+    offset: usize,
+    type_code: u8,
+    base: GritPackBase,
+    data: Vec<u8>,
+}
+
+fn get_type_code(type_name: &str) -> Option<u8> {
+    match type_name {
+        "commit" => Some(OBJ_COMMIT),
+        "tree" => Some(OBJ_TREE),
+        "blob" => Some(OBJ_BLOB),
+        "tag" => Some(OBJ_TAG),
+        _ => {
+            println!("Cannot pack object of unknown type {type_name}");
+            None
+        }
+    }
+}
+
+fn get_object_type(type_code: u8) -> Option<GritObjectType> {
+    match type_code {
+        OBJ_COMMIT => Some(GritObjectType::Commit),
+        OBJ_TREE => Some(GritObjectType::Tree),
+        OBJ_BLOB => Some(GritObjectType::Blob),
+        OBJ_TAG => Some(GritObjectType::Tag),
+        _ => {
+            println!("Unknown object type {type_code} in pack");
+            None
+        }
+    }
+}
+
+/// Opens a locally stored object as its type code, its size and a reader of
+/// its raw content.
+fn open_object<'a>(
+    store: &'a dyn GritObjectStore,
+    oid: &str,
+) -> Option<(u8, usize, GritObjectReader<'a>)> {
+    let (object_type, size, reader) = object_store::open_object(store, oid)?;
+
+    match size.parse::<usize>() {
+        Ok(size) => Some((get_type_code(&object_type)?, size, reader)),
+        Err(_) => {
+            println!("Object {oid} is corrupt");
+            None
+        }
+    }
+}
+
+/// The type code and content of a locally stored object.
+fn read_object(store: &dyn GritObjectStore, oid: &str) -> Option<(u8, Vec<u8>)> {
+    let (type_code, size, mut reader) = open_object(store, oid)?;
+    let mut content = Vec::with_capacity(size);
+
+    match reader.read_to_end(&mut content) {
+        Ok(read) if read == size => Some((type_code, content)),
+        Ok(_) => {
+            println!("Object {oid} is corrupt");
+            None
+        }
+        Err(e) => {
+            println!("Failed to read object {}: {}", oid, e);
+            None
+        }
+    }
+}
+
+fn encode_entry_header(type_code: u8, size: usize) -> Vec<u8> {
+    let mut header = vec![];
+    let mut byte = (type_code << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+
+    while size > 0 {
+        header.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+
+    header.push(byte);
+    header
+}
+
+/// Builds a version 2 pack holding the given objects, each stored whole.
+/// Objects are compressed into the pack as they are read from the store, so
+/// none is ever held in memory whole.
+pub fn create_pack(oids: &[String]) -> Option<Vec<u8>> {
+    let store = object_store::get_object_store()?;
+
+    let mut pack = b"PACK".to_vec();
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    pack.extend_from_slice(&(oids.len() as u32).to_be_bytes());
+
+    for oid in oids {
+        let (type_code, size, mut reader) = open_object(store.as_ref(), oid)?;
+
+        pack.extend(encode_entry_header(type_code, size));
+
+        let mut encoder = ZlibEncoder::new(mem::take(&mut pack), Compression::default());
+
+        let compressed = io::copy(&mut reader, &mut encoder).and_then(|copied| {
+            let compressed = encoder.finish()?;
+
+            if copied != size as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "its content does not match its size",
+                ));
+            }
+
+            Ok(compressed)
+        });
+
+        pack = match compressed {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                println!("Failed to compress object {}: {}", oid, e);
+                return None;
+            }
+        };
+    }
+
+    let checksum = Sha1::digest(&pack);
+    pack.extend_from_slice(&checksum);
+
+    Some(pack)
+}
+
+fn read_byte(data: &[u8], offset: &mut usize) -> Option<u8> {
+    match data.get(*offset) {
+        Some(byte) => {
+            *offset += 1;
+            Some(*byte)
+        }
+        None => {
+            println!("Pack is truncated");
+            None
+        }
+    }
+}
+
+/// Inflates the zlib stream at the front of `data`, returning the content
+/// and how many compressed bytes it used.
+fn inflate(data: &[u8], size: usize) -> Option<(Vec<u8>, usize)> {
+    let mut decompress = Decompress::new(true);
+    let mut content = Vec::with_capacity(size + 1);
+
+    loop {
+        let consumed = decompress.total_in() as usize;
+
+        let status =
+            decompress.decompress_vec(&data[consumed..], &mut content, FlushDecompress::Finish);
+
+        match status {
+            Ok(Status::StreamEnd) => break,
+            Ok(_) if content.len() == content.capacity() => content.reserve(1024),
+            Ok(_) if consumed == decompress.total_in() as usize => {
+                println!("Pack is truncated");
+                return None;
+            }
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to inflate packed object: {}", e);
+                return None;
+            }
+        }
+    }
+
+    if content.len() != size {
+        println!(
+            "Packed object has size {}, expected {}",
+            content.len(),
+            size
+        );
+        return None;
+    }
+
+    Some((content, decompress.total_in() as usize))
+}
+
+fn read_delta_size(delta: &[u8], offset: &mut usize) -> Option<usize> {
+    let mut size = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = read_byte(delta, offset)?;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Some(size);
+        }
+    }
+}
+
+/// Rebuilds an object from its base and a delta of copy and insert instructions.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut offset = 0;
+
+    if read_delta_size(delta, &mut offset)? != base.len() {
+        println!("Delta does not match the size of its base object");
+        return None;
+    }
+
+    let result_size = read_delta_size(delta, &mut offset)?;
+    let mut result = Vec::with_capacity(result_size);
+
+    while offset < delta.len() {
+        let instruction = read_byte(delta, &mut offset)?;
+
+        if instruction & 0x80 != 0 {
+            let mut copy_offset = 0;
+            let mut copy_size = 0;
+
+            for i in 0..4 {
+                if instruction & (1 << i) != 0 {
+                    copy_offset |= (read_byte(delta, &mut offset)? as usize) << (8 * i);
+                }
+            }
+
+            for i in 0..3 {
+                if instruction & (0x10 << i) != 0 {
+                    copy_size |= (read_byte(delta, &mut offset)? as usize) << (8 * i);
+                }
+            }
+
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+
+            match base.get(copy_offset..copy_offset + copy_size) {
+                Some(copied) => result.extend_from_slice(copied),
+                None => {
+                    println!("Delta copies past the end of its base object");
+                    return None;
+                }
+            }
+        } else if instruction != 0 {
+            let size = instruction as usize;
+
+            match delta.get(offset..offset + size) {
+                Some(inserted) => result.extend_from_slice(inserted),
+                None => {
+                    println!("Pack is truncated");
+                    return None;
+                }
+            }
+
+            offset += size;
+        } else {
+            println!("Invalid delta instruction");
+            return None;
+        }
+    }
+
+    if result.len() != result_size {
+        println!(
+            "Delta produced {} bytes, expected {}",
+            result.len(),
+            result_size
+        );
+        return None;
+    }
+
+    Some(result)
+}
+
+fn read_entries(pack: &[u8]) -> Option<Vec<GritPackEntry>> {
+    if pack.len() < 32 || &pack[..4] != b"PACK" {
+        println!("Not a pack file");
+        return None;
+    }
+
+    let (data, checksum) = pack.split_at(pack.len() - 20);
+
+    if Sha1::digest(data).as_slice() != checksum {
+        println!("Pack checksum mismatch");
+        return None;
+    }
+
+    let version = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+    if version != 2 && version != 3 {
+        println!("Unsupported pack version {version}");
+        return None;
+    }
+
+    let count = u32::from_be_bytes([data[8], data[9], data[10], data[11]]);
+    let mut entries = vec![];
+    let mut offset = 12;
+
+    for _ in 0..count {
+        let entry_offset = offset;
+
+        let mut byte = read_byte(data, &mut offset)?;
+        let type_code = (byte >> 4) & 0x07;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+
+        while byte & 0x80 != 0 {
+            byte = read_byte(data, &mut offset)?;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let base = match type_code {
+            OBJ_OFS_DELTA => {
+                byte = read_byte(data, &mut offset)?;
+                let mut distance = (byte & 0x7f) as usize;
+
+                while byte & 0x80 != 0 {
+                    byte = read_byte(data, &mut offset)?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as usize;
+                }
+
+                match entry_offset.checked_sub(distance) {
+                    Some(base_offset) => GritPackBase::Offset(base_offset),
+                    None => {
+                        println!("Delta base offset is outside the pack");
+                        return None;
+                    }
+                }
+            }
+            OBJ_REF_DELTA => {
+                let base_oid = match data.get(offset..offset + 20) {
+                    Some(base_oid) => hex::encode(base_oid),
+                    None => {
+                        println!("Pack is truncated");
+                        return None;
+                    }
+                };
+                offset += 20;
+
+                GritPackBase::Oid(base_oid)
+            }
+            _ => GritPackBase::None,
+        };
+
+        let (content, consumed) = inflate(&data[offset..], size)?;
+        offset += consumed;
+
+        entries.push(GritPackEntry {
+            offset: entry_offset,
+            type_code,
+            base,
+            data: content,
+        });
+    }
+
+    Some(entries)
+}
+
+/// Checks a pack's checksum and that every entry in it can be read, returning the number of objects.
+pub fn verify_pack(pack: &[u8]) -> Option<usize> {
+    read_entries(pack).map(|entries| entries.len())
+}
+
+/// Stores every object in a pack as it is, whatever its content, resolving
+/// deltas against other objects in the pack or ones already stored locally.
+/// Returns the number of objects stored.
+pub fn unpack(pack: &[u8]) -> Option<usize> {
+    let store = object_store::get_object_store()?;
+    let entries = read_entries(pack)?;
+
+    let mut resolved: HashMap<usize, (u8, Vec<u8>)> = HashMap::new();
+    let mut offsets_by_oid = HashMap::new();
+
+    // Bases may come after their deltas, so keep going over what is left until nothing changes
+    while resolved.len() < entries.len() {
+        let mut progressed = false;
+
+        for entry in &entries {
+            if resolved.contains_key(&entry.offset) {
+                continue;
+            }
+
+            let base = match &entry.base {
+                GritPackBase::None => None,
+                GritPackBase::Offset(base_offset) => match resolved.get(base_offset) {
+                    Some(base) => Some(base.clone()),
+                    None => continue,
+                },
+                GritPackBase::Oid(base_oid) => match offsets_by_oid.get(base_oid) {
+                    Some(base_offset) => Some(resolved[base_offset].clone()),
+                    None if store.exists(base_oid) => Some(read_object(store.as_ref(), base_oid)?),
+                    None => continue,
+                },
+            };
+
+            let (type_code, content) = match base {
+                Some((base_type, base_content)) => {
+                    (base_type, apply_delta(&base_content, &entry.data)?)
+                }
+                None => (entry.type_code, entry.data.clone()),
+            };
+
+            let oid = store.write_stream(
+                get_object_type(type_code)?,
+                content.len() as u64,
+                &mut content.as_slice(),
+            )?;
+
+            offsets_by_oid.insert(oid, entry.offset);
+            resolved.insert(entry.offset, (type_code, content));
+            progressed = true;
+        }
+
+        if !progressed {
+            println!("Pack contains deltas whose base objects are missing");
+            return None;
+        }
+    }
+
+    Some(entries.len())
+}
+
+/// Passes reads through while keeping a copy of every byte consumed.
+struct GritRecordingReader<'a, R: BufRead> {
+    inner: &'a mut R,
+    recorded: Vec<u8>,
+}
+
+impl<R: BufRead> Read for GritRecordingReader<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let size = available.len().min(buffer.len());
+        buffer[..size].copy_from_slice(&available[..size]);
+        self.consume(size);
+        Ok(size)
+    }
+}
+
+impl<R: BufRead> BufRead for GritRecordingReader<'_, R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        if let Ok(buffer) = self.inner.fill_buf() {
+            let amount = amount.min(buffer.len());
+            self.recorded.extend_from_slice(&buffer[..amount]);
+        }
+        self.inner.consume(amount);
+    }
+}
+
+fn read_stream_byte(reader: &mut impl Read) -> std::io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Walks the entries of a pack as it arrives on a stream that stays open
+/// afterwards, reading exactly as far as the end of its checksum.
+fn read_pack_entries(reader: &mut GritRecordingReader<impl BufRead>) -> std::io::Result<()> {
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+
+    if &header[..4] != b"PACK" {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "not a pack file",
+        ));
+    }
+
+    let count = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+
+    for _ in 0..count {
+        let mut byte = read_stream_byte(reader)?;
+        let type_code = (byte >> 4) & 0x07;
+
+        while byte & 0x80 != 0 {
+            byte = read_stream_byte(reader)?;
+        }
+
+        match type_code {
+            OBJ_OFS_DELTA => {
+                byte = read_stream_byte(reader)?;
+                while byte & 0x80 != 0 {
+                    byte = read_stream_byte(reader)?;
+                }
+            }
+            OBJ_REF_DELTA => reader.read_exact(&mut [0; 20])?,
+            _ => (),
+        }
+
+        // The decoder only consumes the compressed bytes of this entry
+        std::io::copy(&mut ZlibDecoder::new(&mut *reader), &mut std::io::sink())?;
+    }
+
+    reader.read_exact(&mut [0; 20])
+}
+
+/// Reads one pack from a stream, such as the one following the commands sent to
+/// receive-pack, without needing the other end to close it.
+pub fn read_pack(reader: &mut impl BufRead) -> Option<Vec<u8>> {
+    let mut reader = GritRecordingReader {
+        inner: reader,
+        recorded: vec![],
+    };
+
+    match read_pack_entries(&mut reader) {
+        Ok(_) => Some(reader.recorded),
+        Err(e) => {
+            println!("Failed to read pack: {}", e);
+            None
+        }
+    }
+}
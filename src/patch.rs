@@ -0,0 +1,717 @@
+use crate::config::WORKING_DIR;
+use crate::diff;
+use crate::file_handling;
+use crate::merge;
+use crate::plumbing::{self, GritCatType, GritObjectType};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path;
+
+/// One `@@` section of a unified diff. Each line keeps its ` `, `-` or `+`
+/// prefix and its line terminator, which is missing where the file has none.
+pub struct GritPatchHunk {
+    pub old_start: usize,
+    pub new_start: usize,
+    pub lines: Vec<String>,
+}
+
+impl GritPatchHunk {
+    /// The lines the hunk expects to find, and the lines it replaces them with,
+    /// leaving out up to `fuzz` lines of context at each end. Also gives how
+    /// many lines were left out at the start.
+    fn get_images(&self, fuzz: usize) -> (usize, Vec<&str>, Vec<&str>) {
+        let is_context = |line: &&String| line.starts_with(' ');
+
+        let leading = self.lines.iter().take_while(is_context).count().min(fuzz);
+        let trailing = self.lines[leading..]
+            .iter()
+            .rev()
+            .take_while(is_context)
+            .count()
+            .min(fuzz);
+
+        let mut preimage = vec![];
+        let mut postimage = vec![];
+
+        for line in &self.lines[leading..self.lines.len() - trailing] {
+            let (prefix, text) = line.split_at(1);
+
+            if prefix != "+" {
+                preimage.push(text);
+            }
+            if prefix != "-" {
+                postimage.push(text);
+            }
+        }
+
+        (leading, preimage, postimage)
+    }
+
+    /// The hunk as it appears in a patch.
+    fn format(&self) -> String {
+        let (_leading, preimage, postimage) = self.get_images(0);
+        let mut report = format!(
+            "@@ -{},{} +{},{} @@\n",
+            self.old_start,
+            preimage.len(),
+            self.new_start,
+            postimage.len()
+        );
+
+        for line in &self.lines {
+            report += line;
+
+            if !line.ends_with('\n') {
+                report += "\n\\ No newline at end of file\n";
+            }
+        }
+
+        report
+    }
+}
+
+/// The changes a patch makes to one file. A missing path is `/dev/null`:
+/// the file is created or deleted.
+pub struct GritFilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_mode: Option<String>,
+    pub new_mode: Option<String>,
+    /// The possibly abbreviated blob ids from the `index` line, if there was one.
+    pub old_oid: Option<String>,
+    pub new_oid: Option<String>,
+    pub hunks: Vec<GritPatchHunk>,
+}
+
+impl GritFilePatch {
+    fn new() -> GritFilePatch {
+        GritFilePatch {
+            old_path: None,
+            new_path: None,
+            old_mode: None,
+            new_mode: None,
+            old_oid: None,
+            new_oid: None,
+            hunks: vec![],
+        }
+    }
+
+    /// The path the patch is reported under: the new one, unless the file goes away.
+    pub fn get_path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or("")
+    }
+}
+
+/// Strips the `a/` or `b/` of a path in a diff header, or gives `None` for `/dev/null`.
+fn parse_header_path(path: &str) -> Option<String> {
+    // Anything after a tab is a timestamp
+    let path = path.split('\t').next().unwrap_or(path).trim_end();
+
+    if path == "/dev/null" {
+        return None;
+    }
+
+    let path = path
+        .strip_prefix("a/")
+        .or(path.strip_prefix("b/"))
+        .unwrap_or(path);
+
+    Some(path.to_string())
+}
+
+/// Reads `-old_start,old_count +new_start,new_count` from a hunk header.
+fn parse_hunk_header(line: &str) -> Option<(usize, usize, usize, usize)> {
+    let ranges = line.strip_prefix("@@ -")?.split(" @@").next()?;
+    let (old_range, new_range) = ranges.split_once(" +")?;
+
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+
+    let (old_start, old_count) = parse_range(old_range)?;
+    let (new_start, new_count) = parse_range(new_range)?;
+
+    Some((old_start, old_count, new_start, new_count))
+}
+
+/// Reads the lines of a hunk until both of its ranges are used up.
+fn parse_hunk(
+    header: &str,
+    lines: &mut std::iter::Peekable<std::slice::Iter<&str>>,
+) -> Option<GritPatchHunk> {
+    let (old_start, mut old_count, new_start, mut new_count) = match parse_hunk_header(header) {
+        Some(ranges) => ranges,
+        None => {
+            println!("error: corrupt hunk header '{}'", header.trim_end());
+            return None;
+        }
+    };
+
+    let mut hunk_lines: Vec<String> = vec![];
+
+    while old_count > 0 || new_count > 0 {
+        let line = match lines.next() {
+            Some(line) => *line,
+            None => {
+                println!("error: patch ends in the middle of a hunk");
+                return None;
+            }
+        };
+
+        // Mail programs can strip the space from a blank context line
+        let line = if line == "\n" { " \n" } else { line };
+
+        match line.chars().next() {
+            Some(' ') => {
+                old_count = old_count.saturating_sub(1);
+                new_count = new_count.saturating_sub(1);
+            }
+            Some('-') => old_count = old_count.saturating_sub(1),
+            Some('+') => new_count = new_count.saturating_sub(1),
+            Some('\\') => {
+                strip_line_terminator(&mut hunk_lines);
+                continue;
+            }
+            _ => {
+                println!("error: corrupt patch line '{}'", line.trim_end());
+                return None;
+            }
+        }
+
+        hunk_lines.push(line.to_string());
+    }
+
+    // A `\ No newline at end of file` belongs to the last line of the hunk
+    if lines.peek().is_some_and(|line| line.starts_with('\\')) {
+        lines.next();
+        strip_line_terminator(&mut hunk_lines);
+    }
+
+    Some(GritPatchHunk {
+        old_start,
+        new_start,
+        lines: hunk_lines,
+    })
+}
+
+fn strip_line_terminator(hunk_lines: &mut [String]) {
+    if let Some(last) = hunk_lines.last_mut() {
+        if last.ends_with('\n') {
+            last.pop();
+        }
+    }
+}
+
+/// Reads the file patches in a unified diff as written by `grit diff`,
+/// `grit show` or `git diff`. Text before the first file is ignored.
+pub fn parse_patch(patch: &str) -> Option<Vec<GritFilePatch>> {
+    let lines = diff::split_lines(patch);
+    let mut lines = lines.iter().peekable();
+    let mut file_patches: Vec<GritFilePatch> = vec![];
+    let mut current: Option<GritFilePatch> = None;
+
+    while let Some(line) = lines.next() {
+        let text = line.trim_end_matches('\n');
+
+        if text.starts_with("diff --grit ") || text.starts_with("diff --git ") {
+            file_patches.extend(current.take());
+
+            let mut file_patch = GritFilePatch::new();
+
+            // The paths are only final once `---` and `+++` say so, but a
+            // change of mode alone has nothing else to go by
+            if let Some((old_path, new_path)) = text
+                .split_once(" a/")
+                .and_then(|(_diff, paths)| paths.split_once(" b/"))
+            {
+                file_patch.old_path = Some(old_path.to_string());
+                file_patch.new_path = Some(new_path.to_string());
+            }
+
+            current = Some(file_patch);
+            continue;
+        }
+
+        if text.starts_with("--- ") && lines.peek().is_some_and(|next| next.starts_with("+++ ")) {
+            let file_patch = match current.as_mut() {
+                Some(file_patch) if file_patch.hunks.is_empty() => file_patch,
+                _ => {
+                    file_patches.extend(current.take());
+                    current.insert(GritFilePatch::new())
+                }
+            };
+
+            let new_line = lines.next()?.trim_end_matches('\n');
+            file_patch.old_path = parse_header_path(&text[4..]);
+            file_patch.new_path = parse_header_path(&new_line[4..]);
+            continue;
+        }
+
+        let file_patch = match current.as_mut() {
+            Some(file_patch) => file_patch,
+            None => continue,
+        };
+
+        if text.starts_with("@@ ") {
+            let hunk = parse_hunk(line, &mut lines)?;
+            file_patch.hunks.push(hunk);
+        } else if let Some(mode) = text.strip_prefix("new file mode ") {
+            file_patch.old_path = None;
+            file_patch.new_mode = Some(mode.to_string());
+        } else if let Some(mode) = text.strip_prefix("deleted file mode ") {
+            file_patch.new_path = None;
+            file_patch.old_mode = Some(mode.to_string());
+        } else if let Some(path) = text.strip_prefix("rename from ") {
+            file_patch.old_path = Some(path.to_string());
+        } else if let Some(path) = text.strip_prefix("rename to ") {
+            file_patch.new_path = Some(path.to_string());
+        } else if text.starts_with("GIT binary patch")
+            || (text.starts_with("Binary files ") && text.ends_with(" differ"))
+        {
+            println!(
+                "error: cannot apply binary patch to '{}' without full index line",
+                file_patch.get_path()
+            );
+            return None;
+        } else if let Some(mode) = text.strip_prefix("old mode ") {
+            file_patch.old_mode = Some(mode.to_string());
+        } else if let Some(mode) = text.strip_prefix("new mode ") {
+            file_patch.new_mode = Some(mode.to_string());
+        } else if let Some(index) = text.strip_prefix("index ") {
+            let oids = index.split(' ').next().unwrap_or("");
+
+            if let Some((old_oid, new_oid)) = oids.split_once("..") {
+                file_patch.old_oid = Some(old_oid.to_string());
+                file_patch.new_oid = Some(new_oid.to_string());
+            }
+
+            // The mode after the ids is the mode on both sides
+            if let Some(mode) = index.split(' ').nth(1) {
+                file_patch.old_mode.get_or_insert(mode.to_string());
+                file_patch.new_mode.get_or_insert(mode.to_string());
+            }
+        } else if !file_patch.hunks.is_empty() {
+            // Whatever follows the last hunk, such as a mail signature, ends the file
+            file_patches.extend(current.take());
+        }
+    }
+
+    file_patches.extend(current);
+
+    Some(file_patches)
+}
+
+fn matches_at(lines: &[&str], position: usize, preimage: &[&str]) -> bool {
+    position + preimage.len() <= lines.len()
+        && lines[position..position + preimage.len()] == *preimage
+}
+
+/// Finds where a hunk's preimage is, starting from where it should be and moving
+/// further away in both directions.
+fn find_preimage(lines: &[&str], expected: usize, preimage: &[&str]) -> Option<usize> {
+    let expected = expected.min(lines.len());
+
+    (0..=lines.len()).find_map(|distance| {
+        if expected + distance <= lines.len() && matches_at(lines, expected + distance, preimage) {
+            return Some(expected + distance);
+        }
+
+        match expected.checked_sub(distance) {
+            Some(position) if distance > 0 && matches_at(lines, position, preimage) => {
+                Some(position)
+            }
+            _ => None,
+        }
+    })
+}
+
+/// How many lines of context at either end of a hunk may be ignored to place
+/// it, when the file has changed around the lines the hunk changes.
+const MAX_FUZZ: usize = 2;
+
+/// Applies hunks to the content of a file. Hunks that cannot be placed are
+/// left out, and their positions in `hunks` are returned alongside the result.
+pub fn apply_hunks(content: &str, hunks: &[GritPatchHunk]) -> (String, Vec<usize>) {
+    let mut lines = diff::split_lines(content);
+    let mut failed = vec![];
+
+    // How far earlier hunks have moved the lines of later ones
+    let mut offset: isize = 0;
+
+    for (i, hunk) in hunks.iter().enumerate() {
+        let placed = (0..=MAX_FUZZ).find_map(|fuzz| {
+            let (leading, preimage, postimage) = hunk.get_images(fuzz);
+
+            // Without any context left, a hunk could go anywhere
+            if fuzz > 0 && preimage.is_empty() {
+                return None;
+            }
+
+            // An empty preimage is placed after its start line rather than at it
+            let start = if preimage.is_empty() {
+                hunk.old_start
+            } else {
+                hunk.old_start.saturating_sub(1) + leading
+            };
+            let expected = (start as isize + offset).max(0) as usize;
+
+            find_preimage(&lines, expected, &preimage)
+                .map(|position| (position, start, preimage, postimage))
+        });
+
+        match placed {
+            Some((position, start, preimage, postimage)) => {
+                lines.splice(
+                    position..position + preimage.len(),
+                    postimage.iter().copied(),
+                );
+                offset = position as isize - start as isize + postimage.len() as isize
+                    - preimage.len() as isize;
+            }
+            None => failed.push(i),
+        }
+    }
+
+    (lines.concat(), failed)
+}
+
+/// A file after a file patch has been applied to it, before it is written anywhere.
+pub struct GritPatchedFile {
+    pub old_path: Option<String>,
+    /// `None` when the file is deleted.
+    pub new_path: Option<String>,
+    pub content: String,
+    pub mode: String,
+    /// The hunks that did not apply, by position in the file patch.
+    pub rejected: Vec<usize>,
+}
+
+/// Applies file patches in order to the files `get_file` finds, which gives the
+/// content and mode at a path, or `None` if nothing is there. `location` names
+/// where the files are in errors. With `reject`, files are patched as far as
+/// their hunks allow; otherwise `None` in the option when any hunk fails.
+pub fn patch_files(
+    file_patches: &[GritFilePatch],
+    get_file: impl Fn(&str) -> Option<Option<(String, String)>>,
+    location: &str,
+    reject: bool,
+) -> Option<Option<Vec<GritPatchedFile>>> {
+    // Files as earlier file patches left them, `None` where they were removed
+    let mut files: HashMap<String, Option<(String, String)>> = HashMap::new();
+    let mut patched_files = vec![];
+    let mut applies = true;
+
+    for file_patch in file_patches {
+        let find_file = |path: &str| match files.get(path) {
+            Some(file) => Some(file.clone()),
+            None => get_file(path),
+        };
+
+        let old_file = match &file_patch.old_path {
+            Some(old_path) => match find_file(old_path)? {
+                Some(file) => Some(file),
+                None => {
+                    println!("error: {old_path}: does not exist in {location}");
+                    applies = false;
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        if let Some(new_path) = &file_patch.new_path {
+            if file_patch.old_path.as_ref() != Some(new_path) && find_file(new_path)?.is_some() {
+                println!("error: {new_path}: already exists in {location}");
+                applies = false;
+                continue;
+            }
+        }
+
+        let (old_content, old_mode) = old_file.unwrap_or_default();
+        let (content, rejected) = apply_hunks(&old_content, &file_patch.hunks);
+        let path = file_patch.get_path();
+
+        for i in &rejected {
+            println!(
+                "error: patch failed: {path}:{}",
+                file_patch.hunks[*i].old_start
+            );
+        }
+
+        if !rejected.is_empty() && !reject {
+            println!("error: {path}: patch does not apply");
+            applies = false;
+            continue;
+        }
+
+        // A deletion that only partly applied leaves what is left of the file
+        let new_path = match &file_patch.new_path {
+            None if !rejected.is_empty() => file_patch.old_path.clone(),
+            None if !content.is_empty() => {
+                println!("error: removal patch leaves file contents: {path}");
+                applies = false;
+                continue;
+            }
+            new_path => new_path.clone(),
+        };
+
+        let mode = file_patch
+            .new_mode
+            .clone()
+            .or(Some(old_mode).filter(|mode| !mode.is_empty()))
+            .unwrap_or("100644".to_string());
+
+        if let Some(old_path) = &file_patch.old_path {
+            files.insert(old_path.to_string(), None);
+        }
+        if let Some(new_path) = &new_path {
+            files.insert(new_path.to_string(), Some((content.clone(), mode.clone())));
+        }
+
+        patched_files.push(GritPatchedFile {
+            old_path: file_patch.old_path.clone(),
+            new_path,
+            content,
+            mode,
+            rejected,
+        });
+    }
+
+    Some(if applies { Some(patched_files) } else { None })
+}
+
+fn get_entry_file(
+    entries: &HashMap<String, String>,
+    path: &str,
+) -> Option<Option<(String, String)>> {
+    match entries.get(path) {
+        Some(entry) => {
+            let entry = plumbing::parse_entry(entry)?;
+
+            Some(Some((
+                plumbing::generate_cat_content(&entry.oid, GritCatType::Pretty)?,
+                entry.mode.value().to_string(),
+            )))
+        }
+        None => Some(None),
+    }
+}
+
+/// Puts patched files into tree entries keyed by path, writing their blobs.
+fn update_entries(
+    entries: &HashMap<String, String>,
+    patched_files: &[GritPatchedFile],
+) -> Option<HashMap<String, String>> {
+    let mut result = entries.clone();
+
+    for patched_file in patched_files {
+        if let Some(old_path) = &patched_file.old_path {
+            result.remove(old_path);
+        }
+
+        if let Some(new_path) = &patched_file.new_path {
+            let oid = plumbing::hash_object(&patched_file.content, GritObjectType::Blob, true)?;
+
+            result.insert(
+                new_path.to_string(),
+                format!("{} blob {oid} {new_path}", patched_file.mode),
+            );
+        }
+    }
+
+    Some(result)
+}
+
+/// Works out the tree entries, keyed by path, that applying the file patches
+/// to `entries` gives. New blobs are written to the object store. `None` in
+/// the option when any part of the patch does not apply, which is reported.
+pub fn apply_to_entries(
+    entries: &HashMap<String, String>,
+    file_patches: &[GritFilePatch],
+) -> Option<Option<HashMap<String, String>>> {
+    let get_file = |path: &str| get_entry_file(entries, path);
+
+    match patch_files(file_patches, get_file, "index", false)? {
+        Some(patched_files) => Some(Some(update_entries(entries, &patched_files)?)),
+        None => Some(None),
+    }
+}
+
+pub struct GritApplyOptions {
+    /// Applies to the index alone, leaving the working tree as it is.
+    pub cached: bool,
+    /// Only reports whether the patch applies.
+    pub check: bool,
+    /// Applies what it can and writes the hunks that fail to `<path>.rej`.
+    pub reject: bool,
+}
+
+fn get_working_file(path: &str) -> Option<Option<(String, String)>> {
+    let file_path = format!("{WORKING_DIR}/{path}");
+
+    let metadata = match fs::metadata(&file_path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Some(None),
+    };
+
+    let mode = if metadata.permissions().mode() & 0o111 != 0 {
+        "100755"
+    } else {
+        "100644"
+    };
+
+    Some(Some((
+        file_handling::read_file(&file_path)?,
+        mode.to_string(),
+    )))
+}
+
+fn write_working_file(path: &str, content: &str, mode: &str) -> Option<()> {
+    let file_path = format!("{WORKING_DIR}/{path}");
+
+    if let Some(parent) = path::Path::new(&file_path).parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            println!("Failed to create directory {:?}: {}", parent, e);
+            return None;
+        }
+    }
+
+    let permissions = if mode == "100755" { 0o755 } else { 0o644 };
+
+    match fs::write(&file_path, content)
+        .and_then(|_| fs::set_permissions(&file_path, fs::Permissions::from_mode(permissions)))
+    {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to write file {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Writes the hunks of a file patch that did not apply next to the file, in
+/// patch form, so they can be applied by hand.
+fn write_rejects(file_patch: &GritFilePatch, patched_file: &GritPatchedFile) -> Option<()> {
+    let path = file_patch.get_path();
+    let hunk_count = file_patch.hunks.len();
+
+    match patched_file.rejected.len() {
+        1 => println!("Applying patch {path} with 1 reject..."),
+        count => println!("Applying patch {path} with {count} rejects..."),
+    }
+
+    let mut rejects = format!("diff a/{path} b/{path}\t(rejected hunks)\n");
+
+    for i in 0..hunk_count {
+        if patched_file.rejected.contains(&i) {
+            println!("Rejected hunk #{}.", i + 1);
+            rejects += file_patch.hunks[i].format().as_str();
+        } else {
+            println!("Hunk #{} applied cleanly.", i + 1);
+        }
+    }
+
+    let rejects_path = format!("{WORKING_DIR}/{path}.rej");
+
+    match fs::write(&rejects_path, rejects) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to write {}: {}", rejects_path, e);
+            None
+        }
+    }
+}
+
+/// Applies a unified diff to the working tree, or with `--cached` to the
+/// index. Nothing is changed unless the whole patch applies, except with
+/// `--reject`, where failing hunks go to `.rej` files instead. Reads stdin
+/// when no patch file is given.
+pub fn apply(patch_path: Option<&str>, options: &GritApplyOptions) -> Option<()> {
+    let patch = match patch_path {
+        Some(patch_path) if patch_path != "-" => file_handling::read_file(patch_path)?,
+        _ => {
+            let mut patch = String::new();
+
+            if let Err(e) = io::stdin().read_to_string(&mut patch) {
+                println!("Failed to read patch from stdin: {}", e);
+                return None;
+            }
+
+            patch
+        }
+    };
+
+    let file_patches = parse_patch(&patch)?;
+
+    if file_patches.is_empty() {
+        println!("error: No valid patches in input");
+        return None;
+    }
+
+    let index_entries = if options.cached {
+        let (_index_map, _index_paths, index_entries, _index_oids) = plumbing::get_index_entries()?;
+        merge::get_entries_by_path(&index_entries)
+    } else {
+        HashMap::new()
+    };
+
+    let patched_files = if options.cached {
+        let get_file = |path: &str| get_entry_file(&index_entries, path);
+        patch_files(&file_patches, get_file, "index", options.reject)?
+    } else {
+        patch_files(
+            &file_patches,
+            get_working_file,
+            "working directory",
+            options.reject,
+        )?
+    };
+
+    let patched_files = patched_files?;
+    let rejected = patched_files.iter().any(|file| !file.rejected.is_empty());
+
+    if options.check {
+        return if rejected { None } else { Some(()) };
+    }
+
+    if options.cached {
+        let mut entries = update_entries(&index_entries, &patched_files)?
+            .into_values()
+            .collect::<Vec<String>>();
+        entries.sort_by_key(|entry| plumbing::get_entry_path(entry));
+
+        plumbing::write_index(&entries)?;
+    } else {
+        for patched_file in &patched_files {
+            if let Some(old_path) = &patched_file.old_path {
+                if patched_file.new_path.as_ref() != Some(old_path) {
+                    plumbing::remove_working_file(old_path)?;
+                }
+            }
+
+            if let Some(new_path) = &patched_file.new_path {
+                write_working_file(new_path, &patched_file.content, &patched_file.mode)?;
+            }
+        }
+    }
+
+    for (file_patch, patched_file) in file_patches.iter().zip(&patched_files) {
+        if !patched_file.rejected.is_empty() {
+            write_rejects(file_patch, patched_file)?;
+        }
+    }
+
+    if rejected {
+        None
+    } else {
+        Some(())
+    }
+}
@@ -0,0 +1,180 @@
+use std::io::{ErrorKind, Read};
+
+/// One unit of git's pkt-line framing: data prefixed by its length as four
+/// hex digits, or one of the special zero-length packets.
+#[derive(Debug, PartialEq)]
+pub enum GritPktLine {
+    Data(Vec<u8>),
+    /// `0000`, the end of a message.
+    Flush,
+    /// `0001`, the end of a section within a protocol v2 message.
+    Delimiter,
+    /// `0002`, the end of a protocol v2 response.
+    ResponseEnd,
+}
+
+/// The largest payload a single packet can carry.
+pub const MAX_PKT_DATA: usize = 65516;
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut pkt_line = format!("{:04x}", data.len() + 4).into_bytes();
+    pkt_line.extend_from_slice(data);
+    pkt_line
+}
+
+pub fn encode_str(line: &str) -> Vec<u8> {
+    encode(line.as_bytes())
+}
+
+pub fn flush() -> Vec<u8> {
+    b"0000".to_vec()
+}
+
+pub fn delimiter() -> Vec<u8> {
+    b"0001".to_vec()
+}
+
+/// Splits `data` over as many side-band packets on `band` as it needs.
+pub fn encode_side_band(band: u8, data: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![];
+
+    for chunk in data.chunks(MAX_PKT_DATA - 1) {
+        let mut payload = vec![band];
+        payload.extend_from_slice(chunk);
+        encoded.extend(encode(&payload));
+    }
+
+    encoded
+}
+
+/// Reads one packet from the front of `data`, returning it with the number of bytes it took up.
+pub fn read_pkt_line(data: &[u8]) -> Option<(GritPktLine, usize)> {
+    if data.len() < 4 {
+        println!("Truncated pkt-line in response");
+        return None;
+    }
+
+    let length = match std::str::from_utf8(&data[..4])
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+    {
+        Some(length) => length,
+        None => {
+            println!(
+                "Invalid pkt-line length: {:?}",
+                String::from_utf8_lossy(&data[..4])
+            );
+            return None;
+        }
+    };
+
+    match length {
+        0 => Some((GritPktLine::Flush, 4)),
+        1 => Some((GritPktLine::Delimiter, 4)),
+        2 => Some((GritPktLine::ResponseEnd, 4)),
+        3 => {
+            println!("Invalid pkt-line length: 3");
+            None
+        }
+        _ if length > data.len() => {
+            println!("Truncated pkt-line in response");
+            None
+        }
+        _ => Some((GritPktLine::Data(data[4..length].to_vec()), length)),
+    }
+}
+
+/// Reads packets up to and including the first flush, returning them with the number of bytes read.
+pub fn read_until_flush(data: &[u8]) -> Option<(Vec<GritPktLine>, usize)> {
+    let mut pkt_lines = vec![];
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let (pkt_line, length) = read_pkt_line(&data[offset..])?;
+        offset += length;
+
+        if pkt_line == GritPktLine::Flush {
+            return Some((pkt_lines, offset));
+        }
+
+        pkt_lines.push(pkt_line);
+    }
+
+    Some((pkt_lines, offset))
+}
+
+/// The text of a data packet, without its trailing newline.
+pub fn to_text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+/// Reads one packet from a stream. Returns `None` inside the option at the end of the stream.
+pub fn read_pkt_line_from(reader: &mut impl Read) -> Option<Option<GritPktLine>> {
+    let mut length = [0; 4];
+
+    match reader.read_exact(&mut length) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Some(None),
+        Err(e) => {
+            println!("Failed to read pkt-line: {}", e);
+            return None;
+        }
+    }
+
+    let length = match std::str::from_utf8(&length)
+        .ok()
+        .and_then(|length| usize::from_str_radix(length, 16).ok())
+    {
+        Some(length) => length,
+        None => {
+            println!(
+                "Invalid pkt-line length: {:?}",
+                String::from_utf8_lossy(&length)
+            );
+            return None;
+        }
+    };
+
+    match length {
+        0 => Some(Some(GritPktLine::Flush)),
+        1 => Some(Some(GritPktLine::Delimiter)),
+        2 => Some(Some(GritPktLine::ResponseEnd)),
+        3 => {
+            println!("Invalid pkt-line length: 3");
+            None
+        }
+        _ => {
+            let mut data = vec![0; length - 4];
+
+            match reader.read_exact(&mut data) {
+                Ok(_) => Some(Some(GritPktLine::Data(data))),
+                Err(e) => {
+                    println!("Failed to read pkt-line: {}", e);
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Reads packets from a stream up to and including the next flush, returning
+/// their encoded bytes. Empty at the end of the stream.
+pub fn read_message_from(reader: &mut impl Read) -> Option<Vec<u8>> {
+    let mut message = vec![];
+
+    while let Some(pkt_line) = read_pkt_line_from(reader)? {
+        match pkt_line {
+            GritPktLine::Data(data) => message.extend(encode(&data)),
+            GritPktLine::Delimiter => message.extend(delimiter()),
+            GritPktLine::ResponseEnd => message.extend(b"0002"),
+            GritPktLine::Flush => {
+                message.extend(flush());
+                break;
+            }
+        }
+    }
+
+    Some(message)
+}
@@ -1,49 +1,171 @@
-use crate::config::{GRIT_DIRECTORY, WORKING_DIR};
+use crate::config::{self, GRIT_DIRECTORY, WORKING_DIR};
 use crate::file_handling;
+use crate::ignore;
+use crate::lockfile::GritLockFile;
+use crate::object::{self, GritCommit, GritObject, GritTreeEntry};
+use crate::object_store::{self, GritLooseObjectStore, GritObjectStore};
+use crate::reflog;
+use crate::refs::{self, GritRefTransaction};
 use crate::utils;
+use rayon::prelude::*;
 use std::collections::HashMap;
-use std::fs::{self, File, OpenOptions};
-use std::io::prelude::*;
+use std::fs::{self, File};
+use std::io::{self, Write};
 use std::path;
-use walkdir::WalkDir;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum GritObjectType {
     Blob,
     Tree,
     Commit,
+    Tag,
+}
+
+impl GritObjectType {
+    pub fn parse(name: &str) -> Option<GritObjectType> {
+        match name {
+            "blob" => Some(GritObjectType::Blob),
+            "tree" => Some(GritObjectType::Tree),
+            "commit" => Some(GritObjectType::Commit),
+            "tag" => Some(GritObjectType::Tag),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GritObjectType::Blob => "blob",
+            GritObjectType::Tree => "tree",
+            GritObjectType::Commit => "commit",
+            GritObjectType::Tag => "tag",
+        }
+    }
 }
 
 pub enum GritCatType {
     Type,
     Pretty,
     Size,
+    /// The content exactly as stored, of an object that must have this type.
+    Raw(GritObjectType),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum GritMode {
     Normal,
     Executable,
     Symlink,
 }
 
+impl GritMode {
+    pub fn parse(value: &str) -> Option<GritMode> {
+        match value {
+            "100644" => Some(GritMode::Normal),
+            "100755" => Some(GritMode::Executable),
+            "120000" => Some(GritMode::Symlink),
+            _ => None,
+        }
+    }
+
+    pub fn value(&self) -> &'static str {
+        match self {
+            GritMode::Normal => "100644",
+            GritMode::Executable => "100755",
+            GritMode::Symlink => "120000",
+        }
+    }
+}
+
+/// Entries keyed by path, followed by the paths, raw entry lines and oids in file order.
+pub type GritEntries = (
+    HashMap<String, String>,
+    Vec<String>,
+    Vec<String>,
+    Vec<String>,
+);
+
+pub const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
 struct GritAuthor {
     pub name: String,
+    pub email: String,
 }
 
-pub fn hash_object(content: &str, object_type: GritObjectType, write: bool) -> Option<String> {
-    let bytes_size = content.len();
+fn get_author() -> GritAuthor {
+    GritAuthor {
+        name: config::get_config_value("user.name").unwrap_or("Bhekani Khumalo".to_string()),
+        email: config::get_config_value("user.email").unwrap_or_default(),
+    }
+}
+
+pub fn get_identity_name() -> String {
+    get_author().name
+}
+
+/// The `name <email>` identity recorded in reflogs.
+pub fn get_identity() -> String {
+    let author = get_author();
 
-    let header = match object_type {
-        GritObjectType::Blob => format!("blob {}\0", bytes_size),
-        GritObjectType::Tree => format!("tree {}\0", bytes_size),
-        GritObjectType::Commit => format!("commit {}\0", bytes_size),
+    format!("{} <{}>", author.name, author.email)
+}
+
+/// Hashes a file as an object a chunk at a time, with the size for the header
+/// taken from its metadata, and stores it too when `write` is set. The file is
+/// never held in memory whole.
+pub fn hash_file_object(
+    filepath: &str,
+    object_type: GritObjectType,
+    write: bool,
+) -> Option<String> {
+    let opened = File::open(filepath).and_then(|file| {
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    });
+
+    let (mut file, size) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            println!("Failed to read file {}: {}", filepath, e);
+            return None;
+        }
     };
 
+    if write {
+        return object_store::get_object_store()?.write_stream(object_type, size, &mut file);
+    }
+
+    match file_handling::hash_stream(object_type.name(), size, &mut file, &mut |_chunk| Ok(())) {
+        Ok(oid) => Some(oid),
+        Err(e) => {
+            println!("Failed to read file {}: {}", filepath, e);
+            None
+        }
+    }
+}
+
+/// Hashes a working tree file as a blob without storing it, quietly, so it can
+/// run on any thread and leave reporting to the caller.
+fn hash_working_file(filename: &str) -> io::Result<String> {
+    let mut file = File::open(format!("{WORKING_DIR}/{filename}"))?;
+    let size = file.metadata()?.len();
+
+    file_handling::hash_stream(
+        GritObjectType::Blob.name(),
+        size,
+        &mut file,
+        &mut |_chunk| Ok(()),
+    )
+}
+
+pub fn hash_object(content: &str, object_type: GritObjectType, write: bool) -> Option<String> {
+    let header = format!("{} {}\0", object_type.name(), content.len());
+
     let store = format!("{header}{content}");
 
     let oid = file_handling::hash_file(&store);
 
     if write {
-        file_handling::save_object(&oid, store)?;
+        file_handling::save_object(&oid, store.as_bytes())?;
     }
 
     Some(oid)
@@ -54,12 +176,7 @@ pub fn index_is_empty() -> Option<bool> {
     Some(index_file_content.is_empty())
 }
 
-pub fn get_index_entries() -> Option<(
-    HashMap<String, String>,
-    Vec<String>,
-    Vec<String>,
-    Vec<String>,
-)> {
+pub fn get_index_entries() -> Option<GritEntries> {
     let index_file_content = file_handling::read_grit_file("index")?;
     let mut index_objects_map = HashMap::new();
     let mut paths = vec![];
@@ -76,57 +193,55 @@ pub fn get_index_entries() -> Option<(
         .filter(|line| !line.is_empty())
         .collect::<Vec<String>>();
 
-    index_entries.iter().for_each(|entry| {
-        let entry = entry.split(" ").collect::<Vec<&str>>();
-        index_objects_map.insert(entry[3].to_string(), entry[2].to_string());
-        paths.push(entry[3].to_string());
-        oids.push(entry[2].to_string());
-    });
+    for entry in &index_entries {
+        let entry = match GritTreeEntry::parse(entry) {
+            Some(entry) => entry,
+            None => {
+                println!("Malformed index entry '{entry}'");
+                return None;
+            }
+        };
+
+        index_objects_map.insert(entry.path.clone(), entry.oid.clone());
+        paths.push(entry.path);
+        oids.push(entry.oid);
+    }
 
     Some((index_objects_map, paths, index_entries, oids))
 }
 
-pub fn get_head_tree_entries(
-    commit_oid: &str,
-) -> Option<(
-    HashMap<String, String>,
-    Vec<String>,
-    Vec<String>,
-    Vec<String>,
-)> {
-    let mut head_objects_map = HashMap::new();
-    let mut paths = vec![];
-    let mut oids = vec![];
-
-    if commit_oid == "" {
-        return Some((head_objects_map, paths, vec![], oids));
+pub fn get_head_tree_entries(commit_oid: &str) -> Option<GritEntries> {
+    if commit_oid.is_empty() {
+        return Some((HashMap::new(), vec![], vec![], vec![]));
     }
 
-    let commit_content = generate_cat_content(&commit_oid, GritCatType::Pretty)?;
+    let tree_oid = get_commit_tree_oid(commit_oid)?;
 
-    let tree_line = commit_content
-        .split("\n")
-        .find(|line| line.starts_with("tree"))?;
+    get_tree_entries(&tree_oid)
+}
 
-    let tree_oid = tree_line.split(" ").collect::<Vec<&str>>()[1];
+pub fn get_tree_entries(tree_oid: &str) -> Option<GritEntries> {
+    let mut tree_objects_map = HashMap::new();
+    let mut paths = vec![];
+    let mut tree_entries = vec![];
+    let mut oids = vec![];
 
-    let tree = generate_cat_content(tree_oid, GritCatType::Pretty)?;
+    for entry in object::read_tree(tree_oid)?.entries {
+        tree_objects_map.insert(entry.path.clone(), entry.oid.clone());
+        tree_entries.push(entry.to_string());
+        paths.push(entry.path);
+        oids.push(entry.oid);
+    }
 
-    let tree_entries = tree
-        .trim()
-        .split("\n")
-        .map(|line| line.trim().to_string())
-        .filter(|line| !line.is_empty())
-        .collect::<Vec<String>>();
+    Some((tree_objects_map, paths, tree_entries, oids))
+}
 
-    tree_entries.iter().for_each(|entry| {
-        let entry = entry.split(" ").collect::<Vec<&str>>();
-        head_objects_map.insert(entry[3].to_string(), entry[2].to_string());
-        paths.push(entry[3].to_string());
-        oids.push(entry[2].to_string());
-    });
+pub fn get_commit_tree_oid(commit_oid: &str) -> Option<String> {
+    Some(object::read_commit(commit_oid)?.tree)
+}
 
-    Some((head_objects_map, paths, tree_entries, oids))
+pub fn get_commit_parents(commit_oid: &str) -> Option<Vec<String>> {
+    Some(object::read_commit(commit_oid)?.parents)
 }
 
 pub fn exists_in_index(filename: &str) -> Option<bool> {
@@ -136,111 +251,66 @@ pub fn exists_in_index(filename: &str) -> Option<bool> {
         return Some(false);
     }
 
-    Some(match index_objects_map.get(filename) {
-        Some(_) => true,
-        None => false,
-    })
+    Some(index_objects_map.contains_key(filename))
 }
 
-pub fn update_in_index(filename: &str, entry: &str) -> Option<()> {
-    let (index_objects_map, _paths, index_entries, _oids) = get_index_entries()?;
+fn get_index_path() -> String {
+    format!("{GRIT_DIRECTORY}/index")
+}
 
-    if index_objects_map.is_empty() {
-        return None;
-    }
+/// Rewrites the index while holding `index.lock`. The entries are read after
+/// the lock is taken, so concurrent changes are never lost.
+fn modify_index(modify: impl FnOnce(Vec<String>) -> Option<Vec<String>>) -> Option<()> {
+    let mut lock = GritLockFile::acquire(&get_index_path())?;
 
-    let updated_lines = index_entries
-        .iter()
-        .map(|path| {
-            if path.split(" ").collect::<Vec<&str>>()[3] == filename {
-                entry.trim().to_string()
-            } else {
-                path.to_string()
-            }
-        })
-        .collect::<Vec<String>>()
-        .join("\n");
+    let (_index_objects_map, _paths, index_entries, _oids) = get_index_entries()?;
+    let updated_entries = modify(index_entries)?;
 
-    let index_file_path = format!("{GRIT_DIRECTORY}/index");
+    lock.write(updated_entries.join("\n").as_bytes())?;
+    lock.commit()
+}
 
-    let mut index_file = match File::create(index_file_path) {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Failed to create index: {}", e);
+pub fn update_in_index(filename: &str, entry: &str) -> Option<()> {
+    modify_index(|index_entries| {
+        if index_entries.is_empty() {
             return None;
         }
-    };
 
-    match index_file.write_all(updated_lines.as_bytes()) {
-        Ok(_) => Some(()),
-        Err(e) => {
-            println!("Failed to update index: {}", e);
-            return None;
-        }
-    }
+        let updated_entries = index_entries
+            .iter()
+            .map(|path| {
+                if get_entry_path(path) == filename {
+                    entry.trim().to_string()
+                } else {
+                    path.to_string()
+                }
+            })
+            .collect();
+
+        Some(updated_entries)
+    })
 }
 
 pub fn remove_from_index(filename: &str) -> Option<()> {
-    let (index_objects_map, _paths, index_entries, _oids) = get_index_entries()?;
-
-    if index_objects_map.is_empty() {
-        return None;
-    }
-
-    let updated_lines = index_entries
-        .iter()
-        .filter(|path| path.split(" ").collect::<Vec<&str>>()[3] != filename)
-        .map(|path| path.to_string())
-        .collect::<Vec<String>>()
-        .join("\n");
-
-    let index_file_path = format!("{GRIT_DIRECTORY}/index");
-
-    let mut index_file = match File::create(index_file_path) {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Failed to create index: {}", e);
+    modify_index(|index_entries| {
+        if index_entries.is_empty() {
             return None;
         }
-    };
 
-    match index_file.write_all(updated_lines.as_bytes()) {
-        Ok(_) => Some(()),
-        Err(e) => {
-            println!("Failed to update index: {}", e);
-            return None;
-        }
-    }
+        let updated_entries = index_entries
+            .into_iter()
+            .filter(|path| get_entry_path(path) != filename)
+            .collect();
+
+        Some(updated_entries)
+    })
 }
 
 pub fn append_to_index(entry: &str) -> Option<()> {
-    let index_file_path = format!("{GRIT_DIRECTORY}/index");
-
-    let mut index_file = match OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(index_file_path)
-    {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Failed to create index: {}", e);
-            return None;
-        }
-    };
-
-    let content_to_write = if !index_is_empty()? {
-        format!("\n{}", &entry)
-    } else {
-        entry.to_string()
-    };
-
-    match index_file.write_all(content_to_write.as_bytes()) {
-        Ok(_) => Some(()),
-        Err(e) => {
-            println!("Failed to update index: {}", e);
-            return None;
-        }
-    }
+    modify_index(|mut index_entries| {
+        index_entries.push(entry.to_string());
+        Some(index_entries)
+    })
 }
 
 pub fn update_index(
@@ -249,53 +319,66 @@ pub fn update_index(
     oid: &str,
     filename: &str,
 ) -> Option<()> {
-    let mode_value = match mode {
-        GritMode::Normal => 100644,
-        GritMode::Executable => 100755,
-        GritMode::Symlink => 120000,
-    };
-
-    let object_type = match object_type {
-        GritObjectType::Blob => "blob",
-        GritObjectType::Tree => "tree",
-        GritObjectType::Commit => "commit",
-    };
-
-    let entry = format!("{mode_value} {object_type} {oid} {filename}");
+    let entry = format!("{} {} {oid} {filename}", mode.value(), object_type.name());
 
     let exists = exists_in_index(filename)?;
 
     if exists {
-        update_in_index(&filename, &entry)
+        update_in_index(filename, &entry)
     } else {
         append_to_index(&entry)
     }
 }
 
-pub fn clear_index() -> Option<()> {
-    let index_file_path = format!("{GRIT_DIRECTORY}/index");
+/// Stages `(oid, path)` blobs with one rewrite of the index, replacing the
+/// entries at those paths in place and adding the rest at the end.
+pub fn update_index_blobs(mode: GritMode, blobs: &[(String, String)]) -> Option<()> {
+    if blobs.is_empty() {
+        return Some(());
+    }
 
-    let mut file = match File::create(&index_file_path) {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Failed to open index file: {}", e);
-            return None;
-        }
-    };
+    let mut entries = blobs
+        .iter()
+        .map(|(oid, path)| {
+            let entry = GritTreeEntry {
+                mode,
+                object_type: GritObjectType::Blob,
+                oid: oid.to_string(),
+                path: path.to_string(),
+            };
+
+            (path.as_str(), entry.to_string())
+        })
+        .collect::<HashMap<&str, String>>();
+
+    modify_index(|index_entries| {
+        let mut updated_entries = index_entries
+            .into_iter()
+            .map(|index_entry| {
+                entries
+                    .remove(get_entry_path(&index_entry).as_str())
+                    .unwrap_or(index_entry)
+            })
+            .collect::<Vec<String>>();
+
+        updated_entries.extend(
+            blobs
+                .iter()
+                .filter_map(|(_oid, path)| entries.remove(path.as_str())),
+        );
+
+        Some(updated_entries)
+    })
+}
 
-    match file.write_all("".as_bytes()) {
-        Ok(_) => Some(()),
-        Err(e) => {
-            println!("Failed to update index file: {}", e);
-            return None;
-        }
-    }
+pub fn clear_index() -> Option<()> {
+    write_index(&[])
 }
 
 pub fn write_tree() -> Option<String> {
     let index_file_path = "index";
 
-    let content = file_handling::read_grit_file(&index_file_path)?;
+    let content = file_handling::read_grit_file(index_file_path)?;
 
     if content.is_empty() {
         println!("Nothing to write");
@@ -307,104 +390,347 @@ pub fn write_tree() -> Option<String> {
     Some(tree_oid)
 }
 
-pub fn read_tree(directory_name: &str, oid: &str) {
-    update_index(GritMode::Normal, GritObjectType::Tree, oid, directory_name);
+/// Writes a tree object holding the given `mode type oid path` entries, sorted by path.
+pub fn write_tree_from_entries(entries: &[String]) -> Option<String> {
+    hash_tree_from_entries(entries, true)
 }
 
-pub fn unhash_object(oid: &str) -> Option<String> {
-    let sub_directory = &oid[..2].to_string();
-    let filename = &oid[2..];
-    let object_path = format!("{GRIT_DIRECTORY}/objects/{sub_directory}");
-    let read_dirs = match fs::read_dir(&object_path) {
-        Ok(result) => result,
-        Err(e) => {
-            println!("Failed to read directory {}: {}", &object_path, e);
-            return None;
-        }
-    };
+/// The oid of a tree holding the given `mode type oid path` entries, sorted by
+/// path. The tree is only stored when `write` is set.
+pub fn hash_tree_from_entries(entries: &[String], write: bool) -> Option<String> {
+    let mut entries = entries.to_vec();
+    entries.sort_by_key(|entry| get_entry_path(entry));
 
-    let paths = read_dirs
-        .map(
-            |object| match object.expect("Failed to read file").file_name().to_str() {
-                Some(path) => path.to_string(),
-                None => "".to_string(),
-            },
-        )
-        .collect::<Vec<String>>();
+    hash_object(&entries.join("\n"), GritObjectType::Tree, write)
+}
 
-    let full_oid = format!(
-        "{}{}",
-        sub_directory,
-        paths.iter().find(|path| path.starts_with(&filename))?
-    );
+/// Replaces the whole index with the given `mode type oid path` entries.
+pub fn write_index(entries: &[String]) -> Option<()> {
+    let mut lock = GritLockFile::acquire(&get_index_path())?;
 
-    let file_path = file_handling::get_object_path_from_oid(&full_oid, false);
+    lock.write(entries.join("\n").as_bytes())?;
+    lock.commit()
+}
+
+/// Reads a `mode type oid path` entry, reporting it when it is malformed.
+pub fn parse_entry(entry: &str) -> Option<GritTreeEntry> {
+    let parsed = GritTreeEntry::parse(entry);
+
+    if parsed.is_none() {
+        println!("Malformed entry '{entry}'");
+    }
 
-    let compressed_contents = file_handling::read_file_as_bytes(&file_path)?;
+    parsed
+}
+
+/// The path of a `mode type oid path` entry, which may itself contain spaces.
+pub fn get_entry_path(entry: &str) -> String {
+    entry.splitn(4, ' ').nth(3).unwrap_or("").to_string()
+}
+
+pub fn read_tree(directory_name: &str, oid: &str) {
+    update_index(GritMode::Normal, GritObjectType::Tree, oid, directory_name);
+}
+
+/// The full id of a stored object. Abbreviated ids take the first object they match.
+fn get_stored_oid(store: &dyn GritObjectStore, oid: &str) -> Option<String> {
+    if store.exists(oid) {
+        Some(oid.to_string())
+    } else {
+        store.find_by_prefix(oid).into_iter().next()
+    }
+}
 
-    let content = utils::decompress_object_content(&compressed_contents)?;
+pub fn unhash_object(oid: &str) -> Option<Vec<u8>> {
+    let store = object_store::get_object_store()?;
 
-    Some(content)
+    store.read(&get_stored_oid(store.as_ref(), oid)?)
 }
 
-fn get_object_contents(content: &str) -> String {
-    content.split("\0").collect::<Vec<&str>>()[1].to_string()
+/// Splits a stored object into its `type size` header and its content. Only
+/// the first NUL ends the header; the content may contain more.
+fn split_object(oid: &str, object: &[u8]) -> Option<(String, String, Vec<u8>)> {
+    let parts = object
+        .iter()
+        .position(|byte| *byte == b'\0')
+        .and_then(|end| {
+            let header = std::str::from_utf8(&object[..end]).ok()?;
+            let (object_type, size) = header.split_once(' ')?;
+            Some((
+                object_type.to_string(),
+                size.to_string(),
+                object[end + 1..].to_vec(),
+            ))
+        });
+
+    if parts.is_none() {
+        println!("Object {oid} is corrupt");
+    }
+
+    parts
 }
 
-fn get_object_size(content: &str) -> String {
-    content.split("\0").collect::<Vec<&str>>()[0]
-        .split(" ")
-        .collect::<Vec<&str>>()[1]
-        .to_string()
+/// Reads an object from the object store as its type and content, which is
+/// left as stored bytes for the callers that know whether it is text.
+pub fn read_object(oid: &str) -> Option<(GritObjectType, Vec<u8>)> {
+    let (object_type, _size, content) = split_object(oid, &unhash_object(oid)?)?;
+
+    match GritObjectType::parse(&object_type) {
+        Some(object_type) => Some((object_type, content)),
+        None => {
+            println!("Object {oid} has unknown type {object_type}");
+            None
+        }
+    }
 }
 
-fn get_object_type(content: &str) -> String {
-    content.split("\0").collect::<Vec<&str>>()[0]
-        .split(" ")
-        .collect::<Vec<&str>>()[0]
-        .to_string()
+/// The content of an object as text, for the commands that work on lines.
+pub fn decode_text(oid: &str, content: Vec<u8>) -> Option<String> {
+    match String::from_utf8(content) {
+        Ok(text) => Some(text),
+        Err(_) => {
+            println!("Object {oid} is not text");
+            None
+        }
+    }
 }
 
+/// The type, size or content of an object. The type and size only need the
+/// header, so the content is never decompressed for them.
 pub fn generate_cat_content(oid: &str, cat_type: GritCatType) -> Option<String> {
-    let content = unhash_object(oid)?;
+    if let GritCatType::Pretty | GritCatType::Raw(_) = cat_type {
+        let (_object_type, _size, content) = split_object(oid, &unhash_object(oid)?)?;
+        return decode_text(oid, content);
+    }
+
+    let store = object_store::get_object_store()?;
+    let oid = get_stored_oid(store.as_ref(), oid)?;
+    let (object_type, size, _reader) = object_store::open_object(store.as_ref(), &oid)?;
+
+    match cat_type {
+        GritCatType::Type => Some(object_type),
+        _ => Some(size),
+    }
+}
+
+/// Prints an object, copying its content to stdout a chunk at a time as it is
+/// decompressed. The type and size only need the header to be read. Blobs and
+/// raw content come out byte for byte; the other types are text, so `-p` ends
+/// them with a newline.
+pub fn cat_file(oid: &str, cat_type: GritCatType) -> Option<()> {
+    let store = object_store::get_object_store()?;
+    let oid = get_stored_oid(store.as_ref(), oid)?;
+    let (object_type, size, mut reader) = object_store::open_object(store.as_ref(), &oid)?;
 
     match cat_type {
-        GritCatType::Type => {
-            let report = get_object_type(&content);
-            Some(report)
+        GritCatType::Type => println!("{object_type}"),
+        GritCatType::Size => println!("{size}"),
+        GritCatType::Raw(expected) if expected.name() != object_type => {
+            eprintln!("fatal: cat-file {}: bad file", expected.name());
+            return None;
+        }
+        GritCatType::Pretty | GritCatType::Raw(_) => {
+            let terminator: &[u8] = match cat_type {
+                GritCatType::Pretty if object_type != GritObjectType::Blob.name() => b"\n",
+                _ => b"",
+            };
+
+            let mut stdout = io::stdout().lock();
+
+            let copied = io::copy(&mut reader, &mut stdout)
+                .and_then(|_| stdout.write_all(terminator))
+                .and_then(|_| stdout.flush());
+
+            if let Err(e) = copied {
+                eprintln!("Failed to print object {}: {}", oid, e);
+                return None;
+            }
+        }
+    }
+
+    Some(())
+}
+
+pub struct GritLsFilesOptions {
+    pub stage: bool,
+    pub modified: bool,
+    pub deleted: bool,
+    pub others: bool,
+    pub ignored: bool,
+    pub null_terminated: bool,
+}
+
+pub fn ls_files(options: &GritLsFilesOptions) -> Option<()> {
+    let (index_tree_map, _index_paths, index_entries, _index_oids) = get_index_entries()?;
+
+    let mut lines = vec![];
+    let show_cached = !(options.modified || options.deleted || options.others || options.ignored);
+
+    let mut index_entries = index_entries;
+    index_entries.sort_by_key(|entry| get_entry_path(entry));
+
+    for entry in &index_entries {
+        let fields = entry.splitn(4, ' ').collect::<Vec<&str>>();
+        let path = fields[3];
+        let working_oid = get_working_tree_oid(path);
+
+        let listed = show_cached
+            || options.stage
+            || (options.modified && working_oid.as_deref() != Some(fields[2]))
+            || (options.deleted && working_oid.is_none());
+
+        if !listed {
+            continue;
+        }
+
+        if options.stage {
+            lines.push(format!("{} {} 0\t{path}", fields[0], fields[2]));
+        } else {
+            lines.push(path.to_string());
         }
-        GritCatType::Pretty => {
-            let report = get_object_contents(&content);
-            Some(report)
+    }
+
+    if options.others || options.ignored {
+        let patterns = ignore::get_ignore_patterns()?;
+        let mut untracked = get_working_tree_entries_recursively()?
+            .into_iter()
+            .filter(|path| !index_tree_map.contains_key(path))
+            .filter(|path| ignore::is_ignored(path, &patterns) == options.ignored)
+            .collect::<Vec<String>>();
+        untracked.sort();
+
+        lines.extend(untracked);
+    }
+
+    let terminator = if options.null_terminated { "\0" } else { "\n" };
+    let report = lines
+        .iter()
+        .map(|line| format!("{line}{terminator}"))
+        .collect::<String>();
+
+    print!("{report}");
+
+    Some(())
+}
+
+/// Lists a tree in `ls-tree` style. Trees are stored flat, so unless `recursive`
+/// is set, files below the top level are folded into a `tree` entry for their
+/// top-level directory. Its oid is hashed from the entries inside it without
+/// storing anything, as listing a tree never writes to the repository.
+pub fn format_tree_listing(
+    tree_oid: &str,
+    pathspecs: &[String],
+    recursive: bool,
+    name_only: bool,
+    long: bool,
+) -> Option<String> {
+    let (_tree_map, _tree_paths, tree_entries, _tree_oids) = get_tree_entries(tree_oid)?;
+
+    let mut listing: Vec<(String, String, String, String)> = vec![];
+    let mut directories: HashMap<String, Vec<String>> = HashMap::new();
+
+    for entry in &tree_entries {
+        let fields = entry.splitn(4, ' ').collect::<Vec<&str>>();
+        let path = fields[3];
+
+        match path.split_once('/') {
+            Some((directory, rest)) if !recursive => directories
+                .entry(directory.to_string())
+                .or_default()
+                .push(format!("{} {} {} {rest}", fields[0], fields[1], fields[2])),
+            _ => listing.push((
+                fields[0].to_string(),
+                fields[1].to_string(),
+                fields[2].to_string(),
+                path.to_string(),
+            )),
         }
-        GritCatType::Size => {
-            let report = get_object_size(&content);
-            Some(report)
+    }
+
+    for (directory, entries) in directories {
+        let oid = hash_tree_from_entries(&entries, false)?;
+        listing.push(("040000".to_string(), "tree".to_string(), oid, directory));
+    }
+
+    listing.sort_by(|a, b| a.3.cmp(&b.3));
+
+    let mut report = String::new();
+
+    for (mode, object_type, oid, path) in listing {
+        if !utils::matches_pathspec(&path, pathspecs) {
+            continue;
+        }
+
+        if name_only {
+            report += format!("{path}\n").as_str();
+        } else if long {
+            let size = if object_type == "blob" {
+                generate_cat_content(&oid, GritCatType::Size)?
+            } else {
+                "-".to_string()
+            };
+            report += format!("{mode} {object_type} {oid} {size:>7}\t{path}\n").as_str();
+        } else {
+            report += format!("{mode} {object_type} {oid}\t{path}\n").as_str();
         }
     }
+
+    Some(report)
 }
 
-pub fn cat_file(oid: &str, cat_type: GritCatType) -> Option<()> {
-    let report = generate_cat_content(oid, cat_type)?;
+pub fn ls_tree(
+    tree_ish: &str,
+    pathspecs: &[String],
+    recursive: bool,
+    name_only: bool,
+    long: bool,
+) -> Option<()> {
+    let oid = peel_tag(&rev_parse(tree_ish)?)?;
 
-    println!("{report}");
+    let tree_oid = match generate_cat_content(&oid, GritCatType::Type)?.as_str() {
+        "tree" => oid,
+        "commit" => get_commit_tree_oid(&oid)?,
+        object_type => {
+            println!("Not a tree object: {tree_ish} is a {object_type}");
+            return None;
+        }
+    };
+
+    print!(
+        "{}",
+        format_tree_listing(&tree_oid, pathspecs, recursive, name_only, long)?
+    );
 
     Some(())
 }
 
+/// The ref HEAD points at, or `HEAD` itself when HEAD is detached.
 pub fn get_head_ref() -> Option<String> {
-    let head_path = "HEAD";
-
-    let head = file_handling::read_grit_file(&head_path)?;
+    let head = match refs::read_ref_value("HEAD")? {
+        Some(head) => head,
+        None => {
+            println!("fatal: not a grit repository: HEAD is missing");
+            return None;
+        }
+    };
 
-    let head_ref = head.split(" ").collect::<Vec<&str>>()[1].to_string();
+    match head.strip_prefix("ref: ") {
+        Some(head_ref) => Some(head_ref.trim().to_string()),
+        None => Some("HEAD".to_string()),
+    }
+}
 
-    Some(head_ref)
+pub fn is_head_detached() -> Option<bool> {
+    Some(get_head_ref()? == "HEAD")
 }
 
 pub fn get_current_branch() -> Option<String> {
     let head_ref = get_head_ref()?;
 
+    if head_ref == "HEAD" {
+        return Some("detached HEAD".to_string());
+    }
+
     let branch = head_ref.replace("refs/heads/", "");
 
     Some(branch)
@@ -413,33 +739,247 @@ pub fn get_current_branch() -> Option<String> {
 pub fn get_head_oid() -> Option<String> {
     let head_ref = get_head_ref()?;
 
-    file_handling::read_grit_file(&head_ref)
+    match refs::read_ref_value(&head_ref)? {
+        Some(head_oid) => Some(head_oid),
+        None => {
+            println!("fatal: ref {head_ref} does not exist");
+            None
+        }
+    }
+}
+
+/// Points a ref at an object without recording it in the reflog.
+pub fn write_ref(ref_name: &str, oid: &str) -> Option<()> {
+    refs::write_ref_value(ref_name, oid)
+}
+
+pub fn update_ref(ref_name: &str, oid: &str, reason: &str) -> Option<()> {
+    let mut transaction = GritRefTransaction::new();
+    transaction.update(ref_name, oid, None, reason);
+    transaction.commit()
 }
 
-pub fn update_head(commit_oid: &str) -> Option<()> {
+pub fn delete_ref(ref_name: &str) -> Option<()> {
+    if get_ref_oid(ref_name)?.is_empty() {
+        return reflog::delete_reflog(ref_name);
+    }
+
+    let mut transaction = GritRefTransaction::new();
+    transaction.delete(ref_name, None);
+    transaction.commit()
+}
+
+pub fn update_head(commit_oid: &str, reason: &str) -> Option<()> {
     let head_ref = get_head_ref()?;
 
-    let head_ref_path = format!("{GRIT_DIRECTORY}/{head_ref}");
+    if head_ref == "HEAD" {
+        return update_ref(&head_ref, commit_oid, reason);
+    }
 
-    let mut head_ref_file = match OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&head_ref_path)
-    {
-        Ok(file) => file,
-        Err(e) => {
-            println!("Failed to open head ref file: {}", e);
-            return None;
+    let old_oid = get_ref_oid(&head_ref)?;
+
+    update_ref(&head_ref, commit_oid, reason)?;
+
+    reflog::append_reflog_entry("HEAD", &old_oid, commit_oid, reason)
+}
+
+/// Expands a short name such as `main` or `HEAD` into the ref it refers to.
+pub fn resolve_ref_name(name: &str) -> Option<String> {
+    if name == "HEAD" || name == "@" {
+        return Some("HEAD".to_string());
+    }
+
+    let candidates = [
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ];
+
+    for candidate in candidates {
+        if candidate.starts_with("refs/")
+            && (refs::read_ref_value(&candidate)?.is_some() || reflog::reflog_exists(&candidate))
+        {
+            return Some(candidate);
         }
+    }
+
+    None
+}
+
+pub fn get_ref_oid(ref_name: &str) -> Option<String> {
+    if ref_name == "HEAD" {
+        let head_ref = get_head_ref()?;
+
+        if head_ref != "HEAD" {
+            return get_ref_oid(&head_ref);
+        }
+
+        return Some(get_head_oid()?.trim().to_string());
+    }
+
+    let content = match refs::read_ref_value(ref_name)? {
+        Some(content) => content,
+        None => return Some("".to_string()),
     };
 
-    match head_ref_file.write_all(commit_oid.as_bytes()) {
-        Ok(_) => Some(()),
-        Err(e) => {
-            println!("Failed to update head ref file: {}", e);
+    // Symbolic refs such as `refs/remotes/origin/HEAD` name another ref
+    match content.strip_prefix("ref: ") {
+        Some(target) => get_ref_oid(target),
+        None => Some(content),
+    }
+}
+
+/// Expands an abbreviated object id into the full id of the single object it matches.
+pub fn find_object_oid(prefix: &str) -> Option<String> {
+    if prefix.len() < 4 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let prefix = prefix.to_lowercase();
+    let matches = object_store::get_object_store()?.find_by_prefix(&prefix);
+
+    match matches.len() {
+        1 => Some(matches[0].clone()),
+        0 => None,
+        _ => {
+            println!("Short object id {prefix} is ambiguous");
+            None
+        }
+    }
+}
+
+/// Follows annotated tags until they reach the object they point at.
+pub fn peel_tag(oid: &str) -> Option<String> {
+    let mut oid = oid.to_string();
+
+    while generate_cat_content(&oid, GritCatType::Type)? == "tag" {
+        let tag = object::read_tag(&oid)?;
+
+        if tag.object.is_empty() {
+            println!("Tag {oid} has no target object");
             return None;
         }
+
+        oid = tag.object;
+    }
+
+    Some(oid)
+}
+
+/// Resolves `<rev>:<path>` to the blob at that path, or to the tree when the path is empty.
+fn resolve_revision_path(commit_rev: &str, path: &str) -> Option<String> {
+    let tree_oid = match peel_tag(&rev_parse(commit_rev)?)? {
+        oid if generate_cat_content(&oid, GritCatType::Type)? == "tree" => oid,
+        oid => get_commit_tree_oid(&oid)?,
+    };
+
+    let path = path.trim_start_matches("./").trim_end_matches('/');
+
+    if path.is_empty() {
+        return Some(tree_oid);
+    }
+
+    let (tree_map, _paths, _entries, _oids) = get_tree_entries(&tree_oid)?;
+
+    match tree_map.get(path) {
+        Some(oid) => Some(oid.to_string()),
+        None => {
+            println!("Path '{path}' does not exist in '{commit_rev}'");
+            None
+        }
+    }
+}
+
+/// Resolves a revision such as `HEAD`, `main~2`, `HEAD^2`, `HEAD@{2}`, `@{1}`
+/// or an abbreviated object id into a full object id.
+pub fn rev_parse(rev: &str) -> Option<String> {
+    if let Some((commit_rev, path)) = rev.split_once(':') {
+        return resolve_revision_path(commit_rev, path);
+    }
+
+    // Ancestry suffixes start at the first `~` or `^` after any reflog selector
+    let selector_end = rev.find('}').map(|position| position + 1).unwrap_or(0);
+    let (name, suffixes) = match rev[selector_end..].find(['~', '^']) {
+        Some(position) => rev.split_at(selector_end + position),
+        None => (rev, ""),
+    };
+
+    let mut oid = resolve_revision_name(name)?;
+
+    if !suffixes.is_empty() {
+        oid = peel_tag(&oid)?;
+    }
+
+    let mut suffixes = suffixes.chars().peekable();
+    while let Some(operator) = suffixes.next() {
+        let mut digits = String::new();
+        while let Some(digit) = suffixes.next_if(|c| c.is_ascii_digit()) {
+            digits.push(digit);
+        }
+
+        let count = if digits.is_empty() {
+            1
+        } else {
+            match digits.parse::<usize>() {
+                Ok(count) => count,
+                Err(_) => {
+                    println!("Unknown revision {rev}");
+                    return None;
+                }
+            }
+        };
+
+        let ancestor = match operator {
+            '~' => (0..count).try_fold(oid.clone(), |oid, _| {
+                get_commit_parents(&oid)?.into_iter().next()
+            }),
+            '^' if count == 0 => Some(oid.clone()),
+            '^' => get_commit_parents(&oid)?.into_iter().nth(count - 1),
+            _ => None,
+        };
+
+        oid = match ancestor {
+            Some(ancestor) => ancestor,
+            None => {
+                println!("Unknown revision {rev}");
+                return None;
+            }
+        };
+    }
+
+    Some(oid)
+}
+
+fn resolve_revision_name(rev: &str) -> Option<String> {
+    if rev.contains("@{") {
+        let (ref_name, n) = match reflog::parse_reflog_selector(rev) {
+            Some(selector) => selector,
+            None => {
+                println!("Unknown revision {rev}");
+                return None;
+            }
+        };
+
+        return reflog::get_reflog_oid(&ref_name, n);
+    }
+
+    if let Some(ref_name) = resolve_ref_name(rev) {
+        let oid = get_ref_oid(&ref_name)?;
+
+        if !oid.is_empty() {
+            return Some(oid);
+        }
+    }
+
+    match find_object_oid(rev) {
+        Some(oid) => Some(oid),
+        None => {
+            println!("Unknown revision {rev}");
+            None
+        }
     }
 }
 
@@ -448,31 +988,60 @@ pub fn commit_tree(
     message: &str,
     parent_commit_oid: Option<&str>,
 ) -> Option<String> {
-    let author = GritAuthor {
-        name: "Bhekani Khumalo".to_string(),
+    let parent_commit_oids = match parent_commit_oid {
+        Some(parent_oid) => vec![parent_oid.to_string()],
+        None => vec![],
     };
 
-    let mut formatted_commit = format!("tree {tree_oid}")
-        + format!("\nauthor {}", &author.name).as_str()
-        + format!("\ncommitter {}", &author.name).as_str()
-        + format!("\ndate {}", utils::get_current_time()).as_str()
-        + format!("\n\n{}", message).as_str();
+    commit_tree_with_parents(tree_oid, message, &parent_commit_oids)
+}
 
-    match parent_commit_oid {
-        Some(parent_oid) => {
-            formatted_commit = format!("parent {parent_oid}\n{formatted_commit}");
-        }
-        None => (),
+pub fn commit_tree_with_parents(
+    tree_oid: &str,
+    message: &str,
+    parent_commit_oids: &[String],
+) -> Option<String> {
+    let author = get_author();
+
+    commit_tree_with_author(tree_oid, message, parent_commit_oids, &author.name)
+}
+
+/// Like `commit_tree_with_parents`, but credits the change to `author` while
+/// recording the configured user as the committer.
+pub fn commit_tree_with_author(
+    tree_oid: &str,
+    message: &str,
+    parent_commit_oids: &[String],
+    author: &str,
+) -> Option<String> {
+    let commit = GritCommit {
+        tree: tree_oid.to_string(),
+        parents: parent_commit_oids.to_vec(),
+        author: author.to_string(),
+        committer: get_author().name,
+        date: utils::get_current_time(),
+        extra_headers: vec![],
+        message: message.to_string(),
     };
 
-    let oid = hash_object(&formatted_commit, GritObjectType::Commit, true)?;
+    GritObject::Commit(commit).write()
+}
 
-    Some(oid)
+pub fn get_commit_author(commit_oid: &str) -> Option<String> {
+    Some(object::read_commit(commit_oid)?.author)
+}
+
+pub fn get_commit_date(commit_oid: &str) -> Option<String> {
+    Some(object::read_commit(commit_oid)?.date)
+}
+
+pub fn get_commit_message(commit_oid: &str) -> Option<String> {
+    Some(object::read_commit(commit_oid)?.message)
 }
 
 pub fn get_working_tree_entries() -> Option<Vec<String>> {
     let working_dir_path = path::Path::new(WORKING_DIR);
-    let read_dirs = match fs::read_dir(&working_dir_path) {
+    let read_dirs = match fs::read_dir(working_dir_path) {
         Ok(result) => result,
         Err(e) => {
             println!("Failed to read directory {:?}: {}", &working_dir_path, e);
@@ -485,7 +1054,7 @@ pub fn get_working_tree_entries() -> Option<Vec<String>> {
             Ok(item) => item.path().display().to_string(),
             Err(e) => {
                 println!("Failed to read item: {}", e);
-                return "".to_string();
+                "".to_string()
             }
         })
         .collect::<Vec<String>>();
@@ -493,22 +1062,219 @@ pub fn get_working_tree_entries() -> Option<Vec<String>> {
     Some(paths)
 }
 
+/// Every file in the working tree, relative to its top and sorted, so the
+/// order does not depend on the file system or on how the threads ran.
 pub fn get_working_tree_entries_recursively() -> Option<Vec<String>> {
-    let source_dir_path = path::Path::new(WORKING_DIR);
-    let items = WalkDir::new(source_dir_path);
-
-    let paths: Vec<String> = items
-        .into_iter()
-        .filter_map(Result::ok) // Unwrap the entry, ignoring any errors
-        .filter(|entry| entry.file_type().is_file()) // Filter out directories, leaving only file paths
-        .map(|entry| {
-            entry
-                .into_path()
-                .display()
-                .to_string()
-                .replace("source/", "")
-        }) // Convert entries to paths
-        .collect();
+    let pool = utils::get_thread_pool()?;
+
+    let mut paths = pool.install(|| walk_working_directory(path::Path::new(WORKING_DIR)));
+    paths.sort();
 
     Some(paths)
 }
+
+/// Reads the directories below `directory` in parallel.
+fn walk_working_directory(directory: &path::Path) -> Vec<String> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .collect::<Vec<fs::DirEntry>>(),
+        Err(_) => return vec![],
+    };
+
+    entries
+        .par_iter()
+        .flat_map_iter(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => walk_working_directory(&entry.path()),
+            Ok(file_type) if file_type.is_file() => entry
+                .path()
+                .strip_prefix(WORKING_DIR)
+                .map(|path| vec![path.display().to_string()])
+                .unwrap_or_default(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+/// Stores a working tree file as a blob in a store of its own, quietly, so it
+/// can run on any thread and leave reporting to the caller.
+fn write_working_file(store: &GritLooseObjectStore, filename: &str) -> io::Result<String> {
+    let mut file = File::open(format!("{WORKING_DIR}/{filename}"))?;
+    let size = file.metadata()?.len();
+
+    store.try_write_stream(GritObjectType::Blob, size, &mut file)
+}
+
+/// Stores working tree files as blobs, giving their ids in the order of
+/// `filenames`; files that cannot be read give their error, for the caller to
+/// report. Stores that keep one file per object are written to from the worker
+/// threads, each file being hashed and deflated in one pass into a temporary
+/// file that is renamed to its id, or dropped if the blob already exists. The
+/// other stores only exist on this thread, so files are hashed on the workers
+/// and only the missing blobs are written here.
+pub fn write_working_tree_files(filenames: &[String]) -> Option<Vec<io::Result<String>>> {
+    let store = object_store::get_object_store()?;
+    let loose_store = store.get_objects_dir().map(GritLooseObjectStore::new);
+    let pool = utils::get_thread_pool()?;
+
+    let oids = pool.install(|| {
+        filenames
+            .par_iter()
+            .map(|filename| match &loose_store {
+                Some(loose_store) => write_working_file(loose_store, filename),
+                None => hash_working_file(filename),
+            })
+            .collect::<Vec<io::Result<String>>>()
+    });
+
+    if loose_store.is_some() {
+        return Some(oids);
+    }
+
+    let mut written_oids = vec![];
+
+    for (filename, oid) in filenames.iter().zip(oids) {
+        written_oids.push(match oid {
+            Ok(oid) if !store.exists(&oid) => Ok(hash_file_object(
+                &format!("{WORKING_DIR}/{filename}"),
+                GritObjectType::Blob,
+                true,
+            )?),
+            oid => oid,
+        });
+    }
+
+    Some(written_oids)
+}
+
+/// Hashes working tree files as blobs without writing them, spread over the
+/// worker threads. Results are in the order of `filenames`; files that cannot
+/// be read give their error, for the caller to report.
+pub fn hash_working_tree_files(filenames: &[String]) -> Option<Vec<io::Result<String>>> {
+    let pool = utils::get_thread_pool()?;
+
+    let oids = pool.install(|| {
+        filenames
+            .par_iter()
+            // Hashing alone never touches the object store, so it is safe off this thread
+            .map(|filename| hash_working_file(filename))
+            .collect()
+    });
+
+    Some(oids)
+}
+
+/// Hashes a file in the working tree without writing it, `None` if the file does not exist.
+pub fn get_working_tree_oid(filename: &str) -> Option<String> {
+    if !path::Path::new(&format!("{WORKING_DIR}/{filename}")).is_file() {
+        return None;
+    }
+
+    hash_file_object(
+        &format!("{WORKING_DIR}/{filename}"),
+        GritObjectType::Blob,
+        false,
+    )
+}
+
+/// Writes the content of a blob to a file in the working tree, a chunk at a
+/// time as it is decompressed.
+pub fn checkout_file(filename: &str, oid: &str) -> Option<()> {
+    let store = object_store::get_object_store()?;
+    let oid = get_stored_oid(store.as_ref(), oid)?;
+    let (_object_type, _size, mut reader) = object_store::open_object(store.as_ref(), &oid)?;
+    let file_path = format!("{WORKING_DIR}/{filename}");
+
+    if let Some(parent) = path::Path::new(&file_path).parent() {
+        match fs::create_dir_all(parent) {
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to create directory {:?}: {}", parent, e);
+                return None;
+            }
+        }
+    }
+
+    match File::create(&file_path).and_then(|mut file| io::copy(&mut reader, &mut file)) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to write file {}: {}", filename, e);
+            None
+        }
+    }
+}
+
+/// Deletes a file from the working tree along with any directories it leaves empty.
+pub fn remove_working_file(filename: &str) -> Option<()> {
+    let file_path = format!("{WORKING_DIR}/{filename}");
+
+    if path::Path::new(&file_path).exists() {
+        match fs::remove_file(&file_path) {
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to remove file {}: {}", filename, e);
+                return None;
+            }
+        }
+    }
+
+    let mut parent = path::Path::new(&file_path).parent();
+    while let Some(directory) = parent {
+        if directory == path::Path::new(WORKING_DIR) || fs::remove_dir(directory).is_err() {
+            break;
+        }
+        parent = directory.parent();
+    }
+
+    Some(())
+}
+
+/// Tracked paths whose index entry differs from HEAD or whose working tree
+/// file differs from the index.
+pub fn get_local_changes() -> Option<Vec<String>> {
+    let head_oid = get_head_oid()?.trim().to_string();
+
+    let (index_tree_map, _index_paths, _index_entries, _index_oids) = get_index_entries()?;
+    let (head_tree_map, _head_paths, _head_entries, _head_oids) = get_head_tree_entries(&head_oid)?;
+
+    let mut changes = vec![];
+
+    for (path, index_oid) in &index_tree_map {
+        if head_tree_map.get(path) != Some(index_oid)
+            || get_working_tree_oid(path).as_ref() != Some(index_oid)
+        {
+            changes.push(path.to_string());
+        }
+    }
+
+    for path in head_tree_map.keys() {
+        if !index_tree_map.contains_key(path) {
+            changes.push(path.to_string());
+        }
+    }
+
+    changes.sort();
+
+    Some(changes)
+}
+
+/// Makes the index and the tracked files in the working tree match a tree,
+/// discarding any local changes to them.
+pub fn checkout_tree(tree_oid: &str) -> Option<()> {
+    let (_index_tree_map, index_paths, _index_entries, _index_oids) = get_index_entries()?;
+    let (tree_map, _tree_paths, tree_entries, _tree_oids) = get_tree_entries(tree_oid)?;
+
+    for path in index_paths {
+        if !tree_map.contains_key(&path) {
+            remove_working_file(&path)?;
+        }
+    }
+
+    for (path, oid) in &tree_map {
+        if get_working_tree_oid(path).as_ref() != Some(oid) {
+            checkout_file(path, oid)?;
+        }
+    }
+
+    write_index(&tree_entries)
+}
@@ -0,0 +1,688 @@
+use crate::config::GRIT_DIRECTORY;
+use crate::file_handling;
+use crate::pack;
+use crate::pkt_line::{self, GritPktLine};
+use crate::plumbing::{self, GritCatType};
+use crate::refs;
+use crate::transport::{self, GritRefCommand, GritRemoteState};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// The object id that stands for a ref that does not exist, on either side of an update.
+pub const ZERO_OID: &str = "0000000000000000000000000000000000000000";
+
+const AGENT: &str = "agent=grit/0.1";
+
+fn get_data_lines(pkt_lines: Vec<GritPktLine>) -> Vec<String> {
+    pkt_lines
+        .into_iter()
+        .filter_map(|pkt_line| match pkt_line {
+            GritPktLine::Data(data) => Some(pkt_line::to_text(&data)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn to_wire_oid(oid: &str) -> &str {
+    if oid.is_empty() {
+        ZERO_OID
+    } else {
+        oid
+    }
+}
+
+fn from_wire_oid(oid: &str) -> String {
+    if oid == ZERO_OID {
+        "".to_string()
+    } else {
+        oid.to_string()
+    }
+}
+
+/// The capability advertisement that opens a protocol v2 upload-pack conversation.
+pub fn advertise_upload_pack() -> Vec<u8> {
+    let mut advertisement = vec![];
+
+    for capability in [
+        "version 2",
+        AGENT,
+        "ls-refs=unborn",
+        "fetch",
+        "object-format=sha1",
+    ] {
+        advertisement.extend(pkt_line::encode_str(&format!("{capability}\n")));
+    }
+
+    advertisement.extend(pkt_line::flush());
+    advertisement
+}
+
+/// Reports an `ERR` line sent by the remote in place of what was asked for.
+fn check_remote_error(lines: &[String]) -> Option<()> {
+    match lines.iter().find_map(|line| line.strip_prefix("ERR ")) {
+        Some(error) => {
+            println!("fatal: remote error: {error}");
+            None
+        }
+        None => Some(()),
+    }
+}
+
+/// Checks that a capability advertisement is for protocol v2.
+pub fn check_upload_pack_advertisement(advertisement: &[u8]) -> Option<()> {
+    let mut lines = vec![];
+    let mut offset = 0;
+
+    // Over HTTP the advertisement follows a `# service=` section
+    while offset < advertisement.len() {
+        let (pkt_lines, length) = pkt_line::read_until_flush(&advertisement[offset..])?;
+        lines.extend(get_data_lines(pkt_lines));
+        offset += length;
+    }
+
+    check_remote_error(&lines)?;
+
+    if !lines.iter().any(|line| line == "version 2") {
+        println!("fatal: the remote does not support protocol version 2");
+        return None;
+    }
+
+    Some(())
+}
+
+fn build_command(command: &str, arguments: &[String]) -> Vec<u8> {
+    let mut request = pkt_line::encode_str(&format!("command={command}\n"));
+    request.extend(pkt_line::encode_str(&format!("{AGENT}\n")));
+    request.extend(pkt_line::encode_str("object-format=sha1\n"));
+    request.extend(pkt_line::delimiter());
+
+    for argument in arguments {
+        request.extend(pkt_line::encode_str(&format!("{argument}\n")));
+    }
+
+    request.extend(pkt_line::flush());
+    request
+}
+
+pub fn build_ls_refs_request() -> Vec<u8> {
+    build_command(
+        "ls-refs",
+        &[
+            "peel",
+            "symrefs",
+            "unborn",
+            "ref-prefix HEAD",
+            "ref-prefix refs/heads/",
+            "ref-prefix refs/tags/",
+        ]
+        .map(|argument| argument.to_string()),
+    )
+}
+
+/// Reads an ls-refs response into the remote's HEAD, branches and tags.
+pub fn parse_ls_refs_response(response: &[u8]) -> Option<GritRemoteState> {
+    let (pkt_lines, _length) = pkt_line::read_until_flush(response)?;
+
+    let mut remote_state = GritRemoteState {
+        head_ref: "HEAD".to_string(),
+        head_oid: "".to_string(),
+        refs: vec![],
+    };
+
+    let lines = get_data_lines(pkt_lines);
+    check_remote_error(&lines)?;
+
+    for line in lines {
+        let mut fields = line.split(' ');
+        let oid = fields.next().unwrap_or("");
+        let ref_name = fields.next().unwrap_or("");
+        let symref_target = fields.find_map(|field| field.strip_prefix("symref-target:"));
+
+        if ref_name == "HEAD" {
+            remote_state.head_oid = if oid == "unborn" {
+                "".to_string()
+            } else {
+                oid.to_string()
+            };
+
+            if let Some(target) = symref_target {
+                remote_state.head_ref = target.to_string();
+            }
+        } else if ref_name.starts_with("refs/heads/") || ref_name.starts_with("refs/tags/") {
+            remote_state
+                .refs
+                .push((ref_name.to_string(), oid.to_string()));
+        }
+    }
+
+    Some(remote_state)
+}
+
+pub fn build_fetch_request(wants: &[String], haves: &[String]) -> Vec<u8> {
+    let mut arguments = wants
+        .iter()
+        .map(|oid| format!("want {oid}"))
+        .collect::<Vec<String>>();
+
+    arguments.extend(haves.iter().map(|oid| format!("have {oid}")));
+    arguments.push("ofs-delta".to_string());
+    arguments.push("done".to_string());
+
+    build_command("fetch", &arguments)
+}
+
+/// Pulls the pack out of a fetch response, skipping the sections before it
+/// and showing any progress the remote sent.
+pub fn read_fetch_response(response: &[u8]) -> Option<Vec<u8>> {
+    let mut offset = 0;
+    let mut in_packfile = false;
+    let mut pack = vec![];
+
+    while offset < response.len() {
+        let (pkt_line, length) = pkt_line::read_pkt_line(&response[offset..])?;
+        offset += length;
+
+        let data = match pkt_line {
+            GritPktLine::Data(data) => data,
+            GritPktLine::Flush | GritPktLine::ResponseEnd => break,
+            GritPktLine::Delimiter => continue,
+        };
+
+        if !in_packfile {
+            let line = pkt_line::to_text(&data);
+
+            if let Some(error) = line.strip_prefix("ERR ") {
+                println!("fatal: remote error: {error}");
+                return None;
+            }
+
+            in_packfile = line == "packfile";
+            continue;
+        }
+
+        match data.first() {
+            Some(1) => pack.extend_from_slice(&data[1..]),
+            Some(2) => eprint!("remote: {}", String::from_utf8_lossy(&data[1..])),
+            Some(3) => {
+                println!("fatal: remote error: {}", pkt_line::to_text(&data[1..]));
+                return None;
+            }
+            _ => {
+                println!("fatal: invalid side-band packet in fetch response");
+                return None;
+            }
+        }
+    }
+
+    if !in_packfile {
+        println!("fatal: the remote did not send a pack");
+        return None;
+    }
+
+    Some(pack)
+}
+
+fn format_error(message: &str) -> Vec<u8> {
+    let mut response = pkt_line::encode_str(&format!("ERR {message}\n"));
+    response.extend(pkt_line::flush());
+    response
+}
+
+fn serve_ls_refs(arguments: &[String]) -> Option<Vec<u8>> {
+    let peel = arguments.iter().any(|argument| argument == "peel");
+    let symrefs = arguments.iter().any(|argument| argument == "symrefs");
+    let unborn = arguments.iter().any(|argument| argument == "unborn");
+    let prefixes = arguments
+        .iter()
+        .filter_map(|argument| argument.strip_prefix("ref-prefix "))
+        .collect::<Vec<&str>>();
+
+    let wanted = |ref_name: &str| {
+        prefixes.is_empty() || prefixes.iter().any(|prefix| ref_name.starts_with(prefix))
+    };
+
+    let mut response = vec![];
+
+    if wanted("HEAD") {
+        let head_ref = plumbing::get_head_ref()?;
+        let head_oid = plumbing::get_ref_oid("HEAD")?;
+
+        let mut line = match head_oid.is_empty() {
+            true if unborn => "unborn HEAD".to_string(),
+            true => "".to_string(),
+            false => format!("{head_oid} HEAD"),
+        };
+
+        if !line.is_empty() {
+            if symrefs && head_ref != "HEAD" {
+                line += format!(" symref-target:{head_ref}").as_str();
+            }
+
+            response.extend(pkt_line::encode_str(&format!("{line}\n")));
+        }
+    }
+
+    for ref_name in refs::get_all_refs()? {
+        if !wanted(&ref_name) {
+            continue;
+        }
+
+        let oid = plumbing::get_ref_oid(&ref_name)?;
+        if oid.is_empty() {
+            continue;
+        }
+
+        let mut line = format!("{oid} {ref_name}");
+
+        if peel && plumbing::generate_cat_content(&oid, GritCatType::Type)? == "tag" {
+            line += format!(" peeled:{}", plumbing::peel_tag(&oid)?).as_str();
+        }
+
+        response.extend(pkt_line::encode_str(&format!("{line}\n")));
+    }
+
+    response.extend(pkt_line::flush());
+
+    Some(response)
+}
+
+fn serve_fetch(arguments: &[String]) -> Option<Vec<u8>> {
+    let wants = arguments
+        .iter()
+        .filter_map(|argument| argument.strip_prefix("want "))
+        .map(|oid| oid.to_string())
+        .collect::<Vec<String>>();
+    let haves = arguments
+        .iter()
+        .filter_map(|argument| argument.strip_prefix("have "))
+        .map(|oid| oid.to_string())
+        .collect::<Vec<String>>();
+    let done = arguments.iter().any(|argument| argument == "done");
+
+    if let Some(missing) = wants.iter().find(|oid| !file_handling::object_exists(oid)) {
+        return Some(format_error(&format!("upload-pack: not our ref {missing}")));
+    }
+
+    let mut response = vec![];
+
+    // Every want can be served in one go, so negotiation ends at once with `ready`
+    if !done {
+        let common = haves
+            .iter()
+            .filter(|oid| file_handling::object_exists(oid))
+            .collect::<Vec<&String>>();
+
+        response.extend(pkt_line::encode_str("acknowledgments\n"));
+
+        if common.is_empty() {
+            response.extend(pkt_line::encode_str("NAK\n"));
+        }
+
+        for oid in common {
+            response.extend(pkt_line::encode_str(&format!("ACK {oid}\n")));
+        }
+
+        response.extend(pkt_line::encode_str("ready\n"));
+        response.extend(pkt_line::delimiter());
+    }
+
+    let objects = transport::get_objects_to_send(&wants, &haves)?;
+    let pack = pack::create_pack(&objects)?;
+
+    response.extend(pkt_line::encode_str("packfile\n"));
+    response.extend(pkt_line::encode_side_band(1, &pack));
+    response.extend(pkt_line::flush());
+
+    Some(response)
+}
+
+/// Answers one protocol v2 upload-pack request for the current repository.
+pub fn serve_upload_pack(request: &[u8]) -> Option<Vec<u8>> {
+    let mut offset = 0;
+    let mut command = String::new();
+    let mut arguments = vec![];
+    let mut in_arguments = false;
+
+    while offset < request.len() {
+        let (pkt_line, length) = pkt_line::read_pkt_line(&request[offset..])?;
+        offset += length;
+
+        match pkt_line {
+            GritPktLine::Data(data) => {
+                let line = pkt_line::to_text(&data);
+
+                if in_arguments {
+                    arguments.push(line);
+                } else if let Some(name) = line.strip_prefix("command=") {
+                    command = name.to_string();
+                }
+            }
+            GritPktLine::Delimiter => in_arguments = true,
+            GritPktLine::Flush | GritPktLine::ResponseEnd => break,
+        }
+    }
+
+    match command.as_str() {
+        "ls-refs" => serve_ls_refs(&arguments),
+        "fetch" => serve_fetch(&arguments),
+        _ => Some(format_error(&format!("unknown command '{command}'"))),
+    }
+}
+
+/// The protocol v0 ref advertisement that opens a receive-pack conversation.
+pub fn advertise_receive_pack() -> Option<Vec<u8>> {
+    let capabilities = format!("report-status delete-refs ofs-delta {AGENT}");
+    let mut advertised = vec![];
+
+    for ref_name in refs::get_all_refs()? {
+        let oid = plumbing::get_ref_oid(&ref_name)?;
+
+        if !oid.is_empty() {
+            advertised.push(format!("{oid} {ref_name}"));
+        }
+    }
+
+    if advertised.is_empty() {
+        advertised.push(format!("{ZERO_OID} capabilities^{{}}"));
+    }
+
+    let mut advertisement = vec![];
+
+    for (i, line) in advertised.iter().enumerate() {
+        let line = if i == 0 {
+            format!("{line}\0{capabilities}\n")
+        } else {
+            format!("{line}\n")
+        };
+
+        advertisement.extend(pkt_line::encode_str(&line));
+    }
+
+    advertisement.extend(pkt_line::flush());
+
+    Some(advertisement)
+}
+
+/// Reads the refs from a receive-pack advertisement.
+pub fn parse_receive_pack_advertisement(advertisement: &[u8]) -> Option<Vec<(String, String)>> {
+    let mut remote_refs = vec![];
+    let mut offset = 0;
+
+    while offset < advertisement.len() {
+        let (pkt_lines, length) = pkt_line::read_until_flush(&advertisement[offset..])?;
+        offset += length;
+
+        let lines = get_data_lines(pkt_lines);
+        check_remote_error(&lines)?;
+
+        for line in lines {
+            if line.starts_with('#') {
+                continue;
+            }
+
+            let line = line.split('\0').next().unwrap_or("");
+
+            if let Some((oid, ref_name)) = line.split_once(' ') {
+                if ref_name != "capabilities^{}" {
+                    remote_refs.push((ref_name.to_string(), oid.to_string()));
+                }
+            }
+        }
+    }
+
+    Some(remote_refs)
+}
+
+pub fn build_receive_pack_request(commands: &[GritRefCommand], pack: Option<Vec<u8>>) -> Vec<u8> {
+    let mut request = vec![];
+
+    for (i, command) in commands.iter().enumerate() {
+        let mut line = format!(
+            "{} {} {}",
+            to_wire_oid(&command.old_oid),
+            to_wire_oid(&command.new_oid),
+            command.ref_name
+        );
+
+        if i == 0 {
+            line += format!("\0report-status {AGENT}").as_str();
+        }
+
+        request.extend(pkt_line::encode_str(&format!("{line}\n")));
+    }
+
+    request.extend(pkt_line::flush());
+    request.extend(pack.unwrap_or_default());
+
+    request
+}
+
+/// Reads a report-status response into the outcome of each command, in order.
+pub fn parse_report_status(
+    response: &[u8],
+    commands: &[GritRefCommand],
+) -> Option<Vec<Option<String>>> {
+    let (pkt_lines, _length) = pkt_line::read_until_flush(response)?;
+    let lines = get_data_lines(pkt_lines);
+    check_remote_error(&lines)?;
+
+    match lines.first().and_then(|line| line.strip_prefix("unpack ")) {
+        Some("ok") => (),
+        Some(error) => {
+            println!("error: remote unpack failed: {error}");
+            return None;
+        }
+        None => {
+            println!("fatal: the remote did not report the push status");
+            return None;
+        }
+    }
+
+    let mut results = vec![];
+
+    for command in commands {
+        let result = lines.iter().find_map(|line| {
+            if line == &format!("ok {}", command.ref_name) {
+                Some(None)
+            } else {
+                line.strip_prefix(&format!("ng {} ", command.ref_name))
+                    .map(|reason| Some(reason.to_string()))
+            }
+        });
+
+        results.push(result.unwrap_or(Some("no report from the remote".to_string())));
+    }
+
+    Some(results)
+}
+
+/// Applies a receive-pack request to the current repository and reports the outcome of each command.
+pub fn serve_receive_pack(request: &[u8]) -> Option<Vec<u8>> {
+    let (pkt_lines, length) = pkt_line::read_until_flush(request)?;
+
+    let commands = get_data_lines(pkt_lines)
+        .iter()
+        .filter_map(|line| {
+            let mut fields = line.split('\0').next().unwrap_or("").split(' ');
+
+            Some(GritRefCommand {
+                old_oid: from_wire_oid(fields.next()?),
+                new_oid: from_wire_oid(fields.next()?),
+                ref_name: fields.next()?.to_string(),
+            })
+        })
+        .collect::<Vec<GritRefCommand>>();
+
+    let pack = &request[length..];
+
+    let unpacked = pack.is_empty() || pack::unpack(pack).is_some();
+    let mut response = pkt_line::encode_str(if unpacked {
+        "unpack ok\n"
+    } else {
+        "unpack index-pack failed\n"
+    });
+
+    let results = if unpacked {
+        transport::receive_updates(&commands)?
+    } else {
+        commands
+            .iter()
+            .map(|_command| Some("unpacker error".to_string()))
+            .collect()
+    };
+
+    for (command, result) in commands.iter().zip(results) {
+        let line = match result {
+            None => format!("ok {}\n", command.ref_name),
+            Some(reason) => format!("ng {} {reason}\n", command.ref_name),
+        };
+
+        response.extend(pkt_line::encode_str(&line));
+    }
+
+    response.extend(pkt_line::flush());
+
+    Some(response)
+}
+
+/// Like `transport::find_repository`, but keeps stdout clear for the protocol.
+fn find_served_repository(repository_path: &str) -> Option<PathBuf> {
+    match fs::canonicalize(repository_path) {
+        Ok(repository_dir) if repository_dir.join(GRIT_DIRECTORY).is_dir() => Some(repository_dir),
+        _ => {
+            eprintln!("fatal: '{repository_path}' does not appear to be a grit repository");
+            None
+        }
+    }
+}
+
+/// Points stdout at stderr for the rest of the process and gives back the
+/// original stdout, for protocol data alone. Whatever grit prints while it
+/// serves a request then reaches the client as a diagnostic on stderr
+/// instead of corrupting the pkt-line stream.
+#[cfg(unix)]
+pub fn take_protocol_output() -> Option<Box<dyn Write>> {
+    use std::os::fd::AsFd;
+
+    let mut stdout = io::stdout();
+    let _ = stdout.flush();
+
+    let output = match stdout.as_fd().try_clone_to_owned() {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("fatal: failed to duplicate stdout: {}", e);
+            return None;
+        }
+    };
+
+    // SAFETY: both are descriptors this process has open for its whole life
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } == -1 {
+        eprintln!(
+            "fatal: failed to redirect stdout: {}",
+            io::Error::last_os_error()
+        );
+        return None;
+    }
+
+    Some(Box::new(File::from(output)))
+}
+
+#[cfg(not(unix))]
+pub fn take_protocol_output() -> Option<Box<dyn Write>> {
+    Some(Box::new(io::stdout()))
+}
+
+fn write_to_client(output: &mut dyn Write, data: &[u8]) -> Option<()> {
+    match output.write_all(data).and_then(|_| output.flush()) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            eprintln!("fatal: failed to write to the client: {}", e);
+            None
+        }
+    }
+}
+
+/// Serves upload-pack over stdin and stdout, the way a client reaches it
+/// through ssh or another command. Requests are answered one at a time until
+/// the client hangs up or sends an empty request. A request that cannot be
+/// served is answered with an `ERR` line.
+pub fn upload_pack(repository_path: &str) -> Option<()> {
+    let mut output = take_protocol_output()?;
+    let repository_dir = find_served_repository(repository_path)?;
+
+    transport::with_repository(&repository_dir, || {
+        write_to_client(&mut output, &advertise_upload_pack())?;
+
+        let mut stdin = io::stdin().lock();
+        let mut failed = false;
+
+        loop {
+            let request = pkt_line::read_message_from(&mut stdin)?;
+
+            if request.is_empty() || request == pkt_line::flush() {
+                return if failed { None } else { Some(()) };
+            }
+
+            let response = serve_upload_pack(&request).unwrap_or_else(|| {
+                failed = true;
+                format_error("upload-pack: failed to serve the request")
+            });
+
+            write_to_client(&mut output, &response)?;
+        }
+    })
+}
+
+/// Serves receive-pack over stdin and stdout. The pack after the commands is
+/// read up to its checksum, so the client can keep its end open for the report.
+pub fn receive_pack(repository_path: &str) -> Option<()> {
+    let mut output = take_protocol_output()?;
+    let repository_dir = find_served_repository(repository_path)?;
+
+    transport::with_repository(&repository_dir, || {
+        let advertisement = match advertise_receive_pack() {
+            Some(advertisement) => advertisement,
+            None => {
+                write_to_client(
+                    &mut output,
+                    &format_error("receive-pack: failed to list refs"),
+                )?;
+                return None;
+            }
+        };
+
+        write_to_client(&mut output, &advertisement)?;
+
+        let mut stdin = io::stdin().lock();
+        let mut request = pkt_line::read_message_from(&mut stdin)?;
+
+        // A client with nothing to push hangs up straight after the advertisement
+        if request.is_empty() || request == pkt_line::flush() {
+            return Some(());
+        }
+
+        let (pkt_lines, _length) = pkt_line::read_until_flush(&request)?;
+
+        let has_pack = get_data_lines(pkt_lines).iter().any(|line| {
+            line.split(' ')
+                .nth(1)
+                .is_some_and(|new_oid| new_oid != ZERO_OID)
+        });
+
+        if has_pack {
+            request.extend(pack::read_pack(&mut stdin)?);
+        }
+
+        match serve_receive_pack(&request) {
+            Some(response) => write_to_client(&mut output, &response),
+            None => {
+                write_to_client(
+                    &mut output,
+                    &format_error("receive-pack: failed to apply the push"),
+                )?;
+                None
+            }
+        }
+    })
+}
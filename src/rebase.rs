@@ -0,0 +1,670 @@
+use crate::config::GRIT_DIRECTORY;
+use crate::file_handling;
+use crate::merge;
+use crate::plumbing;
+use crate::reflog;
+use crate::sequencer::{self, GritPickOutcome, GritSequencerAction};
+use crate::utils;
+use std::collections::HashSet;
+use std::fs;
+use std::path;
+use std::process::Command;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GritRebaseCommand {
+    Pick,
+    Reword,
+    Edit,
+    Squash,
+    Fixup,
+    Drop,
+    Exec,
+}
+
+impl GritRebaseCommand {
+    fn parse(command: &str) -> Option<GritRebaseCommand> {
+        match command {
+            "p" | "pick" => Some(GritRebaseCommand::Pick),
+            "r" | "reword" => Some(GritRebaseCommand::Reword),
+            "e" | "edit" => Some(GritRebaseCommand::Edit),
+            "s" | "squash" => Some(GritRebaseCommand::Squash),
+            "f" | "fixup" => Some(GritRebaseCommand::Fixup),
+            "d" | "drop" => Some(GritRebaseCommand::Drop),
+            "x" | "exec" => Some(GritRebaseCommand::Exec),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            GritRebaseCommand::Pick => "pick",
+            GritRebaseCommand::Reword => "reword",
+            GritRebaseCommand::Edit => "edit",
+            GritRebaseCommand::Squash => "squash",
+            GritRebaseCommand::Fixup => "fixup",
+            GritRebaseCommand::Drop => "drop",
+            GritRebaseCommand::Exec => "exec",
+        }
+    }
+}
+
+/// One line of the todo list: a command and either a commit or, for `exec`, a shell command.
+pub struct GritRebaseStep {
+    pub command: GritRebaseCommand,
+    pub argument: String,
+}
+
+impl GritRebaseStep {
+    fn format(&self) -> String {
+        if self.command == GritRebaseCommand::Exec {
+            return format!("exec {}", self.argument);
+        }
+
+        let message = plumbing::get_commit_message(&self.argument).unwrap_or_default();
+        let subject = message.lines().next().unwrap_or("");
+
+        format!("{} {} {subject}", self.command.name(), &self.argument[..7])
+    }
+}
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit> = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# e, edit <commit> = use commit, but stop for amending
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit> = like \"squash\", but discard this commit's log message
+# x, exec <command> = run command (the rest of the line) using shell
+# d, drop <commit> = remove commit
+#
+# These lines can be re-ordered; they are executed from top to bottom.
+#
+# If you remove a line here THAT COMMIT WILL BE LOST.
+#
+# However, if you remove everything, the rebase will be aborted.
+";
+
+fn get_state_path(filename: &str) -> String {
+    format!("{GRIT_DIRECTORY}/rebase-merge/{filename}")
+}
+
+fn read_state(filename: &str) -> Option<String> {
+    Some(
+        file_handling::read_file(&get_state_path(filename))?
+            .trim_end()
+            .to_string(),
+    )
+}
+
+pub fn rebase_in_progress() -> bool {
+    path::Path::new(&get_state_path("head-name")).exists()
+}
+
+fn parse_todo(todo: &str) -> Option<Vec<GritRebaseStep>> {
+    let mut steps = vec![];
+
+    for line in todo.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+
+        let command = match GritRebaseCommand::parse(command) {
+            Some(command) => command,
+            None => {
+                println!("Unknown rebase command {command} in: {line}");
+                return None;
+            }
+        };
+
+        let argument = if command == GritRebaseCommand::Exec {
+            argument.trim().to_string()
+        } else {
+            let commit = argument.split(' ').next().unwrap_or("");
+            plumbing::rev_parse(commit)?
+        };
+
+        steps.push(GritRebaseStep { command, argument });
+    }
+
+    Some(steps)
+}
+
+/// Checks an edited todo list before anything runs. A `squash` or `fixup`
+/// needs a commit picked earlier in the list to meld into, or it would
+/// rewrite the upstream commit the rebase starts from.
+fn check_todo(steps: &[GritRebaseStep]) -> Option<()> {
+    let mut has_previous_commit = false;
+
+    for step in steps {
+        match step.command {
+            GritRebaseCommand::Squash | GritRebaseCommand::Fixup if !has_previous_commit => {
+                println!(
+                    "error: cannot '{}' without a previous commit",
+                    step.command.name()
+                );
+                return None;
+            }
+            GritRebaseCommand::Drop | GritRebaseCommand::Exec => (),
+            _ => has_previous_commit = true,
+        }
+    }
+
+    Some(())
+}
+
+fn write_todo(steps: &[GritRebaseStep]) -> Option<()> {
+    let todo = steps
+        .iter()
+        .map(|step| format!("{}\n", step.format()))
+        .collect::<String>();
+
+    file_handling::write_file(&get_state_path("git-rebase-todo"), &todo)
+}
+
+fn append_done(step: &GritRebaseStep) -> Option<()> {
+    let done = if path::Path::new(&get_state_path("done")).exists() {
+        read_state("done")? + "\n"
+    } else {
+        "".to_string()
+    };
+
+    file_handling::write_file(&get_state_path("done"), &format!("{done}{}", step.format()))
+}
+
+/// The commits on the current branch that are not reachable from `upstream`, oldest first.
+pub fn get_commits_to_replay(head_oid: &str, upstream_oid: &str) -> Option<Vec<String>> {
+    let mut upstream_ancestors = HashSet::new();
+    let mut pending = vec![upstream_oid.to_string()];
+
+    while let Some(oid) = pending.pop() {
+        if upstream_ancestors.insert(oid.clone()) {
+            pending.extend(plumbing::get_commit_parents(&oid)?);
+        }
+    }
+
+    let mut commits = vec![];
+    let mut current = Some(head_oid.to_string());
+
+    while let Some(oid) = current {
+        if upstream_ancestors.contains(&oid) {
+            break;
+        }
+
+        let parents = plumbing::get_commit_parents(&oid)?;
+
+        // Merge commits are dropped, as their changes come in through their parents
+        if parents.len() < 2 {
+            commits.push(oid);
+        }
+
+        current = parents.into_iter().next();
+    }
+
+    commits.reverse();
+
+    Some(commits)
+}
+
+fn edit_message(message: &str, comment: &str) -> Option<String> {
+    let message_path = format!("{GRIT_DIRECTORY}/COMMIT_EDITMSG");
+
+    let content = format!("{message}\n\n# {comment}\n# Lines starting with '#' will be ignored.\n");
+    file_handling::write_file(&message_path, &content)?;
+
+    utils::launch_editor(&message_path)?;
+
+    let edited = file_handling::read_file(&message_path)?
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    if edited.is_empty() {
+        println!("Aborting commit due to empty commit message");
+        return None;
+    }
+
+    Some(edited)
+}
+
+/// Replaces HEAD with a commit that has the same parents and author but the given tree and message.
+fn amend_head(tree_oid: &str, message: &str) -> Option<String> {
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    let parent_oids = plumbing::get_commit_parents(&head_oid)?;
+    let author = plumbing::get_commit_author(&head_oid)?;
+
+    let amended_oid = plumbing::commit_tree_with_author(tree_oid, message, &parent_oids, &author)?;
+
+    let subject = message.lines().next().unwrap_or("");
+    plumbing::update_head(&amended_oid, &format!("rebase (amend): {subject}"))?;
+
+    Some(amended_oid)
+}
+
+/// Folds HEAD into its parent for `squash` and `fixup`.
+fn meld_head_into_parent(command: GritRebaseCommand) -> Option<()> {
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    let parent_oid = plumbing::get_commit_parents(&head_oid)?
+        .into_iter()
+        .next()?;
+
+    let parent_message = plumbing::get_commit_message(&parent_oid)?;
+
+    let message = match command {
+        GritRebaseCommand::Squash => edit_message(
+            &format!(
+                "{}\n\n{}",
+                parent_message.trim_end(),
+                plumbing::get_commit_message(&head_oid)?.trim_end()
+            ),
+            "This is a combination of 2 commits.",
+        )?,
+        _ => parent_message,
+    };
+
+    let tree_oid = plumbing::get_commit_tree_oid(&head_oid)?;
+    let grandparent_oids = plumbing::get_commit_parents(&parent_oid)?;
+    let author = plumbing::get_commit_author(&parent_oid)?;
+
+    let melded_oid =
+        plumbing::commit_tree_with_author(&tree_oid, &message, &grandparent_oids, &author)?;
+
+    let subject = message.lines().next().unwrap_or("");
+    plumbing::update_head(
+        &melded_oid,
+        &format!("rebase ({}): {subject}", command.name()),
+    )
+}
+
+/// What is left to do for a step once its commit has been applied.
+fn finish_step(step: &GritRebaseStep) -> Option<bool> {
+    match step.command {
+        GritRebaseCommand::Reword => {
+            let head_oid = plumbing::get_head_oid()?.trim().to_string();
+            let message = edit_message(
+                &plumbing::get_commit_message(&head_oid)?,
+                "Please enter the commit message for your changes.",
+            )?;
+
+            amend_head(&plumbing::get_commit_tree_oid(&head_oid)?, &message)?;
+        }
+        GritRebaseCommand::Squash | GritRebaseCommand::Fixup => {
+            meld_head_into_parent(step.command)?;
+        }
+        GritRebaseCommand::Edit => {
+            let head_oid = plumbing::get_head_oid()?.trim().to_string();
+            file_handling::write_file(&get_state_path("amend"), &head_oid)?;
+
+            let report = format!("Stopped at {}", step.format())
+                + "\nYou can amend the commit now by staging changes with `grit add`,"
+                + "\nthen continue with `grit rebase --continue`";
+
+            println!("{report}");
+
+            return Some(false);
+        }
+        _ => (),
+    }
+
+    Some(true)
+}
+
+/// Applies the commit of a step. Returns `false` when the rebase has to stop.
+fn apply_step(step: &GritRebaseStep) -> Option<bool> {
+    match step.command {
+        GritRebaseCommand::Drop => return Some(true),
+        GritRebaseCommand::Exec => {
+            println!("Executing: {}", step.argument);
+
+            let status = match Command::new("sh").arg("-c").arg(&step.argument).status() {
+                Ok(status) => status,
+                Err(e) => {
+                    println!("Failed to run {}: {}", step.argument, e);
+                    return Some(false);
+                }
+            };
+
+            if !status.success() {
+                let report = format!("Execution failed: {}", step.argument)
+                    + "\nYou can fix the problem, and then run `grit rebase --continue`";
+
+                println!("{report}");
+
+                return Some(false);
+            }
+
+            return Some(true);
+        }
+        _ => (),
+    }
+
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    let parent_oids = plumbing::get_commit_parents(&step.argument)?;
+
+    // A commit that already sits on top of HEAD can be reused as it is
+    let fast_forward = parent_oids.len() == 1
+        && parent_oids[0] == head_oid
+        && !matches!(
+            step.command,
+            GritRebaseCommand::Squash | GritRebaseCommand::Fixup
+        );
+
+    if fast_forward {
+        plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&step.argument)?)?;
+        let message = plumbing::get_commit_message(&step.argument)?;
+        let subject = message.lines().next().unwrap_or("");
+        plumbing::update_head(&step.argument, &format!("rebase (fast-forward): {subject}"))?;
+
+        return finish_step(step);
+    }
+
+    let message = plumbing::get_commit_message(&step.argument)?;
+
+    match sequencer::pick_commit(
+        GritSequencerAction::Pick,
+        &step.argument,
+        &message,
+        &format!("rebase ({})", step.command.name()),
+    )? {
+        GritPickOutcome::Committed(_oid) => finish_step(step),
+        GritPickOutcome::Empty => {
+            println!(
+                "Dropping {}, its changes are already upstream",
+                step.format()
+            );
+            Some(true)
+        }
+        GritPickOutcome::Conflicted => {
+            file_handling::write_file(&get_state_path("stopped-sha"), &step.argument)?;
+            file_handling::write_file(&get_state_path("message"), &message)?;
+
+            let report = format!("error: could not apply {}", step.format())
+                + "\nhint: Resolve all conflicts manually, mark them as resolved with"
+                + "\nhint: `grit add <paths>`, then run `grit rebase --continue`."
+                + "\nhint: You can instead skip this commit with `grit rebase --skip`."
+                + "\nhint: To abort and get back to the state before the rebase, run `grit rebase --abort`.";
+
+            println!("{report}");
+
+            Some(false)
+        }
+    }
+}
+
+fn run_todo() -> Option<()> {
+    loop {
+        let todo = file_handling::read_file(&get_state_path("git-rebase-todo"))?;
+        let mut steps = parse_todo(&todo)?;
+
+        if steps.is_empty() {
+            return finish_rebase();
+        }
+
+        let step = steps.remove(0);
+        write_todo(&steps)?;
+        append_done(&step)?;
+        file_handling::write_file(&get_state_path("current"), &step.format())?;
+
+        if !apply_step(&step)? {
+            return Some(());
+        }
+    }
+}
+
+fn finish_rebase() -> Option<()> {
+    let head_name = read_state("head-name")?;
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+
+    reflog::append_reflog_entry(
+        "HEAD",
+        &head_oid,
+        &head_oid,
+        &format!("rebase (finish): returning to {head_name}"),
+    )?;
+
+    remove_rebase_state()?;
+
+    println!("Successfully rebased and updated {head_name}.");
+
+    Some(())
+}
+
+fn remove_rebase_state() -> Option<()> {
+    merge::clear_unmerged_paths()?;
+
+    let rebase_dir_path = format!("{GRIT_DIRECTORY}/rebase-merge");
+
+    match fs::remove_dir_all(&rebase_dir_path) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to remove {}: {}", rebase_dir_path, e);
+            None
+        }
+    }
+}
+
+pub fn rebase(upstream: &str, interactive: bool) -> Option<()> {
+    if rebase_in_progress() {
+        println!("A rebase is already in progress\n  (use `grit rebase --continue`, `--skip` or `--abort`)");
+        return None;
+    }
+
+    if sequencer::sequencer_in_progress() {
+        println!("A cherry-pick or revert is in progress, finish it before rebasing");
+        return None;
+    }
+
+    if !plumbing::get_local_changes()?.is_empty() {
+        println!("Cannot rebase: You have unstaged changes.\n  (commit your changes or stash them to proceed)");
+        return None;
+    }
+
+    let head_name = plumbing::get_head_ref()?;
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    let upstream_oid = plumbing::rev_parse(upstream)?;
+
+    if head_oid.is_empty() {
+        println!("Cannot rebase a branch without commits");
+        return None;
+    }
+
+    let commits = get_commits_to_replay(&head_oid, &upstream_oid)?;
+
+    let mut based_on = upstream_oid.clone();
+    let mut already_based = true;
+
+    for oid in &commits {
+        already_based &= plumbing::get_commit_parents(oid)? == vec![based_on];
+        based_on = oid.to_string();
+    }
+
+    // A branch that already sits on top of upstream has nothing to rewrite
+    if head_oid == upstream_oid || (!commits.is_empty() && already_based && !interactive) {
+        println!(
+            "Current branch {} is up to date.",
+            plumbing::get_current_branch()?
+        );
+        return Some(());
+    }
+
+    if commits.is_empty() {
+        // Nothing of ours to replay, so the branch can simply move to upstream
+        plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&upstream_oid)?)?;
+        plumbing::update_head(
+            &upstream_oid,
+            &format!("rebase: fast-forward to {upstream}"),
+        )?;
+
+        println!("Fast-forwarded {head_name} to {upstream}.");
+
+        return Some(());
+    }
+
+    let mut steps = commits
+        .into_iter()
+        .map(|oid| GritRebaseStep {
+            command: GritRebaseCommand::Pick,
+            argument: oid,
+        })
+        .collect::<Vec<GritRebaseStep>>();
+
+    match fs::create_dir_all(format!("{GRIT_DIRECTORY}/rebase-merge")) {
+        Ok(_) => (),
+        Err(e) => {
+            println!("Failed to create rebase directory: {}", e);
+            return None;
+        }
+    }
+
+    file_handling::write_file(&get_state_path("head-name"), &head_name)?;
+    file_handling::write_file(&get_state_path("orig-head"), &head_oid)?;
+    file_handling::write_file(&get_state_path("onto"), &upstream_oid)?;
+    write_todo(&steps)?;
+
+    if interactive {
+        let todo_path = get_state_path("git-rebase-todo");
+        let todo = file_handling::read_file(&todo_path)?
+            + format!(
+                "\n# Rebase {}..{} onto {} ({} command{})\n#",
+                &upstream_oid[..7],
+                &head_oid[..7],
+                &upstream_oid[..7],
+                steps.len(),
+                if steps.len() == 1 { "" } else { "s" }
+            )
+            .as_str()
+            + TODO_HELP;
+
+        file_handling::write_file(&todo_path, &todo)?;
+
+        let edited = utils::launch_editor(&todo_path)
+            .and_then(|_| file_handling::read_file(&todo_path))
+            .and_then(|todo| parse_todo(&todo))
+            .and_then(|steps| {
+                check_todo(&steps)?;
+                Some(steps)
+            });
+
+        steps = match edited {
+            Some(steps) => steps,
+            None => {
+                remove_rebase_state()?;
+                return None;
+            }
+        };
+
+        if steps.is_empty() {
+            println!("Nothing to do");
+            return remove_rebase_state();
+        }
+
+        write_todo(&steps)?;
+    }
+
+    plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&upstream_oid)?)?;
+    plumbing::update_head(
+        &upstream_oid,
+        &format!("rebase (start): checkout {upstream}"),
+    )?;
+
+    run_todo()
+}
+
+pub fn rebase_continue() -> Option<()> {
+    if !rebase_in_progress() {
+        println!("No rebase in progress");
+        return None;
+    }
+
+    if path::Path::new(&get_state_path("stopped-sha")).exists() {
+        let unresolved = merge::get_unresolved_paths()?;
+        if !unresolved.is_empty() {
+            println!(
+                "You must edit all merge conflicts and then mark them as resolved using `grit add`:\n\t{}",
+                unresolved.join("\n\t")
+            );
+            return None;
+        }
+
+        let stopped_oid = read_state("stopped-sha")?;
+        let message = read_state("message")?;
+        let current = parse_todo(&read_state("current")?)?;
+
+        file_handling::remove_file(&get_state_path("stopped-sha"))?;
+        file_handling::remove_file(&get_state_path("message"))?;
+        merge::clear_unmerged_paths()?;
+
+        let step = current.into_iter().next()?;
+
+        let outcome = sequencer::commit_picked_changes(
+            GritSequencerAction::Pick,
+            &stopped_oid,
+            &message,
+            &format!("rebase ({})", step.command.name()),
+        )?;
+
+        if let GritPickOutcome::Committed(_oid) = outcome {
+            if !finish_step(&step)? {
+                return Some(());
+            }
+        }
+    } else if path::Path::new(&get_state_path("amend")).exists() {
+        // Changes staged while stopped at an `edit` are folded into that commit
+        let head_oid = plumbing::get_head_oid()?.trim().to_string();
+        let (_index_tree_map, _index_paths, index_entries, _index_oids) =
+            plumbing::get_index_entries()?;
+        let tree_oid = plumbing::write_tree_from_entries(&index_entries)?;
+
+        if tree_oid != plumbing::get_commit_tree_oid(&head_oid)? {
+            amend_head(&tree_oid, &plumbing::get_commit_message(&head_oid)?)?;
+        }
+
+        file_handling::remove_file(&get_state_path("amend"))?;
+    }
+
+    if !plumbing::get_local_changes()?.is_empty() {
+        println!("You have uncommitted changes in your working tree, commit or stash them first");
+        return None;
+    }
+
+    run_todo()
+}
+
+pub fn rebase_skip() -> Option<()> {
+    if !rebase_in_progress() {
+        println!("No rebase in progress");
+        return None;
+    }
+
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&head_oid)?)?;
+
+    file_handling::remove_file(&get_state_path("stopped-sha"))?;
+    file_handling::remove_file(&get_state_path("message"))?;
+    file_handling::remove_file(&get_state_path("amend"))?;
+    merge::clear_unmerged_paths()?;
+
+    run_todo()
+}
+
+pub fn rebase_abort() -> Option<()> {
+    if !rebase_in_progress() {
+        println!("No rebase in progress");
+        return None;
+    }
+
+    let head_name = read_state("head-name")?;
+    let original_head_oid = read_state("orig-head")?;
+
+    plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&original_head_oid)?)?;
+    plumbing::update_head(
+        &original_head_oid,
+        &format!("rebase (abort): returning to {head_name}"),
+    )?;
+
+    remove_rebase_state()
+}
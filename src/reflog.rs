@@ -0,0 +1,339 @@
+use crate::config::{self, GRIT_DIRECTORY};
+use crate::file_handling;
+use crate::plumbing;
+use crate::storage::{self, GritStorage};
+use crate::utils;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path;
+use walkdir::WalkDir;
+
+const DEFAULT_REFLOG_EXPIRE: &str = "90.days.ago";
+
+#[derive(Clone)]
+pub struct GritReflogEntry {
+    pub old_oid: String,
+    pub new_oid: String,
+    pub identity: String,
+    pub timestamp: i64,
+    pub timezone: String,
+    pub message: String,
+}
+
+impl GritReflogEntry {
+    /// An entry for a ref moving from `old_oid` to `new_oid` now, made by the current identity.
+    pub fn new(old_oid: &str, new_oid: &str, message: &str) -> GritReflogEntry {
+        GritReflogEntry {
+            old_oid: if old_oid.is_empty() {
+                plumbing::NULL_OID.to_string()
+            } else {
+                old_oid.to_string()
+            },
+            new_oid: new_oid.to_string(),
+            identity: plumbing::get_identity(),
+            timestamp: utils::get_unix_time(),
+            timezone: "+0000".to_string(),
+            // Each entry has to stay on a single line
+            message: message.lines().next().unwrap_or("").to_string(),
+        }
+    }
+
+    fn parse(line: &str) -> Option<GritReflogEntry> {
+        let (details, message) = line.split_once('\t').unwrap_or((line, ""));
+
+        let (old_oid, rest) = details.split_once(' ')?;
+        let (new_oid, rest) = rest.split_once(' ')?;
+        let (rest, timezone) = rest.rsplit_once(' ')?;
+        let (identity, timestamp) = rest.rsplit_once(' ')?;
+
+        Some(GritReflogEntry {
+            old_oid: old_oid.to_string(),
+            new_oid: new_oid.to_string(),
+            identity: identity.to_string(),
+            timestamp: timestamp.parse().ok()?,
+            timezone: timezone.to_string(),
+            message: message.to_string(),
+        })
+    }
+
+    fn format(&self) -> String {
+        format!(
+            "{} {} {} {} {}\t{}",
+            self.old_oid, self.new_oid, self.identity, self.timestamp, self.timezone, self.message
+        )
+    }
+}
+
+fn get_reflog_path(ref_name: &str) -> String {
+    format!("{GRIT_DIRECTORY}/logs/{ref_name}")
+}
+
+pub fn reflog_exists(ref_name: &str) -> bool {
+    if let Some(GritStorage::Database(database)) = storage::get_storage() {
+        return database
+            .read_reflog(ref_name)
+            .is_some_and(|entries| !entries.is_empty());
+    }
+
+    path::Path::new(&get_reflog_path(ref_name)).is_file()
+}
+
+pub fn append_reflog_entry(
+    ref_name: &str,
+    old_oid: &str,
+    new_oid: &str,
+    message: &str,
+) -> Option<()> {
+    let entry = GritReflogEntry::new(old_oid, new_oid, message);
+
+    if let GritStorage::Database(database) = storage::get_storage()? {
+        return database.append_reflog_entry(ref_name, &entry);
+    }
+
+    let reflog_path = get_reflog_path(ref_name);
+
+    if let Some(parent) = path::Path::new(&reflog_path).parent() {
+        match fs::create_dir_all(parent) {
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to create directory {:?}: {}", parent, e);
+                return None;
+            }
+        }
+    }
+
+    let mut reflog_file = match OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&reflog_path)
+    {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Failed to open reflog {}: {}", reflog_path, e);
+            return None;
+        }
+    };
+
+    match reflog_file.write_all(format!("{}\n", entry.format()).as_bytes()) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to update reflog {}: {}", reflog_path, e);
+            None
+        }
+    }
+}
+
+/// Returns the entries of a reflog, oldest first.
+pub fn read_reflog(ref_name: &str) -> Option<Vec<GritReflogEntry>> {
+    if let GritStorage::Database(database) = storage::get_storage()? {
+        return database.read_reflog(ref_name);
+    }
+
+    if !reflog_exists(ref_name) {
+        return Some(vec![]);
+    }
+
+    let content = file_handling::read_file(&get_reflog_path(ref_name))?;
+
+    Some(content.lines().filter_map(GritReflogEntry::parse).collect())
+}
+
+pub fn write_reflog(ref_name: &str, entries: &[GritReflogEntry]) -> Option<()> {
+    if let GritStorage::Database(database) = storage::get_storage()? {
+        return database.write_reflog(ref_name, entries);
+    }
+
+    let reflog_path = get_reflog_path(ref_name);
+
+    if let Some(parent) = path::Path::new(&reflog_path).parent() {
+        match fs::create_dir_all(parent) {
+            Ok(_) => (),
+            Err(e) => {
+                println!("Failed to create directory {:?}: {}", parent, e);
+                return None;
+            }
+        }
+    }
+
+    let content = entries
+        .iter()
+        .map(|entry| format!("{}\n", entry.format()))
+        .collect::<String>();
+
+    let mut reflog_file = match File::create(&reflog_path) {
+        Ok(file) => file,
+        Err(e) => {
+            println!("Failed to open reflog {}: {}", reflog_path, e);
+            return None;
+        }
+    };
+
+    match reflog_file.write_all(content.as_bytes()) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to update reflog {}: {}", reflog_path, e);
+            None
+        }
+    }
+}
+
+pub fn delete_reflog(ref_name: &str) -> Option<()> {
+    if let GritStorage::Database(database) = storage::get_storage()? {
+        return database.delete_reflog(ref_name);
+    }
+
+    if !reflog_exists(ref_name) {
+        return Some(());
+    }
+
+    match fs::remove_file(get_reflog_path(ref_name)) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to delete reflog for {}: {}", ref_name, e);
+            None
+        }
+    }
+}
+
+/// Looks up the `n`th most recent value of a ref, as in `HEAD@{n}`.
+pub fn get_reflog_oid(ref_name: &str, n: usize) -> Option<String> {
+    let entries = read_reflog(ref_name)?;
+
+    if n >= entries.len() {
+        println!(
+            "Log for {} only has {} entr{}",
+            ref_name,
+            entries.len(),
+            if entries.len() == 1 { "y" } else { "ies" }
+        );
+        return None;
+    }
+
+    Some(entries[entries.len() - 1 - n].new_oid.clone())
+}
+
+/// Splits `main@{2}` into the ref to look up and the reflog position.
+/// A bare `@{n}` refers to the current branch.
+pub fn parse_reflog_selector(rev: &str) -> Option<(String, usize)> {
+    let (name, selector) = rev.split_once("@{")?;
+    let n = selector.strip_suffix('}')?.parse::<usize>().ok()?;
+
+    let ref_name = if name.is_empty() {
+        plumbing::get_head_ref()?
+    } else {
+        plumbing::resolve_ref_name(name)?
+    };
+
+    Some((ref_name, n))
+}
+
+pub fn get_all_reflogs() -> Vec<String> {
+    if let Some(GritStorage::Database(database)) = storage::get_storage() {
+        return database.get_reflog_names().unwrap_or_default();
+    }
+
+    let logs_dir_path = format!("{GRIT_DIRECTORY}/logs");
+
+    WalkDir::new(&logs_dir_path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            entry
+                .path()
+                .strip_prefix(&logs_dir_path)
+                .ok()
+                .map(|ref_path| ref_path.display().to_string())
+        })
+        .collect()
+}
+
+pub fn show_reflog(name: &str) -> Option<()> {
+    let ref_name = match plumbing::resolve_ref_name(name) {
+        Some(ref_name) => ref_name,
+        None => {
+            println!("Unknown ref {name}");
+            return None;
+        }
+    };
+    let entries = read_reflog(&ref_name)?;
+
+    let report = entries
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, entry)| format!("{} {name}@{{{i}}}: {}", &entry.new_oid[..7], entry.message))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    if !report.is_empty() {
+        println!("{report}");
+    }
+
+    Some(())
+}
+
+pub fn expire_reflogs(ref_names: &[String], expire: Option<&str>) -> Option<()> {
+    let expire = match expire {
+        Some(expire) => expire.to_string(),
+        None => {
+            config::get_config_value("gc.reflogExpire").unwrap_or(DEFAULT_REFLOG_EXPIRE.to_string())
+        }
+    };
+    let cutoff = utils::parse_expiry(&expire)?;
+
+    let mut expired = 0;
+
+    for ref_name in ref_names {
+        let entries = read_reflog(ref_name)?;
+        let total = entries.len();
+
+        let entries = entries
+            .into_iter()
+            .filter(|entry| entry.timestamp > cutoff)
+            .collect::<Vec<GritReflogEntry>>();
+
+        if entries.len() != total {
+            expired += total - entries.len();
+            write_reflog(ref_name, &entries)?;
+        }
+    }
+
+    let report = format!(
+        "Expired {} reflog entr{}",
+        expired,
+        if expired == 1 { "y" } else { "ies" }
+    );
+
+    println!("{report}");
+
+    Some(())
+}
+
+pub fn delete_reflog_entries(revs: &[String]) -> Option<()> {
+    for rev in revs {
+        let (ref_name, n) = match parse_reflog_selector(rev) {
+            Some(selector) => selector,
+            None => {
+                println!("Not a reflog entry: {rev}");
+                return None;
+            }
+        };
+
+        let mut entries = read_reflog(&ref_name)?;
+
+        if n >= entries.len() {
+            println!("Reflog entry {rev} does not exist");
+            return None;
+        }
+
+        let position = entries.len() - 1 - n;
+        entries.remove(position);
+
+        write_reflog(&ref_name, &entries)?;
+
+        println!("Deleted {rev}");
+    }
+
+    Some(())
+}
@@ -0,0 +1,850 @@
+use crate::config::GRIT_DIRECTORY;
+use crate::file_handling;
+use crate::lockfile::GritLockFile;
+use crate::plumbing::{self, GritCatType};
+use crate::reflog::{self, GritReflogEntry};
+use crate::storage::{self, GritStorage};
+use crate::utils;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path;
+use walkdir::WalkDir;
+
+const PACKED_REFS_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted \n";
+
+/// What a transaction does to one ref.
+pub enum GritRefChange {
+    Update(String),
+    Delete,
+    /// Only checks the old value, leaving the ref as it is.
+    Verify,
+}
+
+pub struct GritRefUpdate {
+    pub ref_name: String,
+    pub change: GritRefChange,
+    /// The value the ref must have before the change, empty if it must not
+    /// exist yet, or `None` to skip the check.
+    pub old_oid: Option<String>,
+    pub reason: String,
+}
+
+/// A set of ref updates that are applied all together or not at all.
+pub struct GritRefTransaction {
+    updates: Vec<GritRefUpdate>,
+}
+
+/// Checks a ref name against the same rules as `git check-ref-format`.
+pub fn check_ref_format(ref_name: &str, allow_onelevel: bool) -> bool {
+    if ref_name.is_empty()
+        || ref_name == "@"
+        || ref_name.starts_with('/')
+        || ref_name.ends_with('/')
+        || ref_name.ends_with('.')
+        || ref_name.contains("//")
+        || ref_name.contains("..")
+        || ref_name.contains("@{")
+    {
+        return false;
+    }
+
+    let has_forbidden_char = ref_name.chars().any(|c| {
+        c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+    });
+
+    if has_forbidden_char {
+        return false;
+    }
+
+    if !allow_onelevel && !ref_name.contains('/') {
+        return false;
+    }
+
+    ref_name
+        .split('/')
+        .all(|component| !component.starts_with('.') && !component.ends_with(".lock"))
+}
+
+pub fn check_ref_format_command(ref_name: &str, allow_onelevel: bool, branch: bool) -> Option<()> {
+    let full_name = if branch {
+        format!("refs/heads/{ref_name}")
+    } else {
+        ref_name.to_string()
+    };
+
+    let valid =
+        check_ref_format(&full_name, allow_onelevel) && !(branch && ref_name.starts_with('-'));
+
+    if !valid {
+        println!("fatal: '{ref_name}' is not a valid ref name");
+        return None;
+    }
+
+    if branch {
+        println!("{ref_name}");
+    }
+
+    Some(())
+}
+
+fn get_packed_refs_path() -> String {
+    format!("{GRIT_DIRECTORY}/packed-refs")
+}
+
+fn get_loose_ref_path(ref_name: &str) -> String {
+    format!("{GRIT_DIRECTORY}/{ref_name}")
+}
+
+/// The value stored for HEAD or a ref: an oid, an empty value for a branch
+/// without commits, or `ref: <target>`. A loose ref takes precedence over a
+/// packed one, which may be stale.
+pub fn read_ref_value(ref_name: &str) -> Option<Option<String>> {
+    if let GritStorage::Database(database) = storage::get_storage()? {
+        return database.read_ref(ref_name);
+    }
+
+    let ref_path = get_loose_ref_path(ref_name);
+
+    if !path::Path::new(&ref_path).is_file() {
+        return get_packed_ref_oid(ref_name);
+    }
+
+    Some(Some(
+        file_handling::read_file(&ref_path)?.trim().to_string(),
+    ))
+}
+
+/// Stores `value` for HEAD or a ref as it is, without any checks or reflog entry.
+pub fn write_ref_value(ref_name: &str, value: &str) -> Option<()> {
+    if let GritStorage::Database(database) = storage::get_storage()? {
+        return database.write_ref(ref_name, value);
+    }
+
+    let mut lock = GritLockFile::acquire(&get_loose_ref_path(ref_name))?;
+
+    lock.write(value.as_bytes())?;
+    lock.commit()
+}
+
+/// Removes the stored value of a ref, without touching packed refs or its reflog.
+pub fn remove_ref_value(ref_name: &str) -> Option<()> {
+    if let GritStorage::Database(database) = storage::get_storage()? {
+        return database.remove_ref(ref_name);
+    }
+
+    file_handling::remove_file(&get_loose_ref_path(ref_name))
+}
+
+/// Reads `packed-refs` into a map of ref name to oid. Peeled `^oid` lines are skipped.
+pub fn read_packed_refs() -> Option<BTreeMap<String, String>> {
+    let packed_refs_path = get_packed_refs_path();
+
+    if !path::Path::new(&packed_refs_path).exists() {
+        return Some(BTreeMap::new());
+    }
+
+    let content = file_handling::read_file(&packed_refs_path)?;
+
+    let packed_refs = content
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+        .filter_map(|line| line.split_once(' '))
+        .map(|(oid, ref_name)| (ref_name.to_string(), oid.to_string()))
+        .collect();
+
+    Some(packed_refs)
+}
+
+fn format_packed_refs(packed_refs: &BTreeMap<String, String>) -> Option<String> {
+    let mut content = PACKED_REFS_HEADER.to_string();
+
+    for (ref_name, oid) in packed_refs {
+        content += format!("{oid} {ref_name}\n").as_str();
+
+        // Annotated tags also record the object they point at
+        let peeled_oid = plumbing::peel_tag(oid)?;
+        if &peeled_oid != oid {
+            content += format!("^{peeled_oid}\n").as_str();
+        }
+    }
+
+    Some(content)
+}
+
+pub fn get_packed_ref_oid(ref_name: &str) -> Option<Option<String>> {
+    Some(read_packed_refs()?.get(ref_name).cloned())
+}
+
+/// All refs below `refs/`, loose and packed, sorted by name.
+pub fn get_all_refs() -> Option<Vec<String>> {
+    if let GritStorage::Database(database) = storage::get_storage()? {
+        return database.get_ref_names();
+    }
+
+    let refs_dir_path = format!("{GRIT_DIRECTORY}/refs");
+
+    let mut ref_names = WalkDir::new(&refs_dir_path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            entry
+                .path()
+                .display()
+                .to_string()
+                .replace(&format!("{GRIT_DIRECTORY}/"), "")
+        })
+        .filter(|ref_name| !ref_name.ends_with(".lock"))
+        .collect::<Vec<String>>();
+
+    ref_names.extend(read_packed_refs()?.into_keys());
+    ref_names.sort();
+    ref_names.dedup();
+
+    Some(ref_names)
+}
+
+/// Resolves the value given for an old or new oid, where an empty value or the
+/// null oid stands for a ref that does not exist.
+fn resolve_oid_argument(value: &str) -> Option<String> {
+    if value.is_empty() || value == plumbing::NULL_OID {
+        return Some("".to_string());
+    }
+
+    plumbing::rev_parse(value)
+}
+
+/// Follows HEAD to the branch it points at, unless HEAD is detached or
+/// `no_deref` asks for HEAD itself.
+fn dereference(ref_name: &str, no_deref: bool) -> Option<String> {
+    if ref_name == "HEAD" && !no_deref {
+        return plumbing::get_head_ref();
+    }
+
+    Some(ref_name.to_string())
+}
+
+/// `update-ref`: points a ref at an object, optionally only if it currently has
+/// `old_value`. With `no_deref`, HEAD itself is overwritten, detaching it.
+pub fn update_ref_command(
+    ref_name: &str,
+    new_value: &str,
+    old_value: Option<&str>,
+    reason: &str,
+    no_deref: bool,
+) -> Option<()> {
+    if ref_name != "HEAD" && !check_ref_format(ref_name, false) {
+        println!("fatal: '{ref_name}' is not a valid ref name");
+        return None;
+    }
+
+    let ref_name = dereference(ref_name, no_deref)?;
+    let new_oid = resolve_oid_argument(new_value)?;
+
+    if new_oid.is_empty() {
+        println!("fatal: {new_value}: not a valid object name");
+        return None;
+    }
+
+    let old_oid = match old_value {
+        Some(old_value) => Some(resolve_oid_argument(old_value)?),
+        None => None,
+    };
+
+    let reason = if reason.is_empty() {
+        "update-ref"
+    } else {
+        reason
+    };
+
+    let previous_oid = plumbing::get_ref_oid(&ref_name)?;
+
+    let mut transaction = GritRefTransaction::new();
+    transaction.update(&ref_name, &new_oid, old_oid.as_deref(), reason);
+    transaction.commit()?;
+
+    // Moving the checked out branch moves HEAD too, so both reflogs record it
+    if ref_name != "HEAD" && plumbing::get_head_ref()? == ref_name {
+        reflog::append_reflog_entry("HEAD", &previous_oid, &new_oid, reason)?;
+    }
+
+    Some(())
+}
+
+/// `update-ref -d`: deletes a ref, optionally only if it currently has `old_value`.
+pub fn delete_ref_command(ref_name: &str, old_value: Option<&str>, no_deref: bool) -> Option<()> {
+    let ref_name = dereference(ref_name, no_deref)?;
+
+    if ref_name == "HEAD" {
+        println!("fatal: refusing to delete HEAD");
+        return None;
+    }
+
+    let old_oid = match old_value {
+        Some(old_value) => Some(resolve_oid_argument(old_value)?),
+        None => None,
+    };
+
+    let mut transaction = GritRefTransaction::new();
+    transaction.delete(&ref_name, old_oid.as_deref());
+    transaction.commit()
+}
+
+/// `symbolic-ref`: prints the ref HEAD points at, or points HEAD at `target`.
+pub fn symbolic_ref(name: &str, target: Option<&str>, short: bool, reason: &str) -> Option<()> {
+    if name != "HEAD" {
+        println!("fatal: only HEAD can be a symbolic ref");
+        return None;
+    }
+
+    let target = match target {
+        Some(target) => target,
+        None => {
+            if plumbing::is_head_detached()? {
+                println!("fatal: ref HEAD is not a symbolic ref");
+                return None;
+            }
+
+            let head_ref = plumbing::get_head_ref()?;
+
+            if short {
+                println!("{}", get_short_name(&head_ref));
+            } else {
+                println!("{head_ref}");
+            }
+
+            return Some(());
+        }
+    };
+
+    if !target.starts_with("refs/") || !check_ref_format(target, false) {
+        println!("fatal: refusing to point HEAD outside of refs/: {target}");
+        return None;
+    }
+
+    let old_oid = plumbing::get_ref_oid("HEAD")?;
+
+    write_ref_value("HEAD", &format!("ref: {target}"))?;
+
+    let new_oid = plumbing::get_ref_oid(target)?;
+
+    if !reason.is_empty() || old_oid != new_oid {
+        let reason = if reason.is_empty() {
+            format!("symbolic-ref: moving to {target}")
+        } else {
+            reason.to_string()
+        };
+
+        reflog::append_reflog_entry("HEAD", &old_oid, &new_oid, &reason)?;
+    }
+
+    Some(())
+}
+
+/// Shortens a ref name the way it would be typed, e.g. `refs/heads/main` to `main`.
+pub fn get_short_name(ref_name: &str) -> String {
+    for prefix in ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"] {
+        if let Some(short_name) = ref_name.strip_prefix(prefix) {
+            return short_name.to_string();
+        }
+    }
+
+    ref_name.to_string()
+}
+
+/// Everything a `for-each-ref` format or sort key can ask about a ref.
+struct GritRefInfo {
+    ref_name: String,
+    oid: String,
+    object_type: String,
+    commit_oid: Option<String>,
+}
+
+impl GritRefInfo {
+    fn load(ref_name: &str) -> Option<GritRefInfo> {
+        let oid = plumbing::get_ref_oid(ref_name)?;
+        let object_type = plumbing::generate_cat_content(&oid, GritCatType::Type)?;
+
+        let peeled_oid = plumbing::peel_tag(&oid)?;
+        let commit_oid =
+            match plumbing::generate_cat_content(&peeled_oid, GritCatType::Type)?.as_str() {
+                "commit" => Some(peeled_oid),
+                _ => None,
+            };
+
+        Some(GritRefInfo {
+            ref_name: ref_name.to_string(),
+            oid,
+            object_type,
+            commit_oid,
+        })
+    }
+
+    fn get_field(&self, field: &str) -> Option<String> {
+        let commit_oid = self.commit_oid.as_deref().unwrap_or("");
+        let has_commit = self.commit_oid.is_some();
+
+        let value = match field {
+            "refname" => self.ref_name.clone(),
+            "refname:short" => get_short_name(&self.ref_name),
+            "objectname" => self.oid.clone(),
+            "objectname:short" => self.oid[..7].to_string(),
+            "objecttype" => self.object_type.clone(),
+            "HEAD" => {
+                if plumbing::get_head_ref()? == self.ref_name {
+                    "*".to_string()
+                } else {
+                    " ".to_string()
+                }
+            }
+            "authorname" | "committername" if has_commit => {
+                plumbing::get_commit_author(commit_oid)?
+            }
+            "authordate" | "committerdate" if has_commit => plumbing::get_commit_date(commit_oid)?,
+            "subject" | "contents:subject" if has_commit => {
+                plumbing::get_commit_message(commit_oid)?
+                    .lines()
+                    .next()
+                    .unwrap_or("")
+                    .to_string()
+            }
+            "body" | "contents:body" if has_commit => {
+                let message = plumbing::get_commit_message(commit_oid)?;
+                match message.split_once("\n\n") {
+                    Some((_subject, body)) => body.to_string(),
+                    None => "".to_string(),
+                }
+            }
+            "contents" if has_commit => plumbing::get_commit_message(commit_oid)?,
+            "authorname" | "committername" | "authordate" | "committerdate" | "subject"
+            | "contents:subject" | "body" | "contents:body" | "contents" => "".to_string(),
+            _ => {
+                println!("fatal: unknown field name: {field}");
+                return None;
+            }
+        };
+
+        Some(value)
+    }
+
+    fn format(&self, format: &str) -> Option<String> {
+        let mut output = String::new();
+        let mut rest = format;
+
+        while let Some(position) = rest.find('%') {
+            output.push_str(&rest[..position]);
+            rest = &rest[position + 1..];
+
+            if let Some(after) = rest.strip_prefix('%') {
+                output.push('%');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('(') {
+                let end = match after.find(')') {
+                    Some(end) => end,
+                    None => {
+                        println!("fatal: malformed format string {format}");
+                        return None;
+                    }
+                };
+
+                output.push_str(&self.get_field(&after[..end])?);
+                rest = &after[end + 1..];
+            } else if rest.len() >= 2 && u8::from_str_radix(&rest[..2], 16).is_ok() {
+                output.push(u8::from_str_radix(&rest[..2], 16).ok()? as char);
+                rest = &rest[2..];
+            } else {
+                output.push('%');
+            }
+        }
+
+        output.push_str(rest);
+
+        Some(output)
+    }
+
+    fn compare(&self, other: &GritRefInfo, key: &str) -> Option<Ordering> {
+        let ordering = match key {
+            "authordate" | "committerdate" => {
+                let time = |info: &GritRefInfo| -> Option<i64> {
+                    match info.get_field(key)?.as_str() {
+                        "" => Some(0),
+                        date => utils::parse_time(date),
+                    }
+                };
+                time(self)?.cmp(&time(other)?)
+            }
+            _ => self.get_field(key)?.cmp(&other.get_field(key)?),
+        };
+
+        Some(ordering)
+    }
+}
+
+fn matches_ref_pattern(ref_name: &str, patterns: &[String]) -> bool {
+    patterns.is_empty()
+        || patterns.iter().any(|pattern| {
+            let pattern = pattern.trim_end_matches('/');
+            ref_name == pattern
+                || ref_name.starts_with(&format!("{pattern}/"))
+                || utils::matches_glob(pattern, ref_name)
+        })
+}
+
+/// `for-each-ref`: prints refs matching `patterns`, ordered by `sort_keys`
+/// (the last key is the primary one, a leading `-` reverses it).
+pub fn for_each_ref(format: Option<&str>, sort_keys: &[String], patterns: &[String]) -> Option<()> {
+    let format = format.unwrap_or("%(objectname) %(objecttype)\t%(refname)");
+
+    let mut refs = vec![];
+
+    for ref_name in get_all_refs()? {
+        // Branches without commits yet have an empty ref file
+        if matches_ref_pattern(&ref_name, patterns) && !plumbing::get_ref_oid(&ref_name)?.is_empty()
+        {
+            refs.push(GritRefInfo::load(&ref_name)?);
+        }
+    }
+
+    for sort_key in sort_keys {
+        let (key, descending) = match sort_key.strip_prefix('-') {
+            Some(key) => (key, true),
+            None => (sort_key.as_str(), false),
+        };
+
+        // Surface an unknown key before sorting, where errors cannot be returned
+        if let Some(info) = refs.first() {
+            info.get_field(key)?;
+        }
+
+        refs.sort_by(|a, b| {
+            let ordering = a.compare(b, key).unwrap_or(Ordering::Equal);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
+    let mut report = String::new();
+
+    for info in &refs {
+        report += format!("{}\n", info.format(format)?).as_str();
+    }
+
+    print!("{report}");
+
+    Some(())
+}
+
+/// Checks every update against the current value of its ref, giving back
+/// those values in the same order.
+fn check_updates(updates: &[GritRefUpdate]) -> Option<Vec<String>> {
+    let mut old_oids = vec![];
+    for update in updates {
+        let current_oid = plumbing::get_ref_oid(&update.ref_name)?;
+
+        if let Some(expected_oid) = &update.old_oid {
+            if expected_oid != &current_oid {
+                let expected = if expected_oid.is_empty() {
+                    "it not to exist".to_string()
+                } else {
+                    expected_oid.to_string()
+                };
+                let current = if current_oid.is_empty() {
+                    "it does not exist".to_string()
+                } else {
+                    format!("it is at {current_oid}")
+                };
+
+                println!(
+                    "fatal: cannot lock ref '{}': expected {expected}, but {current}",
+                    update.ref_name
+                );
+                return None;
+            }
+        }
+
+        if let GritRefChange::Delete = update.change {
+            if current_oid.is_empty() {
+                println!("fatal: ref '{}' does not exist", update.ref_name);
+                return None;
+            }
+        }
+
+        old_oids.push(current_oid);
+    }
+
+    Some(old_oids)
+}
+
+impl GritRefTransaction {
+    pub fn new() -> GritRefTransaction {
+        GritRefTransaction { updates: vec![] }
+    }
+
+    pub fn update(&mut self, ref_name: &str, new_oid: &str, old_oid: Option<&str>, reason: &str) {
+        self.updates.push(GritRefUpdate {
+            ref_name: ref_name.to_string(),
+            change: GritRefChange::Update(new_oid.to_string()),
+            old_oid: old_oid.map(|oid| oid.to_string()),
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn delete(&mut self, ref_name: &str, old_oid: Option<&str>) {
+        self.updates.push(GritRefUpdate {
+            ref_name: ref_name.to_string(),
+            change: GritRefChange::Delete,
+            old_oid: old_oid.map(|oid| oid.to_string()),
+            reason: "".to_string(),
+        });
+    }
+
+    pub fn verify(&mut self, ref_name: &str, old_oid: Option<&str>) {
+        self.updates.push(GritRefUpdate {
+            ref_name: ref_name.to_string(),
+            change: GritRefChange::Verify,
+            old_oid: old_oid.map(|oid| oid.to_string()),
+            reason: "".to_string(),
+        });
+    }
+
+    /// Locks every ref, checks the old values, then moves the new values into
+    /// place. If anything fails before the first rename, no ref is changed.
+    pub fn commit(self) -> Option<()> {
+        let mut seen = HashSet::new();
+        for update in &self.updates {
+            if !seen.insert(update.ref_name.as_str()) {
+                println!(
+                    "fatal: multiple updates for ref '{}' not allowed",
+                    update.ref_name
+                );
+                return None;
+            }
+        }
+
+        let mut updates = self.updates;
+        updates.sort_by(|a, b| a.ref_name.cmp(&b.ref_name));
+
+        if let GritStorage::Database(database) = storage::get_storage()? {
+            return database.transaction(&mut || {
+                let old_oids = check_updates(&updates)?;
+
+                for (update, old_oid) in updates.iter().zip(old_oids) {
+                    match &update.change {
+                        GritRefChange::Update(new_oid) => {
+                            database.write_ref(&update.ref_name, new_oid)?;
+                            database.append_reflog_entry(
+                                &update.ref_name,
+                                &GritReflogEntry::new(&old_oid, new_oid, &update.reason),
+                            )?;
+                        }
+                        GritRefChange::Delete => {
+                            database.remove_ref(&update.ref_name)?;
+                            database.delete_reflog(&update.ref_name)?;
+                        }
+                        GritRefChange::Verify => (),
+                    }
+                }
+
+                Some(())
+            });
+        }
+
+        // Dropping the locks on an early return rolls every one of them back
+        let mut locks = vec![];
+        for update in &updates {
+            locks.push(GritLockFile::acquire(&get_loose_ref_path(
+                &update.ref_name,
+            ))?);
+        }
+
+        let old_oids = check_updates(&updates)?;
+
+        for (update, lock) in updates.iter().zip(locks.iter_mut()) {
+            if let GritRefChange::Update(new_oid) = &update.change {
+                lock.write(new_oid.as_bytes())?;
+            }
+        }
+
+        // Deleted refs also have to disappear from packed-refs
+        let packed_refs = read_packed_refs()?;
+        let packed_deletions = updates
+            .iter()
+            .filter(|update| matches!(update.change, GritRefChange::Delete))
+            .filter(|update| packed_refs.contains_key(&update.ref_name))
+            .map(|update| update.ref_name.as_str())
+            .collect::<Vec<&str>>();
+
+        if !packed_deletions.is_empty() {
+            let mut packed_refs_lock = GritLockFile::acquire(&get_packed_refs_path())?;
+            let mut remaining = packed_refs;
+            for ref_name in packed_deletions {
+                remaining.remove(ref_name);
+            }
+            packed_refs_lock.write(format_packed_refs(&remaining)?.as_bytes())?;
+            packed_refs_lock.commit()?;
+        }
+
+        for ((update, lock), old_oid) in updates.iter().zip(locks).zip(old_oids) {
+            match &update.change {
+                GritRefChange::Update(new_oid) => {
+                    lock.commit()?;
+                    reflog::append_reflog_entry(
+                        &update.ref_name,
+                        &old_oid,
+                        new_oid,
+                        &update.reason,
+                    )?;
+                }
+                GritRefChange::Delete => {
+                    lock.commit_removal()?;
+                    reflog::delete_reflog(&update.ref_name)?;
+                }
+                GritRefChange::Verify => drop(lock),
+            }
+        }
+
+        Some(())
+    }
+}
+
+impl Default for GritRefTransaction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `update-ref --stdin`: applies `update`, `create`, `delete` and `verify`
+/// commands read from stdin as a single transaction.
+pub fn update_refs_from_commands(commands: &str, reason: &str, no_deref: bool) -> Option<()> {
+    let mut transaction = GritRefTransaction::new();
+    let reason = if reason.is_empty() {
+        "update-ref"
+    } else {
+        reason
+    };
+
+    for line in commands.lines().filter(|line| !line.trim().is_empty()) {
+        let fields = line.split_whitespace().collect::<Vec<&str>>();
+
+        let ref_name = match fields.get(1) {
+            Some(ref_name) => dereference(ref_name, no_deref)?,
+            None => {
+                println!("fatal: missing ref in: {line}");
+                return None;
+            }
+        };
+
+        if ref_name != "HEAD" && !check_ref_format(&ref_name, false) {
+            println!("fatal: '{ref_name}' is not a valid ref name");
+            return None;
+        }
+
+        let old_oid = match fields.get(3).or(fields.get(2)) {
+            Some(value) if fields[0] != "update" || fields.len() > 3 => {
+                Some(resolve_oid_argument(value)?)
+            }
+            _ => None,
+        };
+
+        match (fields[0], fields.len()) {
+            ("update", 3..=4) => transaction.update(
+                &ref_name,
+                &resolve_oid_argument(fields[2])?,
+                old_oid.as_deref(),
+                reason,
+            ),
+            ("create", 3) => transaction.update(
+                &ref_name,
+                &resolve_oid_argument(fields[2])?,
+                Some(""),
+                reason,
+            ),
+            ("delete", 2..=3) => transaction.delete(&ref_name, old_oid.as_deref()),
+            ("verify", 2..=3) => {
+                transaction.verify(&ref_name, Some(old_oid.as_deref().unwrap_or("")))
+            }
+            _ => {
+                println!("fatal: invalid command: {line}");
+                return None;
+            }
+        }
+    }
+
+    transaction.commit()
+}
+
+/// `pack-refs`: moves tags, or with `all` every ref, into `packed-refs` and
+/// removes the loose files unless `prune` is off.
+pub fn pack_refs(all: bool, prune: bool) -> Option<()> {
+    // A database has no loose ref files to pack
+    if let GritStorage::Database(_) = storage::get_storage()? {
+        return Some(());
+    }
+
+    let mut packed_refs_lock = GritLockFile::acquire(&get_packed_refs_path())?;
+    let mut packed_refs = read_packed_refs()?;
+    let mut packed_loose_refs = vec![];
+
+    for ref_name in get_all_refs()? {
+        let loose_ref_path = get_loose_ref_path(&ref_name);
+
+        if !path::Path::new(&loose_ref_path).is_file() {
+            continue;
+        }
+
+        if !all && !ref_name.starts_with("refs/tags/") {
+            continue;
+        }
+
+        let oid = file_handling::read_file(&loose_ref_path)?
+            .trim()
+            .to_string();
+        if oid.is_empty() {
+            continue;
+        }
+
+        packed_refs.insert(ref_name.clone(), oid.clone());
+        packed_loose_refs.push((ref_name, oid));
+    }
+
+    packed_refs_lock.write(format_packed_refs(&packed_refs)?.as_bytes())?;
+    packed_refs_lock.commit()?;
+
+    if !prune {
+        return Some(());
+    }
+
+    for (ref_name, oid) in packed_loose_refs {
+        let lock = GritLockFile::acquire(&get_loose_ref_path(&ref_name))?;
+
+        // A ref that moved since it was packed keeps its loose file
+        if file_handling::read_file(&get_loose_ref_path(&ref_name))?.trim() == oid {
+            lock.commit_removal()?;
+            remove_empty_ref_directories(&ref_name);
+        }
+    }
+
+    Some(())
+}
+
+fn remove_empty_ref_directories(ref_name: &str) {
+    let mut directory = path::Path::new(&get_loose_ref_path(ref_name))
+        .parent()
+        .map(|parent| parent.to_path_buf());
+
+    while let Some(current) = directory {
+        let is_top = current.ends_with("refs/heads")
+            || current.ends_with("refs/tags")
+            || current.ends_with("refs");
+
+        if is_top || fs::remove_dir(&current).is_err() {
+            break;
+        }
+
+        directory = current.parent().map(|parent| parent.to_path_buf());
+    }
+}
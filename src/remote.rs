@@ -0,0 +1,709 @@
+use crate::config;
+use crate::file_handling;
+use crate::hooks;
+use crate::merge;
+use crate::plumbing;
+use crate::rebase;
+use crate::refs::{self, GritRefTransaction};
+use crate::transport::{self, GritRefCommand, GritRemoteState};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// A `[+]source:destination` mapping between remote and local refs, where a
+/// trailing `*` on both sides stands for the rest of the ref name.
+pub struct GritRefspec {
+    pub force: bool,
+    pub source: String,
+    pub destination: String,
+}
+
+impl GritRefspec {
+    pub fn parse(refspec: &str) -> Option<GritRefspec> {
+        let (force, refspec) = match refspec.strip_prefix('+') {
+            Some(refspec) => (true, refspec),
+            None => (false, refspec),
+        };
+
+        let (source, destination) = refspec.split_once(':').unwrap_or((refspec, refspec));
+
+        if source.ends_with('*') != destination.ends_with('*') {
+            println!("fatal: invalid refspec '{refspec}'");
+            return None;
+        }
+
+        Some(GritRefspec {
+            force,
+            source: source.to_string(),
+            destination: destination.to_string(),
+        })
+    }
+
+    /// The local ref that `ref_name` on the remote is fetched into, if the refspec covers it.
+    pub fn map(&self, ref_name: &str) -> Option<String> {
+        match self.source.strip_suffix('*') {
+            Some(prefix) => {
+                let rest = ref_name.strip_prefix(prefix)?;
+                Some(self.destination.replacen('*', rest, 1))
+            }
+            None if self.source == ref_name => Some(self.destination.clone()),
+            None => None,
+        }
+    }
+}
+
+/// The outcome of moving one ref, as shown in the fetch and push reports.
+enum GritRefUpdateStatus {
+    New,
+    FastForward(String),
+    Forced(String),
+    Deleted,
+    Rejected(String),
+    RemoteRejected(String),
+}
+
+struct GritReportLine {
+    status: GritRefUpdateStatus,
+    new_oid: String,
+    source: String,
+    destination: String,
+}
+
+fn format_report(lines: &[GritReportLine]) -> String {
+    let width = lines
+        .iter()
+        .map(|line| line.source.len())
+        .max()
+        .unwrap_or(0);
+
+    lines
+        .iter()
+        .map(|line| {
+            let (flag, summary, suffix) = match &line.status {
+                GritRefUpdateStatus::New if line.destination.starts_with("refs/tags/") => {
+                    ("*", "[new tag]".to_string(), "".to_string())
+                }
+                GritRefUpdateStatus::New => ("*", "[new branch]".to_string(), "".to_string()),
+                GritRefUpdateStatus::FastForward(old_oid) => (
+                    " ",
+                    format!("{}..{}", &old_oid[..7], &line.new_oid[..7]),
+                    "".to_string(),
+                ),
+                GritRefUpdateStatus::Forced(old_oid) => (
+                    "+",
+                    format!("{}...{}", &old_oid[..7], &line.new_oid[..7]),
+                    "  (forced update)".to_string(),
+                ),
+                GritRefUpdateStatus::Deleted => ("-", "[deleted]".to_string(), "".to_string()),
+                GritRefUpdateStatus::Rejected(reason) => {
+                    ("!", "[rejected]".to_string(), format!(" ({reason})"))
+                }
+                GritRefUpdateStatus::RemoteRejected(reason) => {
+                    ("!", "[remote rejected]".to_string(), format!(" ({reason})"))
+                }
+            };
+
+            if let GritRefUpdateStatus::Deleted = line.status {
+                return format!(" {flag} {summary:<17} {}\n", line.source);
+            }
+
+            format!(
+                " {flag} {summary:<17} {:<width$} -> {}{suffix}\n",
+                line.source,
+                refs::get_short_name(&line.destination)
+            )
+        })
+        .collect()
+}
+
+fn remote_exists(name: &str) -> Option<bool> {
+    Some(
+        config::get_subsections("remote")?
+            .iter()
+            .any(|remote| remote == name),
+    )
+}
+
+pub fn remote_add(name: &str, url: &str) -> Option<()> {
+    if !refs::check_ref_format(&format!("refs/remotes/{name}"), false) {
+        println!("fatal: '{name}' is not a valid remote name");
+        return None;
+    }
+
+    if remote_exists(name)? {
+        println!("error: remote {name} already exists.");
+        return None;
+    }
+
+    config::set_config_value(&format!("remote.{name}.url"), url)?;
+    config::set_config_value(
+        &format!("remote.{name}.fetch"),
+        &format!("+refs/heads/*:refs/remotes/{name}/*"),
+    )
+}
+
+/// Removes a remote along with its remote-tracking refs and the upstream
+/// settings of branches that track it.
+pub fn remote_remove(name: &str) -> Option<()> {
+    if !remote_exists(name)? {
+        println!("error: No such remote: '{name}'");
+        return None;
+    }
+
+    for branch in config::get_subsections("branch")? {
+        if config::get_config_value(&format!("branch.{branch}.remote")).as_deref() == Some(name) {
+            config::unset_config_value(&format!("branch.{branch}.remote"))?;
+            config::unset_config_value(&format!("branch.{branch}.merge"))?;
+        }
+    }
+
+    let prefix = format!("refs/remotes/{name}/");
+    let mut transaction = GritRefTransaction::new();
+
+    for ref_name in refs::get_all_refs()? {
+        if ref_name.starts_with(&prefix) && ref_name != format!("{prefix}HEAD") {
+            transaction.delete(&ref_name, None);
+        }
+    }
+
+    transaction.commit()?;
+
+    let head_path = format!("{}/{prefix}HEAD", config::GRIT_DIRECTORY);
+    if Path::new(&head_path).exists() {
+        file_handling::remove_file(&head_path)?;
+    }
+
+    // The directory is left behind only if something else still lives in it
+    let _ = fs::remove_dir(format!("{}/{prefix}", config::GRIT_DIRECTORY));
+
+    config::remove_config_section(&format!("remote.{name}"))
+}
+
+pub fn remote_list(verbose: bool) -> Option<()> {
+    let mut report = String::new();
+
+    for name in config::get_subsections("remote")? {
+        if !verbose {
+            report += format!("{name}\n").as_str();
+            continue;
+        }
+
+        let url = config::get_config_value(&format!("remote.{name}.url")).unwrap_or_default();
+        let push_url =
+            config::get_config_value(&format!("remote.{name}.pushurl")).unwrap_or(url.clone());
+
+        report += format!("{name}\t{url} (fetch)\n{name}\t{push_url} (push)\n").as_str();
+    }
+
+    print!("{report}");
+
+    Some(())
+}
+
+/// The url of a configured remote and its fetch refspec. Anything that is not
+/// the name of a remote is taken to be a path, fetched without a refspec.
+fn get_remote_config(remote: &str) -> Option<(String, Option<GritRefspec>)> {
+    match config::get_config_value(&format!("remote.{remote}.url")) {
+        Some(url) => {
+            let refspec = match config::get_config_value(&format!("remote.{remote}.fetch")) {
+                Some(refspec) => Some(GritRefspec::parse(&refspec)?),
+                None => None,
+            };
+
+            Some((url, refspec))
+        }
+        None => Some((remote.to_string(), None)),
+    }
+}
+
+fn get_default_remote() -> Option<String> {
+    let branch = plumbing::get_current_branch()?;
+
+    Some(
+        config::get_config_value(&format!("branch.{branch}.remote"))
+            .unwrap_or("origin".to_string()),
+    )
+}
+
+/// Copies the objects reachable from the remote's branches and tags, then
+/// moves the remote-tracking refs. Returns what the remote looked like.
+fn fetch_remote(remote: &str, merge_ref: Option<&str>) -> Option<GritRemoteState> {
+    let (url, refspec) = get_remote_config(remote)?;
+    let mut transport = transport::open_remote_transport(remote, &url)?;
+    let remote_state = transport.get_remote_state()?;
+
+    let mut wants = remote_state
+        .refs
+        .iter()
+        .map(|(_ref_name, oid)| oid.to_string())
+        .filter(|oid| !file_handling::object_exists(oid))
+        .collect::<Vec<String>>();
+    wants.sort();
+    wants.dedup();
+
+    if !wants.is_empty() {
+        let mut haves = vec![];
+        for ref_name in refs::get_all_refs()? {
+            let oid = plumbing::get_ref_oid(&ref_name)?;
+            if !oid.is_empty() {
+                haves.push(oid);
+            }
+        }
+
+        transport.fetch_objects(&wants, &haves)?;
+    }
+
+    let reason = format!("fetch {remote}");
+    let mut transaction = GritRefTransaction::new();
+    let mut report_lines = vec![];
+
+    for (ref_name, oid) in &remote_state.refs {
+        let (destination, force) = if ref_name.starts_with("refs/tags/") {
+            (ref_name.to_string(), false)
+        } else {
+            match refspec.as_ref().and_then(|refspec| refspec.map(ref_name)) {
+                Some(destination) => (destination, refspec.as_ref()?.force),
+                None => continue,
+            }
+        };
+
+        let old_oid = plumbing::get_ref_oid(&destination)?;
+
+        if &old_oid == oid {
+            continue;
+        }
+
+        let status = if old_oid.is_empty() {
+            GritRefUpdateStatus::New
+        } else if destination.starts_with("refs/tags/") {
+            GritRefUpdateStatus::Rejected("would clobber existing tag".to_string())
+        } else if merge::is_ancestor(&old_oid, oid)? {
+            GritRefUpdateStatus::FastForward(old_oid.clone())
+        } else if force {
+            GritRefUpdateStatus::Forced(old_oid.clone())
+        } else {
+            GritRefUpdateStatus::Rejected("non-fast-forward".to_string())
+        };
+
+        if !matches!(status, GritRefUpdateStatus::Rejected(_)) {
+            transaction.update(&destination, oid, Some(&old_oid), &reason);
+        }
+
+        report_lines.push(GritReportLine {
+            status,
+            new_oid: oid.to_string(),
+            source: refs::get_short_name(ref_name),
+            destination,
+        });
+    }
+
+    transaction.commit()?;
+
+    if !report_lines.is_empty() {
+        print!("From {url}\n{}", format_report(&report_lines));
+    }
+
+    // FETCH_HEAD lists the fetched branches, the one to merge first, for `pull`
+    let merge_ref = merge_ref.unwrap_or(&remote_state.head_ref);
+    let mut fetch_head = vec![];
+
+    for (ref_name, oid) in &remote_state.refs {
+        if let Some(branch) = ref_name.strip_prefix("refs/heads/") {
+            let line = format!("\tbranch '{branch}' of {url}");
+
+            if ref_name == merge_ref {
+                fetch_head.insert(0, format!("{oid}\t{line}"));
+            } else {
+                fetch_head.push(format!("{oid}\tnot-for-merge{line}"));
+            }
+        }
+    }
+
+    file_handling::write_file(
+        &format!("{}/FETCH_HEAD", config::GRIT_DIRECTORY),
+        &fetch_head.join("\n"),
+    )?;
+
+    Some(remote_state)
+}
+
+pub fn fetch(remote: Option<&str>) -> Option<()> {
+    let remote = match remote {
+        Some(remote) => remote.to_string(),
+        None => get_default_remote()?,
+    };
+
+    fetch_remote(&remote, None).map(|_| ())
+}
+
+/// Fetches and then merges, or rebases onto, the branch the current branch tracks.
+pub fn pull(remote: Option<&str>, branch: Option<&str>, rebase: Option<bool>) -> Option<()> {
+    let current_branch = plumbing::get_current_branch()?;
+
+    let remote = match remote {
+        Some(remote) => remote.to_string(),
+        None => get_default_remote()?,
+    };
+
+    let merge_ref = match branch {
+        Some(branch) => Some(get_full_remote_name(branch, false)),
+        None => {
+            let tracked_remote =
+                config::get_config_value(&format!("branch.{current_branch}.remote"));
+
+            if tracked_remote.as_deref() == Some(remote.as_str()) {
+                config::get_config_value(&format!("branch.{current_branch}.merge"))
+            } else {
+                None
+            }
+        }
+    };
+
+    let remote_state = fetch_remote(&remote, merge_ref.as_deref())?;
+    let merge_ref = merge_ref.unwrap_or(remote_state.head_ref.clone());
+
+    let merge_oid = match remote_state
+        .refs
+        .iter()
+        .find(|(ref_name, _oid)| ref_name == &merge_ref)
+    {
+        Some((_ref_name, oid)) => oid.to_string(),
+        None => {
+            println!("fatal: couldn't find remote ref {merge_ref}");
+            return None;
+        }
+    };
+
+    // Name the commit by its remote-tracking ref when there is one
+    let (url, refspec) = get_remote_config(&remote)?;
+    let tracking_ref = refspec
+        .and_then(|refspec| refspec.map(&merge_ref))
+        .filter(|tracking_ref| {
+            plumbing::get_ref_oid(tracking_ref).as_deref() == Some(merge_oid.as_str())
+        });
+    let rev = match &tracking_ref {
+        Some(tracking_ref) => refs::get_short_name(tracking_ref),
+        None => merge_oid.clone(),
+    };
+
+    let rebase = match rebase {
+        Some(rebase) => rebase,
+        None => config::get_config_value("pull.rebase").as_deref() == Some("true"),
+    };
+
+    if rebase {
+        return rebase::rebase(&rev, false);
+    }
+
+    let message = format!(
+        "Merge branch '{}' of {url}",
+        refs::get_short_name(&merge_ref)
+    );
+
+    merge::merge(&rev, Some(&message))
+}
+
+pub struct GritPushOptions {
+    pub force: bool,
+    /// `--force-with-lease[=<ref>[:<expect>]]`, with an empty string for the bare flag.
+    pub force_with_lease: Option<String>,
+    pub set_upstream: bool,
+    /// Skips the pre-push hook.
+    pub no_verify: bool,
+}
+
+/// One ref to push: the local commit (empty to delete) and the remote ref it goes to.
+struct GritPushUpdate {
+    source: String,
+    new_oid: String,
+    destination: String,
+    force: bool,
+}
+
+fn parse_push_refspec(refspec: &str) -> Option<GritPushUpdate> {
+    let (force, refspec) = match refspec.strip_prefix('+') {
+        Some(refspec) => (true, refspec),
+        None => (false, refspec),
+    };
+
+    let (source, destination) = refspec.split_once(':').unwrap_or((refspec, refspec));
+
+    if source.is_empty() {
+        return Some(GritPushUpdate {
+            source: "".to_string(),
+            new_oid: "".to_string(),
+            destination: get_full_remote_name(destination, false),
+            force,
+        });
+    }
+
+    let new_oid = plumbing::rev_parse(source)?;
+    let source_ref = plumbing::resolve_ref_name(source).unwrap_or_default();
+    let is_tag = source_ref.starts_with("refs/tags/");
+
+    let destination = if destination == source && source_ref.starts_with("refs/") {
+        source_ref.replacen("refs/remotes/", "refs/heads/", 1)
+    } else {
+        get_full_remote_name(destination, is_tag)
+    };
+
+    Some(GritPushUpdate {
+        source: source.to_string(),
+        new_oid,
+        destination,
+        force,
+    })
+}
+
+fn get_full_remote_name(name: &str, is_tag: bool) -> String {
+    if name.starts_with("refs/") {
+        name.to_string()
+    } else if is_tag {
+        format!("refs/tags/{name}")
+    } else {
+        format!("refs/heads/{name}")
+    }
+}
+
+/// What the remote would expect a lease to hold for `destination`: the value
+/// given on the command line, or else our remote-tracking ref for it.
+fn get_lease(
+    lease: &str,
+    destination: &str,
+    refspec: Option<&GritRefspec>,
+) -> Option<Option<String>> {
+    let (lease_ref, expected) = match lease.split_once(':') {
+        Some((lease_ref, expected)) => (lease_ref, Some(expected)),
+        None => (lease, None),
+    };
+
+    if !lease_ref.is_empty() && get_full_remote_name(lease_ref, false) != destination {
+        return Some(None);
+    }
+
+    match expected {
+        Some("") => Some(Some("".to_string())),
+        Some(expected) => Some(Some(plumbing::rev_parse(expected)?)),
+        None => match refspec.and_then(|refspec| refspec.map(destination)) {
+            Some(tracking_ref) => Some(Some(plumbing::get_ref_oid(&tracking_ref)?)),
+            None => Some(Some("".to_string())),
+        },
+    }
+}
+
+/// Gives the pre-push hook the remote and, on stdin, a line per ref about to be
+/// pushed: `<local ref> <local oid> <remote ref> <remote oid>`.
+fn run_pre_push_hook(
+    remote: &str,
+    url: &str,
+    accepted: &[(usize, &GritPushUpdate, String)],
+) -> Option<()> {
+    let mut input = String::new();
+
+    for (_line, update, old_oid) in accepted {
+        let (local_ref, local_oid) = if update.new_oid.is_empty() {
+            ("(delete)".to_string(), plumbing::NULL_OID)
+        } else {
+            (
+                plumbing::resolve_ref_name(&update.source).unwrap_or(update.source.clone()),
+                update.new_oid.as_str(),
+            )
+        };
+        let remote_oid = if old_oid.is_empty() {
+            plumbing::NULL_OID
+        } else {
+            old_oid.as_str()
+        };
+
+        input += format!(
+            "{local_ref} {local_oid} {} {remote_oid}\n",
+            update.destination
+        )
+        .as_str();
+    }
+
+    match hooks::run_hook("pre-push", &[remote, url], Some(&input)) {
+        Some(()) => Some(()),
+        None => {
+            println!("error: failed to push some refs to '{url}'");
+            None
+        }
+    }
+}
+
+/// Sends commits to a remote repository and moves its refs, refusing
+/// anything that is not a fast-forward unless forced.
+pub fn push(remote: Option<&str>, refspecs: &[String], options: &GritPushOptions) -> Option<()> {
+    let remote = match remote {
+        Some(remote) => remote.to_string(),
+        None => get_default_remote()?,
+    };
+
+    let (url, refspec) = get_remote_config(&remote)?;
+    let url = config::get_config_value(&format!("remote.{remote}.pushurl")).unwrap_or(url);
+    let mut transport = transport::open_remote_transport(&remote, &url)?;
+
+    let refspecs = if refspecs.is_empty() {
+        if plumbing::is_head_detached()? {
+            println!("fatal: You are not currently on a branch.");
+            return None;
+        }
+
+        vec![plumbing::get_current_branch()?]
+    } else {
+        refspecs.to_vec()
+    };
+
+    let mut updates = vec![];
+    for refspec in &refspecs {
+        updates.push(parse_push_refspec(refspec)?);
+    }
+
+    let remote_refs = transport
+        .get_remote_state()?
+        .refs
+        .into_iter()
+        .collect::<HashMap<String, String>>();
+
+    let mut report_lines = vec![];
+    let mut accepted = vec![];
+
+    for update in &updates {
+        let old_oid = &remote_refs
+            .get(&update.destination)
+            .cloned()
+            .unwrap_or_default();
+
+        if old_oid == &update.new_oid {
+            continue;
+        }
+
+        let lease = match &options.force_with_lease {
+            Some(lease) => get_lease(lease, &update.destination, refspec.as_ref())?,
+            None => None,
+        };
+        let forced = update.force || options.force || lease.is_some();
+
+        let status = if lease.is_some_and(|expected| &expected != old_oid) {
+            GritRefUpdateStatus::Rejected("stale info".to_string())
+        } else if update.new_oid.is_empty() {
+            GritRefUpdateStatus::Deleted
+        } else if old_oid.is_empty() {
+            GritRefUpdateStatus::New
+        } else if !file_handling::object_exists(old_oid) && !forced {
+            GritRefUpdateStatus::Rejected("fetch first".to_string())
+        } else if file_handling::object_exists(old_oid)
+            && merge::is_ancestor(old_oid, &update.new_oid)?
+        {
+            GritRefUpdateStatus::FastForward(old_oid.to_string())
+        } else if forced {
+            GritRefUpdateStatus::Forced(old_oid.to_string())
+        } else {
+            GritRefUpdateStatus::Rejected("non-fast-forward".to_string())
+        };
+
+        if !matches!(status, GritRefUpdateStatus::Rejected(_)) {
+            accepted.push((report_lines.len(), update, old_oid.to_string()));
+        }
+
+        report_lines.push(GritReportLine {
+            status,
+            new_oid: update.new_oid.clone(),
+            source: if update.source.is_empty() {
+                refs::get_short_name(&update.destination)
+            } else {
+                update.source.clone()
+            },
+            destination: update.destination.clone(),
+        });
+    }
+
+    if report_lines.is_empty() {
+        println!("Everything up-to-date");
+        return Some(());
+    }
+
+    if !accepted.is_empty() && !options.no_verify {
+        run_pre_push_hook(&remote, &url, &accepted)?;
+    }
+
+    if !accepted.is_empty() {
+        let commands = accepted
+            .iter()
+            .map(|(_line, update, old_oid)| GritRefCommand {
+                old_oid: old_oid.to_string(),
+                new_oid: update.new_oid.clone(),
+                ref_name: update.destination.clone(),
+            })
+            .collect::<Vec<GritRefCommand>>();
+
+        // Whatever the remote's refs already reach does not need to be sent
+        let wants = commands
+            .iter()
+            .filter(|command| !command.new_oid.is_empty())
+            .map(|command| command.new_oid.clone())
+            .collect::<Vec<String>>();
+        let haves = remote_refs.values().cloned().collect::<Vec<String>>();
+        let objects = transport::get_objects_to_send(&wants, &haves)?;
+
+        let results = transport.push_updates(&commands, &objects)?;
+        let mut transaction = GritRefTransaction::new();
+
+        for ((line, update, _old_oid), result) in accepted.iter().zip(&results) {
+            if let Some(reason) = result {
+                report_lines[*line].status = GritRefUpdateStatus::RemoteRejected(reason.clone());
+                continue;
+            }
+
+            let tracking_ref = match refspec
+                .as_ref()
+                .and_then(|refspec| refspec.map(&update.destination))
+            {
+                Some(tracking_ref) => tracking_ref,
+                None => continue,
+            };
+
+            if update.new_oid.is_empty() {
+                if !plumbing::get_ref_oid(&tracking_ref)?.is_empty() {
+                    transaction.delete(&tracking_ref, None);
+                }
+            } else {
+                transaction.update(&tracking_ref, &update.new_oid, None, "update by push");
+            }
+        }
+
+        transaction.commit()?;
+
+        accepted = accepted
+            .into_iter()
+            .zip(results)
+            .filter(|(_accepted, result)| result.is_none())
+            .map(|(accepted, _result)| accepted)
+            .collect();
+    }
+
+    print!("To {url}\n{}", format_report(&report_lines));
+
+    if options.set_upstream {
+        for (_line, update, _old_oid) in &accepted {
+            let source_ref = plumbing::resolve_ref_name(&update.source).unwrap_or_default();
+
+            if let Some(branch) = source_ref.strip_prefix("refs/heads/") {
+                config::set_config_value(&format!("branch.{branch}.remote"), &remote)?;
+                config::set_config_value(&format!("branch.{branch}.merge"), &update.destination)?;
+
+                println!(
+                    "branch '{branch}' set up to track '{remote}/{}'.",
+                    refs::get_short_name(&update.destination)
+                );
+            }
+        }
+    }
+
+    if accepted.len() < report_lines.len() {
+        println!("error: failed to push some refs to '{url}'");
+        return None;
+    }
+
+    Some(())
+}
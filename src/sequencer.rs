@@ -0,0 +1,426 @@
+use crate::config::GRIT_DIRECTORY;
+use crate::file_handling;
+use crate::merge;
+use crate::plumbing;
+use std::fs;
+use std::path;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GritSequencerAction {
+    Pick,
+    Revert,
+}
+
+impl GritSequencerAction {
+    fn name(&self) -> &'static str {
+        match self {
+            GritSequencerAction::Pick => "cherry-pick",
+            GritSequencerAction::Revert => "revert",
+        }
+    }
+
+    fn todo_command(&self) -> &'static str {
+        match self {
+            GritSequencerAction::Pick => "pick",
+            GritSequencerAction::Revert => "revert",
+        }
+    }
+
+    fn head_file(&self) -> &'static str {
+        match self {
+            GritSequencerAction::Pick => "CHERRY_PICK_HEAD",
+            GritSequencerAction::Revert => "REVERT_HEAD",
+        }
+    }
+}
+
+/// How applying a single commit on top of HEAD turned out.
+pub enum GritPickOutcome {
+    Committed(String),
+    Empty,
+    Conflicted,
+}
+
+fn get_sequencer_path(filename: &str) -> String {
+    format!("{GRIT_DIRECTORY}/sequencer/{filename}")
+}
+
+pub fn sequencer_in_progress() -> bool {
+    path::Path::new(&get_sequencer_path("todo")).exists()
+}
+
+fn read_todo() -> Option<Vec<(GritSequencerAction, String)>> {
+    let todo = file_handling::read_file(&get_sequencer_path("todo"))?;
+
+    let steps = todo
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(command, oid)| {
+            let action = if command == "revert" {
+                GritSequencerAction::Revert
+            } else {
+                GritSequencerAction::Pick
+            };
+            (action, oid.to_string())
+        })
+        .collect();
+
+    Some(steps)
+}
+
+fn write_todo(steps: &[(GritSequencerAction, String)]) -> Option<()> {
+    let todo = steps
+        .iter()
+        .map(|(action, oid)| format!("{} {oid}\n", action.todo_command()))
+        .collect::<String>();
+
+    file_handling::write_file(&get_sequencer_path("todo"), &todo)
+}
+
+fn get_short_description(commit_oid: &str) -> Option<String> {
+    let message = plumbing::get_commit_message(commit_oid)?;
+    let subject = message.lines().next().unwrap_or("");
+
+    Some(format!("{}... {subject}", &commit_oid[..7]))
+}
+
+/// Merges the change made by a commit (or its inverse, for a revert) into the
+/// index and working tree, then commits it on top of HEAD unless it conflicts.
+pub fn pick_commit(
+    action: GritSequencerAction,
+    commit_oid: &str,
+    message: &str,
+    reflog_action: &str,
+) -> Option<GritPickOutcome> {
+    let parent_oids = plumbing::get_commit_parents(commit_oid)?;
+
+    if parent_oids.len() > 1 {
+        println!(
+            "Commit {} is a merge, which cannot be {}ed",
+            &commit_oid[..7],
+            action.todo_command()
+        );
+        return None;
+    }
+
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    let commit_message = plumbing::get_commit_message(commit_oid)?;
+    let subject = commit_message.lines().next().unwrap_or("");
+
+    let (_commit_map, _commit_paths, commit_entries, _commit_oids) =
+        plumbing::get_head_tree_entries(commit_oid)?;
+    let (_parent_map, _parent_paths, parent_entries, _parent_oids) =
+        plumbing::get_head_tree_entries(parent_oids.first().map_or("", |oid| oid.as_str()))?;
+    let (_head_map, _head_paths, head_entries, _head_oids) =
+        plumbing::get_head_tree_entries(&head_oid)?;
+
+    let commit_entries = merge::get_entries_by_path(&commit_entries);
+    let parent_entries = merge::get_entries_by_path(&parent_entries);
+    let head_entries = merge::get_entries_by_path(&head_entries);
+
+    let (base_entries, theirs_entries, theirs_label) = match action {
+        GritSequencerAction::Pick => (
+            parent_entries,
+            commit_entries,
+            format!("{} ({subject})", &commit_oid[..7]),
+        ),
+        GritSequencerAction::Revert => (
+            commit_entries,
+            parent_entries,
+            format!("parent of {} ({subject})", &commit_oid[..7]),
+        ),
+    };
+
+    let merge_result = merge::merge_trees(
+        &base_entries,
+        &head_entries,
+        &theirs_entries,
+        "HEAD",
+        &theirs_label,
+    )?;
+
+    merge::write_merge_result(&head_entries, &merge_result)?;
+
+    if !merge_result.conflicts.is_empty() {
+        let mut conflicted_paths = merge_result
+            .conflicts
+            .keys()
+            .cloned()
+            .collect::<Vec<String>>();
+        conflicted_paths.sort();
+
+        for path in &conflicted_paths {
+            println!("CONFLICT (content): Merge conflict in {path}");
+        }
+
+        merge::save_unmerged_paths(&conflicted_paths)?;
+
+        return Some(GritPickOutcome::Conflicted);
+    }
+
+    commit_picked_changes(action, commit_oid, message, reflog_action)
+}
+
+/// Commits the index on top of HEAD, crediting the original author of a picked commit.
+pub fn commit_picked_changes(
+    action: GritSequencerAction,
+    commit_oid: &str,
+    message: &str,
+    reflog_action: &str,
+) -> Option<GritPickOutcome> {
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    let branch = plumbing::get_current_branch()?;
+
+    let (_index_tree_map, _index_paths, index_entries, _index_oids) =
+        plumbing::get_index_entries()?;
+    let tree_oid = plumbing::write_tree_from_entries(&index_entries)?;
+
+    if !head_oid.is_empty() && plumbing::get_commit_tree_oid(&head_oid)? == tree_oid {
+        return Some(GritPickOutcome::Empty);
+    }
+
+    let author = match action {
+        GritSequencerAction::Pick => plumbing::get_commit_author(commit_oid)?,
+        GritSequencerAction::Revert => plumbing::get_identity_name(),
+    };
+
+    let parent_oids = if head_oid.is_empty() {
+        vec![]
+    } else {
+        vec![head_oid]
+    };
+
+    let new_commit_oid =
+        plumbing::commit_tree_with_author(&tree_oid, message, &parent_oids, &author)?;
+
+    let subject = message.lines().next().unwrap_or("");
+    plumbing::update_head(&new_commit_oid, &format!("{reflog_action}: {subject}"))?;
+
+    println!("[{branch} {}] {subject}", &new_commit_oid[..7]);
+
+    Some(GritPickOutcome::Committed(new_commit_oid))
+}
+
+fn get_message_for(action: GritSequencerAction, commit_oid: &str) -> Option<String> {
+    let message = plumbing::get_commit_message(commit_oid)?;
+
+    match action {
+        GritSequencerAction::Pick => Some(message),
+        GritSequencerAction::Revert => {
+            let subject = message.lines().next().unwrap_or("");
+            Some(format!(
+                "Revert \"{subject}\"\n\nThis reverts commit {commit_oid}."
+            ))
+        }
+    }
+}
+
+/// Puts the index and working tree back to HEAD after a pick failed part way.
+/// The commit stays at the top of the todo list, so `--continue` retries it.
+fn stop_after_failed_pick(action: GritSequencerAction, commit_oid: &str) -> Option<()> {
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+    plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&head_oid)?)?;
+
+    println!(
+        "error: could not {} {}",
+        action.todo_command(),
+        &commit_oid[..7]
+    );
+
+    // Nothing has been applied yet, so there is no sequence left to continue
+    let original_head_oid = file_handling::read_file(&get_sequencer_path("head"))?;
+
+    if original_head_oid.trim() == head_oid {
+        remove_sequencer_state()?;
+    } else {
+        println!(
+            "hint: fix the problem and run `grit {} --continue`, or `grit {} --abort`",
+            action.name(),
+            action.name()
+        );
+    }
+
+    None
+}
+
+fn run_todo() -> Option<()> {
+    loop {
+        let mut steps = read_todo()?;
+
+        if steps.is_empty() {
+            return remove_sequencer_state();
+        }
+
+        let (action, commit_oid) = steps[0].clone();
+        let message = get_message_for(action, &commit_oid)?;
+
+        let outcome = match pick_commit(action, &commit_oid, &message, action.name()) {
+            Some(outcome) => outcome,
+            None => return stop_after_failed_pick(action, &commit_oid),
+        };
+
+        steps.remove(0);
+        write_todo(&steps)?;
+
+        match outcome {
+            GritPickOutcome::Committed(_oid) => (),
+            GritPickOutcome::Empty => {
+                println!(
+                    "Skipping {}, its changes are already in HEAD",
+                    get_short_description(&commit_oid)?
+                );
+            }
+            GritPickOutcome::Conflicted => {
+                file_handling::write_file(
+                    &format!("{GRIT_DIRECTORY}/{}", action.head_file()),
+                    &commit_oid,
+                )?;
+                file_handling::write_file(&format!("{GRIT_DIRECTORY}/MERGE_MSG"), &message)?;
+
+                let report = format!(
+                    "error: could not {} {}",
+                    action.todo_command(),
+                    get_short_description(&commit_oid)?
+                ) + "\nhint: after resolving the conflicts, mark the corrected paths"
+                    + "\nhint: with `grit add <paths>` and run `grit "
+                    + action.name()
+                    + " --continue`";
+
+                println!("{report}");
+
+                return Some(());
+            }
+        }
+    }
+}
+
+fn remove_sequencer_state() -> Option<()> {
+    for action in [GritSequencerAction::Pick, GritSequencerAction::Revert] {
+        file_handling::remove_file(&format!("{GRIT_DIRECTORY}/{}", action.head_file()))?;
+    }
+    file_handling::remove_file(&format!("{GRIT_DIRECTORY}/MERGE_MSG"))?;
+    merge::clear_unmerged_paths()?;
+
+    let sequencer_path = format!("{GRIT_DIRECTORY}/sequencer");
+
+    if !path::Path::new(&sequencer_path).exists() {
+        return Some(());
+    }
+
+    match fs::remove_dir_all(&sequencer_path) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to remove {}: {}", sequencer_path, e);
+            None
+        }
+    }
+}
+
+pub fn sequencer_start(action: GritSequencerAction, revs: &[String]) -> Option<()> {
+    if sequencer_in_progress() {
+        println!(
+            "A cherry-pick or revert is already in progress\n  (use `grit {} --continue` or `grit {} --abort`)",
+            action.name(),
+            action.name()
+        );
+        return None;
+    }
+
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+
+    if head_oid.is_empty() {
+        println!("Cannot {} on a branch without commits", action.name());
+        return None;
+    }
+
+    if !plumbing::get_local_changes()?.is_empty() {
+        println!(
+            "Your local changes would be overwritten by {}.\n  (commit your changes or stash them to proceed)",
+            action.name()
+        );
+        return None;
+    }
+
+    let mut steps = vec![];
+
+    for rev in revs {
+        let commit_oid = plumbing::rev_parse(rev)?;
+        steps.push((action, commit_oid));
+    }
+
+    match fs::create_dir_all(format!("{GRIT_DIRECTORY}/sequencer")) {
+        Ok(_) => (),
+        Err(e) => {
+            println!("Failed to create sequencer directory: {}", e);
+            return None;
+        }
+    }
+
+    file_handling::write_file(&get_sequencer_path("head"), &head_oid)?;
+    write_todo(&steps)?;
+
+    run_todo()
+}
+
+pub fn sequencer_continue(action: GritSequencerAction) -> Option<()> {
+    if !sequencer_in_progress() {
+        println!("No {} in progress", action.name());
+        return None;
+    }
+
+    for stopped_action in [GritSequencerAction::Pick, GritSequencerAction::Revert] {
+        let head_file_path = format!("{GRIT_DIRECTORY}/{}", stopped_action.head_file());
+
+        if !path::Path::new(&head_file_path).exists() {
+            continue;
+        }
+
+        let unresolved = merge::get_unresolved_paths()?;
+        if !unresolved.is_empty() {
+            println!(
+                "You must edit all merge conflicts and then mark them as resolved using `grit add`:\n\t{}",
+                unresolved.join("\n\t")
+            );
+            return None;
+        }
+
+        let commit_oid = file_handling::read_file(&head_file_path)?
+            .trim()
+            .to_string();
+        let message = file_handling::read_file(&format!("{GRIT_DIRECTORY}/MERGE_MSG"))?;
+
+        if let GritPickOutcome::Empty =
+            commit_picked_changes(stopped_action, &commit_oid, &message, stopped_action.name())?
+        {
+            println!(
+                "Skipping {}, its changes are already in HEAD",
+                get_short_description(&commit_oid)?
+            );
+        }
+
+        file_handling::remove_file(&head_file_path)?;
+        file_handling::remove_file(&format!("{GRIT_DIRECTORY}/MERGE_MSG"))?;
+        merge::clear_unmerged_paths()?;
+    }
+
+    run_todo()
+}
+
+pub fn sequencer_abort(action: GritSequencerAction) -> Option<()> {
+    if !sequencer_in_progress() {
+        println!("No {} in progress", action.name());
+        return None;
+    }
+
+    let original_head_oid = file_handling::read_file(&get_sequencer_path("head"))?
+        .trim()
+        .to_string();
+
+    plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&original_head_oid)?)?;
+
+    if plumbing::get_head_oid()?.trim() != original_head_oid {
+        plumbing::update_head(&original_head_oid, &format!("{}: abort", action.name()))?;
+    }
+
+    remove_sequencer_state()
+}
@@ -0,0 +1,155 @@
+use crate::diff;
+use crate::merge;
+use crate::plumbing::{self, GritCatType};
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GritShowFormat {
+    Patch,
+    Stat,
+    NameOnly,
+}
+
+fn indent_message(message: &str) -> String {
+    message
+        .trim_end()
+        .lines()
+        .map(|line| {
+            if line.is_empty() {
+                "\n".to_string()
+            } else {
+                format!("    {line}\n")
+            }
+        })
+        .collect()
+}
+
+fn show_commit(commit_oid: &str, format: GritShowFormat) -> Option<String> {
+    let parent_oids = plumbing::get_commit_parents(commit_oid)?;
+
+    let mut report = format!("commit {commit_oid}\n");
+
+    if parent_oids.len() > 1 {
+        report += format!(
+            "Merge: {}\n",
+            parent_oids
+                .iter()
+                .map(|oid| &oid[..7])
+                .collect::<Vec<&str>>()
+                .join(" ")
+        )
+        .as_str();
+    }
+
+    report += format!(
+        "Author: {}\nDate:   {}\n\n",
+        plumbing::get_commit_author(commit_oid)?,
+        plumbing::get_commit_date(commit_oid)?
+    )
+    .as_str();
+    report += indent_message(&plumbing::get_commit_message(commit_oid)?).as_str();
+
+    let (_parent_map, _parent_paths, parent_entries, _parent_oids) =
+        plumbing::get_head_tree_entries(parent_oids.first().map_or("", |oid| oid.as_str()))?;
+    let (_commit_map, _commit_paths, commit_entries, _commit_oids) =
+        plumbing::get_head_tree_entries(commit_oid)?;
+
+    let changes = diff::get_changed_paths(
+        &merge::get_entries_by_path(&parent_entries),
+        &merge::get_entries_by_path(&commit_entries),
+    );
+
+    if changes.is_empty() {
+        return Some(report);
+    }
+
+    match format {
+        GritShowFormat::Patch => {
+            report += "\n";
+            report += diff::format_patch(&changes)?.as_str();
+        }
+        GritShowFormat::Stat => {
+            report += "\n";
+            report += diff::format_stat(&changes)?.as_str();
+        }
+        GritShowFormat::NameOnly => {
+            report += "\n";
+            for change in &changes {
+                report += format!("{}\n", change.path).as_str();
+            }
+        }
+    }
+
+    Some(report)
+}
+
+fn show_tag(tag_oid: &str, format: GritShowFormat) -> Option<Vec<u8>> {
+    let tag = plumbing::generate_cat_content(tag_oid, GritCatType::Pretty)?;
+    let (headers, message) = tag.split_once("\n\n").unwrap_or((&tag, ""));
+
+    let get_header = |name: &str| {
+        headers
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{name} ")))
+            .unwrap_or("")
+            .to_string()
+    };
+
+    let mut report = format!(
+        "tag {}\nTagger: {}\n\n{}\n\n",
+        get_header("tag"),
+        get_header("tagger"),
+        message.trim_end()
+    )
+    .into_bytes();
+
+    report.extend(show_object(&get_header("object"), format)?);
+
+    Some(report)
+}
+
+/// Blobs are shown as they are stored, byte for byte, whatever they hold.
+fn show_object(oid: &str, format: GritShowFormat) -> Option<Vec<u8>> {
+    match plumbing::generate_cat_content(oid, GritCatType::Type)?.as_str() {
+        "commit" => show_commit(oid, format).map(String::into_bytes),
+        "tag" => show_tag(oid, format),
+        "tree" => {
+            plumbing::format_tree_listing(oid, &[], false, false, false).map(String::into_bytes)
+        }
+        _ => plumbing::read_object(oid).map(|(_object_type, content)| content),
+    }
+}
+
+pub fn show(revs: &[String], format: GritShowFormat) -> Option<()> {
+    let revs = if revs.is_empty() {
+        vec!["HEAD".to_string()]
+    } else {
+        revs.to_vec()
+    };
+
+    let mut reports = vec![];
+
+    for rev in &revs {
+        let oid = plumbing::rev_parse(rev)?;
+        let mut report = show_object(&oid, format)?;
+
+        if plumbing::generate_cat_content(&oid, GritCatType::Type)? == "tree" {
+            report = [format!("tree {rev}\n\n").into_bytes(), report].concat();
+        }
+
+        reports.push(report);
+    }
+
+    let mut stdout = io::stdout().lock();
+
+    match stdout
+        .write_all(&reports.join(&b"\n"[..]))
+        .and_then(|_| stdout.flush())
+    {
+        Ok(_) => Some(()),
+        Err(e) => {
+            eprintln!("Failed to print {}: {}", revs.join(" "), e);
+            None
+        }
+    }
+}
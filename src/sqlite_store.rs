@@ -0,0 +1,413 @@
+use crate::object_store::{self, GritObjectStore};
+use crate::plumbing::GritObjectType;
+use crate::reflog::GritReflogEntry;
+use crate::storage::GritDatabase;
+use crate::utils;
+use flate2::read::DeflateDecoder;
+use rusqlite::{params, Connection, DatabaseName, OpenFlags, OptionalExtension};
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::time::Duration;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS objects (
+    oid TEXT PRIMARY KEY,
+    object BLOB NOT NULL,
+    created INTEGER
+);
+CREATE TABLE IF NOT EXISTS refs (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS reflog (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    old_oid TEXT NOT NULL,
+    new_oid TEXT NOT NULL,
+    identity TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    timezone TEXT NOT NULL,
+    message TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS reflog_name ON reflog (name, id);
+";
+
+/// How long to wait for another grit process to finish writing.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keeps objects, refs and reflogs in a single SQLite database. Objects are
+/// stored zlib compressed under the same ids as loose objects.
+pub struct GritSqliteStore {
+    connection: Connection,
+}
+
+fn report_error<T>(action: &str, result: rusqlite::Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            println!("Failed to {action}: {e}");
+            None
+        }
+    }
+}
+
+impl GritSqliteStore {
+    /// Opens the database at `path`, creating it first if `create` is set.
+    pub fn open(path: &Path, create: bool) -> Option<GritSqliteStore> {
+        let mut flags = OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+
+        if create {
+            flags |= OpenFlags::SQLITE_OPEN_CREATE;
+        }
+
+        let connection = report_error(
+            &format!("open database {}", path.display()),
+            Connection::open_with_flags(path, flags),
+        )?;
+
+        report_error("configure database", connection.busy_timeout(BUSY_TIMEOUT))?;
+
+        if create {
+            report_error("create database", connection.execute_batch(SCHEMA))?;
+
+            // Readers then never block the writer, nor it them
+            report_error(
+                "create database",
+                connection.query_row("PRAGMA journal_mode = WAL", [], |_row| Ok(())),
+            )?;
+        }
+
+        let store = GritSqliteStore { connection };
+        store.add_created_column()?;
+
+        Some(store)
+    }
+
+    /// Databases made before objects had a creation time get the column, left
+    /// empty for the objects already there, whose age is then unknown.
+    fn add_created_column(&self) -> Option<()> {
+        let columns = self.query_names(
+            "read database schema",
+            "SELECT name FROM pragma_table_info('objects')",
+        )?;
+
+        if columns.is_empty() || columns.iter().any(|column| column == "created") {
+            return Some(());
+        }
+
+        report_error(
+            "update database schema",
+            self.connection
+                .execute("ALTER TABLE objects ADD COLUMN created INTEGER", []),
+        )?;
+
+        Some(())
+    }
+
+    fn insert_object(&self, oid: &str, compressed_object_content: &[u8]) -> Option<()> {
+        report_error(
+            &format!("save object {oid}"),
+            self.connection.execute(
+                "INSERT OR IGNORE INTO objects (oid, object, created) VALUES (?1, ?2, ?3)",
+                params![oid, compressed_object_content, utils::get_unix_time()],
+            ),
+        )?;
+
+        Some(())
+    }
+
+    fn query_names(&self, action: &str, sql: &str) -> Option<Vec<String>> {
+        let mut statement = report_error(action, self.connection.prepare_cached(sql))?;
+
+        let names = report_error(
+            action,
+            statement
+                .query_map([], |row| row.get(0))
+                .and_then(|rows| rows.collect::<rusqlite::Result<Vec<String>>>()),
+        )?;
+
+        Some(names)
+    }
+}
+
+impl GritObjectStore for GritSqliteStore {
+    fn read(&self, oid: &str) -> Option<Vec<u8>> {
+        let compressed_contents: Option<Vec<u8>> = report_error(
+            &format!("read object {oid}"),
+            self.connection
+                .query_row("SELECT object FROM objects WHERE oid = ?1", [oid], |row| {
+                    row.get(0)
+                })
+                .optional(),
+        )?;
+
+        match compressed_contents {
+            Some(compressed_contents) => utils::decompress_object_content(&compressed_contents),
+            None => {
+                println!("Object {oid} not found");
+                None
+            }
+        }
+    }
+
+    fn write(&self, oid: &str, object: &[u8]) -> Option<()> {
+        let compressed_object_content = utils::compress_object_content(object)?;
+
+        self.insert_object(oid, &compressed_object_content)
+    }
+
+    /// Only the compressed object is held in memory, never the whole content.
+    fn write_stream(
+        &self,
+        object_type: GritObjectType,
+        size: u64,
+        reader: &mut dyn Read,
+    ) -> Option<String> {
+        let (oid, compressed_object_content) =
+            match object_store::compress_object_stream(object_type, size, reader, vec![]) {
+                Ok(compressed) => compressed,
+                Err(e) => {
+                    println!("Failed to save object: {}", e);
+                    return None;
+                }
+            };
+
+        self.insert_object(&oid, &compressed_object_content)?;
+
+        Some(oid)
+    }
+
+    /// Reads the stored object straight out of the database, without loading it first.
+    fn open(&self, oid: &str) -> Option<Box<dyn Read + '_>> {
+        let rowid: Option<i64> = report_error(
+            &format!("read object {oid}"),
+            self.connection
+                .query_row("SELECT rowid FROM objects WHERE oid = ?1", [oid], |row| {
+                    row.get(0)
+                })
+                .optional(),
+        )?;
+
+        let rowid = match rowid {
+            Some(rowid) => rowid,
+            None => {
+                println!("Object {oid} not found");
+                return None;
+            }
+        };
+
+        let blob = report_error(
+            &format!("read object {oid}"),
+            self.connection
+                .blob_open(DatabaseName::Main, "objects", "object", rowid, true),
+        )?;
+
+        Some(Box::new(DeflateDecoder::new(BufReader::new(blob))))
+    }
+
+    fn exists(&self, oid: &str) -> bool {
+        self.connection
+            .query_row("SELECT 1 FROM objects WHERE oid = ?1", [oid], |_row| Ok(()))
+            .is_ok()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        let oids = self
+            .query_names("list objects", "SELECT oid FROM objects ORDER BY oid")
+            .unwrap_or_default();
+
+        Box::new(oids.into_iter())
+    }
+
+    fn remove(&self, oid: &str) -> Option<()> {
+        report_error(
+            &format!("remove object {oid}"),
+            self.connection
+                .execute("DELETE FROM objects WHERE oid = ?1", [oid]),
+        )?;
+
+        Some(())
+    }
+
+    fn count_objects(&self) -> Option<(u64, u64)> {
+        report_error(
+            "count objects",
+            self.connection.query_row(
+                "SELECT COUNT(*), COALESCE(SUM(length(object)), 0) FROM objects",
+                [],
+                |row| Ok((row.get::<_, i64>(0)? as u64, row.get::<_, i64>(1)? as u64)),
+            ),
+        )
+    }
+
+    fn get_modified_time(&self, oid: &str) -> Option<i64> {
+        self.connection
+            .query_row("SELECT created FROM objects WHERE oid = ?1", [oid], |row| {
+                row.get::<_, Option<i64>>(0)
+            })
+            .ok()
+            .flatten()
+    }
+
+    fn find_by_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut statement = match self
+            .connection
+            .prepare_cached("SELECT oid FROM objects WHERE substr(oid, 1, ?2) = ?1 ORDER BY oid")
+        {
+            Ok(statement) => statement,
+            Err(e) => {
+                println!("Failed to look up objects: {e}");
+                return vec![];
+            }
+        };
+
+        statement
+            .query_map(params![prefix, prefix.len()], |row| row.get(0))
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<String>>>())
+            .unwrap_or_default()
+    }
+}
+
+impl GritDatabase for GritSqliteStore {
+    fn read_ref(&self, ref_name: &str) -> Option<Option<String>> {
+        report_error(
+            &format!("read ref {ref_name}"),
+            self.connection
+                .query_row(
+                    "SELECT value FROM refs WHERE name = ?1",
+                    [ref_name],
+                    |row| row.get(0),
+                )
+                .optional(),
+        )
+    }
+
+    fn write_ref(&self, ref_name: &str, value: &str) -> Option<()> {
+        report_error(
+            &format!("update ref {ref_name}"),
+            self.connection.execute(
+                "INSERT OR REPLACE INTO refs (name, value) VALUES (?1, ?2)",
+                [ref_name, value],
+            ),
+        )?;
+
+        Some(())
+    }
+
+    fn remove_ref(&self, ref_name: &str) -> Option<()> {
+        report_error(
+            &format!("remove ref {ref_name}"),
+            self.connection
+                .execute("DELETE FROM refs WHERE name = ?1", [ref_name]),
+        )?;
+
+        Some(())
+    }
+
+    fn get_ref_names(&self) -> Option<Vec<String>> {
+        self.query_names(
+            "list refs",
+            "SELECT name FROM refs WHERE name LIKE 'refs/%' ORDER BY name",
+        )
+    }
+
+    fn read_reflog(&self, ref_name: &str) -> Option<Vec<GritReflogEntry>> {
+        let action = format!("read reflog {ref_name}");
+
+        let mut statement = report_error(
+            &action,
+            self.connection.prepare_cached(
+                "SELECT old_oid, new_oid, identity, timestamp, timezone, message
+                 FROM reflog WHERE name = ?1 ORDER BY id",
+            ),
+        )?;
+
+        let entries = statement
+            .query_map([ref_name], |row| {
+                Ok(GritReflogEntry {
+                    old_oid: row.get(0)?,
+                    new_oid: row.get(1)?,
+                    identity: row.get(2)?,
+                    timestamp: row.get(3)?,
+                    timezone: row.get(4)?,
+                    message: row.get(5)?,
+                })
+            })
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<GritReflogEntry>>>());
+
+        report_error(&action, entries)
+    }
+
+    fn append_reflog_entry(&self, ref_name: &str, entry: &GritReflogEntry) -> Option<()> {
+        report_error(
+            &format!("update reflog {ref_name}"),
+            self.connection.execute(
+                "INSERT INTO reflog (name, old_oid, new_oid, identity, timestamp, timezone, message)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    ref_name,
+                    entry.old_oid,
+                    entry.new_oid,
+                    entry.identity,
+                    entry.timestamp,
+                    entry.timezone,
+                    entry.message
+                ],
+            ),
+        )?;
+
+        Some(())
+    }
+
+    fn write_reflog(&self, ref_name: &str, entries: &[GritReflogEntry]) -> Option<()> {
+        self.transaction(&mut || {
+            self.delete_reflog(ref_name)?;
+
+            for entry in entries {
+                self.append_reflog_entry(ref_name, entry)?;
+            }
+
+            Some(())
+        })
+    }
+
+    fn delete_reflog(&self, ref_name: &str) -> Option<()> {
+        report_error(
+            &format!("delete reflog for {ref_name}"),
+            self.connection
+                .execute("DELETE FROM reflog WHERE name = ?1", [ref_name]),
+        )?;
+
+        Some(())
+    }
+
+    fn get_reflog_names(&self) -> Option<Vec<String>> {
+        self.query_names(
+            "list reflogs",
+            "SELECT DISTINCT name FROM reflog ORDER BY name",
+        )
+    }
+
+    fn transaction(&self, run: &mut dyn FnMut() -> Option<()>) -> Option<()> {
+        // Already inside a transaction, which the outermost one commits
+        if !self.connection.is_autocommit() {
+            return run();
+        }
+
+        report_error(
+            "start transaction",
+            self.connection.execute_batch("BEGIN IMMEDIATE"),
+        )?;
+
+        match run() {
+            Some(()) => report_error(
+                "commit transaction",
+                self.connection.execute_batch("COMMIT"),
+            ),
+            None => {
+                let _ = self.connection.execute_batch("ROLLBACK");
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,444 @@
+use crate::config::WORKING_DIR;
+use crate::object::GritTreeEntry;
+use crate::plumbing::{self, GritMode, GritObjectType};
+use crate::reflog;
+use crate::utils;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+const STASH_REF: &str = "refs/stash";
+
+fn get_entries_by_path(entries: &[String]) -> Option<HashMap<String, GritTreeEntry>> {
+    entries
+        .iter()
+        .map(|entry| plumbing::parse_entry(entry).map(|entry| (entry.path.clone(), entry)))
+        .collect()
+}
+
+/// Stores a working tree file as a blob, whatever its content, `None` if the
+/// file does not exist.
+fn stash_working_file(path: &str) -> Option<Option<String>> {
+    let filepath = format!("{WORKING_DIR}/{path}");
+
+    if !Path::new(&filepath).is_file() {
+        return Some(None);
+    }
+
+    plumbing::hash_file_object(&filepath, GritObjectType::Blob, true).map(Some)
+}
+
+fn get_stash_name(n: usize) -> String {
+    format!("stash@{{{n}}}")
+}
+
+/// Turns `stash@{n}`, `n` or nothing into a position in the stash list.
+fn parse_stash_position(stash: Option<&str>) -> Option<usize> {
+    let stash = match stash {
+        Some(stash) => stash,
+        None => return Some(0),
+    };
+
+    let position = stash
+        .strip_prefix("stash@{")
+        .and_then(|position| position.strip_suffix('}'))
+        .unwrap_or(stash);
+
+    match position.parse::<usize>() {
+        Ok(position) => Some(position),
+        Err(_) => {
+            println!("{stash} is not a valid stash reference");
+            None
+        }
+    }
+}
+
+/// The parents of a stash commit: the commit it was made on, the index, and
+/// the untracked files when they were stashed too.
+fn get_stash_parents(stash_oid: &str, position: usize) -> Option<Vec<String>> {
+    let parent_oids = plumbing::get_commit_parents(stash_oid)?;
+
+    if parent_oids.len() < 2 {
+        println!("{} is not a stash commit", get_stash_name(position));
+        return None;
+    }
+
+    Some(parent_oids)
+}
+
+fn get_stash_oid(position: usize) -> Option<String> {
+    let entries = reflog::read_reflog(STASH_REF)?;
+
+    if position >= entries.len() {
+        println!("{} does not exist", get_stash_name(position));
+        return None;
+    }
+
+    Some(entries[entries.len() - 1 - position].new_oid.clone())
+}
+
+pub fn stash_push(
+    message: Option<&str>,
+    pathspecs: &[String],
+    include_untracked: bool,
+) -> Option<()> {
+    let head_oid = plumbing::get_head_oid()?.trim().to_string();
+
+    if head_oid.is_empty() {
+        println!("You do not have the initial commit yet");
+        return None;
+    }
+
+    let branch = plumbing::get_current_branch()?;
+
+    let (_index_tree_map, _index_paths, index_entries, _index_oids) =
+        plumbing::get_index_entries()?;
+    let (_head_tree_map, _head_paths, head_entries, _head_oids) =
+        plumbing::get_head_tree_entries(&head_oid)?;
+
+    let index_entries = get_entries_by_path(&index_entries)?;
+    let head_entries = get_entries_by_path(&head_entries)?;
+    let working_tree_paths = plumbing::get_working_tree_entries_recursively()?;
+
+    let tracked_paths = head_entries
+        .keys()
+        .chain(index_entries.keys())
+        .cloned()
+        .collect::<BTreeSet<String>>();
+
+    let untracked_paths = working_tree_paths
+        .iter()
+        .filter(|path| !index_entries.contains_key(*path))
+        .filter(|path| utils::matches_pathspec(path, pathspecs))
+        .cloned()
+        .collect::<Vec<String>>();
+
+    let mut index_stash_entries = vec![];
+    let mut working_tree_stash_entries = vec![];
+    let mut stashed_paths = vec![];
+
+    for path in &tracked_paths {
+        let head_entry = head_entries.get(path);
+
+        // Paths outside the pathspec are stashed as they are in HEAD so that
+        // applying the stash leaves them alone
+        if !utils::matches_pathspec(path, pathspecs) {
+            if let Some(head_entry) = head_entry {
+                index_stash_entries.push(head_entry.to_string());
+                working_tree_stash_entries.push(head_entry.to_string());
+            }
+            continue;
+        }
+
+        let index_entry = index_entries.get(path);
+
+        if let Some(index_entry) = index_entry {
+            index_stash_entries.push(index_entry.to_string());
+        }
+
+        // The file keeps the mode it is staged or committed with
+        let mode = index_entry
+            .or(head_entry)
+            .map(|entry| entry.mode)
+            .unwrap_or(GritMode::Normal);
+
+        let working_tree_oid = stash_working_file(path)?;
+
+        if let Some(working_tree_oid) = &working_tree_oid {
+            working_tree_stash_entries
+                .push(format!("{} blob {working_tree_oid} {path}", mode.value()));
+        }
+
+        let head_oid = head_entry.map(|entry| &entry.oid);
+        let index_oid = index_entry.map(|entry| &entry.oid);
+        let working_tree_oid = working_tree_oid.as_ref();
+
+        if head_oid != index_oid || index_oid != working_tree_oid {
+            stashed_paths.push(path.to_string());
+        }
+    }
+
+    let include_untracked = include_untracked && !untracked_paths.is_empty();
+
+    if stashed_paths.is_empty() && !include_untracked {
+        println!("No local changes to save");
+        return Some(());
+    }
+
+    let head_message = plumbing::get_commit_message(&head_oid)?;
+    let head_subject = head_message.lines().next().unwrap_or("");
+    let head_description = format!("{branch}: {} {head_subject}", &head_oid[..7]);
+
+    let index_tree_oid = plumbing::write_tree_from_entries(&index_stash_entries)?;
+    let index_commit_oid = plumbing::commit_tree(
+        &index_tree_oid,
+        &format!("index on {head_description}"),
+        Some(&head_oid),
+    )?;
+
+    let mut parent_oids = vec![head_oid.clone(), index_commit_oid];
+
+    if include_untracked {
+        let mut untracked_entries = vec![];
+
+        for path in &untracked_paths {
+            if let Some(oid) = stash_working_file(path)? {
+                untracked_entries.push(format!("{} blob {oid} {path}", GritMode::Normal.value()));
+            }
+        }
+
+        let untracked_tree_oid = plumbing::write_tree_from_entries(&untracked_entries)?;
+        let untracked_commit_oid = plumbing::commit_tree(
+            &untracked_tree_oid,
+            &format!("untracked files on {head_description}"),
+            None,
+        )?;
+
+        parent_oids.push(untracked_commit_oid);
+    }
+
+    let stash_message = match message {
+        Some(message) => format!("On {branch}: {message}"),
+        None => format!("WIP on {head_description}"),
+    };
+
+    let working_tree_oid = plumbing::write_tree_from_entries(&working_tree_stash_entries)?;
+    let stash_oid =
+        plumbing::commit_tree_with_parents(&working_tree_oid, &stash_message, &parent_oids)?;
+
+    plumbing::update_ref(STASH_REF, &stash_oid, &stash_message)?;
+
+    // Put the stashed paths back to how they are in HEAD
+    let mut updated_index_entries = index_entries
+        .iter()
+        .filter(|(path, _entry)| !stashed_paths.contains(path))
+        .map(|(_path, entry)| entry.to_string())
+        .collect::<Vec<String>>();
+
+    for path in &stashed_paths {
+        match head_entries.get(path) {
+            Some(head_entry) => {
+                updated_index_entries.push(head_entry.to_string());
+                plumbing::checkout_file(path, &head_entry.oid)?;
+            }
+            None => {
+                plumbing::remove_working_file(path)?;
+            }
+        }
+    }
+
+    updated_index_entries.sort_by_key(|entry| plumbing::get_entry_path(entry));
+    plumbing::write_index(&updated_index_entries)?;
+
+    if include_untracked {
+        for path in &untracked_paths {
+            plumbing::remove_working_file(path)?;
+        }
+    }
+
+    println!("Saved working directory and index state {stash_message}");
+
+    Some(())
+}
+
+pub fn stash_apply(stash: Option<&str>, restore_index: bool) -> Option<()> {
+    let position = parse_stash_position(stash)?;
+    let stash_oid = get_stash_oid(position)?;
+
+    let parent_oids = get_stash_parents(&stash_oid, position)?;
+
+    let (_base_tree_map, _base_paths, base_entries, _base_oids) =
+        plumbing::get_head_tree_entries(&parent_oids[0])?;
+    let (_stash_tree_map, _stash_paths, stash_entries, _stash_oids) =
+        plumbing::get_head_tree_entries(&stash_oid)?;
+    let (_stash_index_map, _stash_index_paths, stash_index_entries, _stash_index_oids) =
+        plumbing::get_head_tree_entries(&parent_oids[1])?;
+    let (_untracked_map, _untracked_paths, untracked_lines, _untracked_oids) =
+        match parent_oids.get(2) {
+            Some(untracked_oid) => plumbing::get_head_tree_entries(untracked_oid)?,
+            None => plumbing::get_head_tree_entries("")?,
+        };
+
+    let base_entries = get_entries_by_path(&base_entries)?;
+    let stash_entries = get_entries_by_path(&stash_entries)?;
+    let stash_index_entries = get_entries_by_path(&stash_index_entries)?;
+    let untracked_entries = untracked_lines
+        .iter()
+        .map(|entry| plumbing::parse_entry(entry))
+        .collect::<Option<Vec<GritTreeEntry>>>()?;
+
+    let changed_paths = base_entries
+        .keys()
+        .chain(stash_entries.keys())
+        .filter(|path| {
+            base_entries.get(*path).map(|entry| &entry.oid)
+                != stash_entries.get(*path).map(|entry| &entry.oid)
+        })
+        .cloned()
+        .collect::<BTreeSet<String>>();
+
+    // Refuse to touch anything that has been changed since the stash was made
+    let mut conflicts = vec![];
+
+    for path in &changed_paths {
+        let current_oid = plumbing::get_working_tree_oid(path);
+        let base_oid = base_entries.get(path).map(|entry| entry.oid.clone());
+        let stash_oid = stash_entries.get(path).map(|entry| entry.oid.clone());
+
+        if current_oid != base_oid && current_oid != stash_oid {
+            conflicts.push(path.to_string());
+        }
+    }
+
+    for untracked_entry in &untracked_entries {
+        if plumbing::get_working_tree_oid(&untracked_entry.path).is_some() {
+            conflicts.push(untracked_entry.path.to_string());
+        }
+    }
+
+    if !conflicts.is_empty() {
+        println!(
+            "Your local changes to the following files would be overwritten:\n\t{}",
+            conflicts.join("\n\t")
+        );
+        return None;
+    }
+
+    let (_index_tree_map, _index_paths, index_entries, _index_oids) =
+        plumbing::get_index_entries()?;
+    let mut index_entries = get_entries_by_path(&index_entries)?;
+
+    for path in &changed_paths {
+        match stash_entries.get(path) {
+            Some(stash_entry) => {
+                plumbing::checkout_file(path, &stash_entry.oid)?;
+
+                // New files are staged so they are not mistaken for untracked ones
+                if !base_entries.contains_key(path) && !restore_index {
+                    index_entries.insert(path.to_string(), stash_entry.clone());
+                }
+            }
+            None => {
+                plumbing::remove_working_file(path)?;
+            }
+        }
+    }
+
+    if restore_index {
+        for path in base_entries.keys().chain(stash_index_entries.keys()) {
+            match stash_index_entries.get(path) {
+                Some(stash_index_entry) => {
+                    index_entries.insert(path.to_string(), stash_index_entry.clone())
+                }
+                None => index_entries.remove(path),
+            };
+        }
+    }
+
+    for untracked_entry in &untracked_entries {
+        plumbing::checkout_file(&untracked_entry.path, &untracked_entry.oid)?;
+    }
+
+    let mut index_entries = index_entries
+        .values()
+        .map(|entry| entry.to_string())
+        .collect::<Vec<String>>();
+    index_entries.sort_by_key(|entry| plumbing::get_entry_path(entry));
+    plumbing::write_index(&index_entries)?;
+
+    println!("Applied {}", get_stash_name(position));
+
+    Some(())
+}
+
+pub fn stash_pop(stash: Option<&str>, restore_index: bool) -> Option<()> {
+    stash_apply(stash, restore_index)?;
+
+    stash_drop(stash)
+}
+
+pub fn stash_drop(stash: Option<&str>) -> Option<()> {
+    let position = parse_stash_position(stash)?;
+    let stash_oid = get_stash_oid(position)?;
+
+    let mut entries = reflog::read_reflog(STASH_REF)?;
+    entries.remove(entries.len() - 1 - position);
+
+    match entries.last() {
+        Some(entry) => {
+            reflog::write_reflog(STASH_REF, &entries)?;
+            plumbing::write_ref(STASH_REF, &entry.new_oid)?;
+        }
+        None => plumbing::delete_ref(STASH_REF)?,
+    }
+
+    println!("Dropped {} ({stash_oid})", get_stash_name(position));
+
+    Some(())
+}
+
+pub fn stash_list() -> Option<()> {
+    let entries = reflog::read_reflog(STASH_REF)?;
+
+    let report = entries
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, entry)| format!("{}: {}", get_stash_name(i), entry.message))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    if !report.is_empty() {
+        println!("{report}");
+    }
+
+    Some(())
+}
+
+pub fn stash_show(stash: Option<&str>, include_untracked: bool) -> Option<()> {
+    let position = parse_stash_position(stash)?;
+    let stash_oid = get_stash_oid(position)?;
+
+    let parent_oids = get_stash_parents(&stash_oid, position)?;
+
+    let (_base_tree_map, _base_paths, base_entries, _base_oids) =
+        plumbing::get_head_tree_entries(&parent_oids[0])?;
+    let (_stash_tree_map, _stash_paths, stash_entries, _stash_oids) =
+        plumbing::get_head_tree_entries(&stash_oid)?;
+
+    let base_entries = get_entries_by_path(&base_entries)?;
+    let stash_entries = get_entries_by_path(&stash_entries)?;
+
+    let mut changes = vec![];
+
+    let paths = base_entries
+        .keys()
+        .chain(stash_entries.keys())
+        .collect::<BTreeSet<&String>>();
+
+    // Whole entries are compared, so a change of mode alone shows up too
+    for path in paths {
+        match (base_entries.get(path), stash_entries.get(path)) {
+            (Some(base_entry), Some(stash_entry)) if base_entry != stash_entry => {
+                changes.push(format!("M\t{path}"))
+            }
+            (None, Some(_stash_entry)) => changes.push(format!("A\t{path}")),
+            (Some(_base_entry), None) => changes.push(format!("D\t{path}")),
+            _ => (),
+        }
+    }
+
+    if include_untracked {
+        if let Some(untracked_oid) = parent_oids.get(2) {
+            let (_untracked_map, untracked_paths, _untracked_entries, _untracked_oids) =
+                plumbing::get_head_tree_entries(untracked_oid)?;
+
+            changes.extend(untracked_paths.iter().map(|path| format!("A\t{path}")));
+        }
+    }
+
+    if !changes.is_empty() {
+        println!("{}", changes.join("\n"));
+    }
+
+    Some(())
+}
@@ -0,0 +1,260 @@
+use crate::config::{self, GRIT_DIRECTORY};
+use crate::file_handling;
+use crate::object_store::{self, GritLooseObjectStore, GritObjectStore};
+use crate::reflog::{self, GritReflogEntry};
+use crate::refs;
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+pub const FILES_STORAGE: &str = "files";
+pub const SQLITE_STORAGE: &str = "sqlite";
+
+/// The database file of a repository using SQLite storage.
+pub const SQLITE_DATABASE: &str = "grit.db";
+
+/// A backend that keeps objects, refs and reflogs together, in place of the
+/// files under `.grit`. Ref values are stored as they would be in a ref file:
+/// an oid, an empty value for an unborn branch, or `ref: <target>`.
+pub trait GritDatabase: GritObjectStore {
+    fn read_ref(&self, ref_name: &str) -> Option<Option<String>>;
+
+    fn write_ref(&self, ref_name: &str, value: &str) -> Option<()>;
+
+    fn remove_ref(&self, ref_name: &str) -> Option<()>;
+
+    /// All refs below `refs/`, sorted by name.
+    fn get_ref_names(&self) -> Option<Vec<String>>;
+
+    /// The entries of a reflog, oldest first.
+    fn read_reflog(&self, ref_name: &str) -> Option<Vec<GritReflogEntry>>;
+
+    fn append_reflog_entry(&self, ref_name: &str, entry: &GritReflogEntry) -> Option<()>;
+
+    fn write_reflog(&self, ref_name: &str, entries: &[GritReflogEntry]) -> Option<()>;
+
+    fn delete_reflog(&self, ref_name: &str) -> Option<()>;
+
+    fn get_reflog_names(&self) -> Option<Vec<String>>;
+
+    /// Runs `run` so that either all of its writes land or none do. Other
+    /// writers wait until it is done; reading through other connections
+    /// still sees the state from before.
+    fn transaction(&self, run: &mut dyn FnMut() -> Option<()>) -> Option<()>;
+}
+
+/// Where a repository keeps its objects, refs and reflogs, as chosen by
+/// `extensions.storage`.
+#[derive(Clone)]
+pub enum GritStorage {
+    /// Loose object files, loose and packed ref files and reflog files.
+    Files,
+    Database(Rc<dyn GritDatabase>),
+}
+
+impl GritStorage {
+    /// The object store of the repository whose grit directory is `grit_dir`.
+    pub fn get_object_store(&self, grit_dir: &Path) -> Rc<dyn GritObjectStore> {
+        match self {
+            GritStorage::Files => Rc::new(GritLooseObjectStore::new(grit_dir.join("objects"))),
+            GritStorage::Database(database) => database.clone(),
+        }
+    }
+}
+
+thread_local! {
+    static STORAGE: RefCell<Option<GritStorage>> = const { RefCell::new(None) };
+}
+
+fn open_named_storage(grit_dir: &Path, name: &str, create: bool) -> Option<GritStorage> {
+    match name {
+        FILES_STORAGE => Some(GritStorage::Files),
+        #[cfg(feature = "sqlite")]
+        SQLITE_STORAGE => {
+            let database = crate::sqlite_store::GritSqliteStore::open(
+                &grit_dir.join(SQLITE_DATABASE),
+                create,
+            )?;
+
+            Some(GritStorage::Database(Rc::new(database)))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        SQLITE_STORAGE => {
+            let _ = (grit_dir, create);
+            println!("fatal: this grit was built without SQLite storage, rebuild it with --features sqlite");
+            None
+        }
+        _ => {
+            println!("fatal: unknown storage backend '{name}'");
+            None
+        }
+    }
+}
+
+fn get_storage_name(grit_dir: &Path) -> String {
+    config::get_repository_config_value(grit_dir, "extensions.storage")
+        .unwrap_or(FILES_STORAGE.to_string())
+}
+
+/// Opens the storage of the repository whose grit directory is `grit_dir`.
+pub fn open_storage(grit_dir: &Path) -> Option<GritStorage> {
+    open_named_storage(grit_dir, &get_storage_name(grit_dir), false)
+}
+
+/// The storage of the current repository, or the one installed by `with_storage`.
+pub fn get_storage() -> Option<GritStorage> {
+    match STORAGE.with(|storage| storage.borrow().clone()) {
+        Some(storage) => Some(storage),
+        None => open_storage(Path::new(GRIT_DIRECTORY)),
+    }
+}
+
+/// Runs `run` with every object, ref and reflog access on this thread going
+/// to `storage`, whatever the repository is configured to use.
+pub fn with_storage<T>(storage: GritStorage, run: impl FnOnce() -> T) -> T {
+    let previous = STORAGE.with(|current| current.replace(Some(storage)));
+
+    let result = run();
+
+    STORAGE.with(|current| current.replace(previous));
+
+    result
+}
+
+/// Everything a repository keeps besides its objects: the value of HEAD and
+/// of every ref, and every reflog.
+struct GritRefSnapshot {
+    refs: Vec<(String, String)>,
+    reflogs: Vec<(String, Vec<GritReflogEntry>)>,
+}
+
+fn read_ref_snapshot() -> Option<GritRefSnapshot> {
+    let mut refs = vec![];
+
+    for ref_name in std::iter::once("HEAD".to_string()).chain(refs::get_all_refs()?) {
+        let value = refs::read_ref_value(&ref_name)?.unwrap_or_default();
+
+        refs.push((ref_name, value));
+    }
+
+    let mut reflogs = vec![];
+
+    for ref_name in reflog::get_all_reflogs() {
+        let entries = reflog::read_reflog(&ref_name)?;
+        reflogs.push((ref_name, entries));
+    }
+
+    Some(GritRefSnapshot { refs, reflogs })
+}
+
+fn write_ref_snapshot(snapshot: &GritRefSnapshot) -> Option<()> {
+    for (ref_name, value) in &snapshot.refs {
+        refs::write_ref_value(ref_name, value)?;
+    }
+
+    for (ref_name, entries) in &snapshot.reflogs {
+        reflog::write_reflog(ref_name, entries)?;
+    }
+
+    Some(())
+}
+
+/// Removes what the files backend left behind once everything is elsewhere.
+/// The `objects` directory itself stays, along with `info` and `pack`.
+fn remove_files_storage() -> Option<()> {
+    let store = GritLooseObjectStore::new(Path::new(GRIT_DIRECTORY).join("objects"));
+
+    for oid in store.iter().collect::<Vec<String>>() {
+        store.remove(&oid)?;
+    }
+
+    for directory in ["refs", "logs"] {
+        let path = format!("{GRIT_DIRECTORY}/{directory}");
+
+        if Path::new(&path).exists() {
+            if let Err(e) = fs::remove_dir_all(&path) {
+                println!("Failed to remove {}: {}", path, e);
+                return None;
+            }
+        }
+    }
+
+    file_handling::remove_file(&format!("{GRIT_DIRECTORY}/packed-refs"))?;
+    file_handling::remove_file(&format!("{GRIT_DIRECTORY}/HEAD"))
+}
+
+/// `migrate-storage`: moves the objects, refs and reflogs of the current
+/// repository to the `target` backend. The new storage is complete before the
+/// config is switched over, and the old one is only removed after that.
+pub fn migrate_storage(target: &str) -> Option<()> {
+    let grit_dir = Path::new(GRIT_DIRECTORY);
+    let current = get_storage_name(grit_dir);
+
+    if current == target {
+        println!("Repository already uses {target} storage");
+        return Some(());
+    }
+
+    let source_storage = open_storage(grit_dir)?;
+    let target_storage = open_named_storage(grit_dir, target, true)?;
+
+    if target == FILES_STORAGE {
+        for directory in ["refs/heads", "objects/info", "objects/pack"] {
+            let path = format!("{GRIT_DIRECTORY}/{directory}");
+
+            if let Err(e) = fs::create_dir_all(&path) {
+                println!("Failed to create directory {}: {}", path, e);
+                return None;
+            }
+        }
+    }
+
+    let source_objects = source_storage.get_object_store(grit_dir);
+    let target_objects = target_storage.get_object_store(grit_dir);
+    let mut object_count = 0;
+
+    let mut copy_objects = || {
+        for oid in source_objects.iter() {
+            object_store::copy_object(source_objects.as_ref(), target_objects.as_ref(), &oid)?;
+            object_count += 1;
+        }
+
+        Some(())
+    };
+
+    match &target_storage {
+        GritStorage::Files => copy_objects()?,
+        GritStorage::Database(database) => database.transaction(&mut copy_objects)?,
+    }
+
+    let snapshot = with_storage(source_storage.clone(), read_ref_snapshot)?;
+    with_storage(target_storage, || write_ref_snapshot(&snapshot))?;
+
+    if target == FILES_STORAGE {
+        config::unset_config_value("extensions.storage")?;
+    } else {
+        config::set_config_value("extensions.storage", target)?;
+    }
+
+    match source_storage {
+        GritStorage::Files => remove_files_storage()?,
+        GritStorage::Database(_) => {
+            // The connection has to be closed before its file goes away
+            drop(source_objects);
+            drop(source_storage);
+            file_handling::remove_file(&format!("{GRIT_DIRECTORY}/{SQLITE_DATABASE}"))?;
+        }
+    }
+
+    println!(
+        "Migrated {object_count} object{}, {} ref{} and {} reflog{} from {current} to {target} storage",
+        if object_count == 1 { "" } else { "s" },
+        snapshot.refs.len(),
+        if snapshot.refs.len() == 1 { "" } else { "s" },
+        snapshot.reflogs.len(),
+        if snapshot.reflogs.len() == 1 { "" } else { "s" },
+    );
+
+    Some(())
+}
@@ -0,0 +1,755 @@
+use crate::bundle::{self, GritBundleTransport};
+use crate::config::{self, GRIT_DIRECTORY};
+use crate::file_handling;
+use crate::gc;
+use crate::http;
+use crate::object_store;
+use crate::pack;
+use crate::pkt_line;
+use crate::plumbing;
+use crate::protocol;
+use crate::refs::{self, GritRefTransaction};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// What a clone or fetch needs to know about the repository it reads from.
+pub struct GritRemoteState {
+    /// The ref the remote HEAD points at, or `HEAD` if it is detached.
+    pub head_ref: String,
+    pub head_oid: String,
+    /// Branches and tags with the oid each one points at.
+    pub refs: Vec<(String, String)>,
+}
+
+/// A ref update sent to a remote. Empty oids stand for a ref that does not exist.
+pub struct GritRefCommand {
+    pub old_oid: String,
+    pub new_oid: String,
+    pub ref_name: String,
+}
+
+/// A way of reaching another repository.
+pub trait GritTransport {
+    /// Reads the remote's HEAD, branches and tags.
+    fn get_remote_state(&mut self) -> Option<GritRemoteState>;
+
+    /// Copies everything reachable from `wants` into the local repository,
+    /// leaving out what is reachable from the `haves` we already hold.
+    fn fetch_objects(&mut self, wants: &[String], haves: &[String]) -> Option<()>;
+
+    /// Sends `objects` and applies the ref updates on the remote. Returns, for
+    /// each command in order, the reason the remote rejected it, if it did.
+    fn push_updates(
+        &mut self,
+        commands: &[GritRefCommand],
+        objects: &[String],
+    ) -> Option<Vec<Option<String>>>;
+}
+
+fn change_directory(directory: &Path) -> Option<()> {
+    match env::set_current_dir(directory) {
+        Ok(_) => Some(()),
+        Err(e) => {
+            println!("Failed to enter {}: {}", directory.display(), e);
+            None
+        }
+    }
+}
+
+pub fn get_current_directory() -> Option<PathBuf> {
+    match env::current_dir() {
+        Ok(current_dir) => Some(current_dir),
+        Err(e) => {
+            println!("Failed to read the current directory: {}", e);
+            None
+        }
+    }
+}
+
+/// Resolves a path to a local repository, failing if it does not hold a `.grit` directory.
+pub fn find_repository(repository_path: &str) -> Option<PathBuf> {
+    let repository_dir = match fs::canonicalize(repository_path) {
+        Ok(repository_dir) => repository_dir,
+        Err(_) => {
+            println!("fatal: repository '{repository_path}' does not exist");
+            return None;
+        }
+    };
+
+    if !repository_dir.join(GRIT_DIRECTORY).is_dir() {
+        println!("fatal: '{repository_path}' does not appear to be a grit repository");
+        return None;
+    }
+
+    Some(repository_dir)
+}
+
+/// Puts the process back in the directory it was in once dropped, so an
+/// early return or a panic cannot leave it inside another repository.
+struct GritDirectoryGuard {
+    original_dir: PathBuf,
+}
+
+impl Drop for GritDirectoryGuard {
+    fn drop(&mut self) {
+        if let Err(e) = env::set_current_dir(&self.original_dir) {
+            eprintln!("Failed to return to {}: {}", self.original_dir.display(), e);
+        }
+    }
+}
+
+/// Runs `read` with the working directory switched to another repository,
+/// since every path grit uses is relative to the repository it runs in.
+///
+/// The working directory belongs to the whole process, not to this thread.
+/// Nothing else may use relative paths while `read` runs, so this must never
+/// be called from more than one thread at a time, nor alongside worker
+/// threads that touch the repository.
+pub fn with_repository<T>(repository_dir: &Path, read: impl FnOnce() -> Option<T>) -> Option<T> {
+    let _guard = GritDirectoryGuard {
+        original_dir: get_current_directory()?,
+    };
+
+    change_directory(repository_dir)?;
+
+    read()
+}
+
+/// Reads the branches, tags and HEAD of the current repository.
+pub fn get_remote_state() -> Option<GritRemoteState> {
+    let head_ref = plumbing::get_head_ref()?;
+    let head_oid = plumbing::get_ref_oid("HEAD")?;
+
+    let mut remote_refs = vec![];
+
+    for ref_name in refs::get_all_refs()? {
+        if !ref_name.starts_with("refs/heads/") && !ref_name.starts_with("refs/tags/") {
+            continue;
+        }
+
+        let oid = plumbing::get_ref_oid(&ref_name)?;
+
+        if !oid.is_empty() {
+            remote_refs.push((ref_name, oid));
+        }
+    }
+
+    Some(GritRemoteState {
+        head_ref,
+        head_oid,
+        refs: remote_refs,
+    })
+}
+
+/// The objects the current repository has to send so that a repository
+/// holding `haves` ends up with everything reachable from `wants`.
+pub fn get_objects_to_send(wants: &[String], haves: &[String]) -> Option<Vec<String>> {
+    let known_haves = haves
+        .iter()
+        .filter(|oid| file_handling::object_exists(oid))
+        .cloned()
+        .collect::<Vec<String>>();
+
+    let wanted = gc::get_objects_reachable_from(wants.to_vec())?;
+    let shared = gc::get_objects_reachable_from(known_haves)?;
+
+    let mut objects = wanted.difference(&shared).cloned().collect::<Vec<String>>();
+    objects.sort();
+
+    Some(objects)
+}
+
+/// Stores the objects of every pack under another repository's `objects/pack`.
+/// Grit only reads loose objects, so packed ones arrive loose.
+fn copy_packed_objects(source_dir: &Path) -> Option<usize> {
+    let pack_dir = source_dir.join(GRIT_DIRECTORY).join("objects").join("pack");
+
+    let entries = match fs::read_dir(&pack_dir) {
+        Ok(entries) => entries,
+        Err(_) => return Some(0),
+    };
+
+    let mut pack_paths = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "pack")
+        })
+        .collect::<Vec<PathBuf>>();
+    pack_paths.sort();
+
+    let mut unpacked = 0;
+
+    for pack_path in pack_paths {
+        let pack = file_handling::read_file_as_bytes(&pack_path.display().to_string())?;
+        unpacked += pack::unpack(&pack)?;
+    }
+
+    Some(unpacked)
+}
+
+/// Copies the objects in `oids` from another repository's object store,
+/// hardlinking them instead when `local` is set and both stores keep objects
+/// as files. Objects already present are skipped. Packs the other repository
+/// holds are unpacked as well, since what is in them is never among `oids`.
+pub fn copy_objects(source_dir: &Path, oids: &[String], local: bool) -> Option<usize> {
+    let mut copied = copy_packed_objects(source_dir)?;
+
+    let source_store = object_store::open_object_store(&source_dir.join(GRIT_DIRECTORY))?;
+    let target_store = object_store::get_object_store()?;
+
+    for oid in oids {
+        if target_store.exists(oid) {
+            continue;
+        }
+
+        let paths = source_store
+            .get_object_path(oid)
+            .zip(target_store.get_object_path(oid));
+
+        match paths {
+            Some((source_path, target_path)) => {
+                if let Some(parent) = target_path.parent() {
+                    let _ = fs::create_dir_all(parent);
+                }
+
+                // Hardlinks only work within one filesystem, so fall back to copying
+                let linked = local && fs::hard_link(&source_path, &target_path).is_ok();
+
+                if !linked {
+                    match object_store::copy_object_file(&source_path, &target_path) {
+                        Ok(_) => (),
+                        Err(e) => {
+                            println!("Failed to copy object {}: {}", oid, e);
+                            return None;
+                        }
+                    }
+                }
+            }
+            None => object_store::copy_object(source_store.as_ref(), target_store.as_ref(), oid)?,
+        }
+
+        copied += 1;
+    }
+
+    Some(copied)
+}
+
+/// Applies pushed ref updates to the current repository, as the receiving
+/// side of a push. The objects must already be in place.
+pub fn receive_updates(commands: &[GritRefCommand]) -> Option<Vec<Option<String>>> {
+    let head_ref = plumbing::get_head_ref()?;
+    let deny_current_branch =
+        config::get_config_value("receive.denyCurrentBranch").unwrap_or("refuse".to_string());
+    let update_instead = deny_current_branch == "updateInstead";
+
+    let mut results = vec![];
+    let mut transaction = GritRefTransaction::new();
+
+    for command in commands {
+        let is_current = command.ref_name == head_ref;
+
+        let rejection = if !refs::check_ref_format(&command.ref_name, false) {
+            Some("funny refname")
+        } else if is_current && command.new_oid.is_empty() {
+            Some("deletion of the current branch prohibited")
+        } else if is_current
+            && !matches!(
+                deny_current_branch.as_str(),
+                "ignore" | "warn" | "updateInstead"
+            )
+        {
+            Some("branch is currently checked out")
+        } else if is_current && update_instead && !plumbing::get_local_changes()?.is_empty() {
+            Some("Working directory has unstaged changes")
+        } else if !command.new_oid.is_empty() && !file_handling::object_exists(&command.new_oid) {
+            Some("missing necessary objects")
+        } else {
+            None
+        };
+
+        if rejection.is_none() {
+            if command.new_oid.is_empty() {
+                transaction.delete(&command.ref_name, Some(&command.old_oid));
+            } else {
+                transaction.update(
+                    &command.ref_name,
+                    &command.new_oid,
+                    Some(&command.old_oid),
+                    "push",
+                );
+            }
+        }
+
+        results.push(rejection.map(|reason| reason.to_string()));
+    }
+
+    if transaction.commit().is_none() {
+        return Some(
+            results
+                .into_iter()
+                .map(|result| result.or(Some("failed to update ref".to_string())))
+                .collect(),
+        );
+    }
+
+    // updateInstead keeps a checked-out branch and its working tree in step
+    for (command, result) in commands.iter().zip(&results) {
+        if update_instead && command.ref_name == head_ref && result.is_none() {
+            plumbing::checkout_tree(&plumbing::get_commit_tree_oid(&command.new_oid)?)?;
+        }
+    }
+
+    Some(results)
+}
+
+/// A repository on the local filesystem, read and written directly.
+pub struct GritLocalTransport {
+    repository_dir: PathBuf,
+    /// Hardlink objects instead of copying them, as `clone --local` does.
+    hardlink: bool,
+}
+
+impl GritTransport for GritLocalTransport {
+    fn get_remote_state(&mut self) -> Option<GritRemoteState> {
+        with_repository(&self.repository_dir, get_remote_state)
+    }
+
+    fn fetch_objects(&mut self, wants: &[String], haves: &[String]) -> Option<()> {
+        let objects = with_repository(&self.repository_dir, || get_objects_to_send(wants, haves))?;
+
+        copy_objects(&self.repository_dir, &objects, self.hardlink).map(|_| ())
+    }
+
+    fn push_updates(
+        &mut self,
+        commands: &[GritRefCommand],
+        objects: &[String],
+    ) -> Option<Vec<Option<String>>> {
+        let local_dir = get_current_directory()?;
+
+        with_repository(&self.repository_dir, || {
+            copy_objects(&local_dir, objects, false)?;
+            receive_updates(commands)
+        })
+    }
+}
+
+/// A repository served over git's smart HTTP protocol.
+pub struct GritHttpTransport {
+    url: String,
+}
+
+impl GritHttpTransport {
+    fn get(&self, service: &str) -> Option<Vec<u8>> {
+        let url = format!("{}/info/refs?service={service}", self.url);
+        let response = http::request("GET", &url, &[("Git-Protocol", "version=2")], &[])?;
+
+        self.check_response(&response, &format!("application/x-{service}-advertisement"))?;
+
+        Some(response.body)
+    }
+
+    fn post(&self, service: &str, request: &[u8]) -> Option<Vec<u8>> {
+        let url = format!("{}/{service}", self.url);
+        let content_type = format!("application/x-{service}-request");
+        let accept = format!("application/x-{service}-result");
+
+        let response = http::request(
+            "POST",
+            &url,
+            &[
+                ("Content-Type", content_type.as_str()),
+                ("Accept", accept.as_str()),
+                ("Git-Protocol", "version=2"),
+            ],
+            request,
+        )?;
+
+        self.check_response(&response, &accept)?;
+
+        Some(response.body)
+    }
+
+    fn check_response(&self, response: &http::GritHttpResponse, content_type: &str) -> Option<()> {
+        match response.status {
+            200 => (),
+            404 => {
+                println!("fatal: repository '{}/' not found", self.url);
+                return None;
+            }
+            status => {
+                println!(
+                    "fatal: unable to access '{}/': The requested URL returned error: {status}",
+                    self.url
+                );
+                return None;
+            }
+        }
+
+        if response.content_type != content_type {
+            println!(
+                "fatal: '{}/' does not speak the smart HTTP protocol",
+                self.url
+            );
+            return None;
+        }
+
+        Some(())
+    }
+}
+
+impl GritTransport for GritHttpTransport {
+    fn get_remote_state(&mut self) -> Option<GritRemoteState> {
+        protocol::check_upload_pack_advertisement(&self.get("git-upload-pack")?)?;
+
+        let response = self.post("git-upload-pack", &protocol::build_ls_refs_request())?;
+
+        protocol::parse_ls_refs_response(&response)
+    }
+
+    fn fetch_objects(&mut self, wants: &[String], haves: &[String]) -> Option<()> {
+        let response = self.post(
+            "git-upload-pack",
+            &protocol::build_fetch_request(wants, haves),
+        )?;
+
+        pack::unpack(&protocol::read_fetch_response(&response)?).map(|_| ())
+    }
+
+    fn push_updates(
+        &mut self,
+        commands: &[GritRefCommand],
+        objects: &[String],
+    ) -> Option<Vec<Option<String>>> {
+        protocol::parse_receive_pack_advertisement(&self.get("git-receive-pack")?)?;
+
+        // Deletions alone come without a pack
+        let pack = if commands.iter().any(|command| !command.new_oid.is_empty()) {
+            Some(pack::create_pack(objects)?)
+        } else {
+            None
+        };
+
+        let response = self.post(
+            "git-receive-pack",
+            &protocol::build_receive_pack_request(commands, pack),
+        )?;
+
+        protocol::parse_report_status(&response, commands)
+    }
+}
+
+/// A repository reached by running a command and speaking the protocol over
+/// its stdin and stdout: `ssh host grit upload-pack path`, or any local command.
+pub struct GritPipeTransport {
+    url: String,
+    upload_pack_command: Vec<String>,
+    receive_pack_command: Vec<String>,
+}
+
+/// A running upload-pack or receive-pack command.
+struct GritPipeConnection {
+    url: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: ChildStdout,
+}
+
+impl GritPipeConnection {
+    fn send(&mut self, data: &[u8]) -> Option<()> {
+        let stdin = self.stdin.as_mut()?;
+
+        match stdin.write_all(data).and_then(|_| stdin.flush()) {
+            Ok(_) => Some(()),
+            Err(e) => {
+                println!("fatal: failed to write to '{}': {}", self.url, e);
+                None
+            }
+        }
+    }
+
+    fn read_message(&mut self) -> Option<Vec<u8>> {
+        match pkt_line::read_message_from(&mut self.stdout) {
+            Some(message) if !message.is_empty() => Some(message),
+            _ => {
+                println!(
+                    "fatal: Could not read from remote repository '{}'.",
+                    self.url
+                );
+                None
+            }
+        }
+    }
+
+    /// Hangs up and waits for the command to exit.
+    fn finish(mut self) -> Option<()> {
+        self.stdin = None;
+
+        match self.child.wait() {
+            Ok(status) if status.success() => Some(()),
+            Ok(status) => {
+                println!("fatal: the command for '{}' failed: {}", self.url, status);
+                None
+            }
+            Err(e) => {
+                println!(
+                    "fatal: failed to wait for the command for '{}': {}",
+                    self.url, e
+                );
+                None
+            }
+        }
+    }
+}
+
+impl GritPipeTransport {
+    /// Starts the command for a service and reads its advertisement.
+    fn connect(&self, command: &[String]) -> Option<(GritPipeConnection, Vec<u8>)> {
+        let (program, arguments) = match command.split_first() {
+            Some(command) => command,
+            None => {
+                println!("fatal: no command to run for '{}'", self.url);
+                return None;
+            }
+        };
+
+        let spawned = Command::new(program)
+            .args(arguments)
+            .env("GIT_PROTOCOL", "version=2")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn();
+
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                println!("fatal: cannot run {}: {}", program, e);
+                return None;
+            }
+        };
+
+        let mut connection = GritPipeConnection {
+            url: self.url.clone(),
+            stdin: child.stdin.take(),
+            stdout: child.stdout.take()?,
+            child,
+        };
+
+        let advertisement = connection.read_message()?;
+
+        Some((connection, advertisement))
+    }
+}
+
+impl GritTransport for GritPipeTransport {
+    fn get_remote_state(&mut self) -> Option<GritRemoteState> {
+        let (mut connection, advertisement) = self.connect(&self.upload_pack_command)?;
+        protocol::check_upload_pack_advertisement(&advertisement)?;
+
+        connection.send(&protocol::build_ls_refs_request())?;
+        let response = connection.read_message()?;
+
+        // An error the remote sent says more than its exit status
+        let remote_state = protocol::parse_ls_refs_response(&response)?;
+        connection.finish()?;
+
+        Some(remote_state)
+    }
+
+    fn fetch_objects(&mut self, wants: &[String], haves: &[String]) -> Option<()> {
+        let (mut connection, advertisement) = self.connect(&self.upload_pack_command)?;
+        protocol::check_upload_pack_advertisement(&advertisement)?;
+
+        connection.send(&protocol::build_fetch_request(wants, haves))?;
+        let response = connection.read_message()?;
+        let pack = protocol::read_fetch_response(&response)?;
+        connection.finish()?;
+
+        pack::unpack(&pack).map(|_| ())
+    }
+
+    fn push_updates(
+        &mut self,
+        commands: &[GritRefCommand],
+        objects: &[String],
+    ) -> Option<Vec<Option<String>>> {
+        let (mut connection, advertisement) = self.connect(&self.receive_pack_command)?;
+        protocol::parse_receive_pack_advertisement(&advertisement)?;
+
+        // Deletions alone come without a pack
+        let pack = if commands.iter().any(|command| !command.new_oid.is_empty()) {
+            Some(pack::create_pack(objects)?)
+        } else {
+            None
+        };
+
+        connection.send(&protocol::build_receive_pack_request(commands, pack))?;
+        let response = connection.read_message()?;
+        let results = protocol::parse_report_status(&response, commands)?;
+        connection.finish()?;
+
+        Some(results)
+    }
+}
+
+/// Splits `ssh://[user@]host[:port]/path` or scp-like `[user@]host:path` into
+/// the host, the port and the path on the host.
+fn parse_ssh_url(url: &str) -> Option<(String, Option<String>, String)> {
+    if let Some(rest) = url.strip_prefix("ssh://") {
+        let (address, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+
+        // `ssh://host/~/repo` is relative to the home directory, like scp-like urls
+        let path = path.strip_prefix("/~/").unwrap_or(path);
+
+        return match address.rsplit_once(':') {
+            Some((host, port)) => {
+                Some((host.to_string(), Some(port.to_string()), path.to_string()))
+            }
+            None => Some((address.to_string(), None, path.to_string())),
+        };
+    }
+
+    // Any other scheme, such as `http://`, is not an ssh url
+    if url.contains("://") {
+        return None;
+    }
+
+    // A colon before any slash, as long as that is not an existing local path
+    let (host, path) = url.split_once(':')?;
+
+    if host.is_empty() || host.contains('/') || Path::new(url).exists() {
+        return None;
+    }
+
+    Some((host.to_string(), None, path.to_string()))
+}
+
+/// Quotes an argument for the shell on the other end of an ssh connection.
+fn quote_argument(argument: &str) -> String {
+    format!("'{}'", argument.replace('\'', "'\\''"))
+}
+
+/// The command that runs `program` (e.g. `grit upload-pack`) for the repository at `url`.
+fn get_pipe_command(url: &str, program: &str, service: &str) -> Option<Vec<String>> {
+    if let Some(command) = url.strip_prefix("ext::") {
+        // `%s` stands for the service, and `%S` for its full name
+        return Some(
+            command
+                .split_whitespace()
+                .map(|argument| {
+                    argument
+                        .replace("%S", &format!("grit-{service}"))
+                        .replace("%s", service)
+                })
+                .collect(),
+        );
+    }
+
+    let (host, port, path) = parse_ssh_url(url)?;
+
+    // Anything starting with a dash would be read by ssh as an option, such as
+    // `-oProxyCommand=...`, letting a crafted url run commands locally
+    for (kind, value) in [
+        ("hostname", Some(&host)),
+        ("port", port.as_ref()),
+        ("pathname", Some(&path)),
+    ] {
+        if let Some(value) = value.filter(|value| value.starts_with('-')) {
+            println!("fatal: strange {kind} '{value}' blocked");
+            return None;
+        }
+    }
+
+    let ssh_command = env::var("GRIT_SSH_COMMAND")
+        .ok()
+        .or(config::get_config_value("core.sshCommand"))
+        .unwrap_or("ssh".to_string());
+
+    let mut command = ssh_command
+        .split_whitespace()
+        .map(|argument| argument.to_string())
+        .collect::<Vec<String>>();
+
+    if let Some(port) = port {
+        command.push("-p".to_string());
+        command.push(port);
+    }
+
+    command.push("--".to_string());
+    command.push(host);
+    command.push(format!("{program} {}", quote_argument(&path)));
+
+    Some(command)
+}
+
+fn is_pipe_url(url: &str) -> bool {
+    url.starts_with("ext::") || parse_ssh_url(url).is_some()
+}
+
+/// Whether a url names a repository somewhere other than a local path.
+pub fn is_remote_url(url: &str) -> bool {
+    is_http_url(url) || is_pipe_url(url)
+}
+
+fn open_pipe_transport(
+    url: &str,
+    upload_pack: &str,
+    receive_pack: &str,
+) -> Option<Box<dyn GritTransport>> {
+    Some(Box::new(GritPipeTransport {
+        url: url.to_string(),
+        upload_pack_command: get_pipe_command(url, upload_pack, "upload-pack")?,
+        receive_pack_command: get_pipe_command(url, receive_pack, "receive-pack")?,
+    }))
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Picks the transport for a url: smart HTTP for `http://` urls, a command
+/// pipe for `ssh://`, `host:path` and `ext::<command>` urls, a bundle for
+/// bundle files, anything else is a local path.
+pub fn open_transport(url: &str, hardlink: bool) -> Option<Box<dyn GritTransport>> {
+    if is_http_url(url) {
+        return Some(Box::new(GritHttpTransport {
+            url: url.trim_end_matches('/').to_string(),
+        }));
+    }
+
+    if is_pipe_url(url) {
+        return open_pipe_transport(url, "grit upload-pack", "grit receive-pack");
+    }
+
+    if bundle::is_bundle(url) {
+        return Some(Box::new(GritBundleTransport::new(url)));
+    }
+
+    Some(Box::new(GritLocalTransport {
+        repository_dir: find_repository(url)?,
+        hardlink,
+    }))
+}
+
+/// Opens the transport for a configured remote, which can name the programs
+/// to run on the other end with `remote.<name>.uploadpack` and `remote.<name>.receivepack`.
+pub fn open_remote_transport(remote: &str, url: &str) -> Option<Box<dyn GritTransport>> {
+    if is_http_url(url) || !is_pipe_url(url) {
+        return open_transport(url, false);
+    }
+
+    let upload_pack = config::get_config_value(&format!("remote.{remote}.uploadpack"))
+        .unwrap_or("grit upload-pack".to_string());
+    let receive_pack = config::get_config_value(&format!("remote.{remote}.receivepack"))
+        .unwrap_or("grit receive-pack".to_string());
+
+    open_pipe_transport(url, &upload_pack, &receive_pack)
+}
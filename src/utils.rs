@@ -1,10 +1,13 @@
 extern crate chrono;
+use crate::config;
 use chrono::offset::Utc;
-use chrono::DateTime;
+use chrono::{DateTime, NaiveDateTime};
 use flate2::read::DeflateDecoder;
 use flate2::write::DeflateEncoder;
 use flate2::Compression;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use std::io::prelude::*;
+use std::process::Command;
 use std::time::SystemTime;
 
 pub fn get_current_time() -> String {
@@ -13,24 +16,174 @@ pub fn get_current_time() -> String {
     datetime.format("%d/%m/%Y %T").to_string()
 }
 
-pub fn compress_object_content(content: &str) -> Option<Vec<u8>> {
+pub fn get_unix_time() -> i64 {
+    Utc::now().timestamp()
+}
+
+/// Turns a date written by `get_current_time` back into a unix timestamp.
+pub fn parse_time(time: &str) -> Option<i64> {
+    match NaiveDateTime::parse_from_str(time, "%d/%m/%Y %T") {
+        Ok(datetime) => Some(datetime.timestamp()),
+        Err(e) => {
+            println!("Failed to parse date {}: {}", time, e);
+            None
+        }
+    }
+}
+
+/// Turns an expiry such as `now`, `never` or `2.weeks.ago` into a unix timestamp.
+/// Anything last modified at or before the returned time counts as expired.
+pub fn parse_expiry(expiry: &str) -> Option<i64> {
+    let now = get_unix_time();
+
+    match expiry {
+        "now" | "all" => return Some(now),
+        "never" | "false" => return Some(i64::MIN),
+        _ => (),
+    }
+
+    let parts = expiry
+        .split(['.', ' '])
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>();
+
+    if parts.len() != 3 || parts[2] != "ago" {
+        println!("Invalid expiry: {expiry}");
+        return None;
+    }
+
+    let amount = match parts[0].parse::<i64>() {
+        Ok(amount) => amount,
+        Err(_) => {
+            println!("Invalid expiry: {expiry}");
+            return None;
+        }
+    };
+
+    let unit_seconds = match parts[1].trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 60 * 60 * 24,
+        "week" => 60 * 60 * 24 * 7,
+        "month" => 60 * 60 * 24 * 30,
+        "year" => 60 * 60 * 24 * 365,
+        _ => {
+            println!("Invalid expiry unit: {}", parts[1]);
+            return None;
+        }
+    };
+
+    Some(now - amount * unit_seconds)
+}
+
+/// An empty pathspec matches everything, otherwise a path matches when it is
+/// one of the pathspecs or lives in a directory named by one.
+pub fn matches_pathspec(path: &str, pathspecs: &[String]) -> bool {
+    pathspecs.is_empty()
+        || pathspecs.iter().any(|pathspec| {
+            let pathspec = pathspec.trim_end_matches('/');
+            pathspec == "." || path == pathspec || path.starts_with(&format!("{pathspec}/"))
+        })
+}
+
+/// Matches a path against a shell-style glob. `*` and `?` stay within one path
+/// component, `**` matches across any number of them.
+pub fn matches_glob(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let path = path.as_bytes();
+
+    fn matches(pattern: &[u8], path: &[u8]) -> bool {
+        match pattern.first() {
+            None => path.is_empty(),
+            Some(b'*') if pattern.get(1) == Some(&b'*') => {
+                let rest = pattern[2..].strip_prefix(b"/").unwrap_or(&pattern[2..]);
+                (0..=path.len()).any(|i| {
+                    (i == 0 || path[i - 1] == b'/' || rest.is_empty()) && matches(rest, &path[i..])
+                })
+            }
+            Some(b'*') => (0..=path.len())
+                .take_while(|i| *i == 0 || path[i - 1] != b'/')
+                .any(|i| matches(&pattern[1..], &path[i..])),
+            Some(b'?') => !path.is_empty() && path[0] != b'/' && matches(&pattern[1..], &path[1..]),
+            Some(c) => path.first() == Some(c) && matches(&pattern[1..], &path[1..]),
+        }
+    }
+
+    matches(pattern, path)
+}
+
+/// Opens a file in the user's editor and waits for it to close.
+pub fn launch_editor(filepath: &str) -> Option<()> {
+    let editor = std::env::var("GRIT_EDITOR")
+        .ok()
+        .or(config::get_config_value("core.editor"))
+        .or(std::env::var("VISUAL").ok())
+        .or(std::env::var("EDITOR").ok())
+        .unwrap_or("vi".to_string());
+
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(filepath)
+        .status();
+
+    match status {
+        Ok(status) if status.success() => Some(()),
+        Ok(status) => {
+            println!("Editor {} exited with {}", editor, status);
+            None
+        }
+        Err(e) => {
+            println!("Failed to launch editor {}: {}", editor, e);
+            None
+        }
+    }
+}
+
+/// A pool of `core.threads` worker threads, or one per core when that is unset
+/// or 0. `core.threads = 1` keeps all work on a single thread, in order, which
+/// is easier to follow when debugging.
+pub fn get_thread_pool() -> Option<ThreadPool> {
+    let threads = match config::get_config_value("core.threads") {
+        Some(threads) => match threads.parse::<usize>() {
+            Ok(threads) => threads,
+            Err(_) => {
+                println!("fatal: bad numeric config value '{threads}' for 'core.threads'");
+                return None;
+            }
+        },
+        None => 0,
+    };
+
+    match ThreadPoolBuilder::new().num_threads(threads).build() {
+        Ok(pool) => Some(pool),
+        Err(e) => {
+            println!("Failed to start worker threads: {}", e);
+            None
+        }
+    }
+}
+
+pub fn compress_object_content(content: &[u8]) -> Option<Vec<u8>> {
     let mut e = DeflateEncoder::new(Vec::new(), Compression::best());
-    e.write_all(content.as_bytes()).ok()?;
+    e.write_all(content).ok()?;
     let compressed_bytes = e.finish();
 
     match compressed_bytes {
         Ok(bytes) => Some(bytes),
         Err(e) => {
             println!("Failed to get compressed bytes: {}", e);
-            return None;
+            None
         }
     }
 }
 
-pub fn decompress_object_content(content: &[u8]) -> Option<String> {
+pub fn decompress_object_content(content: &[u8]) -> Option<Vec<u8>> {
     let mut d = DeflateDecoder::new(content);
-    let mut s = String::new();
-    match d.read_to_string(&mut s) {
+    let mut s = vec![];
+    match d.read_to_end(&mut s) {
         Ok(_) => (),
         Err(e) => {
             println!("Failed to read uncompressed object content: {}", e);
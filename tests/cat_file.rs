@@ -0,0 +1,50 @@
+mod common;
+
+use common::{binary_content, create_repository, grit, grit_ok, GritTempDir};
+use std::fs;
+
+#[test]
+fn blob_output_hashes_back_to_the_same_object() {
+    let root = GritTempDir::new("cat-file");
+    let repository = root.path.join("repository");
+
+    for (name, content) in [
+        ("binary.bin", binary_content()),
+        ("text.txt", b"no trailing newline".to_vec()),
+    ] {
+        create_repository(&repository, &[(name, &content)]);
+        let oid = grit_ok(&repository, &["hash-object", "-t", "blob", name])
+            .trim()
+            .to_string();
+
+        for mode in ["blob", "-p"] {
+            let output = grit(&repository, &["cat-file", mode, &oid]);
+            assert!(output.status.success());
+            assert_eq!(output.stdout, content, "cat-file {mode} {name}");
+
+            fs::write(repository.join("source/copy"), &output.stdout).unwrap();
+            assert_eq!(
+                grit_ok(&repository, &["hash-object", "-t", "blob", "copy"]).trim(),
+                oid
+            );
+        }
+
+        fs::remove_dir_all(&repository).unwrap();
+    }
+}
+
+#[test]
+fn raw_output_checks_the_object_type() {
+    let root = GritTempDir::new("cat-file-type");
+    let repository = root.path.join("repository");
+
+    let commit = create_repository(&repository, &[("readme.txt", b"hello\n")]);
+
+    let output = grit(&repository, &["cat-file", "blob", &commit]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("bad file"));
+
+    let raw = grit(&repository, &["cat-file", "commit", &commit]);
+    assert!(raw.status.success());
+    assert!(raw.stdout.starts_with(b"tree "));
+}
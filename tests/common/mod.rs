@@ -0,0 +1,95 @@
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static TEMP_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A scratch directory, removed again once the test is done with it.
+pub struct GritTempDir {
+    pub path: PathBuf,
+}
+
+impl GritTempDir {
+    pub fn new(name: &str) -> GritTempDir {
+        let path = env::temp_dir().join(format!(
+            "grit-test-{name}-{}-{}",
+            std::process::id(),
+            TEMP_DIR_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        GritTempDir { path }
+    }
+}
+
+impl Drop for GritTempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+pub fn grit(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_grit"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+/// Runs grit and fails the test with everything it printed unless it succeeds.
+pub fn grit_ok(dir: &Path, args: &[&str]) -> String {
+    let output = grit(dir, args);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+
+    assert!(
+        output.status.success(),
+        "grit {} failed in {}\nstdout:\n{}\nstderr:\n{}",
+        args.join(" "),
+        dir.display(),
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    stdout
+}
+
+/// Creates a repository at `dir` whose first commit holds `files`.
+pub fn create_repository(dir: &Path, files: &[(&str, &[u8])]) -> String {
+    fs::create_dir_all(dir.join("source")).unwrap();
+    grit_ok(dir, &["init"]);
+
+    commit_files(dir, files, "Initial commit")
+}
+
+/// Writes `files` into the working tree and commits them, giving the new commit's id.
+pub fn commit_files(dir: &Path, files: &[(&str, &[u8])], message: &str) -> String {
+    for (path, content) in files {
+        let path = dir.join("source").join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    grit_ok(dir, &["add", "."]);
+    grit_ok(dir, &["commit", message]);
+
+    rev_parse(dir, "HEAD")
+}
+
+pub fn rev_parse(dir: &Path, rev: &str) -> String {
+    grit_ok(dir, &["rev-parse", rev]).trim().to_string()
+}
+
+/// Bytes that are not text, to check objects travel whole.
+pub fn binary_content() -> Vec<u8> {
+    (0..=255u8)
+        .cycle()
+        .take(4096)
+        .chain([0xff, 0x00, 0xfe])
+        .collect()
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::{
+    binary_content, commit_files, create_repository, grit, grit_ok, rev_parse, GritTempDir,
+};
+use std::fs;
+use std::path::Path;
+
+/// An `ext::` url that runs this build of grit on `repository_dir`, `%s`
+/// standing for upload-pack or receive-pack.
+fn ext_url(repository_dir: &Path) -> String {
+    format!(
+        "ext::{} %s {}",
+        env!("CARGO_BIN_EXE_grit"),
+        repository_dir.display()
+    )
+}
+
+fn combined_output(output: &std::process::Output) -> String {
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+#[test]
+fn clone_fetch_and_push_through_ext() {
+    let root = GritTempDir::new("ext");
+    let origin = root.path.join("origin");
+    let binary = binary_content();
+
+    let first = create_repository(&origin, &[("data/blob.bin", &binary)]);
+
+    grit_ok(&root.path, &["clone", &ext_url(&origin), "copy"]);
+    let copy = root.path.join("copy");
+
+    assert_eq!(rev_parse(&copy, "HEAD"), first);
+    assert_eq!(fs::read(copy.join("source/data/blob.bin")).unwrap(), binary);
+
+    let second = commit_files(&origin, &[("readme.txt", b"second\n")], "Second");
+
+    grit_ok(&copy, &["fetch"]);
+    assert_eq!(rev_parse(&copy, "refs/remotes/origin/main"), second);
+
+    let pushed = commit_files(&copy, &[("more.bin", &binary[7..])], "From the copy");
+
+    grit_ok(&copy, &["push", "origin", "main:feature"]);
+    assert_eq!(rev_parse(&origin, "refs/heads/feature"), pushed);
+}
+
+#[test]
+fn clone_through_ext_fails_for_a_missing_repository() {
+    let root = GritTempDir::new("ext-missing");
+
+    let output = grit(
+        &root.path,
+        &["clone", &ext_url(&root.path.join("missing")), "copy"],
+    );
+
+    assert!(!output.status.success());
+    assert!(
+        combined_output(&output).contains("does not appear to be a grit repository"),
+        "{}",
+        combined_output(&output)
+    );
+}
+
+#[test]
+fn fetch_through_ext_reports_a_remote_error() {
+    let root = GritTempDir::new("ext-corrupt");
+    let origin = root.path.join("origin");
+
+    create_repository(&origin, &[("readme.txt", b"first\n")]);
+
+    grit_ok(&root.path, &["clone", &ext_url(&origin), "copy"]);
+    let copy = root.path.join("copy");
+
+    let second = commit_files(&origin, &[("readme.txt", b"second\n")], "Second");
+    let tree = grit_ok(&origin, &["cat-file", "-p", &second])
+        .lines()
+        .find_map(|line| line.strip_prefix("tree ").map(|oid| oid.to_string()))
+        .unwrap();
+
+    // Replace the new tree with something that does not decompress
+    let tree_path = origin
+        .join(".grit/objects")
+        .join(&tree[..2])
+        .join(&tree[2..]);
+    let mut permissions = fs::metadata(&tree_path).unwrap().permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    fs::set_permissions(&tree_path, permissions).unwrap();
+    fs::write(&tree_path, b"not an object").unwrap();
+
+    let output = grit(&copy, &["fetch"]);
+
+    assert!(!output.status.success());
+    assert!(
+        combined_output(&output).contains("remote error: upload-pack: failed to serve the request"),
+        "{}",
+        combined_output(&output)
+    );
+}
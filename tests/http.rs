@@ -0,0 +1,115 @@
+mod common;
+
+use common::{
+    binary_content, commit_files, create_repository, grit, grit_ok, rev_parse, GritTempDir,
+};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+
+/// A running `grit serve`, stopped once dropped.
+struct GritServer {
+    child: Child,
+    url: String,
+}
+
+impl GritServer {
+    /// Serves the repositories under `root` on a port the system picks.
+    fn start(root: &Path) -> GritServer {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_grit"))
+            .args(["serve", "--port", "0"])
+            .current_dir(root)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut banner = String::new();
+        stdout.read_line(&mut banner).unwrap();
+
+        // Keep the pipe drained so the server never blocks on its own output
+        thread::spawn(move || {
+            let _ = stdout.read_to_end(&mut vec![]);
+        });
+
+        let url = match banner.trim_end().rsplit_once(" at ") {
+            Some((_, url)) => url.trim_end_matches('/').to_string(),
+            None => panic!("unexpected banner from grit serve: {banner}"),
+        };
+
+        GritServer { child, url }
+    }
+}
+
+/// Lets the repository at `dir` be pushed to over HTTP.
+fn enable_receive_pack(dir: &Path) {
+    fs::write(dir.join(".grit/config"), "[http]\n\treceivepack = true\n").unwrap();
+}
+
+impl Drop for GritServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn clone_fetch_and_push_over_http() {
+    let root = GritTempDir::new("http");
+    let origin = root.path.join("origin");
+    let binary = binary_content();
+
+    let first = create_repository(
+        &origin,
+        &[("readme.txt", b"hello\n"), ("images/logo.bin", &binary)],
+    );
+
+    let server = GritServer::start(&root.path);
+    let url = format!("{}/origin", server.url);
+
+    grit_ok(&root.path, &["clone", &url, "copy"]);
+    let copy = root.path.join("copy");
+
+    assert_eq!(rev_parse(&copy, "HEAD"), first);
+    assert_eq!(
+        fs::read(copy.join("source/images/logo.bin")).unwrap(),
+        binary
+    );
+
+    let second = commit_files(&origin, &[("readme.txt", b"hello again\n")], "Second");
+
+    grit_ok(&copy, &["fetch"]);
+    assert_eq!(rev_parse(&copy, "refs/remotes/origin/main"), second);
+
+    let pushed = commit_files(&copy, &[("notes.bin", &binary[1..])], "From the copy");
+
+    enable_receive_pack(&origin);
+    grit_ok(&copy, &["push", "origin", "main:feature"]);
+    assert_eq!(rev_parse(&origin, "refs/heads/feature"), pushed);
+    assert_eq!(
+        grit_ok(&origin, &["cat-file", "-t", &pushed]).trim(),
+        "commit"
+    );
+}
+
+#[test]
+fn push_over_http_is_refused_unless_enabled() {
+    let root = GritTempDir::new("http-push");
+    let origin = root.path.join("origin");
+    create_repository(&origin, &[("readme.txt", b"hello\n")]);
+
+    let server = GritServer::start(&root.path);
+    let url = format!("{}/origin", server.url);
+
+    grit_ok(&root.path, &["clone", &url, "copy"]);
+    let copy = root.path.join("copy");
+    commit_files(&copy, &[("readme.txt", b"changed\n")], "Change");
+
+    let output = grit(&copy, &["push", "origin", "main:feature"]);
+    assert!(!output.status.success());
+    assert!(!origin.join(".grit/refs/heads/feature").exists());
+}
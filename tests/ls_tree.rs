@@ -0,0 +1,21 @@
+mod common;
+
+use common::{create_repository, grit_ok, GritTempDir};
+
+#[test]
+fn listing_folded_directories_writes_no_objects() {
+    let root = GritTempDir::new("ls-tree");
+    let repository = root.path.join("repository");
+    create_repository(
+        &repository,
+        &[("directory/file.txt", b"inside\n"), ("top.txt", b"top\n")],
+    );
+
+    let objects = grit_ok(&repository, &["count-objects"]);
+    let listing = grit_ok(&repository, &["ls-tree", "HEAD"]);
+
+    assert!(listing.contains(" tree "), "{listing}");
+    assert!(listing.contains("\tdirectory\n"), "{listing}");
+    assert_eq!(grit_ok(&repository, &["count-objects"]), objects);
+    assert_eq!(grit_ok(&repository, &["ls-tree", "HEAD"]), listing);
+}
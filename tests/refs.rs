@@ -0,0 +1,40 @@
+mod common;
+
+use common::{commit_files, create_repository, grit, grit_ok, rev_parse, GritTempDir};
+
+#[test]
+fn detached_head_round_trip() {
+    let root = GritTempDir::new("refs");
+    let repository = root.path.join("repository");
+
+    let first = create_repository(&repository, &[("file.txt", b"one\n")]);
+    let second = commit_files(&repository, &[("file.txt", b"two\n")], "Second");
+
+    grit_ok(&repository, &["update-ref", "--no-deref", "HEAD", &first]);
+
+    assert_eq!(rev_parse(&repository, "HEAD"), first);
+    assert_eq!(rev_parse(&repository, "refs/heads/main"), second);
+    assert!(!grit(&repository, &["symbolic-ref", "HEAD"])
+        .status
+        .success());
+    assert_eq!(
+        grit_ok(&repository, &["for-each-ref"]),
+        format!("{second} commit\trefs/heads/main\n")
+    );
+
+    grit_ok(&repository, &["symbolic-ref", "HEAD", "refs/heads/main"]);
+
+    assert_eq!(
+        grit_ok(&repository, &["symbolic-ref", "HEAD"]),
+        "refs/heads/main\n"
+    );
+    assert_eq!(rev_parse(&repository, "HEAD"), second);
+
+    grit_ok(&repository, &["update-ref", "HEAD", &first]);
+
+    assert_eq!(rev_parse(&repository, "refs/heads/main"), first);
+    assert_eq!(
+        grit_ok(&repository, &["symbolic-ref", "HEAD"]),
+        "refs/heads/main\n"
+    );
+}
@@ -0,0 +1,28 @@
+mod common;
+
+use common::{create_repository, grit_ok, GritTempDir};
+
+#[test]
+fn list_shows_the_configured_remotes() {
+    let root = GritTempDir::new("remote-list");
+    let repository = root.path.join("repository");
+    create_repository(&repository, &[("file.txt", b"content\n")]);
+
+    grit_ok(&repository, &["remote", "add", "origin", "../upstream"]);
+    grit_ok(&repository, &["remote", "add", "backup", "../backup"]);
+
+    let names = grit_ok(&repository, &["remote"]);
+    assert_eq!(grit_ok(&repository, &["remote", "list"]), names);
+
+    let mut listed = names.lines().collect::<Vec<&str>>();
+    listed.sort();
+    assert_eq!(listed, ["backup", "origin"]);
+
+    let verbose = grit_ok(&repository, &["remote", "list", "-v"]);
+    assert_eq!(verbose, grit_ok(&repository, &["remote", "-v"]));
+    assert!(verbose.contains("origin\t../upstream (fetch)\n"));
+    assert!(verbose.contains("backup\t../backup (push)\n"));
+
+    grit_ok(&repository, &["remote", "remove", "backup"]);
+    assert_eq!(grit_ok(&repository, &["remote", "list"]), "origin\n");
+}
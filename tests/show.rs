@@ -0,0 +1,32 @@
+mod common;
+
+use common::{binary_content, commit_files, create_repository, grit, grit_ok, GritTempDir};
+
+#[test]
+fn show_handles_binary_files() {
+    let root = GritTempDir::new("show-binary");
+    let repository = root.path.join("repository");
+    let binary = binary_content();
+
+    create_repository(
+        &repository,
+        &[("image.bin", &binary[..100]), ("notes.txt", b"one\n")],
+    );
+    commit_files(
+        &repository,
+        &[("image.bin", &binary), ("notes.txt", b"two\n")],
+        "Second",
+    );
+
+    let patch = grit_ok(&repository, &["show", "HEAD"]);
+    assert!(patch.contains("Binary files a/image.bin and b/image.bin differ\n"));
+    assert!(patch.contains("-one\n+two\n"));
+
+    let stat = grit_ok(&repository, &["show", "--stat", "HEAD"]);
+    assert!(stat.contains(&format!(" image.bin | Bin 100 -> {} bytes\n", binary.len())));
+    assert!(stat.contains(" notes.txt | 2 +-\n"));
+
+    let output = grit(&repository, &["show", "HEAD:image.bin"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, binary);
+}
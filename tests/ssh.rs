@@ -0,0 +1,85 @@
+mod common;
+
+use common::{create_repository, rev_parse, GritTempDir};
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A stand-in for ssh that insists on `--` before the host, then runs the
+/// remote command here, with this build of grit first on the `PATH`.
+fn create_fake_ssh(dir: &Path) -> PathBuf {
+    let script = dir.join("fake-ssh");
+
+    fs::write(
+        &script,
+        "#!/bin/sh\n[ \"$1\" = \"--\" ] || { echo \"fake-ssh: no -- before $1\" >&2; exit 99; }\nshift 2\nexec sh -c \"$1\"\n",
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    script
+}
+
+fn grit_over_ssh(dir: &Path, fake_ssh: &Path, args: &[&str]) -> Output {
+    let grit = PathBuf::from(env!("CARGO_BIN_EXE_grit"));
+    let path = format!(
+        "{}:{}",
+        grit.parent().unwrap().display(),
+        env::var("PATH").unwrap_or_default()
+    );
+
+    Command::new(&grit)
+        .args(args)
+        .current_dir(dir)
+        .env("GRIT_SSH_COMMAND", fake_ssh)
+        .env("PATH", path)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn clone_over_ssh_passes_the_host_after_a_double_dash() {
+    let root = GritTempDir::new("ssh");
+    let origin = root.path.join("origin");
+    let fake_ssh = create_fake_ssh(&root.path);
+
+    let head = create_repository(&origin, &[("readme.txt", b"hello\n")]);
+
+    let url = format!("ssh://example.com{}", origin.display());
+    let output = grit_over_ssh(&root.path, &fake_ssh, &["clone", &url, "copy"]);
+
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(rev_parse(&root.path.join("copy"), "HEAD"), head);
+}
+
+#[test]
+fn urls_that_would_pass_options_to_ssh_are_blocked() {
+    let root = GritTempDir::new("ssh-blocked");
+    let fake_ssh = create_fake_ssh(&root.path);
+    let marker = root.path.join("pwned");
+
+    for url in [
+        format!("ssh://-oProxyCommand=touch {}/repo", marker.display()),
+        "ssh://example.com:-oProxyCommand=sh/repo".to_string(),
+        "example.com:-oProxyCommand=sh".to_string(),
+    ] {
+        let output = grit_over_ssh(&root.path, &fake_ssh, &["clone", &url, "copy"]);
+        let printed = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        assert!(!output.status.success(), "{url}: {printed}");
+        assert!(!printed.contains("fake-ssh"), "{url}: {printed}");
+    }
+
+    assert!(!marker.exists());
+}